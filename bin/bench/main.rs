@@ -0,0 +1,195 @@
+use vangers::{
+    cache::Cache,
+    config, level,
+    render::{
+        self, body::GpuStoreInit, object::Context as ObjectContext, select_color_format, Batcher,
+        Render, ScreenTargets, DEPTH_FORMAT,
+    },
+    space,
+};
+
+use log::info;
+
+use std::time;
+
+fn report(timings: &mut [time::Duration]) {
+    timings.sort();
+    let count = timings.len();
+    let total = timings.iter().sum::<time::Duration>();
+    println!("Min: {:?}", timings[0]);
+    println!("Median: {:?}", timings[count / 2]);
+    println!("Max: {:?}", timings[count - 1]);
+    println!("Average: {:?}", total / count as u32);
+}
+
+fn bench_models(
+    settings: &config::Settings,
+    device: &wgpu::Device,
+    object: &ObjectContext,
+    num_rounds: usize,
+) {
+    let game_reg = config::game::Registry::load(settings);
+    info!("Loading the vehicle models {} times", num_rounds);
+    let mut timings = Vec::with_capacity(num_rounds);
+    let mut num_models = 0;
+    for _ in 0..num_rounds {
+        let start = time::Instant::now();
+        let registry =
+            config::car::load_registry(settings, &game_reg, device, object, &mut |_, _| ());
+        timings.push(start.elapsed());
+        num_models = registry.len();
+    }
+
+    println!("Rounds: {}", num_rounds);
+    println!("Models per round: {}", num_models);
+    report(&mut timings);
+}
+
+fn load_level(settings: &config::Settings) -> level::Level {
+    if settings.game.level.is_empty() {
+        info!("Using test level");
+        return level::Level::new_test();
+    }
+    let worlds = config::worlds::load(settings.open_relative("wrlds.dat"));
+    let ini_name = worlds.get(&settings.game.level).unwrap_or_else(|| {
+        panic!(
+            "Unable to find the world, supported: {:?}",
+            worlds.keys().collect::<Vec<_>>()
+        )
+    });
+    info!("Using level {}", ini_name);
+    let level_config = level::LevelConfig::load(&settings.data_path.join(ini_name));
+    level::load(&level_config, &Cache::new(&settings.cache_path))
+}
+
+fn main() {
+    use std::env;
+
+    env_logger::init();
+    let args: Vec<_> = env::args().collect();
+    let mut options = getopts::Options::new();
+    options
+        .parsing_style(getopts::ParsingStyle::StopAtFirstFree)
+        .optflag("h", "help", "print this help menu")
+        .optopt("f", "frames", "number of frames to render", "N")
+        .optopt(
+            "m",
+            "models",
+            "number of times to load the vehicle models, instead of rendering",
+            "N",
+        );
+
+    let matches = options.parse(&args[1..]).unwrap();
+    if matches.opt_present("h") || !matches.free.is_empty() {
+        println!("Vangers headless benchmark");
+        let brief = format!("Usage: {} [options]", args[0]);
+        println!("{}", options.usage(&brief));
+        return;
+    }
+    let num_frames: usize = matches.opt_str("f").map_or(100, |s| s.parse().unwrap());
+    assert_ne!(num_frames, 0, "Need at least one frame to benchmark");
+
+    info!("Loading the settings");
+    let mut settings = config::Settings::load("config/settings.ron");
+    let extent = wgpu::Extent3d {
+        width: settings.window.size[0],
+        height: settings.window.size[1],
+        depth: 1,
+    };
+
+    info!("Initializing the device");
+    let instance = wgpu::Instance::new(settings.backend.to_wgpu());
+    let render::init::Gpu {
+        device,
+        queue,
+        report,
+        ..
+    } = futures::executor::block_on(render::init::init(&instance, None, &settings))
+        .unwrap_or_else(|e| panic!("Unable to initialize GPU: {}", e));
+    settings.render.terrain = report.fallback_terrain(&settings.render.terrain);
+
+    let level = load_level(&settings);
+    let objects_palette = level::read_palette(settings.open_palette(), None);
+    // there is no surface to prefer anything
+    let color_format =
+        select_color_format(settings.window.color_format, render::DEFAULT_COLOR_FORMAT);
+    let store_init = GpuStoreInit::new_dummy(&device);
+    let mut render = Render::new(
+        &device,
+        &queue,
+        &level,
+        &objects_palette,
+        &settings.render,
+        extent,
+        color_format,
+        store_init.resource(),
+    );
+
+    if let Some(rounds) = matches.opt_str("m") {
+        let num_rounds: usize = rounds.parse().unwrap();
+        assert_ne!(num_rounds, 0, "Need at least one round to benchmark");
+        bench_models(&settings, &device, &render.object, num_rounds);
+        render.destroy();
+        return;
+    }
+
+    let color_view = device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some(&render::label::resource("bench", "color")),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: color_format,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default());
+    let depth_view = device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some(&render::label::resource("bench", "depth")),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default());
+
+    let depth = settings.game.camera.depth_range;
+    let mut cam = space::Camera::new(
+        cgmath::vec3(0.0, 0.0, 200.0),
+        cgmath::Rotation3::from_angle_x::<cgmath::Rad<_>>(cgmath::Angle::turn_div_6()),
+        space::Projection::Perspective(cgmath::PerspectiveFov {
+            fovy: cgmath::Deg(45.0).into(),
+            aspect: extent.width as f32 / extent.height as f32,
+            near: depth.0,
+            far: depth.1,
+        }),
+    );
+    let mut batcher = Batcher::new();
+
+    info!("Rendering {} frames", num_frames);
+    let mut timings = Vec::with_capacity(num_frames);
+    for _ in 0..num_frames {
+        let start = time::Instant::now();
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Bench"),
+        });
+        let targets = ScreenTargets {
+            extent,
+            color: &color_view,
+            depth: &depth_view,
+        };
+        render.draw_world(&mut encoder, &mut batcher, &cam, targets, &device, &queue);
+        queue.submit(Some(encoder.finish()));
+        device.poll(wgpu::Maintain::Wait);
+        timings.push(start.elapsed());
+        // fly over the level, so that every frame sees a different view
+        cam.loc.y += 4.0;
+    }
+    render.destroy();
+
+    println!("Frames: {}", num_frames);
+    report(&mut timings);
+}
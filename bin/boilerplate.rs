@@ -1,130 +1,559 @@
+use crate::input::{Action, KeyMap};
 use vangers::{
     config,
-    render::{ScreenTargets, COLOR_FORMAT, DEPTH_FORMAT},
+    diagnostics::{self, Diagnostics},
+    render::{self, loading, readback::Readback, select_color_format, ScreenTargets, DEPTH_FORMAT},
+    telemetry::{self, FrameRateCounter, NoTelemetry, Telemetry},
 };
 
-use env_logger;
 use futures::executor::{LocalPool, LocalSpawner};
-use log::info;
+use log::{error, info};
 use winit::{
     event,
     event_loop::{ControlFlow, EventLoop},
     window::{Window, WindowBuilder},
 };
 
+use std::{
+    fs::File,
+    mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+const SETTINGS_PATH: &str = "config/settings.ron";
+/// Period of the frame rate reports to the telemetry, in seconds.
+const FRAME_RATE_PERIOD: f32 = 10.0;
+/// Height of the loading progress bar, in logical units.
+const LOADING_BAR_HEIGHT: f32 = 24.0;
+
+/// Change of the application state, requested by the current state.
+// not every binary is using all of the transitions
+#[allow(dead_code)]
+pub enum Transition {
+    /// Stay in the current state.
+    None,
+    /// Suspend the current state and enter a new one on top of it.
+    Push(Box<dyn Application>),
+    /// Leave the current state, returning to the previous one.
+    /// The harness exits when there are no states left.
+    Pop,
+    /// Replace the current state with a new one.
+    Replace(Box<dyn Application>),
+}
+
+/// A state of the application. Only the top state on the stack
+/// receives the input, gets updated, and is drawn.
 pub trait Application {
-    fn on_key(&mut self, input: event::KeyboardInput) -> bool;
+    fn on_key(&mut self, input: event::KeyboardInput) -> Transition;
     fn on_mouse_wheel(&mut self, _delta: event::MouseScrollDelta) {}
     fn on_cursor_move(&mut self, _position: (f64, f64)) {}
     fn on_mouse_button(&mut self, _state: event::ElementState, _button: event::MouseButton) {}
+    /// Status text to show in the window title.
+    fn status(&self) -> Option<String> {
+        None
+    }
+    /// Called with the physical size of the framebuffer, in pixels.
     fn resize(&mut self, _device: &wgpu::Device, _extent: wgpu::Extent3d) {}
+    /// Scale of the UI elements, in pixels per logical unit.
+    fn set_ui_scale(&mut self, _scale: f32) {}
     fn reload(&mut self, device: &wgpu::Device);
+    /// Release the GPU resources explicitly, called when the state is removed.
+    fn destroy(self: Box<Self>) {}
+    /// Polled every frame after the update, for transitions that are not caused by the input.
+    fn transition(&mut self) -> Transition {
+        Transition::None
+    }
     fn update(
         &mut self,
         device: &wgpu::Device,
         delta: f32,
         spawner: &LocalSpawner,
     ) -> Vec<wgpu::CommandBuffer>;
+    /// Called after the update is submitted, for the work that needs the queue,
+    /// such as creating the resources of a newly loaded level.
+    /// The screen can show the progress of such work, if it takes long.
+    fn prepare(&mut self, _screen: &mut Screen) {}
+    /// Record the drawing of a frame. The queue is only for writing
+    /// the data of the frame, which is submitted together with it.
     fn draw(
         &mut self,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         targets: ScreenTargets,
         spawner: &LocalSpawner,
     ) -> wgpu::CommandBuffer;
 }
 
+/// Access to the window for the work done in between the frames.
+pub struct Screen<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    window: &'a Window,
+    title: &'a str,
+    swap_chain: &'a mut wgpu::SwapChain,
+    presented: bool,
+}
+
+impl Screen<'_> {
+    /// Show the status in the window title, and present a frame drawn by the given function.
+    pub fn present(
+        &mut self,
+        status: &str,
+        draw: impl FnOnce(&mut wgpu::CommandEncoder, &wgpu::TextureView),
+    ) {
+        self.window
+            .set_title(&format!("{} | {}", self.title, status));
+        self.presented = true;
+        if let Ok(frame) = self.swap_chain.get_current_frame() {
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Present"),
+                });
+            draw(&mut encoder, &frame.output.view);
+            self.queue.submit(Some(encoder.finish()));
+        }
+    }
+}
+
+/// Creates the application state, reporting the progress
+/// of each loading stage in range from 0 to 1.
+pub type Load =
+    Box<dyn FnOnce(&wgpu::Device, &wgpu::Queue, &mut dyn FnMut(&str, f32)) -> Box<dyn Application>>;
+
+/// Loading state, replaced by the application once it's created.
+pub struct Loading {
+    screen: loading::Context,
+    load: Option<Load>,
+    loaded: Option<Box<dyn Application>>,
+    progress: f32,
+    height: u32,
+    ui_scale: f32,
+}
+
+impl Loading {
+    /// Set the function creating the application, which replaces the loading state.
+    pub fn create(mut self, load: Load) -> Self {
+        self.load = Some(load);
+        self
+    }
+
+    fn bar_height(&self) -> f32 {
+        LOADING_BAR_HEIGHT * self.ui_scale / self.height.max(1) as f32
+    }
+}
+
+impl Application for Loading {
+    fn on_key(&mut self, _input: event::KeyboardInput) -> Transition {
+        Transition::None
+    }
+
+    fn resize(&mut self, _device: &wgpu::Device, extent: wgpu::Extent3d) {
+        self.height = extent.height;
+    }
+
+    fn set_ui_scale(&mut self, scale: f32) {
+        self.ui_scale = scale;
+    }
+
+    fn reload(&mut self, _device: &wgpu::Device) {}
+
+    fn transition(&mut self) -> Transition {
+        match self.loaded.take() {
+            Some(app) => Transition::Replace(app),
+            None => Transition::None,
+        }
+    }
+
+    fn update(
+        &mut self,
+        _device: &wgpu::Device,
+        _delta: f32,
+        _spawner: &LocalSpawner,
+    ) -> Vec<wgpu::CommandBuffer> {
+        Vec::new()
+    }
+
+    fn prepare(&mut self, screen: &mut Screen) {
+        let load = match self.load.take() {
+            Some(load) => load,
+            None => return,
+        };
+        let bar_height = self.bar_height();
+        let context = &self.screen;
+        let last_progress = &mut self.progress;
+        let (device, queue) = (screen.device, screen.queue);
+        // the loading blocks the event loop, so the frames are presented in between the stages
+        let app = load(device, queue, &mut |stage, progress| {
+            info!("Loading {} ({:.0}%)", stage, progress * 100.0);
+            *last_progress = progress;
+            screen.present(&format!("Loading {}...", stage), |encoder, output| {
+                context.draw(progress, bar_height, encoder, output, queue)
+            });
+        });
+        self.loaded = Some(app);
+    }
+
+    fn draw(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        targets: ScreenTargets,
+        _spawner: &LocalSpawner,
+    ) -> wgpu::CommandBuffer {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Loading"),
+        });
+        self.screen.draw(
+            self.progress,
+            self.bar_height(),
+            &mut encoder,
+            targets.color,
+            queue,
+        );
+        encoder.finish()
+    }
+}
+
+fn load_image(path: &str) -> Result<(wgpu::Extent3d, Vec<u8>), String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut data = vec![0u8; info.buffer_size()];
+    reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+
+    let rgba = match info.color_type {
+        png::ColorType::RGBA => data,
+        png::ColorType::RGB => data
+            .chunks(3)
+            .flat_map(|c| vec![c[0], c[1], c[2], 0xFF])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => data
+            .chunks(2)
+            .flat_map(|c| vec![c[0], c[0], c[0], c[1]])
+            .collect(),
+        png::ColorType::Grayscale => data.iter().flat_map(|&c| vec![c, c, c, 0xFF]).collect(),
+        other => return Err(format!("unsupported color type {:?}", other)),
+    };
+    let extent = wgpu::Extent3d {
+        width: info.width,
+        height: info.height,
+        depth: 1,
+    };
+    Ok((extent, rgba))
+}
+
+fn create_depth_target(
+    device: &wgpu::Device,
+    extent: wgpu::Extent3d,
+) -> (wgpu::Texture, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(&render::label::resource("screen", "depth")),
+        size: extent,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        // the depth is sampled by the contact shadows
+        usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    (texture, view)
+}
+
+/// Draw an extra frame of the application into an offscreen target,
+/// and write it into the given path once it's read back.
+#[allow(clippy::too_many_arguments)]
+fn capture_screenshot(
+    app: &mut dyn Application,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    extent: wgpu::Extent3d,
+    color_format: wgpu::TextureFormat,
+    depth: &wgpu::TextureView,
+    spawner: &LocalSpawner,
+    path: PathBuf,
+) {
+    let texel_size = match diagnostics::texel_size(color_format) {
+        Ok(size) => size,
+        Err(e) => {
+            error!("Unable to capture the screenshot: {}", e);
+            return;
+        }
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(&render::label::resource("screen", "screenshot")),
+        size: extent,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: color_format,
+        usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let targets = ScreenTargets {
+        extent,
+        color: &view,
+        depth,
+    };
+    let render_command_buffer = app.draw(device, queue, targets, spawner);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Screenshot"),
+    });
+    let readback = Readback::from_texture(
+        device,
+        &mut encoder,
+        "Screenshot",
+        &texture,
+        extent,
+        texel_size,
+    );
+    queue.submit(vec![render_command_buffer, encoder.finish()]);
+    texture.destroy();
+
+    readback.consume(spawner, move |result| {
+        let written = result
+            .map_err(|e| format!("{:?}", e))
+            .and_then(|data| diagnostics::write_screenshot(&path, extent, color_format, data));
+        match written {
+            Ok(()) => info!("Screenshot is written to {:?}", path),
+            Err(e) => error!("Unable to write the screenshot: {}", e),
+        }
+    });
+}
+
+/// Scale of the UI, following the display unless overridden by the settings.
+fn compute_ui_scale(window: &Window, scale_override: Option<f32>) -> f32 {
+    scale_override.unwrap_or(window.scale_factor() as f32)
+}
+
+/// Re-create the swap chain and the depth target for the new physical size.
+fn resize_targets(
+    device: &wgpu::Device,
+    surface: &wgpu::Surface,
+    color_format: wgpu::TextureFormat,
+    size: winit::dpi::PhysicalSize<u32>,
+    swap_chain: &mut wgpu::SwapChain,
+    depth_target: &mut (wgpu::Texture, wgpu::TextureView),
+) -> wgpu::Extent3d {
+    info!("Resizing to {:?}", size);
+    let extent = wgpu::Extent3d {
+        width: size.width,
+        height: size.height,
+        depth: 1,
+    };
+    let sc_desc = wgpu::SwapChainDescriptor {
+        usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        format: color_format,
+        width: size.width,
+        height: size.height,
+        present_mode: wgpu::PresentMode::Mailbox,
+    };
+    *swap_chain = device.create_swap_chain(surface, &sc_desc);
+    let (old_depth, _) = mem::replace(depth_target, create_depth_target(device, extent));
+    old_depth.destroy();
+    extent
+}
+
+fn apply_transition(
+    transition: Transition,
+    states: &mut Vec<Box<dyn Application>>,
+    device: &wgpu::Device,
+    extent: wgpu::Extent3d,
+    ui_scale: f32,
+) {
+    let mut new_state = match transition {
+        Transition::None => return,
+        Transition::Pop => {
+            if let Some(old) = states.pop() {
+                old.destroy();
+            }
+            return;
+        }
+        Transition::Push(state) => state,
+        Transition::Replace(state) => {
+            if let Some(old) = states.pop() {
+                old.destroy();
+            }
+            state
+        }
+    };
+    // the window may have been resized since the state was created
+    new_state.resize(device, extent);
+    new_state.set_ui_scale(ui_scale);
+    states.push(new_state);
+}
+
 pub struct Harness {
     task_pool: LocalPool,
     event_loop: EventLoop<()>,
     window: Window,
+    title: String,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     surface: wgpu::Surface,
     swap_chain: wgpu::SwapChain,
+    /// Physical size of the framebuffer, in pixels.
     pub extent: wgpu::Extent3d,
+    pub color_format: wgpu::TextureFormat,
+    ui_scale_override: Option<f32>,
     reload_on_focus: bool,
-    depth_target: wgpu::TextureView,
+    depth_target: (wgpu::Texture, wgpu::TextureView),
+    diagnostics: Arc<Diagnostics>,
+    telemetry: Arc<dyn Telemetry>,
+    frame_rate: FrameRateCounter,
+    key_map: KeyMap,
 }
 
 impl Harness {
     pub fn init(title: &str) -> (Self, config::Settings) {
-        env_logger::init();
+        // the binaries don't report anywhere
+        let telemetry: Arc<dyn Telemetry> = Arc::new(NoTelemetry);
+        let mut settings = config::Settings::load(SETTINGS_PATH);
+        let diagnostics = Diagnostics::init(&settings.diagnostics, Path::new(SETTINGS_PATH));
         let mut task_pool = LocalPool::new();
 
-        info!("Loading the settings");
-        let settings = config::Settings::load("config/settings.ron");
-        let extent = wgpu::Extent3d {
-            width: settings.window.size[0],
-            height: settings.window.size[1],
-            depth: 1,
-        };
+        if settings.validation.backend {
+            info!("Enabling backend validation");
+            std::env::set_var("VK_INSTANCE_LAYERS", "VK_LAYER_KHRONOS_validation");
+        }
 
         info!("Initializing the window");
         let instance = wgpu::Instance::new(settings.backend.to_wgpu());
         let event_loop = EventLoop::new();
         let window = WindowBuilder::new()
             .with_title(title)
-            .with_inner_size(winit::dpi::PhysicalSize::new(extent.width, extent.height))
+            .with_inner_size(winit::dpi::LogicalSize::new(
+                settings.window.size[0],
+                settings.window.size[1],
+            ))
             .with_resizable(true)
             .build(&event_loop)
             .unwrap();
+        // the window size is logical, while the framebuffer is sized in pixels
+        let physical_size = window.inner_size();
+        info!(
+            "Window size {:?} at the scale factor {}",
+            physical_size,
+            window.scale_factor()
+        );
+        let extent = wgpu::Extent3d {
+            width: physical_size.width,
+            height: physical_size.height,
+            depth: 1,
+        };
         let surface = unsafe { instance.create_surface(&window) };
 
         info!("Initializing the device");
-        let adapter = task_pool
-            .run_until(instance.request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::Default,
-                compatible_surface: Some(&surface),
-            }))
-            .expect("Unable to initialize GPU via the selected backend.");
-        let (device, queue) = task_pool
-            .run_until(adapter.request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
-                    shader_validation: true,
-                },
-                None,
-            ))
-            .unwrap();
+        for report in render::init::enumerate(&instance, settings.backend.to_wgpu()) {
+            info!("\tFound adapter {}", report);
+        }
+        let render::init::Gpu {
+            adapter,
+            device,
+            queue,
+            report,
+        } = task_pool
+            .run_until(render::init::init(&instance, Some(&surface), &settings))
+            .unwrap_or_else(|e| panic!("Unable to initialize GPU: {}", e));
+        diagnostics.set_adapter(&report.info);
+        telemetry.event(&telemetry::Event::Adapter {
+            name: &report.info.name,
+            backend: report.info.backend,
+        });
+        settings.render.terrain = report.fallback_terrain(&settings.render.terrain);
+
+        let fatal_errors = settings.validation.fatal_errors;
+        device.on_uncaptured_error(move |e| {
+            // the error message carries the labels of the offending resources,
+            // which tell us about the subsystem that created them
+            if fatal_errors {
+                panic!("GPU error: {}", e);
+            } else {
+                error!("GPU error: {}", e);
+            }
+        });
 
+        let color_format = select_color_format(
+            settings.window.color_format,
+            adapter.get_swap_chain_preferred_format(&surface),
+        );
+        info!("Using color format {:?}", color_format);
         let sc_desc = wgpu::SwapChainDescriptor {
             usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-            format: COLOR_FORMAT,
+            format: color_format,
             width: extent.width,
             height: extent.height,
             present_mode: wgpu::PresentMode::Mailbox,
         };
         let swap_chain = device.create_swap_chain(&surface, &sc_desc);
-        let depth_target = device
-            .create_texture(&wgpu::TextureDescriptor {
-                label: Some("Depth"),
-                size: extent,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: DEPTH_FORMAT,
-                usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-            })
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let depth_target = create_depth_target(&device, extent);
 
         let harness = Harness {
             task_pool,
             event_loop,
             window: window,
+            title: title.to_string(),
             device,
             queue,
             surface,
             swap_chain,
             extent,
+            color_format,
+            ui_scale_override: settings.window.ui_scale,
             reload_on_focus: settings.window.reload_on_focus,
             depth_target,
+            diagnostics,
+            telemetry,
+            frame_rate: FrameRateCounter::new(settings.render.terrain.name(), FRAME_RATE_PERIOD),
+            key_map: KeyMap::new(&settings.bindings),
         };
 
         (harness, settings)
     }
 
-    pub fn main_loop<A: 'static + Application>(self, mut app: A) {
+    /// Telemetry for the applications to report their own events to.
+    #[allow(dead_code)]
+    pub fn telemetry(&self) -> Arc<dyn Telemetry> {
+        Arc::clone(&self.telemetry)
+    }
+
+    /// Create the loading state, which shows the loading screen while the application is created.
+    pub fn loading(&self, settings: &config::settings::Window) -> Loading {
+        let image = if settings.loading_image.is_empty() {
+            None
+        } else {
+            match load_image(&settings.loading_image) {
+                Ok(image) => Some(image),
+                Err(e) => {
+                    error!("Unable to load {}: {}", settings.loading_image, e);
+                    None
+                }
+            }
+        };
+        let screen = loading::Context::new(
+            image
+                .as_ref()
+                .map(|&(extent, ref data)| (extent, data.as_slice())),
+            self.color_format,
+            &self.device,
+            &self.queue,
+        );
+
+        Loading {
+            screen,
+            load: None,
+            loaded: None,
+            progress: 0.0,
+            height: self.extent.height,
+            ui_scale: compute_ui_scale(&self.window, self.ui_scale_override),
+        }
+    }
+
+    pub fn main_loop<A: 'static + Application>(self, app: A) {
         use std::time;
 
         let mut last_time = time::Instant::now();
@@ -133,51 +562,85 @@ impl Harness {
             mut task_pool,
             event_loop,
             window,
+            title,
             device,
             queue,
             surface,
             mut swap_chain,
             mut extent,
+            color_format,
+            ui_scale_override,
             reload_on_focus,
             mut depth_target,
+            diagnostics,
+            telemetry,
+            mut frame_rate,
+            key_map,
         } = self;
 
+        let mut last_status = None;
+        let mut pending_screenshot = None;
+        let mut frame_capture = render::capture::FrameCapture::new();
+        window.set_title(&title);
+        let mut ui_scale = compute_ui_scale(&window, ui_scale_override);
+        let mut states: Vec<Box<dyn Application>> = vec![Box::new(app)];
+        // the state is created with the logical size from the settings
+        states[0].resize(&device, extent);
+        states[0].set_ui_scale(ui_scale);
+
         event_loop.run(move |event, _, control_flow| {
-            let _ = window;
             *control_flow = ControlFlow::Poll;
             task_pool.run_until_stalled();
 
+            let app = match states.last_mut() {
+                Some(app) => app,
+                None => {
+                    frame_rate.flush(&*telemetry);
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+            };
+
             match event {
                 event::Event::WindowEvent {
                     event: event::WindowEvent::Resized(size),
                     ..
                 } => {
-                    info!("Resizing to {:?}", size);
-                    extent = wgpu::Extent3d {
-                        width: size.width,
-                        height: size.height,
-                        depth: 1,
-                    };
-                    let sc_desc = wgpu::SwapChainDescriptor {
-                        usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-                        format: COLOR_FORMAT,
-                        width: size.width,
-                        height: size.height,
-                        present_mode: wgpu::PresentMode::Mailbox,
-                    };
-                    swap_chain = device.create_swap_chain(&surface, &sc_desc);
-                    depth_target = device
-                        .create_texture(&wgpu::TextureDescriptor {
-                            label: Some("Depth"),
-                            size: extent,
-                            mip_level_count: 1,
-                            sample_count: 1,
-                            dimension: wgpu::TextureDimension::D2,
-                            format: DEPTH_FORMAT,
-                            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-                        })
-                        .create_view(&wgpu::TextureViewDescriptor::default());
-                    app.resize(&device, extent);
+                    extent = resize_targets(
+                        &device,
+                        &surface,
+                        color_format,
+                        size,
+                        &mut swap_chain,
+                        &mut depth_target,
+                    );
+                    for state in states.iter_mut() {
+                        state.resize(&device, extent);
+                    }
+                }
+                event::Event::WindowEvent {
+                    event:
+                        event::WindowEvent::ScaleFactorChanged {
+                            scale_factor,
+                            new_inner_size,
+                        },
+                    ..
+                } => {
+                    info!("Scale factor is changed to {}", scale_factor);
+                    ui_scale = ui_scale_override.unwrap_or(scale_factor as f32);
+                    // the logical size stays, so the framebuffer follows the scale
+                    extent = resize_targets(
+                        &device,
+                        &surface,
+                        color_format,
+                        *new_inner_size,
+                        &mut swap_chain,
+                        &mut depth_target,
+                    );
+                    for state in states.iter_mut() {
+                        state.resize(&device, extent);
+                        state.set_ui_scale(ui_scale);
+                    }
                 }
                 event::Event::WindowEvent { event, .. } => match event {
                     event::WindowEvent::Focused(false) => {
@@ -185,17 +648,50 @@ impl Harness {
                     }
                     event::WindowEvent::Focused(true) if needs_reload => {
                         info!("Reloading shaders");
-                        app.reload(&device);
+                        for state in states.iter_mut() {
+                            state.reload(&device);
+                        }
                         needs_reload = false;
                     }
                     event::WindowEvent::CloseRequested => {
+                        for state in states.drain(..).rev() {
+                            state.destroy();
+                        }
+                        frame_rate.flush(&*telemetry);
                         *control_flow = ControlFlow::Exit;
                     }
-                    event::WindowEvent::KeyboardInput { input, .. } => {
-                        if !app.on_key(input) {
-                            *control_flow = ControlFlow::Exit;
+                    event::WindowEvent::KeyboardInput {
+                        input:
+                            event::KeyboardInput {
+                                state: event::ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    } if key_map.action(key) == Some(Action::Screenshot) => {
+                        match diagnostics.write_bundle("requested by the user") {
+                            Ok(dir) => {
+                                info!("Diagnostic bundle is written to {:?}", dir);
+                                if diagnostics.wants_screenshot() {
+                                    pending_screenshot = Some(dir.join("screenshot.png"));
+                                }
+                            }
+                            Err(e) => error!("Unable to write the diagnostic bundle: {}", e),
                         }
                     }
+                    event::WindowEvent::KeyboardInput {
+                        input:
+                            event::KeyboardInput {
+                                state: event::ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    } if key_map.action(key) == Some(Action::Capture) => frame_capture.request(),
+                    event::WindowEvent::KeyboardInput { input, .. } => {
+                        let transition = app.on_key(input);
+                        apply_transition(transition, &mut states, &device, extent, ui_scale);
+                    }
                     event::WindowEvent::MouseWheel { delta, .. } => app.on_mouse_wheel(delta),
                     event::WindowEvent::CursorMoved { position, .. } => {
                         app.on_cursor_move(position.into())
@@ -210,24 +706,72 @@ impl Harness {
                     let duration = time::Instant::now() - last_time;
                     last_time += duration;
                     let delta = duration.as_secs() as f32 + duration.subsec_nanos() as f32 * 1.0e-9;
+                    diagnostics.record_frame(delta);
+                    frame_rate.record(delta, &*telemetry);
+                    frame_capture.begin_frame();
 
                     let update_command_buffers = app.update(&device, delta, &spawner);
                     if !update_command_buffers.is_empty() {
                         queue.submit(update_command_buffers);
                     }
+                    let mut screen = Screen {
+                        device: &device,
+                        queue: &queue,
+                        window: &window,
+                        title: &title,
+                        swap_chain: &mut swap_chain,
+                        presented: false,
+                    };
+                    app.prepare(&mut screen);
+                    if screen.presented {
+                        // the title is changed by the presented frames
+                        window.set_title(&title);
+                        last_status = None;
+                    }
+
+                    let transition = app.transition();
+                    apply_transition(transition, &mut states, &device, extent, ui_scale);
+                    let app = match states.last_mut() {
+                        Some(app) => app,
+                        None => return,
+                    };
+
+                    let status = app.status();
+                    if status != last_status {
+                        match status {
+                            Some(ref text) => window.set_title(&format!("{} | {}", title, text)),
+                            None => window.set_title(&title),
+                        }
+                        last_status = status;
+                    }
 
                     match swap_chain.get_current_frame() {
                         Ok(frame) => {
                             let targets = ScreenTargets {
                                 extent,
                                 color: &frame.output.view,
-                                depth: &depth_target,
+                                depth: &depth_target.1,
                             };
-                            let render_command_buffer = app.draw(&device, targets, &spawner);
+                            let render_command_buffer =
+                                app.draw(&device, &queue, targets, &spawner);
                             queue.submit(Some(render_command_buffer));
                         }
                         Err(_) => {}
                     };
+                    frame_capture.end_frame();
+
+                    if let Some(path) = pending_screenshot.take() {
+                        capture_screenshot(
+                            app.as_mut(),
+                            &device,
+                            &queue,
+                            extent,
+                            color_format,
+                            &depth_target.1,
+                            &spawner,
+                            path,
+                        );
+                    }
                 }
                 _ => (),
             }
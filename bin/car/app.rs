@@ -1,4 +1,7 @@
-use crate::boilerplate::Application;
+use crate::{
+    boilerplate::{Application, Transition},
+    input::{Action, KeyMap},
+};
 use m3d::Mesh;
 use vangers::{config, level, model, render, space};
 
@@ -16,22 +19,39 @@ pub struct CarView {
     debug_render: render::debug::Context,
     global: render::global::Context,
     object: render::object::Context,
+    batcher: render::Batcher,
     cam: space::Camera,
     rotation: (cgmath::Rad<f32>, cgmath::Rad<f32>),
     light_config: config::settings::Light,
+    key_map: KeyMap,
 }
 
 impl CarView {
-    pub fn new(settings: &config::Settings, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+    pub fn new(
+        settings: &config::Settings,
+        color_format: wgpu::TextureFormat,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Self {
         info!("Initializing the render");
         let pal_data = level::read_palette(settings.open_palette(), None);
         let store_init = render::body::GpuStoreInit::new_dummy(device);
-        let global = render::global::Context::new(device, queue, store_init.resource(), None);
+        let global = render::global::Context::new(
+            device,
+            queue,
+            color_format,
+            1,
+            store_init.resource(),
+            None,
+        );
         let object = render::object::Context::new(device, queue, &pal_data, &global);
 
         info!("Loading car registry");
         let game_reg = config::game::Registry::load(settings);
-        let car_reg = config::car::load_registry(settings, &game_reg, device, &object);
+        let car_reg =
+            config::car::load_registry(settings, &game_reg, device, &object, &mut |done, total| {
+                info!("Loaded {}/{} cars", done, total)
+            });
         let cinfo = match car_reg.get(&settings.car.id) {
             Some(ci) => ci,
             None => {
@@ -43,7 +63,11 @@ impl CarView {
         for (ms, sid) in model.slots.iter_mut().zip(settings.car.slots.iter()) {
             let info = &game_reg.model_infos[sid];
             let raw = Mesh::load(&mut settings.open_relative(&info.path));
-            ms.mesh = Some(model::load_c3d(raw, device));
+            ms.mesh = Some(model::load_c3d(
+                raw,
+                device,
+                !settings.render.debug.raw_vertices,
+            ));
             ms.scale = info.scale;
         }
 
@@ -64,18 +88,20 @@ impl CarView {
             ),
             global,
             object,
-            cam: space::Camera {
-                loc: cgmath::vec3(0.0, -64.0, 32.0),
-                rot: cgmath::Rotation3::from_angle_x::<cgmath::Rad<_>>(cgmath::Angle::turn_div_6()),
-                proj: space::Projection::Perspective(cgmath::PerspectiveFov {
+            batcher: render::Batcher::new(),
+            cam: space::Camera::new(
+                cgmath::vec3(0.0, -64.0, 32.0),
+                cgmath::Rotation3::from_angle_x::<cgmath::Rad<_>>(cgmath::Angle::turn_div_6()),
+                space::Projection::Perspective(cgmath::PerspectiveFov {
                     fovy: cgmath::Deg(45.0).into(),
                     aspect: settings.window.size[0] as f32 / settings.window.size[1] as f32,
                     near: 1.0,
                     far: 100.0,
                 }),
-            },
+            ),
             rotation: (cgmath::Rad(0.), cgmath::Rad(0.)),
             light_config: settings.render.light.clone(),
+            key_map: KeyMap::new(&settings.bindings),
         }
     }
 
@@ -101,8 +127,8 @@ impl CarView {
 }
 
 impl Application for CarView {
-    fn on_key(&mut self, input: winit::event::KeyboardInput) -> bool {
-        use winit::event::{ElementState, KeyboardInput, VirtualKeyCode as Key};
+    fn on_key(&mut self, input: winit::event::KeyboardInput) -> Transition {
+        use winit::event::{ElementState, KeyboardInput};
 
         let angle = cgmath::Rad(2.0);
         match input {
@@ -110,27 +136,31 @@ impl Application for CarView {
                 state: ElementState::Pressed,
                 virtual_keycode: Some(key),
                 ..
-            } => match key {
-                Key::Escape => return false,
-                Key::A => self.rotation.0 = -angle,
-                Key::D => self.rotation.0 = angle,
-                Key::W => self.rotation.1 = -angle,
-                Key::S => self.rotation.1 = angle,
+            } => match self.key_map.action(key) {
+                Some(Action::Exit) => return Transition::Pop,
+                Some(Action::TurnLeft) => self.rotation.0 = -angle,
+                Some(Action::TurnRight) => self.rotation.0 = angle,
+                Some(Action::Accelerate) => self.rotation.1 = -angle,
+                Some(Action::Reverse) => self.rotation.1 = angle,
                 _ => (),
             },
             KeyboardInput {
                 state: ElementState::Released,
                 virtual_keycode: Some(key),
                 ..
-            } => match key {
-                Key::A | Key::D => self.rotation.0 = cgmath::Rad(0.),
-                Key::W | Key::S => self.rotation.1 = cgmath::Rad(0.),
+            } => match self.key_map.action(key) {
+                Some(Action::TurnLeft) | Some(Action::TurnRight) => {
+                    self.rotation.0 = cgmath::Rad(0.)
+                }
+                Some(Action::Accelerate) | Some(Action::Reverse) => {
+                    self.rotation.1 = cgmath::Rad(0.)
+                }
                 _ => (),
             },
             _ => {}
         }
 
-        true
+        Transition::None
     }
 
     fn update(
@@ -163,25 +193,26 @@ impl Application for CarView {
     fn draw(
         &mut self,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         targets: render::ScreenTargets,
         _spawner: &LocalSpawner,
     ) -> wgpu::CommandBuffer {
-        let mut batcher = render::Batcher::new();
-        batcher.add_model(
+        self.batcher.clear();
+        self.batcher.add_model(
             &self.model,
             &self.transform,
             Some(self.physics.scale_bound),
             &render::body::GpuBody::ZERO,
             self.color,
         );
-        batcher.prepare(device);
+        self.batcher.prepare(device, queue);
 
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Draw"),
         });
         let global_data = render::global::Constants::new(&self.cam, &self.light_config, None);
         let global_staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
+            label: Some(&render::label::temp("global")),
             contents: bytemuck::bytes_of(&global_data),
             usage: wgpu::BufferUsage::COPY_SRC,
         });
@@ -218,11 +249,13 @@ impl Application for CarView {
                 }),
             });
 
+            pass.push_debug_group(&render::label::group("car", "objects"));
             pass.set_pipeline(self.object.pipelines.select(render::PipelineKind::Main));
             pass.set_bind_group(0, &self.global.bind_group, &[]);
             pass.set_bind_group(1, &self.object.bind_group, &[]);
 
-            batcher.draw(&mut pass);
+            self.batcher.draw(&mut pass);
+            pass.pop_debug_group();
 
             let _ = &self.debug_render;
             /*TODO:
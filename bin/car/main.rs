@@ -1,11 +1,13 @@
 mod app;
 #[path = "../boilerplate.rs"]
 mod boilerplate;
+#[path = "../input/mod.rs"]
+mod input;
 
 fn main() {
     use std::env;
 
-    let (mut harness, settings) = boilerplate::Harness::init("car");
+    let (harness, settings) = boilerplate::Harness::init("car");
 
     let args: Vec<_> = env::args().collect();
     let mut options = getopts::Options::new();
@@ -24,7 +26,12 @@ fn main() {
         return;
     }
 
-    let app = app::CarView::new(&settings, &harness.device, &mut harness.queue);
+    let color_format = harness.color_format;
+    let loading = harness.loading(&settings.window);
+    let loading = loading.create(Box::new(move |device, queue, progress| {
+        progress("car", 0.0);
+        Box::new(app::CarView::new(&settings, color_format, device, queue))
+    }));
 
-    harness.main_loop(app);
+    harness.main_loop(loading);
 }
@@ -2,12 +2,34 @@ mod layers;
 mod level_png;
 mod model_obj;
 
+use vangers::{cache::Cache, formats};
+
 use std::{
     fs::{read as fs_read, File},
     io::BufWriter,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    process,
 };
 
+/// Open an asset file, making sure that its format is supported.
+fn open_checked(path: &Path) -> File {
+    let mut file = File::open(path).unwrap();
+    match formats::detect_format(&mut file) {
+        Ok(Some(format)) => {
+            if let Err(e) = format.check() {
+                eprintln!("{}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+        Ok(None) => {
+            eprintln!("{}: unrecognized format", path.display());
+            process::exit(1);
+        }
+        Err(e) => panic!("Unable to read {}: {:?}", path.display(), e),
+    }
+    file
+}
+
 pub fn save_tiff(path: &PathBuf, layers: layers::LevelLayers) {
     let images = [
         tiff::Image {
@@ -59,7 +81,19 @@ fn main() {
     let mut options = getopts::Options::new();
     options
         .parsing_style(getopts::ParsingStyle::StopAtFirstFree)
-        .optflag("h", "help", "print this help menu");
+        .optflag("h", "help", "print this help menu")
+        .optopt(
+            "r",
+            "region",
+            "level region to extract, for INI to INI conversion",
+            "X,Y,W,H",
+        )
+        .optopt(
+            "d",
+            "diff",
+            "original level to compare the input with, for INI to PNG conversion",
+            "INI",
+        );
 
     let matches = options.parse(&args[1..]).unwrap();
     if matches.opt_present("h") || matches.free.len() != 2 {
@@ -83,7 +117,7 @@ fn main() {
             .unwrap_or(""),
     ) {
         ("m3d", "ron") => {
-            let file = File::open(&src_path).unwrap();
+            let file = open_checked(&src_path);
             println!("\tLoading M3D...");
             let raw = m3d::FullModel::load(file);
             println!("\tExporting OBJ data...");
@@ -93,10 +127,25 @@ fn main() {
             println!("\tImporting OBJ data...");
             let model = model_obj::import_m3d(&src_path);
             println!("\tSaving M3D...");
-            model.save(File::create(&dst_path).unwrap());
+            if let Err(e) = vangers::model::save_m3d(&model, File::create(&dst_path).unwrap()) {
+                eprintln!("{}: {}", src_path.display(), e);
+                process::exit(1);
+            }
+        }
+        ("obj", "m3d") => {
+            println!("\tImporting OBJ...");
+            let model = vangers::model::import_obj(&src_path).unwrap_or_else(|e| {
+                eprintln!("{}: {}", src_path.display(), e);
+                process::exit(1);
+            });
+            println!("\tSaving M3D...");
+            if let Err(e) = vangers::model::save_m3d(&model, File::create(&dst_path).unwrap()) {
+                eprintln!("{}: {}", src_path.display(), e);
+                process::exit(1);
+            }
         }
         ("a3d", "ron") => {
-            let file = File::open(&src_path).unwrap();
+            let file = open_checked(&src_path);
             println!("\tLoading A3D...");
             let raw = m3d::AnimatedMesh::load(file);
             println!("\tExporting OBJ data...");
@@ -111,7 +160,7 @@ fn main() {
         ("ini", "ron") => {
             println!("\tLoading the level...");
             let config = vangers::level::LevelConfig::load(&src_path);
-            let level = vangers::level::load(&config);
+            let level = vangers::level::load(&config, &Cache::disabled());
             let palette = layers::extract_palette(&level);
             let layers = layers::LevelLayers::from_level_data(
                 &vangers::level::LevelData::from(level),
@@ -123,7 +172,7 @@ fn main() {
         ("ini", "tiff") => {
             println!("\tLoading the level...");
             let config = vangers::level::LevelConfig::load(&src_path);
-            let level = vangers::level::load(&config);
+            let level = vangers::level::load(&config, &Cache::disabled());
             let layers = layers::LevelLayers::from_level_data(
                 &vangers::level::LevelData::from(level),
                 config.terrains.len() as u8,
@@ -131,10 +180,83 @@ fn main() {
             println!("\tSaving TIFF layers...");
             save_tiff(&dst_path, layers);
         }
+        ("ini", "ini") => {
+            let region = match matches.opt_str("r") {
+                Some(string) => {
+                    let v = string
+                        .split(',')
+                        .map(|s| s.trim().parse::<i32>().unwrap())
+                        .collect::<Vec<_>>();
+                    assert_eq!(v.len(), 4, "Region has to be X,Y,W,H");
+                    vangers::level::Rect {
+                        x: v[0],
+                        y: v[1],
+                        w: v[2],
+                        h: v[3],
+                    }
+                }
+                None => panic!("INI to INI conversion requires a `--region`"),
+            };
+            println!("\tLoading the level...");
+            let config = vangers::level::LevelConfig::load(&src_path);
+            let level = vangers::level::load(&config, &Cache::disabled());
+            println!("\tExtracting region {:?}...", region);
+            let mini_level = vangers::level::extract_region(&level, &region);
+            let mini_config = config.extract_region(&region, &dst_path);
+            println!("\tSaving VMP and VPR...");
+            vangers::level::save(&mini_level, &mini_config);
+            println!("\tSaving INI...");
+            if let Err(e) = mini_config.save(&dst_path, &config.path_palette) {
+                eprintln!("{}: {}", dst_path.display(), e);
+                process::exit(1);
+            }
+        }
+        ("ini", "png") => {
+            let original_path = match matches.opt_str("d") {
+                Some(path) => PathBuf::from(path),
+                None => panic!("INI to PNG conversion requires a `--diff`"),
+            };
+            println!("\tLoading the levels...");
+            let original_config = vangers::level::LevelConfig::load(&original_path);
+            let original = vangers::level::load(&original_config, &Cache::disabled());
+            let config = vangers::level::LevelConfig::load(&src_path);
+            let level = vangers::level::load(&config, &Cache::disabled());
+            println!("\tComparing...");
+            let diff = vangers::level::LevelDiff::new(&original, &level).unwrap_or_else(|e| {
+                eprintln!("{}: {}", src_path.display(), e);
+                process::exit(1);
+            });
+            let stats = &diff.stats;
+            println!(
+                "\t\tHeight changed in {} texels, raised up to {}, lowered up to {}, by {:.1} on average",
+                stats.height_changed, stats.max_raise, stats.max_drop, stats.mean_change,
+            );
+            println!("\t\tTerrain changed in {} texels", stats.terrain_changed);
+            match stats.bounds {
+                Some(rect) => println!("\t\tChanges are within {:?}", rect),
+                None => println!("\t\tNo changes"),
+            }
+            println!("\tSaving the difference PNG...");
+            let file = File::create(&dst_path).unwrap();
+            let mut encoder = png::Encoder::new(file, diff.size.0 as u32, diff.size.1 as u32);
+            encoder.set_color(png::ColorType::RGB);
+            encoder
+                .write_header()
+                .unwrap()
+                .write_image_data(&diff.to_rgb(&level))
+                .unwrap();
+        }
+        ("ini", "") => {
+            println!("\tLoading the level...");
+            let config = vangers::level::LevelConfig::load(&src_path);
+            let level = vangers::level::load(&config, &Cache::disabled());
+            println!("\tSaving height, meta, and flood images...");
+            vangers::level::export_images(&level, &dst_path).unwrap();
+        }
         ("ini", "vmp") => {
             println!("\tLoading the VMC...");
             let config = vangers::level::LevelConfig::load(&src_path);
-            let level = vangers::level::load(&config);
+            let level = vangers::level::load(&config, &Cache::disabled());
             println!("\tSaving VMP...");
             vangers::level::LevelData::from(level).save_vmp(&dst_path);
         }
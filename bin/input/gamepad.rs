@@ -0,0 +1,171 @@
+//! Gamepad input, feeding the same actions as the keyboard,
+//! plus the analog steering and throttle.
+
+use super::Action;
+
+use gilrs::{Axis, Button, EventType, Gilrs};
+
+/// Stick positions closer to the center than this are ignored.
+const DEAD_ZONE: f32 = 0.15;
+
+/// Analog driving input, each from -1 to 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Analog {
+    /// Negative for the left, positive for the right.
+    pub steer: f32,
+    /// Negative for the reverse, positive for the forward.
+    pub throttle: f32,
+}
+
+/// Input gathered since the last poll.
+#[derive(Debug, Default)]
+pub struct Polled {
+    /// Actions of the buttons, with `true` for the pressed ones.
+    pub actions: Vec<(Action, bool)>,
+    /// New analog state, if it has changed.
+    pub analog: Option<Analog>,
+}
+
+fn button_action(button: Button) -> Option<Action> {
+    Some(match button {
+        Button::South => Action::Jump,
+        Button::East => Action::Recover,
+        Button::West => Action::Reset,
+        Button::Start => Action::Pause,
+        Button::Select => Action::Debug,
+        _ => return None,
+    })
+}
+
+/// Shoulder buttons, which both hold the turbo.
+#[derive(Debug, Default)]
+struct Shoulders {
+    held: [bool; 2],
+}
+
+impl Shoulders {
+    /// Update the state of a button, returns the new turbo state if it has changed.
+    fn update(&mut self, index: usize, pressed: bool) -> Option<bool> {
+        let was_held = self.held.iter().any(|&held| held);
+        self.held[index] = pressed;
+        let is_held = self.held.iter().any(|&held| held);
+        if is_held != was_held {
+            Some(is_held)
+        } else {
+            None
+        }
+    }
+}
+
+fn dead_zone(value: f32) -> f32 {
+    if value.abs() < DEAD_ZONE {
+        0.0
+    } else {
+        // rescale, so that the output starts from 0 at the edge of the dead zone
+        value.signum() * (value.abs() - DEAD_ZONE) / (1.0 - DEAD_ZONE)
+    }
+}
+
+/// All the connected gamepads, acting as one.
+pub struct Gamepad {
+    gilrs: Gilrs,
+    stick: f32,
+    triggers: [f32; 2],
+    shoulders: Shoulders,
+    analog: Analog,
+}
+
+impl Gamepad {
+    /// Start listening to the gamepads, returns `None` if it's not supported by the platform.
+    pub fn new() -> Option<Self> {
+        match Gilrs::new() {
+            Ok(gilrs) => {
+                for (_, pad) in gilrs.gamepads() {
+                    log::info!("Gamepad {} is connected", pad.name());
+                }
+                Some(Gamepad {
+                    gilrs,
+                    stick: 0.0,
+                    triggers: [0.0; 2],
+                    shoulders: Shoulders::default(),
+                    analog: Analog::default(),
+                })
+            }
+            Err(e) => {
+                log::warn!("Gamepads are not available: {}", e);
+                None
+            }
+        }
+    }
+
+    fn on_button(&mut self, button: Button, pressed: bool, polled: &mut Polled) {
+        let shoulder = match button {
+            Button::LeftTrigger => Some(0),
+            Button::RightTrigger => Some(1),
+            _ => None,
+        };
+        match shoulder {
+            // the turbo is held until both shoulders are released
+            Some(index) => {
+                if let Some(held) = self.shoulders.update(index, pressed) {
+                    polled.actions.push((Action::Turbo, held));
+                }
+            }
+            None => {
+                if let Some(action) = button_action(button) {
+                    polled.actions.push((action, pressed));
+                }
+            }
+        }
+    }
+
+    pub fn poll(&mut self) -> Polled {
+        let mut polled = Polled::default();
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _) => self.on_button(button, true, &mut polled),
+                EventType::ButtonReleased(button, _) => self.on_button(button, false, &mut polled),
+                // the right trigger accelerates, the left one reverses
+                EventType::ButtonChanged(Button::RightTrigger2, value, _) => {
+                    self.triggers[0] = value
+                }
+                EventType::ButtonChanged(Button::LeftTrigger2, value, _) => {
+                    self.triggers[1] = value
+                }
+                EventType::AxisChanged(Axis::LeftStickX, value, _) => self.stick = value,
+                EventType::Connected => {
+                    log::info!(
+                        "Gamepad {} is connected",
+                        self.gilrs.gamepad(event.id).name()
+                    )
+                }
+                EventType::Disconnected => log::info!("Gamepad is disconnected"),
+                _ => {}
+            }
+        }
+
+        let analog = Analog {
+            steer: dead_zone(self.stick),
+            throttle: dead_zone(self.triggers[0]) - dead_zone(self.triggers[1]),
+        };
+        if analog != self.analog {
+            self.analog = analog;
+            polled.analog = Some(analog);
+        }
+        polled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turbo_is_held_until_both_shoulders_are_released() {
+        let mut shoulders = Shoulders::default();
+        assert_eq!(shoulders.update(0, true), Some(true));
+        assert_eq!(shoulders.update(1, true), None);
+        assert_eq!(shoulders.update(0, false), None);
+        assert_eq!(shoulders.update(1, false), Some(false));
+    }
+}
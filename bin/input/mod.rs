@@ -0,0 +1,237 @@
+//! Mapping of the keys to the actions, as configured by `settings::Bindings`.
+
+use vangers::config::settings;
+
+use winit::event::VirtualKeyCode as Key;
+
+use std::collections::HashMap;
+
+// only the game is driven by the gamepad
+#[cfg(feature = "gamepad")]
+#[allow(dead_code)]
+pub mod gamepad;
+
+/// Action of a key, bound in the settings.
+/// The first ones are shared by the binaries, followed by the ones of a single binary.
+// not every binary is using all of the actions
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Action {
+    Accelerate,
+    Reverse,
+    TurnLeft,
+    TurnRight,
+    Turbo,
+    Jump,
+    Reset,
+    Recover,
+    Pause,
+    Debug,
+    Screenshot,
+    Capture,
+    Exit,
+    Garage,
+    RollLeft,
+    RollRight,
+    StepBack,
+    StepForward,
+    Descend,
+    Ascend,
+    ExportScatter,
+    ToggleWheels,
+    ToggleDebris,
+    ToggleSlots,
+    ToggleSamples,
+    ExportModel,
+    Measure,
+}
+
+/// Parse the name of a `VirtualKeyCode` variant, including the digits without the "Key" prefix.
+pub fn parse_key(name: &str) -> Option<Key> {
+    Some(match name {
+        "A" => Key::A,
+        "B" => Key::B,
+        "C" => Key::C,
+        "D" => Key::D,
+        "E" => Key::E,
+        "F" => Key::F,
+        "G" => Key::G,
+        "H" => Key::H,
+        "I" => Key::I,
+        "J" => Key::J,
+        "K" => Key::K,
+        "L" => Key::L,
+        "M" => Key::M,
+        "N" => Key::N,
+        "O" => Key::O,
+        "P" => Key::P,
+        "Q" => Key::Q,
+        "R" => Key::R,
+        "S" => Key::S,
+        "T" => Key::T,
+        "U" => Key::U,
+        "V" => Key::V,
+        "W" => Key::W,
+        "X" => Key::X,
+        "Y" => Key::Y,
+        "Z" => Key::Z,
+        "F1" => Key::F1,
+        "F2" => Key::F2,
+        "F3" => Key::F3,
+        "F4" => Key::F4,
+        "F5" => Key::F5,
+        "F6" => Key::F6,
+        "F7" => Key::F7,
+        "F8" => Key::F8,
+        "F9" => Key::F9,
+        "F10" => Key::F10,
+        "F11" => Key::F11,
+        "F12" => Key::F12,
+        "1" | "Key1" => Key::Key1,
+        "2" | "Key2" => Key::Key2,
+        "3" | "Key3" => Key::Key3,
+        "4" | "Key4" => Key::Key4,
+        "5" | "Key5" => Key::Key5,
+        "6" | "Key6" => Key::Key6,
+        "7" | "Key7" => Key::Key7,
+        "8" | "Key8" => Key::Key8,
+        "9" | "Key9" => Key::Key9,
+        "0" | "Key0" => Key::Key0,
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        "Space" => Key::Space,
+        "Return" => Key::Return,
+        "Tab" => Key::Tab,
+        "Comma" => Key::Comma,
+        "Period" => Key::Period,
+        "Back" => Key::Back,
+        "Escape" => Key::Escape,
+        "LShift" => Key::LShift,
+        "RShift" => Key::RShift,
+        "LControl" => Key::LControl,
+        "RControl" => Key::RControl,
+        "LAlt" => Key::LAlt,
+        "RAlt" => Key::RAlt,
+        "Snapshot" => Key::Snapshot,
+        _ => return None,
+    })
+}
+
+/// Actions of the keys, the unknown key names are skipped with a warning.
+pub struct KeyMap {
+    actions: HashMap<Key, Action>,
+}
+
+impl KeyMap {
+    pub fn new(bindings: &settings::Bindings) -> Self {
+        let mut map = KeyMap {
+            actions: HashMap::new(),
+        };
+        map.bind(&[
+            (&bindings.accelerate, Action::Accelerate),
+            (&bindings.reverse, Action::Reverse),
+            (&bindings.turn_left, Action::TurnLeft),
+            (&bindings.turn_right, Action::TurnRight),
+            (&bindings.turbo, Action::Turbo),
+            (&bindings.jump, Action::Jump),
+            (&bindings.reset, Action::Reset),
+            (&bindings.recover, Action::Recover),
+            (&bindings.pause, Action::Pause),
+            (&bindings.debug, Action::Debug),
+            (&bindings.screenshot, Action::Screenshot),
+            (&bindings.capture, Action::Capture),
+            (&bindings.exit, Action::Exit),
+        ]);
+        map
+    }
+
+    /// Add the keys of a single binary on top of the shared ones,
+    /// taking over the shared actions of the same keys.
+    // not every binary has its own keys
+    #[allow(dead_code)]
+    pub fn with(mut self, layer: &[(&String, Action)]) -> Self {
+        let mut map = KeyMap {
+            actions: HashMap::new(),
+        };
+        map.bind(layer);
+        self.actions.extend(map.actions);
+        self
+    }
+
+    fn bind(&mut self, list: &[(&String, Action)]) {
+        for &(name, action) in list {
+            match parse_key(name) {
+                Some(key) => {
+                    if let Some(other) = self.actions.insert(key, action) {
+                        log::warn!(
+                            "Key '{}' is bound to both {:?} and {:?}",
+                            name,
+                            other,
+                            action
+                        );
+                    }
+                }
+                None => log::warn!("Unknown key '{}' for {:?}", name, action),
+            }
+        }
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.actions.get(&key).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template_bindings() -> settings::Bindings {
+        let text = std::fs::read_to_string("config/settings.template.ron").unwrap();
+        ron::de::from_str::<settings::Settings>(&text)
+            .unwrap()
+            .bindings
+    }
+
+    #[test]
+    fn keys_are_parsed_by_name() {
+        assert_eq!(parse_key("W"), Some(Key::W));
+        assert_eq!(parse_key("LShift"), Some(Key::LShift));
+        assert_eq!(parse_key("1"), Some(Key::Key1));
+        assert_eq!(parse_key("Key1"), Some(Key::Key1));
+        assert_eq!(parse_key("Comma"), Some(Key::Comma));
+        assert_eq!(parse_key("w"), None);
+        assert_eq!(parse_key(""), None);
+    }
+
+    #[test]
+    fn template_keys_are_bound() {
+        let key_map = KeyMap::new(&template_bindings());
+        assert_eq!(key_map.action(Key::W), Some(Action::Accelerate));
+        assert_eq!(key_map.action(Key::Escape), Some(Action::Exit));
+        assert_eq!(key_map.action(Key::Snapshot), Some(Action::Screenshot));
+        assert_eq!(key_map.action(Key::M), None);
+    }
+
+    #[test]
+    fn layer_takes_over_the_shared_keys() {
+        let bindings = template_bindings();
+        let key_map = KeyMap::new(&bindings).with(&[
+            (&bindings.model.wheels, Action::ToggleWheels),
+            (&bindings.model.measure, Action::Measure),
+        ]);
+        assert_eq!(key_map.action(Key::W), Some(Action::ToggleWheels));
+        assert_eq!(key_map.action(Key::M), Some(Action::Measure));
+        assert_eq!(key_map.action(Key::A), Some(Action::TurnLeft));
+    }
+
+    #[test]
+    fn unknown_keys_are_skipped() {
+        let mut bindings = template_bindings();
+        bindings.jump = "Nowhere".to_string();
+        let key_map = KeyMap::new(&bindings);
+        assert_eq!(key_map.action(Key::LAlt), None);
+        assert_eq!(key_map.action(Key::LShift), Some(Action::Turbo));
+    }
+}
@@ -1,4 +1,7 @@
-use crate::boilerplate::Application;
+use crate::{
+    boilerplate::{Application, Transition},
+    input::{Action, KeyMap},
+};
 use vangers::{
     config, level,
     render::{body::GpuStoreInit, Batcher, Render, ScreenTargets},
@@ -9,6 +12,19 @@ use futures::executor::LocalSpawner;
 use log::info;
 use winit::event;
 
+use std::{path::PathBuf, time};
+
+const SCATTER_DUMP_PATH: &str = "scatter.ply";
+
+/// Level painted as images, see `level::from_image`.
+pub struct ImageLevel {
+    pub height: PathBuf,
+    pub terrain: PathBuf,
+    pub palette: PathBuf,
+}
+/// Number of the level rows uploaded at once while streaming.
+const STREAM_CHUNK_ROWS: i32 = 256;
+
 #[derive(Debug)]
 enum Input {
     Hor { dir: f32, alt: bool, shift: bool },
@@ -22,25 +38,42 @@ enum Input {
 
 pub struct LevelView {
     render: Render,
-    _level: level::Level,
+    level: level::Level,
+    stream: Option<level::LevelStream>,
+    /// Source of the level data, and whether its palette is overridden by the cycle.
+    #[cfg_attr(not(feature = "hot-reload"), allow(dead_code))]
+    source: Option<(level::LevelConfig, bool)>,
+    #[cfg(feature = "hot-reload")]
+    watcher: Option<level::LevelWatcher>,
     cam: space::Camera,
     input: Input,
+    key_map: KeyMap,
 
     last_mouse_pos: cgmath::Vector2<f32>,
     alt_button_pressed: bool,
     mouse_button_pressed: bool,
+    dump_scatter: bool,
 }
 
 impl LevelView {
     pub fn new(
         settings: &config::settings::Settings,
+        image: Option<ImageLevel>,
         screen_extent: wgpu::Extent3d,
+        color_format: wgpu::TextureFormat,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        progress: &mut dyn FnMut(&str, f32),
     ) -> Self {
-        let level = if settings.game.level.is_empty() {
+        progress("level", 0.0);
+        let (level, stream, source) = if let Some(image) = image {
+            info!("Using level images {}", image.height.display());
+            let level = level::from_image(&image.height, &image.terrain, &image.palette)
+                .unwrap_or_else(|e| panic!("Unable to import the level images: {}", e));
+            (level, None, None)
+        } else if settings.game.level.is_empty() {
             info!("Using test level");
-            level::Level::new_test()
+            (level::Level::new_test(), None, None)
         } else {
             let escaves = config::escaves::load(settings.open_relative("escaves.prm"));
             let worlds = config::worlds::load(settings.open_relative("wrlds.dat"));
@@ -88,13 +121,24 @@ impl LevelView {
                 override_palette = Some(settings.open_relative(&cycle.palette_path));
             }
 
-            let mut level = level::load(&level_config);
+            // the data is shown as it's being loaded
+            let (mut level, stream) = level::LevelStream::start(&level_config, STREAM_CHUNK_ROWS)
+                .unwrap_or_else(|e| panic!("{}", e));
+            let is_cycle = override_palette.is_some();
             if let Some(pal_file) = override_palette {
                 level.palette = level::read_palette(pal_file, Some(&level_config.terrains));
             }
-            level
+            (level, Some(stream), Some((level_config, is_cycle)))
+        };
+        #[cfg(feature = "hot-reload")]
+        let watcher = match source {
+            Some((ref config, _)) => level::LevelWatcher::new(config)
+                .map_err(|e| log::warn!("Unable to watch the level files: {}", e))
+                .ok(),
+            None => None,
         };
 
+        progress("render", 0.7);
         let objects_palette = level::read_palette(settings.open_palette(), None);
         let depth = settings.game.camera.depth_range;
         let store_init = GpuStoreInit::new_dummy(device);
@@ -105,16 +149,21 @@ impl LevelView {
             &objects_palette,
             &settings.render,
             screen_extent,
+            color_format,
             store_init.resource(),
         );
 
         LevelView {
             render,
-            _level: level,
-            cam: space::Camera {
-                loc: cgmath::vec3(0.0, 0.0, 400.0),
-                rot: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
-                proj: match settings.game.view {
+            level,
+            stream,
+            source,
+            #[cfg(feature = "hot-reload")]
+            watcher,
+            cam: space::Camera::new(
+                cgmath::vec3(0.0, 0.0, 400.0),
+                cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+                match settings.game.view {
                     config::settings::View::Perspective => {
                         let pf = cgmath::PerspectiveFov {
                             fovy: cgmath::Deg(45.0).into(),
@@ -130,13 +179,54 @@ impl LevelView {
                         depth.0..depth.1,
                     ),
                 },
-            },
+            ),
             input: Input::Empty,
+            key_map: KeyMap::new(&settings.bindings).with(&[
+                (&settings.bindings.level.descend, Action::Descend),
+                (&settings.bindings.level.ascend, Action::Ascend),
+                (&settings.bindings.level.export, Action::ExportScatter),
+            ]),
             last_mouse_pos: cgmath::vec2(-1.0, -1.0),
             alt_button_pressed: false,
             mouse_button_pressed: false,
+            dump_scatter: false,
         }
     }
+
+    /// Stream the level in again after its files have changed on disk.
+    #[cfg(feature = "hot-reload")]
+    fn reload_level(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let (config, is_cycle) = match self.source {
+            Some(ref source) => source,
+            None => return,
+        };
+        let (mut level, stream) = match level::LevelStream::start(config, STREAM_CHUNK_ROWS) {
+            Ok(pair) => pair,
+            Err(e) => {
+                log::error!("Unable to reload the level: {}", e);
+                return;
+            }
+        };
+        if level.size != self.level.size {
+            log::warn!(
+                "Level size has changed from {:?} to {:?}, restart to apply",
+                self.level.size,
+                level.size
+            );
+            return;
+        }
+        info!("Reloading the level");
+        if *is_cycle {
+            level.palette = self.level.palette;
+        }
+        self.render.terrain.update_flood(device, encoder, &level);
+        self.render
+            .terrain
+            .palette
+            .update(encoder, device, &level.palette);
+        self.level = level;
+        self.stream = Some(stream);
+    }
 }
 
 impl Application for LevelView {
@@ -176,7 +266,7 @@ impl Application for LevelView {
         }
     }
 
-    fn on_key(&mut self, input: event::KeyboardInput) -> bool {
+    fn on_key(&mut self, input: event::KeyboardInput) -> Transition {
         use winit::event::{ElementState, KeyboardInput, VirtualKeyCode as Key};
 
         let i = &mut self.input;
@@ -187,58 +277,64 @@ impl Application for LevelView {
                 virtual_keycode: Some(key),
                 ref modifiers,
                 ..
-            } => match key {
-                Key::Escape => return false,
-                Key::W => {
+            } => match (key, self.key_map.action(key)) {
+                (_, Some(Action::Exit)) => return Transition::Pop,
+                (_, Some(Action::Accelerate)) => {
                     *i = Input::Ver {
                         dir: 1.0,
                         alt: modifiers.alt(),
                         shift: modifiers.shift(),
                     }
                 }
-                Key::S => {
+                (_, Some(Action::Reverse)) => {
                     *i = Input::Ver {
                         dir: -1.0,
                         alt: modifiers.alt(),
                         shift: modifiers.shift(),
                     }
                 }
-                Key::A => {
+                (_, Some(Action::TurnLeft)) => {
                     *i = Input::Hor {
                         dir: -1.0,
                         alt: modifiers.alt(),
                         shift: modifiers.shift(),
                     }
                 }
-                Key::D => {
+                (_, Some(Action::TurnRight)) => {
                     *i = Input::Hor {
                         dir: 1.0,
                         alt: modifiers.alt(),
                         shift: modifiers.shift(),
                     }
                 }
-                Key::Z => {
+                (_, Some(Action::Descend)) => {
                     *i = Input::Dep {
                         dir: -1.0,
                         alt: modifiers.alt(),
                     }
                 }
-                Key::X => {
+                (_, Some(Action::Ascend)) => {
                     *i = Input::Dep {
                         dir: 1.0,
                         alt: modifiers.alt(),
                     }
                 }
-                Key::LAlt => self.alt_button_pressed = true,
+                (Key::LAlt, _) => self.alt_button_pressed = true,
+                (_, Some(Action::ExportScatter)) => self.dump_scatter = true,
                 _ => (),
             },
             KeyboardInput {
                 state: ElementState::Released,
                 virtual_keycode: Some(key),
                 ..
-            } => match key {
-                Key::W | Key::S | Key::A | Key::D | Key::Z | Key::X => *i = Input::Empty,
-                Key::LAlt => self.alt_button_pressed = false,
+            } => match (key, self.key_map.action(key)) {
+                (_, Some(Action::Accelerate))
+                | (_, Some(Action::Reverse))
+                | (_, Some(Action::TurnLeft))
+                | (_, Some(Action::TurnRight))
+                | (_, Some(Action::Descend))
+                | (_, Some(Action::Ascend)) => *i = Input::Empty,
+                (Key::LAlt, _) => self.alt_button_pressed = false,
                 _ => (),
             },
             /*
@@ -250,16 +346,65 @@ impl Application for LevelView {
             _ => {}
         }
 
-        true
+        Transition::None
+    }
+
+    fn status(&self) -> Option<String> {
+        match self.stream {
+            Some(ref stream) => Some(format!("Streaming {:.0}%", stream.progress() * 100.0)),
+            None if self.render.debug.settings().stats => Some(self.render.stats.to_string()),
+            None => None,
+        }
     }
 
     fn update(
         &mut self,
-        _device: &wgpu::Device,
+        device: &wgpu::Device,
         delta: f32,
-        _spawner: &LocalSpawner,
+        spawner: &LocalSpawner,
     ) -> Vec<wgpu::CommandBuffer> {
         use cgmath::{InnerSpace, Rotation3, Zero};
+
+        self.render.stats.record_frame(delta);
+        let mut command_buffers = Vec::new();
+        #[cfg(feature = "hot-reload")]
+        {
+            if self.watcher.as_ref().map_or(false, |w| w.changed()) {
+                let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Reload level"),
+                });
+                self.reload_level(device, &mut encoder);
+                command_buffers.push(encoder.finish());
+            }
+        }
+        if let Some(ref mut stream) = self.stream {
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Stream"),
+            });
+            for rows in stream.poll(&mut self.level) {
+                self.render
+                    .terrain
+                    .update_rows(device, &mut encoder, &self.level, rows);
+            }
+            command_buffers.push(encoder.finish());
+            match stream.is_done() {
+                Ok(false) => {}
+                Ok(true) => {
+                    info!("Level is streamed in");
+                    self.stream = None;
+                }
+                Err(e) => {
+                    log::error!("{}", e);
+                    self.stream = None;
+                }
+            }
+        }
+
+        self.render.terrain.consume_scatter_dump(
+            PathBuf::from(SCATTER_DUMP_PATH),
+            &self.level.palette,
+            spawner,
+        );
         let move_speed = match self.cam.proj {
             space::Projection::Perspective(_) => 100.0,
             space::Projection::Ortho { .. } => 500.0,
@@ -332,7 +477,7 @@ impl Application for LevelView {
             _ => {}
         }
 
-        Vec::new()
+        command_buffers
     }
 
     fn resize(&mut self, device: &wgpu::Device, extent: wgpu::Extent3d) {
@@ -342,16 +487,26 @@ impl Application for LevelView {
         self.render.resize(extent, device);
     }
 
+    fn set_ui_scale(&mut self, scale: f32) {
+        self.render.set_ui_scale(scale);
+    }
+
     fn reload(&mut self, device: &wgpu::Device) {
         self.render.reload(device);
     }
 
+    fn destroy(self: Box<Self>) {
+        self.render.destroy();
+    }
+
     fn draw(
         &mut self,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         targets: ScreenTargets,
         _spawner: &LocalSpawner,
     ) -> wgpu::CommandBuffer {
+        let encode_start = time::Instant::now();
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Draw"),
         });
@@ -361,7 +516,16 @@ impl Application for LevelView {
             &self.cam,
             targets,
             device,
+            queue,
         );
+        if self.dump_scatter {
+            self.dump_scatter = false;
+            self.render
+                .terrain
+                .produce_scatter_dump(&mut encoder, device, &self.cam);
+        }
+        self.render.stats.record_encode(encode_start.elapsed());
+        self.render.draw_stats(&mut encoder, targets, device, queue);
         encoder.finish()
     }
 }
@@ -1,16 +1,70 @@
 mod app;
 #[path = "../boilerplate.rs"]
 mod boilerplate;
+#[path = "../input/mod.rs"]
+mod input;
 
 fn main() {
-    let (mut harness, settings) = boilerplate::Harness::init("level");
+    use std::{env, path::PathBuf};
 
-    let app = app::LevelView::new(
-        &settings,
-        harness.extent,
-        &harness.device,
-        &mut harness.queue,
-    );
+    let (harness, settings) = boilerplate::Harness::init("level");
 
-    harness.main_loop(app);
+    let args: Vec<_> = env::args().collect();
+    let mut options = getopts::Options::new();
+    options
+        .parsing_style(getopts::ParsingStyle::StopAtFirstFree)
+        .optflag("h", "help", "print this help menu")
+        .optopt("", "height", "grayscale height map to import", "HEIGHT.png")
+        .optopt(
+            "",
+            "terrain",
+            "image of the terrain types to import",
+            "TERRAIN.png",
+        )
+        .optopt(
+            "",
+            "palette",
+            "palette of the imported level",
+            "PALETTE.pal",
+        );
+
+    let matches = options.parse(&args[1..]).unwrap();
+    if matches.opt_present("h") || !matches.free.is_empty() {
+        println!("Vangers level viewer");
+        let brief = format!("Usage: {} [options]", args[0]);
+        println!("{}", options.usage(&brief));
+        return;
+    }
+    let image = match (
+        matches.opt_str("height"),
+        matches.opt_str("terrain"),
+        matches.opt_str("palette"),
+    ) {
+        (Some(height), Some(terrain), Some(palette)) => Some(app::ImageLevel {
+            height: PathBuf::from(height),
+            terrain: PathBuf::from(terrain),
+            palette: PathBuf::from(palette),
+        }),
+        (None, None, None) => None,
+        _ => {
+            println!("The imported level needs all of the height, terrain, and palette");
+            return;
+        }
+    };
+
+    let (extent, color_format) = (harness.extent, harness.color_format);
+    let loading = harness.loading(&settings.window);
+    let loading = loading.create(Box::new(move |device, queue, progress| {
+        Box::new(app::LevelView::new(
+            &settings,
+            image,
+            extent,
+            color_format,
+            device,
+            queue,
+            progress,
+        ))
+    }));
+
+    harness.main_loop(loading);
 }
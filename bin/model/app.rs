@@ -1,67 +1,331 @@
-use crate::boilerplate::Application;
+use crate::{
+    boilerplate::{Application, Transition},
+    input::{Action, KeyMap},
+    measure::Measure,
+};
 use vangers::{config, level, model, render, space};
 
 use futures::executor::LocalSpawner;
 use log::info;
 use wgpu::util::DeviceExt as _;
 
-use std::mem;
+use std::{fs::File, mem, path::PathBuf};
+
+/// Camera rotation per logical pixel of the mouse movement.
+const ORBIT_SPEED: f32 = 0.01;
+/// Camera shift per logical pixel of the mouse movement, relative to the distance to the focus.
+const PAN_SPEED: f32 = 0.002;
+/// Change of the distance to the focus per wheel step.
+const ZOOM_STEP: f32 = 1.1;
+/// Allowed distances to the focus, within the projection depth range.
+const ZOOM_RANGE: [f32; 2] = [10.0, 300.0];
+/// Distance the debris fly away from their places when exploded.
+const EXPLODE_DISTANCE: f32 = 40.0;
+/// Fraction of the explosion done per second.
+const EXPLODE_SPEED: f32 = 2.0;
+/// Length of the slot axes.
+const GIZMO_SIZE: f32 = 10.0;
+/// Half size of the crosses at the collision samples.
+const SAMPLE_SIZE: f32 = 1.0;
+// line colors, as RGBA bytes
+const COLOR_X: u32 = 0xFF0000FF;
+const COLOR_Y: u32 = 0xFF00FF00;
+const COLOR_Z: u32 = 0xFFFF0000;
+const COLOR_SAMPLE: u32 = 0xFF00FFFF;
+
+/// Mouse dragging of the camera.
+#[derive(Clone, Copy, PartialEq)]
+enum Drag {
+    Orbit,
+    Pan,
+}
 
 pub struct ResourceView {
     model: model::VisualModel,
+    source: PathBuf,
     global: render::global::Context,
     object: render::object::Context,
+    debug: render::debug::Context,
+    lines: render::debug::LineBuffer,
+    batcher: render::Batcher,
     transform: space::Transform,
     cam: space::Camera,
+    /// Point the camera orbits around and zooms to.
+    focus: cgmath::Vector3<f32>,
+    drag: Option<Drag>,
     rotation: cgmath::Rad<f32>,
     light_config: config::settings::Light,
+    screen_size: (u32, u32),
+    /// Physical pixels per logical one, for the mouse movement.
+    ui_scale: f32,
+    cursor: (f64, f64),
+    measure: Option<Measure>,
+    show_wheels: bool,
+    show_slots: bool,
+    show_samples: bool,
+    exploded: bool,
+    key_map: KeyMap,
+    /// Progress of the debris explosion, from 0 to 1.
+    explode: f32,
 }
 
 impl ResourceView {
     pub fn new(
         path: &str,
         settings: &config::settings::Settings,
+        color_format: wgpu::TextureFormat,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
     ) -> Self {
         info!("Initializing the render");
         let pal_data = level::read_palette(settings.open_palette(), None);
         let store_init = render::body::GpuStoreInit::new_dummy(device);
-        let global = render::global::Context::new(device, queue, store_init.resource(), None);
+        let global = render::global::Context::new(
+            device,
+            queue,
+            color_format,
+            1,
+            store_init.resource(),
+            None,
+        );
         let object = render::object::Context::new(device, queue, &pal_data, &global);
+        let mut debug_settings = settings.render.debug.clone();
+        // any of the line layers makes the lines drawable
+        debug_settings.wheels = true;
+        let debug = render::debug::Context::new(device, &debug_settings, &global, &object);
 
         info!("Loading model {}", path);
         let file = settings.open_relative(path);
-        let model = model::load_m3d(file, device, &object, settings.game.physics.shape_sampling);
+        let model = model::load_m3d(
+            file,
+            device,
+            &object,
+            settings.game.physics.shape_sampling,
+            !settings.render.debug.raw_vertices,
+        );
 
         ResourceView {
             model,
+            source: settings.data_path.join(path),
             global,
             object,
+            debug,
+            lines: render::debug::LineBuffer::new(),
+            batcher: render::Batcher::new(),
             transform: cgmath::Decomposed {
                 scale: 1.0,
                 disp: cgmath::Vector3::unit_z(),
                 rot: cgmath::One::one(),
             },
-            cam: space::Camera {
-                loc: cgmath::vec3(0.0, -200.0, 100.0),
-                rot: cgmath::Rotation3::from_angle_x::<cgmath::Rad<_>>(cgmath::Angle::turn_div_6()),
-                proj: space::Projection::Perspective(cgmath::PerspectiveFov {
+            cam: space::Camera::new(
+                cgmath::vec3(0.0, -200.0, 100.0),
+                cgmath::Rotation3::from_angle_x::<cgmath::Rad<_>>(cgmath::Angle::turn_div_6()),
+                space::Projection::Perspective(cgmath::PerspectiveFov {
                     fovy: cgmath::Deg(45.0).into(),
                     aspect: settings.window.size[0] as f32 / settings.window.size[1] as f32,
                     near: 5.0,
                     far: 400.0,
                 }),
-            },
+            ),
+            focus: cgmath::Vector3::unit_z(),
+            drag: None,
             rotation: cgmath::Rad(0.),
             light_config: settings.render.light.clone(),
+            screen_size: (settings.window.size[0], settings.window.size[1]),
+            ui_scale: 1.0,
+            cursor: (0.0, 0.0),
+            measure: None,
+            show_wheels: true,
+            show_slots: false,
+            show_samples: false,
+            exploded: false,
+            key_map: KeyMap::new(&settings.bindings).with(&[
+                (&settings.bindings.model.wheels, Action::ToggleWheels),
+                (&settings.bindings.model.debris, Action::ToggleDebris),
+                (&settings.bindings.model.slots, Action::ToggleSlots),
+                (&settings.bindings.model.samples, Action::ToggleSamples),
+                (&settings.bindings.model.export, Action::ExportModel),
+                (&settings.bindings.model.measure, Action::Measure),
+            ]),
+            explode: 0.0,
         }
     }
 }
 
+impl ResourceView {
+    /// Read the viewed model again, since the uploaded one has no geometry on the CPU side.
+    fn load_raw(&self) -> Option<m3d::FullModel> {
+        match File::open(&self.source) {
+            Ok(file) => Some(m3d::FullModel::load(file)),
+            Err(e) => {
+                log::warn!("Unable to open {}: {}", self.source.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Write the viewed model into an OBJ file in the current directory.
+    fn export(&self) {
+        let raw = match self.load_raw() {
+            Some(raw) => raw,
+            None => return,
+        };
+        let stem = self.source.file_stem().unwrap_or_default();
+        let path = PathBuf::from(stem).with_extension("obj");
+        match model::export_obj(&raw, &path) {
+            Ok(()) => info!("Exported {}", path.display()),
+            Err(e) => log::warn!("Unable to export {}: {}", path.display(), e),
+        }
+    }
+
+    fn toggle_measure(&mut self) {
+        self.measure = match self.measure.take() {
+            Some(_) => None,
+            None => self.load_raw().map(|raw| Measure::new(&raw)),
+        };
+    }
+
+    fn focus_transform(&self) -> space::Transform {
+        cgmath::Decomposed {
+            scale: 1.0,
+            disp: self.focus,
+            rot: cgmath::One::one(),
+        }
+    }
+
+    fn orbit(&mut self, dx: f32, dy: f32) {
+        let focus = self.focus_transform();
+        self.cam.rotate_focus(
+            &focus,
+            cgmath::Rad(-dx * ORBIT_SPEED),
+            cgmath::Rad(dy * ORBIT_SPEED),
+        );
+    }
+
+    fn pan(&mut self, dx: f32, dy: f32) {
+        use cgmath::InnerSpace as _;
+
+        let distance = (self.cam.loc - self.focus).magnitude();
+        let right = self.cam.rot * cgmath::Vector3::unit_x();
+        let up = self.cam.rot * cgmath::Vector3::unit_y();
+        let offset = (up * dy - right * dx) * distance * PAN_SPEED;
+        self.cam.loc += offset;
+        self.focus += offset;
+    }
+
+    fn zoom(&mut self, steps: f32) {
+        use cgmath::InnerSpace as _;
+
+        let offset = self.cam.loc - self.focus;
+        let distance = offset.magnitude();
+        let new_distance = (distance * ZOOM_STEP.powf(-steps))
+            .max(ZOOM_RANGE[0])
+            .min(ZOOM_RANGE[1]);
+        self.cam.loc = self.focus + offset * (new_distance / distance);
+    }
+
+    /// Fill the lines of the slot gizmos and the collision samples.
+    fn update_lines(&mut self) {
+        use cgmath::{Rotation3 as _, Transform as _};
+
+        self.lines.clear();
+        if self.show_slots {
+            for slot in self.model.slots.iter() {
+                let local = space::Transform {
+                    disp: cgmath::vec3(slot.pos[0] as f32, slot.pos[1] as f32, slot.pos[2] as f32),
+                    rot: cgmath::Quaternion::from_angle_y(cgmath::Deg(slot.angle as f32)),
+                    scale: 1.0,
+                };
+                let world = self.transform.concat(&local);
+                let origin = world.disp;
+                for &(axis, color) in &[
+                    (cgmath::Vector3::unit_x(), COLOR_X),
+                    (cgmath::Vector3::unit_y(), COLOR_Y),
+                    (cgmath::Vector3::unit_z(), COLOR_Z),
+                ] {
+                    let end = origin + world.transform_vector(axis) * GIZMO_SIZE;
+                    self.lines.add(origin.into(), end.into(), color);
+                }
+            }
+        }
+        if self.show_samples {
+            for s in self.model.shape.samples.iter() {
+                let pos = self.transform.transform_point(cgmath::Point3::new(
+                    s[0] as f32,
+                    s[1] as f32,
+                    s[2] as f32,
+                ));
+                for &axis in &[
+                    cgmath::Vector3::unit_x(),
+                    cgmath::Vector3::unit_y(),
+                    cgmath::Vector3::unit_z(),
+                ] {
+                    let from = pos - axis * SAMPLE_SIZE;
+                    let to = pos + axis * SAMPLE_SIZE;
+                    self.lines.add(from.into(), to.into(), COLOR_SAMPLE);
+                }
+            }
+        }
+    }
+
+    /// Add the visible parts of the model, moving the debris outwards when exploded.
+    fn add_parts(&self, batcher: &mut render::Batcher) {
+        use cgmath::{InnerSpace as _, Transform as _};
+
+        let body = render::body::GpuBody::ZERO;
+        let color = render::object::BodyColor::Dummy;
+        for part in model::parts(&self.model) {
+            match part {
+                model::Part::Body | model::Part::Slot(_) => {}
+                model::Part::Wheel(_) if self.show_wheels => {}
+                model::Part::Debrie(index) if self.explode > 0.0 => {
+                    let mesh = &self.model.debris[index].mesh;
+                    let mut transform =
+                        match model::part_transform(&self.model, part, &self.transform) {
+                            Some(transform) => transform,
+                            None => continue,
+                        };
+                    let offset = cgmath::Vector3::from(mesh.offset);
+                    let dir = if offset.magnitude2() > 0.0 {
+                        offset.normalize()
+                    } else {
+                        cgmath::Vector3::unit_z()
+                    };
+                    transform.disp +=
+                        self.transform.transform_vector(dir) * EXPLODE_DISTANCE * self.explode;
+                    batcher.add_mesh(
+                        mesh,
+                        render::object::Instance::new(&transform, 0.0, &body, color),
+                    );
+                    continue;
+                }
+                _ => continue,
+            }
+            batcher.add_part(&self.model, part, &self.transform, &body, color);
+        }
+    }
+
+    /// Ray under the cursor, in the model space.
+    fn cursor_ray(&self) -> (cgmath::Point3<f32>, cgmath::Vector3<f32>) {
+        use cgmath::{EuclideanSpace as _, InnerSpace as _, Transform as _};
+
+        let x = 2.0 * self.cursor.0 as f32 / self.screen_size.0 as f32 - 1.0;
+        let y = 1.0 - 2.0 * self.cursor.1 as f32 / self.screen_size.1 as f32;
+        let mx = self.cam.inverse_view_proj();
+        let unproject = |z: f32| {
+            let p = mx * cgmath::vec4(x, y, z, 1.0);
+            cgmath::Point3::from_vec(p.truncate() / p.w)
+        };
+        let inverse = self.transform.inverse_transform().unwrap();
+        let near = inverse.transform_point(unproject(0.0));
+        let far = inverse.transform_point(unproject(1.0));
+        (near, (far - near).normalize())
+    }
+}
+
 impl Application for ResourceView {
-    fn on_key(&mut self, input: winit::event::KeyboardInput) -> bool {
-        use winit::event::{ElementState, KeyboardInput, VirtualKeyCode as Key};
+    fn on_key(&mut self, input: winit::event::KeyboardInput) -> Transition {
+        use winit::event::{ElementState, KeyboardInput};
 
         let angle = cgmath::Rad(2.0);
         match input {
@@ -69,24 +333,98 @@ impl Application for ResourceView {
                 state: ElementState::Pressed,
                 virtual_keycode: Some(key),
                 ..
-            } => match key {
-                Key::Escape => return false,
-                Key::A => self.rotation = -angle,
-                Key::D => self.rotation = angle,
+            } => match self.key_map.action(key) {
+                Some(Action::Exit) => return Transition::Pop,
+                Some(Action::TurnLeft) => self.rotation = -angle,
+                Some(Action::TurnRight) => self.rotation = angle,
+                Some(Action::ExportModel) => self.export(),
+                Some(Action::Measure) => self.toggle_measure(),
+                Some(Action::ToggleWheels) => {
+                    self.show_wheels ^= true;
+                    info!("Wheels are shown: {}", self.show_wheels);
+                }
+                Some(Action::ToggleDebris) => {
+                    self.exploded ^= true;
+                    info!("Debris are exploded: {}", self.exploded);
+                }
+                Some(Action::ToggleSlots) => {
+                    self.show_slots ^= true;
+                    info!("Slot gizmos are shown: {}", self.show_slots);
+                }
+                Some(Action::ToggleSamples) => {
+                    self.show_samples ^= true;
+                    info!("Collision samples are shown: {}", self.show_samples);
+                }
                 _ => (),
             },
             KeyboardInput {
                 state: ElementState::Released,
                 virtual_keycode: Some(key),
                 ..
-            } => match key {
-                Key::A | Key::D => self.rotation = cgmath::Rad(0.0),
+            } => match self.key_map.action(key) {
+                Some(Action::TurnLeft) | Some(Action::TurnRight) => {
+                    self.rotation = cgmath::Rad(0.0)
+                }
                 _ => (),
             },
             _ => {}
         }
 
-        true
+        Transition::None
+    }
+
+    fn on_cursor_move(&mut self, position: (f64, f64)) {
+        let dx = (position.0 - self.cursor.0) as f32 / self.ui_scale;
+        let dy = (position.1 - self.cursor.1) as f32 / self.ui_scale;
+        self.cursor = position;
+        match self.drag {
+            Some(Drag::Orbit) => self.orbit(dx, dy),
+            Some(Drag::Pan) => self.pan(dx, dy),
+            None => {}
+        }
+    }
+
+    fn on_mouse_wheel(&mut self, delta: winit::event::MouseScrollDelta) {
+        use winit::event::MouseScrollDelta as Msd;
+
+        match delta {
+            Msd::LineDelta(_, y) => self.zoom(y),
+            // roughly a line per 20 pixels of the touchpad scrolling
+            Msd::PixelDelta(pos) => self.zoom(pos.y as f32 / 20.0),
+        }
+    }
+
+    fn on_mouse_button(
+        &mut self,
+        state: winit::event::ElementState,
+        button: winit::event::MouseButton,
+    ) {
+        use winit::event::{ElementState, MouseButton};
+
+        let drag = match button {
+            // the left button is for picking the points while measuring
+            MouseButton::Left if self.measure.is_some() => {
+                if state == ElementState::Pressed {
+                    let (origin, dir) = self.cursor_ray();
+                    if let Some(ref mut measure) = self.measure {
+                        measure.click(origin, dir);
+                    }
+                }
+                return;
+            }
+            MouseButton::Left | MouseButton::Right => Drag::Orbit,
+            MouseButton::Middle => Drag::Pan,
+            MouseButton::Other(_) => return,
+        };
+        self.drag = match state {
+            ElementState::Pressed => Some(drag),
+            ElementState::Released if self.drag == Some(drag) => None,
+            ElementState::Released => self.drag,
+        };
+    }
+
+    fn status(&self) -> Option<String> {
+        self.measure.as_ref().map(|m| m.status().to_string())
     }
 
     fn update(
@@ -107,15 +445,30 @@ impl Application for ResourceView {
             self.transform = other.concat(&self.transform);
         }
 
+        let target = if self.exploded { 1.0 } else { 0.0 };
+        let step = EXPLODE_SPEED * delta;
+        self.explode = if self.explode < target {
+            (self.explode + step).min(target)
+        } else {
+            (self.explode - step).max(target)
+        };
+        self.update_lines();
+
         Vec::new()
     }
 
     fn resize(&mut self, _device: &wgpu::Device, extent: wgpu::Extent3d) {
+        self.screen_size = (extent.width, extent.height);
         self.cam
             .proj
             .update(extent.width as u16, extent.height as u16);
     }
 
+    fn set_ui_scale(&mut self, scale: f32) {
+        self.ui_scale = scale;
+        self.debug.set_ui_scale(scale);
+    }
+
     fn reload(&mut self, device: &wgpu::Device) {
         self.object.reload(device);
     }
@@ -123,25 +476,22 @@ impl Application for ResourceView {
     fn draw(
         &mut self,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         targets: render::ScreenTargets,
         _spawner: &LocalSpawner,
     ) -> wgpu::CommandBuffer {
-        let mut batcher = render::Batcher::new();
-        batcher.add_model(
-            &self.model,
-            &self.transform,
-            None,
-            &render::body::GpuBody::ZERO,
-            render::object::BodyColor::Dummy,
-        );
-        batcher.prepare(device);
+        // taken out for the duration of the frame, since the parts are added by `self`
+        let mut batcher = mem::replace(&mut self.batcher, render::Batcher::new());
+        batcher.clear();
+        self.add_parts(&mut batcher);
+        batcher.prepare(device, queue);
 
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Draw"),
         });
         let global_data = render::global::Constants::new(&self.cam, &self.light_config, None);
         let global_staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
+            label: Some(&render::label::temp("global")),
             contents: bytemuck::bytes_of(&global_data),
             usage: wgpu::BufferUsage::COPY_SRC,
         });
@@ -178,12 +528,21 @@ impl Application for ResourceView {
                 }),
             });
 
+            pass.push_debug_group(&render::label::group("model", "objects"));
             pass.set_pipeline(self.object.pipelines.select(render::PipelineKind::Main));
             pass.set_bind_group(0, &self.global.bind_group, &[]);
             pass.set_bind_group(1, &self.object.bind_group, &[]);
 
             batcher.draw(&mut pass);
+            pass.pop_debug_group();
+
+            if !self.lines.is_empty() {
+                pass.push_debug_group(&render::label::group("debug", "lines"));
+                self.debug.draw_lines(&mut pass, device, queue, &self.lines);
+                pass.pop_debug_group();
+            }
         }
+        self.batcher = batcher;
 
         encoder.finish()
     }
@@ -1,11 +1,14 @@
 mod app;
 #[path = "../boilerplate.rs"]
 mod boilerplate;
+#[path = "../input/mod.rs"]
+mod input;
+mod measure;
 
 fn main() {
     use std::env;
 
-    let (mut harness, settings) = boilerplate::Harness::init("model");
+    let (harness, settings) = boilerplate::Harness::init("model");
 
     let args: Vec<_> = env::args().collect();
     let mut options = getopts::Options::new();
@@ -21,8 +24,19 @@ fn main() {
         return;
     }
 
-    let path = &matches.free[0];
-    let app = app::ResourceView::new(path, &settings, &harness.device, &mut harness.queue);
+    let path = matches.free[0].clone();
+    let color_format = harness.color_format;
+    let loading = harness.loading(&settings.window);
+    let loading = loading.create(Box::new(move |device, queue, progress| {
+        progress("model", 0.0);
+        Box::new(app::ResourceView::new(
+            &path,
+            &settings,
+            color_format,
+            device,
+            queue,
+        ))
+    }));
 
-    harness.main_loop(app);
+    harness.main_loop(loading);
 }
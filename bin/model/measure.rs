@@ -0,0 +1,161 @@
+//! Measurement of the model proportions, in the model units and meters.
+
+use cgmath::prelude::*;
+
+/// Rough size of a model unit. The game doesn't define one,
+/// so the meters are only a guide for comparing the models.
+pub const METERS_PER_UNIT: f32 = 0.05;
+
+pub type Triangle = [cgmath::Point3<f32>; 3];
+
+fn format_length(units: f32) -> String {
+    format!("{:.1} ({:.2} m)", units, units * METERS_PER_UNIT)
+}
+
+fn collect_triangles(
+    mesh: &m3d::DrawMesh,
+    offset: cgmath::Vector3<f32>,
+    triangles: &mut Vec<Triangle>,
+) {
+    let geometry = &mesh.geometry;
+    for polygon in geometry.polygons.iter() {
+        let mut triangle = [cgmath::Point3::origin(); 3];
+        for (point, vertex) in triangle.iter_mut().zip(polygon.vertices.iter()) {
+            let p = geometry.positions[vertex.pos as usize];
+            *point = cgmath::Point3::new(p[0] as f32, p[1] as f32, p[2] as f32) + offset;
+        }
+        triangles.push(triangle);
+    }
+}
+
+/// Triangles of the body and the wheels, in the model space.
+pub fn triangles(raw: &m3d::FullModel) -> Vec<Triangle> {
+    let mut triangles = Vec::new();
+    collect_triangles(&raw.body, cgmath::Vector3::zero(), &mut triangles);
+    for wheel in raw.wheels.iter() {
+        if let Some(ref mesh) = wheel.mesh {
+            let offset = cgmath::vec3(
+                mesh.parent_off[0] as f32,
+                mesh.parent_off[1] as f32,
+                mesh.parent_off[2] as f32,
+            );
+            collect_triangles(mesh, offset, &mut triangles);
+        }
+    }
+    triangles
+}
+
+/// Closest intersection of the ray with the triangles, if any.
+pub fn cast(
+    triangles: &[Triangle],
+    origin: cgmath::Point3<f32>,
+    dir: cgmath::Vector3<f32>,
+) -> Option<cgmath::Point3<f32>> {
+    let mut closest = None::<f32>;
+    for &[a, b, c] in triangles.iter() {
+        let (e1, e2) = (b - a, c - a);
+        let p = dir.cross(e2);
+        let det = e1.dot(p);
+        if det.abs() < 1.0e-6 {
+            continue;
+        }
+        let t_vec = origin - a;
+        let u = t_vec.dot(p) / det;
+        if u < 0.0 || u > 1.0 {
+            continue;
+        }
+        let q = t_vec.cross(e1);
+        let v = dir.dot(q) / det;
+        if v < 0.0 || u + v > 1.0 {
+            continue;
+        }
+        let t = e2.dot(q) / det;
+        if t > 0.0 && closest.map_or(true, |c| t < c) {
+            closest = Some(t);
+        }
+    }
+    closest.map(|t| origin + dir * t)
+}
+
+/// Dimensions of the bounding box, and the wheel track and base,
+/// as the lines of a report.
+pub fn describe(raw: &m3d::FullModel) -> Vec<String> {
+    let b = &raw.body.bounds;
+    let mut lines = vec![format!(
+        "Bounding box: width {}, length {}, height {}",
+        format_length((b.coord_max[0] - b.coord_min[0]) as f32),
+        format_length((b.coord_max[1] - b.coord_min[1]) as f32),
+        format_length((b.coord_max[2] - b.coord_min[2]) as f32),
+    )];
+    if raw.wheels.is_empty() {
+        lines.push("No wheels".to_string());
+    } else {
+        let mut min = [std::f32::MAX; 2];
+        let mut max = [std::f32::MIN; 2];
+        for wheel in raw.wheels.iter() {
+            for axis in 0..2 {
+                min[axis] = min[axis].min(wheel.pos[axis]);
+                max[axis] = max[axis].max(wheel.pos[axis]);
+            }
+        }
+        lines.push(format!(
+            "Wheel track {}, wheelbase {}",
+            format_length(max[0] - min[0]),
+            format_length(max[1] - min[1]),
+        ));
+    }
+    lines
+}
+
+/// State of the measurement mode: the clicked points are connected
+/// one after another, reporting the distances between them.
+pub struct Measure {
+    triangles: Vec<Triangle>,
+    last_point: Option<cgmath::Point3<f32>>,
+    status: String,
+}
+
+impl Measure {
+    pub fn new(raw: &m3d::FullModel) -> Self {
+        for line in describe(raw) {
+            log::info!("{}", line);
+        }
+        Measure {
+            triangles: triangles(raw),
+            last_point: None,
+            status: "Measuring: click on the model".to_string(),
+        }
+    }
+
+    /// Pick the point under the ray, given in the model space.
+    pub fn click(&mut self, origin: cgmath::Point3<f32>, dir: cgmath::Vector3<f32>) {
+        let point = match cast(&self.triangles, origin, dir) {
+            Some(point) => point,
+            None => {
+                // clicking outside of the model starts over
+                self.last_point = None;
+                self.status = "Measuring: click on the model".to_string();
+                return;
+            }
+        };
+        self.status = match self.last_point {
+            Some(last) => format!(
+                "Distance {} from ({:.0}, {:.0}, {:.0}) to ({:.0}, {:.0}, {:.0})",
+                format_length(point.distance(last)),
+                last.x,
+                last.y,
+                last.z,
+                point.x,
+                point.y,
+                point.z,
+            ),
+            None => format!("Point at ({:.0}, {:.0}, {:.0})", point.x, point.y, point.z),
+        };
+        log::info!("{}", self.status);
+        self.last_point = Some(point);
+    }
+
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+}
@@ -0,0 +1,125 @@
+use vangers::{config::settings, level::TerrainType};
+
+use cgmath::prelude::*;
+
+/// Sound category of the terrain under the wheels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Material {
+    Swamp,
+    Sand,
+    Rock,
+}
+
+impl Material {
+    pub fn from_terrain(terrain: TerrainType) -> Self {
+        match terrain {
+            0 => Material::Swamp,
+            1 | 2 => Material::Sand,
+            _ => Material::Rock,
+        }
+    }
+}
+
+/// Driving sound, produced by a vehicle touching the ground.
+#[derive(Debug)]
+pub struct Event {
+    pub material: Material,
+    pub speed: f32,
+    pub position: cgmath::Vector3<f32>,
+}
+
+/// Consumer of the audible events, i.e. the audio backend.
+pub trait Output {
+    fn play(&mut self, event: &Event, gain: f32);
+}
+
+/// Output that only traces the events, since the game has no audio backend yet.
+pub struct Trace;
+
+impl Output for Trace {
+    fn play(&mut self, event: &Event, gain: f32) {
+        log::trace!("Sound {:?} with gain {}", event, gain);
+    }
+}
+
+pub struct Mixer {
+    volume: f32,
+    falloff_distance: f32,
+    events: Vec<Event>,
+    output: Box<dyn Output>,
+}
+
+impl Mixer {
+    pub fn new(settings: &settings::Audio, output: Box<dyn Output>) -> Self {
+        Mixer {
+            volume: settings.volume,
+            falloff_distance: settings.falloff_distance,
+            events: Vec::new(),
+            output,
+        }
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    /// Attenuates the accumulated events by the distance to the listener,
+    /// and plays the audible ones on the output.
+    pub fn flush(&mut self, listener: cgmath::Vector3<f32>) {
+        let (volume, falloff) = (self.volume, self.falloff_distance);
+        for event in self.events.drain(..) {
+            let distance = (event.position - listener).magnitude();
+            let gain = volume / (1.0 + distance / falloff);
+            if gain > 0.01 && event.speed > 0.0 {
+                self.output.play(&event, gain);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    /// Output recording the played events.
+    struct Record(Arc<Mutex<Vec<(Material, f32)>>>);
+
+    impl Output for Record {
+        fn play(&mut self, event: &Event, gain: f32) {
+            self.0.lock().unwrap().push((event.material, gain));
+        }
+    }
+
+    fn event(material: Material, speed: f32, x: f32) -> Event {
+        Event {
+            material,
+            speed,
+            position: cgmath::vec3(x, 0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn events_are_attenuated_by_distance() {
+        let played = Arc::new(Mutex::new(Vec::new()));
+        let settings = settings::Audio {
+            volume: 1.0,
+            falloff_distance: 10.0,
+        };
+        let mut mixer = Mixer::new(&settings, Box::new(Record(Arc::clone(&played))));
+        mixer.emit(event(Material::Sand, 5.0, 0.0));
+        mixer.emit(event(Material::Rock, 5.0, 10.0));
+        mixer.emit(event(Material::Swamp, 0.0, 0.0));
+        mixer.emit(event(Material::Rock, 5.0, 10000.0));
+        mixer.flush(cgmath::vec3(0.0, 0.0, 0.0));
+        assert_eq!(
+            *played.lock().unwrap(),
+            vec![(Material::Sand, 1.0), (Material::Rock, 0.5)]
+        );
+
+        // the events are only played once
+        mixer.flush(cgmath::vec3(0.0, 0.0, 0.0));
+        assert_eq!(played.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,96 @@
+use vangers::space;
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Camera viewpoint stored in a slot.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub loc: [f32; 3],
+    /// Rotation quaternion, with the scalar part last.
+    pub rot: [f32; 4],
+}
+
+impl Bookmark {
+    pub fn new(cam: &space::Camera) -> Self {
+        Bookmark {
+            loc: cam.loc.into(),
+            rot: [cam.rot.v.x, cam.rot.v.y, cam.rot.v.z, cam.rot.s],
+        }
+    }
+
+    pub fn apply(&self, cam: &mut space::Camera) {
+        cam.loc = self.loc.into();
+        cam.rot = cgmath::Quaternion::new(self.rot[3], self.rot[0], self.rot[1], self.rot[2]);
+    }
+}
+
+/// Camera bookmarks in the slots 1 to 9, persisted in a TOML file.
+pub struct Bookmarks {
+    path: PathBuf,
+    // TOML tables are keyed by strings
+    slots: BTreeMap<String, Bookmark>,
+}
+
+impl Bookmarks {
+    /// Load the bookmarks, starting with none if the file doesn't exist or can't be parsed.
+    pub fn load(path: &Path) -> Self {
+        let slots = match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).unwrap_or_else(|e| {
+                log::warn!("Unable to parse bookmarks {}: {}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Bookmarks {
+            path: path.to_path_buf(),
+            slots,
+        }
+    }
+
+    /// Save the camera into a slot, and write all the bookmarks to the file.
+    pub fn save(&mut self, slot: u8, cam: &space::Camera) {
+        self.slots.insert(slot.to_string(), Bookmark::new(cam));
+        let text = toml::to_string(&self.slots).unwrap();
+        match fs::write(&self.path, text) {
+            Ok(()) => log::info!("Saved camera bookmark {}", slot),
+            Err(e) => log::error!("Unable to write {}: {}", self.path.display(), e),
+        }
+    }
+
+    /// Move the camera to the viewpoint in a slot, returns `false` if it's empty.
+    pub fn recall(&self, slot: u8, cam: &mut space::Camera) -> bool {
+        match self.slots.get(&slot.to_string()) {
+            Some(bookmark) => {
+                bookmark.apply(cam);
+                true
+            }
+            None => {
+                log::warn!("Camera bookmark {} is empty", slot);
+                false
+            }
+        }
+    }
+}
+
+/// Slot of a digit key, from 1 to 9.
+pub fn slot(key: winit::event::VirtualKeyCode) -> Option<u8> {
+    use winit::event::VirtualKeyCode as Key;
+    Some(match key {
+        Key::Key1 => 1,
+        Key::Key2 => 2,
+        Key::Key3 => 3,
+        Key::Key4 => 4,
+        Key::Key5 => 5,
+        Key::Key6 => 6,
+        Key::Key7 => 7,
+        Key::Key8 => 8,
+        Key::Key9 => 9,
+        _ => return None,
+    })
+}
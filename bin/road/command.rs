@@ -0,0 +1,42 @@
+use std::str::FromStr;
+
+/// A debugging command, which can be bound to a key in the settings.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Pause,
+    Reset,
+    Recover,
+    Teleport { x: i32, y: i32 },
+    ToggleDebug(String),
+    Fire,
+}
+
+fn parse_arg(word: Option<&str>, what: &str, string: &str) -> Result<i32, String> {
+    word.ok_or_else(|| format!("Missing {} in '{}'", what, string))?
+        .parse()
+        .map_err(|e| format!("Bad {} in '{}': {:?}", what, string, e))
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let mut words = string.split_whitespace();
+        match words.next() {
+            Some("pause") => Ok(Command::Pause),
+            Some("reset") => Ok(Command::Reset),
+            Some("recover") => Ok(Command::Recover),
+            Some("teleport") => Ok(Command::Teleport {
+                x: parse_arg(words.next(), "X", string)?,
+                y: parse_arg(words.next(), "Y", string)?,
+            }),
+            Some("fire") => Ok(Command::Fire),
+            Some("debug") => match words.next() {
+                Some(layer) => Ok(Command::ToggleDebug(layer.to_string())),
+                None => Err(format!("Missing debug layer in '{}'", string)),
+            },
+            Some(other) => Err(format!("Unknown command '{}'", other)),
+            None => Err("Empty command".to_string()),
+        }
+    }
+}
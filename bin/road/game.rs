@@ -1,21 +1,51 @@
-use crate::{boilerplate::Application, physics};
+use crate::{
+    audio,
+    boilerplate::{Application, Screen, Transition},
+    bookmarks,
+    command::Command,
+    garage,
+    input::{parse_key, Action, KeyMap},
+    passage, pause, physics, props, readout, replay, tuning,
+};
 use m3d::Mesh;
 use vangers::{
+    cache::Cache,
     config, level, model,
     render::{
         body::{GpuBody, GpuStore, GpuStoreInit},
         collision::{GpuCollider, GpuEpoch},
+        custom::CustomPass,
         debug::LineBuffer,
         object::BodyColor,
+        raycast::{GpuRayCaster, Ray},
+        weather::Weather,
         Batcher, Render, ScreenTargets,
     },
     space,
+    telemetry::{self, Telemetry},
 };
 
 use cgmath::prelude::*;
 use futures::executor::LocalSpawner;
 
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, mem, path::Path, rc::Rc, sync::Mutex, time};
+
+const SETTINGS_PATH: &str = "config/settings.ron";
+const BOOKMARKS_PATH: &str = "config/bookmarks.toml";
+/// Side of the flat areas that random agents are spawned in, in texels.
+const SPAWN_REGION_SIZE: i32 = 16;
+/// Maximum height difference per texel of the spawn areas.
+const SPAWN_MAX_SLOPE: f32 = 0.5;
+/// Side of the flat area that a flipped vehicle is recovered onto, in texels.
+const RECOVER_REGION_SIZE: i32 = 8;
+/// Maximum distance to look for the recovery area at.
+const RECOVER_MAX_DISTANCE: f32 = 200.0;
+/// Height above the surface to put the recovered vehicle at.
+const RECOVER_HEIGHT: f32 = 5.0;
+/// Duration of the recovery animation, in seconds.
+const RECOVER_DURATION: f32 = 0.5;
+/// Maximum number of projectiles cast against the terrain per frame with the GPU physics.
+const MAX_PROJECTILE_RAYS: usize = 1024;
 
 #[derive(Debug, PartialEq)]
 struct Ai {
@@ -29,15 +59,6 @@ enum Spirit {
     Other(Ai),
 }
 
-#[derive(Clone, Debug, Default, PartialEq)]
-struct Control {
-    motor: f32,
-    rudder: f32,
-    roll: f32,
-    brake: bool,
-    turbo: bool,
-}
-
 enum Physics {
     Cpu {
         transform: space::Transform,
@@ -46,7 +67,7 @@ enum Physics {
     Gpu {
         body: GpuBody,
         collision_epochs: HashMap<GpuEpoch, usize>,
-        last_control: Control,
+        last_control: physics::Control,
     },
 }
 
@@ -54,7 +75,7 @@ enum SimulationStep<'a> {
     Intermediate,
     Final {
         focus_point: &'a cgmath::Point3<f32>,
-        line_buffer: Option<&'a mut LineBuffer>,
+        debug: Option<physics::DebugDraw<'a>>,
     },
 }
 
@@ -62,8 +83,9 @@ pub struct Agent {
     _name: String,
     spirit: Spirit,
     car: config::car::CarInfo,
+    hull: physics::ConvexHull,
     color: BodyColor,
-    control: Control,
+    control: physics::Control,
     jump: Option<f32>,
     physics: Physics,
 }
@@ -92,14 +114,15 @@ impl Agent {
                 roll_time: 0.0,
             }),
             car: car.clone(),
+            hull: physics::ConvexHull::from_shape(&car.model.shape, car.physics.scale_bound),
             color,
-            control: Control::default(),
+            control: physics::Control::default(),
             jump: None,
             physics: match gpu_store {
                 Some(store) => Physics::Gpu {
                     body: store.alloc(&transform, &car.model, &car.physics),
                     collision_epochs: HashMap::default(),
-                    last_control: Control::default(),
+                    last_control: physics::Control::default(),
                 },
                 None => Physics::Cpu {
                     transform,
@@ -110,19 +133,8 @@ impl Agent {
     }
 
     fn cpu_apply_control(&mut self, dt: f32, common: &config::common::Common) {
-        let dynamo = match self.physics {
-            Physics::Cpu { ref mut dynamo, .. } => dynamo,
-            Physics::Gpu { .. } => return,
-        };
-        if self.control.rudder != 0.0 {
-            let angle = dynamo.rudder.0 + common.car.rudder_step * 2.0 * dt * self.control.rudder;
-            dynamo.rudder.0 = angle.min(common.car.rudder_max).max(-common.car.rudder_max);
-        }
-        if self.control.motor != 0.0 {
-            dynamo.change_traction(self.control.motor * dt * common.car.traction_incr);
-        }
-        if self.control.brake && dynamo.traction != 0.0 {
-            dynamo.traction *= (-dt).exp2();
+        if let Physics::Cpu { ref mut dynamo, .. } = self.physics {
+            physics::apply_control(dynamo, &self.control, self.jump.is_some(), dt, common);
         }
     }
 
@@ -130,6 +142,7 @@ impl Agent {
         &mut self,
         dt: f32,
         level: &level::Level,
+        grid: &physics::TerrainGrid,
         common: &config::common::Common,
         sim_step: SimulationStep,
     ) {
@@ -140,38 +153,27 @@ impl Agent {
             } => (dynamo, transform),
             Physics::Gpu { .. } => return,
         };
-        let (jump, roll, focus_point, line_buffer) = match sim_step {
+        let (jump, roll, focus_point, debug) = match sim_step {
             SimulationStep::Intermediate => (None, 0.0, None, None),
-            SimulationStep::Final {
-                focus_point,
-                line_buffer,
-            } => (
+            SimulationStep::Final { focus_point, debug } => (
                 self.jump.take(),
                 self.control.roll,
                 Some(*focus_point),
-                line_buffer,
+                debug,
             ),
         };
-        physics::step(
+        physics::step_vehicle(
             dynamo,
             transform,
             dt,
-            &self.car,
+            &physics::Chassis::new(&self.car),
             level,
+            grid,
             common,
-            if self.control.turbo {
-                common.global.k_traction_turbo
-            } else {
-                1.0
-            },
-            if self.control.brake {
-                common.global.f_brake_max
-            } else {
-                0.0
-            },
+            &self.control,
             jump,
             roll,
-            line_buffer,
+            debug,
         );
 
         if let Some(focus) = focus_point {
@@ -220,6 +222,34 @@ impl Agent {
     }
 }
 
+/// Create the library rigid body of the car, with the configured parameters.
+fn rigid_body(
+    car: &config::car::CarInfo,
+    transform: space::Transform,
+    config: &config::settings::RigidBody,
+) -> Option<vangers::physics::Body> {
+    let mut body = vangers::physics::Body::new(&car.model, transform);
+    match body {
+        Some(ref mut body) => {
+            body.params.engine_torque = config.engine_torque;
+            body.params.grip = config.grip;
+            body.params.friction = config.friction;
+        }
+        None => log::warn!("Unable to derive the rigid body of the car"),
+    }
+    body
+}
+
+fn bounding_boxes(agents: &[Agent]) -> Vec<physics::Aabb> {
+    agents
+        .iter()
+        .map(|a| {
+            let radius = a.car.model.body.bbox.radius * a.car.scale;
+            physics::Aabb::from_sphere(a.position(), radius)
+        })
+        .collect()
+}
+
 struct DataBase {
     _bunches: Vec<config::bunches::Bunch>,
     cars: HashMap<String, config::car::CarInfo>,
@@ -231,6 +261,7 @@ struct DataBase {
 struct Gpu {
     store: GpuStore,
     collider: GpuCollider,
+    caster: GpuRayCaster,
 }
 
 enum CameraStyle {
@@ -271,7 +302,7 @@ struct Clipper {
 impl Clipper {
     fn new(cam: &space::Camera) -> Self {
         Clipper {
-            mx_vp: cam.get_view_proj(),
+            mx_vp: cam.view_proj(),
             threshold: 1.05,
         }
     }
@@ -288,6 +319,27 @@ struct Roll {
     time: f32,
 }
 
+/// Animated move of the player to a safe spot.
+struct Recovery {
+    from: space::Transform,
+    to: space::Transform,
+    time: f32,
+}
+
+/// Change of the game mode, requested by the input or by the commands,
+/// and turned into a transition by the current state of the game.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Request {
+    /// Pause looking at the player, or resume if already paused.
+    TogglePause,
+    /// Pause without moving the camera, staying paused if already.
+    Pause,
+    /// Enter the garage.
+    Garage,
+    /// Leave the game.
+    Exit,
+}
+
 pub struct Game {
     db: DataBase,
     render: Render,
@@ -296,28 +348,55 @@ pub struct Game {
     //debug_collision_map: bool,
     line_buffer: LineBuffer,
     level: level::Level,
+    terrain_grid: physics::TerrainGrid,
+    level_name: String,
+    passages: Option<passage::Passages>,
+    crossing: Option<passage::Crossing>,
+    props: props::Props,
+    weather: Option<Weather>,
     agents: Vec<Agent>,
+    projectiles: Vec<physics::Projectile>,
+    projectile_config: Option<config::settings::Projectiles>,
+    vehicle_collision: Option<config::settings::VehicleCollision>,
     cam: space::Camera,
     cam_style: CameraStyle,
+    wrap_seam: bool,
     max_quant: f32,
     spin_hor: f32,
     spin_ver: f32,
     turbo: bool,
     jump: Option<f32>,
     roll: Option<Roll>,
-    is_paused: bool,
+    recovery: Option<Recovery>,
+    request: Option<Request>,
     tick: Option<f32>,
+    key_map: KeyMap,
+    #[cfg(feature = "gamepad")]
+    gamepad: Option<crate::input::gamepad::Gamepad>,
+    macros: HashMap<winit::event::VirtualKeyCode, Vec<Command>>,
+    bookmarks: bookmarks::Bookmarks,
+    pending_commands: Vec<Command>,
+    tuning: tuning::Panel,
+    audio: audio::Mixer,
+    replay: Option<replay::Session>,
+    show_readout: bool,
+    car_id: String,
 }
 
 impl Game {
     pub fn new(
         settings: &config::Settings,
         screen_extent: wgpu::Extent3d,
+        color_format: wgpu::TextureFormat,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        telemetry: &dyn Telemetry,
+        progress: &mut dyn FnMut(&str, f32),
     ) -> Self {
+        progress("level", 0.0);
+        let level_start = time::Instant::now();
         log::info!("Loading world parameters");
-        let (level, coords) = if settings.game.level.is_empty() {
+        let (mut level, coords) = if settings.game.level.is_empty() {
             log::info!("Using test level");
             (level::Level::new_test(), (0, 0))
         } else {
@@ -340,11 +419,16 @@ impl Game {
             log::info!("Using level {}", ini_name);
 
             let config = level::LevelConfig::load(&ini_path);
-            let level = level::load(&config);
+            let level = level::load(&config, &Cache::new(&settings.cache_path));
 
             (level, coordinates)
         };
+        telemetry.event(&telemetry::Event::LevelLoaded {
+            name: &settings.game.level,
+            duration: level_start.elapsed(),
+        });
 
+        progress("render", 0.4);
         log::info!("Initializing the render");
         let depth = settings.game.camera.depth_range;
         let pal_data = level::read_palette(settings.open_palette(), Some(&level.terrains));
@@ -359,21 +443,38 @@ impl Game {
             &pal_data,
             &settings.render,
             screen_extent,
+            color_format,
             store_init.resource(),
         );
 
+        progress("database", 0.5);
         log::info!("Loading world database");
-        let db = {
+        let mut db = {
             let game = config::game::Registry::load(settings);
             DataBase {
                 _bunches: config::bunches::load(settings.open_relative("bunches.prm")),
-                cars: config::car::load_registry(settings, &game, device, &render.object),
+                cars: config::car::load_registry(
+                    settings,
+                    &game,
+                    device,
+                    &render.object,
+                    &mut |done, total| {
+                        log::debug!("Loaded {}/{} cars", done, total);
+                        progress("cars", 0.5 + 0.4 * done as f32 / total as f32);
+                    },
+                ),
                 common: config::common::load(settings.open_relative("common.prm")),
                 _escaves: config::escaves::load(settings.open_relative("escaves.prm")),
                 game,
             }
         };
 
+        if let Some(world) = settings.game.physics.world {
+            level.physics = world;
+        }
+        log::info!("World physics {:?}", level.physics);
+        level.physics.apply_common(&mut db.common);
+
         let mut gpu = settings.game.physics.gpu_collision.as_ref().map(|gc| {
             log::info!("Initializing the GPU store and collider");
             let collider = GpuCollider::new(
@@ -385,9 +486,24 @@ impl Game {
                 store_init.resource(),
             );
             let store = GpuStore::new(device, &db.common, store_init, collider.collision_buffer());
-            Gpu { store, collider }
+            let caster = GpuRayCaster::new(device, &render.terrain, MAX_PROJECTILE_RAYS);
+            Gpu {
+                store,
+                collider,
+                caster,
+            }
         });
 
+        let passages = if settings.game.level.is_empty() || settings.game.passages.is_empty() {
+            None
+        } else if gpu.is_some() {
+            log::warn!("Passages are not supported with the GPU physics");
+            None
+        } else {
+            Some(passage::Passages::new(settings))
+        };
+
+        progress("agents", 0.9);
         log::info!("Spawning agents");
         let car_names = db.cars.keys().cloned().collect::<Vec<_>>();
         let mut player_agent = Agent::spawn(
@@ -415,12 +531,22 @@ impl Game {
         {
             let info = &db.game.model_infos[sid];
             let raw = Mesh::load(&mut settings.open_relative(&info.path));
-            ms.mesh = Some(model::load_c3d(raw, device));
+            ms.mesh = Some(model::load_c3d(
+                raw,
+                device,
+                !settings.render.debug.raw_vertices,
+            ));
             ms.scale = info.scale;
         }
 
         let mut agents = vec![player_agent];
         let mut rng = rand::thread_rng();
+        let spawn_regions = match settings.game.other.spawn_at {
+            config::settings::SpawnAt::Player => Vec::new(),
+            config::settings::SpawnAt::Random => {
+                level.find_flat_regions(SPAWN_REGION_SIZE, SPAWN_MAX_SLOPE)
+            }
+        };
         // populate with random agents
         for i in 0..settings.game.other.count {
             use rand::{prelude::SliceRandom, Rng};
@@ -433,10 +559,13 @@ impl Game {
             let car_id = car_names.choose(&mut rng).unwrap();
             let (x, y) = match settings.game.other.spawn_at {
                 config::settings::SpawnAt::Player => coords,
-                config::settings::SpawnAt::Random => (
-                    rng.gen_range(0, level.size.0),
-                    rng.gen_range(0, level.size.1),
-                ),
+                config::settings::SpawnAt::Random => match spawn_regions.choose(&mut rng) {
+                    Some(region) => (region.center.x as i32, region.center.y as i32),
+                    None => (
+                        rng.gen_range(0, level.size.0),
+                        rng.gen_range(0, level.size.1),
+                    ),
+                },
             };
             let agent = Agent::spawn(
                 format!("Other-{}", i),
@@ -450,18 +579,73 @@ impl Game {
             agents.push(agent);
         }
 
+        for agent in agents.iter_mut() {
+            if let Physics::Cpu {
+                ref transform,
+                ref mut dynamo,
+            } = agent.physics
+            {
+                if let Some(ref solver) = settings.game.physics.contact_solver {
+                    dynamo.manifold = Some(physics::Manifold::new(solver.clone()));
+                }
+                dynamo.ackermann = settings.game.physics.ackermann;
+                if let Some(ref config) = settings.game.physics.rigid_body {
+                    dynamo.rigid = rigid_body(&agent.car, *transform, config);
+                }
+            }
+        }
+
+        let props = props::Props::scatter(settings, &db.game, &level, device);
+        let weather = settings
+            .game
+            .weather
+            .get(&settings.game.level)
+            .map(|config| Weather::new(config, render.global(), device));
+
+        let mut macros = HashMap::new();
+        for km in settings.game.macros.iter() {
+            let key = match parse_key(&km.key) {
+                Some(key) => key,
+                None => {
+                    log::warn!("Unknown macro key '{}'", km.key);
+                    continue;
+                }
+            };
+            let commands = km
+                .commands
+                .iter()
+                .filter_map(|string| match string.parse() {
+                    Ok(command) => Some(command),
+                    Err(e) => {
+                        log::warn!("Skipping macro command: {}", e);
+                        None
+                    }
+                })
+                .collect();
+            macros.insert(key, commands);
+        }
+
         Game {
             db,
             render,
             batcher: Batcher::new(),
             gpu,
             line_buffer: LineBuffer::new(),
+            terrain_grid: physics::TerrainGrid::new(&level),
             level,
+            level_name: settings.game.level.clone(),
+            passages,
+            crossing: None,
+            props,
+            weather,
             agents,
-            cam: space::Camera {
-                loc: cgmath::vec3(coords.0 as f32, coords.1 as f32, 200.0),
-                rot: cgmath::Quaternion::new(0.0, 0.0, 1.0, 0.0),
-                proj: match settings.game.view {
+            projectiles: Vec::new(),
+            projectile_config: settings.game.physics.projectiles.clone(),
+            vehicle_collision: settings.game.physics.vehicle_collision,
+            cam: space::Camera::new(
+                cgmath::vec3(coords.0 as f32, coords.1 as f32, 200.0),
+                cgmath::Quaternion::new(0.0, 0.0, 1.0, 0.0),
+                match settings.game.view {
                     config::settings::View::Perspective => {
                         let pf = cgmath::PerspectiveFov {
                             fovy: cgmath::Deg(45.0).into(),
@@ -477,8 +661,9 @@ impl Game {
                         depth.0..depth.1,
                     ),
                 },
-            },
+            ),
             cam_style: CameraStyle::new(&settings.game.camera),
+            wrap_seam: settings.render.world_seam == config::settings::WorldSeam::Wrap,
             max_quant: settings.game.physics.max_quant,
             //debug_collision_map: settings.render.debug.collision_map,
             spin_hor: 0.0,
@@ -486,9 +671,620 @@ impl Game {
             turbo: false,
             jump: None,
             roll: None,
-            is_paused: false,
+            recovery: None,
+            request: None,
             tick: None,
+            key_map: KeyMap::new(&settings.bindings).with(&[
+                (&settings.bindings.road.garage, Action::Garage),
+                (&settings.bindings.road.roll_left, Action::RollLeft),
+                (&settings.bindings.road.roll_right, Action::RollRight),
+                (&settings.bindings.road.step_back, Action::StepBack),
+                (&settings.bindings.road.step_forward, Action::StepForward),
+            ]),
+            #[cfg(feature = "gamepad")]
+            gamepad: crate::input::gamepad::Gamepad::new(),
+            macros,
+            bookmarks: bookmarks::Bookmarks::load(Path::new(BOOKMARKS_PATH)),
+            pending_commands: Vec::new(),
+            tuning: tuning::Panel::default(),
+            audio: audio::Mixer::new(&settings.game.audio, Box::new(audio::Trace)),
+            replay: None,
+            show_readout: false,
+            car_id: settings.car.id.clone(),
+        }
+    }
+
+    /// Start recording or playing a replay. The other agents are removed,
+    /// since their behavior is not recorded.
+    pub fn start_replay(&mut self, session: replay::Session) {
+        self.agents.retain(|a| a.spirit == Spirit::Player);
+        self.replay = Some(session);
+    }
+
+    /// Play the whole replay without drawing anything, then check the golden state hash.
+    pub fn play_replay(&mut self, device: &wgpu::Device) -> Result<(), String> {
+        if self.gpu.is_some() {
+            return Err("Replays are not supported with the GPU physics".to_string());
+        }
+        let pool = futures::executor::LocalPool::new();
+        let spawner = pool.spawner();
+        while self.replay.as_ref().map_or(false, |s| s.is_playing()) {
+            // the time step of the replay is used instead of the frame time
+            self.update(device, 0.0, &spawner);
+        }
+        match self.replay {
+            Some(ref session) => session.check_golden(self.player_state_hash()),
+            None => Err("No replay is started".to_string()),
+        }
+    }
+
+    /// Move the camera and the agents back into the original copy of the level,
+    /// once the player crosses its edges.
+    fn wrap_world(&mut self) {
+        // GPU bodies are simulated in place
+        if self.gpu.is_some() {
+            return;
+        }
+        let player_pos = match self.agents.iter().find(|a| a.spirit == Spirit::Player) {
+            Some(&Agent {
+                physics: Physics::Cpu { ref transform, .. },
+                ..
+            }) => transform.disp,
+            _ => return,
+        };
+        let offset = self.level.wrap_offset(player_pos);
+        if offset == cgmath::Vector3::zero() {
+            return;
+        }
+        for agent in self.agents.iter_mut() {
+            if let Physics::Cpu {
+                ref mut transform, ..
+            } = agent.physics
+            {
+                transform.disp += offset;
+            }
+        }
+        self.cam.loc += offset;
+    }
+
+    fn player_state_hash(&self) -> u64 {
+        let player = self
+            .agents
+            .iter()
+            .find(|a| a.spirit == Spirit::Player)
+            .unwrap();
+        match player.physics {
+            Physics::Cpu {
+                ref transform,
+                ref dynamo,
+            } => vangers::replay::hash_state(
+                transform,
+                dynamo.linear_velocity,
+                dynamo.angular_velocity,
+            ),
+            Physics::Gpu { .. } => {
+                log::warn!("State hashing is not supported on GPU");
+                0
+            }
+        }
+    }
+
+    fn player_mut(&mut self) -> &mut Agent {
+        self.agents
+            .iter_mut()
+            .find(|a| a.spirit == Spirit::Player)
+            .unwrap()
+    }
+
+    #[cfg(feature = "gamepad")]
+    fn poll_gamepad(&mut self) {
+        let polled = match self.gamepad {
+            Some(ref mut gamepad) => gamepad.poll(),
+            None => return,
+        };
+
+        for (action, pressed) in polled.actions {
+            match (action, pressed) {
+                (Action::Pause, true) => self.request = Some(Request::TogglePause),
+                (Action::Debug, true) => self.show_readout = !self.show_readout,
+                (Action::Turbo, _) => self.turbo = pressed,
+                (Action::Jump, true) => self.jump = Some(0.0),
+                (Action::Jump, false) => {
+                    let jump = self.jump.take();
+                    if let Some(player) =
+                        self.agents.iter_mut().find(|a| a.spirit == Spirit::Player)
+                    {
+                        player.jump = jump;
+                    }
+                }
+                (Action::Reset, true) => self.reset_player(),
+                (Action::Recover, true) => self.recover_player(),
+                _ => (),
+            }
+        }
+        // the analog input drives the vehicle, or the camera while paused
+        if let Some(analog) = polled.analog {
+            self.spin_hor = analog.steer;
+            self.spin_ver = analog.throttle;
+        }
+    }
+
+    /// Move the camera to a bookmark, pausing the game so that the view stays.
+    fn recall_bookmark(&mut self, slot: u8) {
+        self.request = Some(Request::Pause);
+        self.bookmarks.recall(slot, &mut self.cam);
+    }
+
+    pub fn player_transform(&self) -> space::Transform {
+        let player = self
+            .agents
+            .iter()
+            .find(|a| a.spirit == Spirit::Player)
+            .unwrap();
+        match player.physics {
+            Physics::Cpu { ref transform, .. } => transform.clone(),
+            Physics::Gpu { ref body, .. } => self
+                .gpu
+                .as_ref()
+                .unwrap()
+                .store
+                .cpu_mirror()
+                .get(body)
+                .cloned()
+                .unwrap_or(space::Transform::one()),
+        }
+    }
+
+    /// Take the pending request. The exit is never taken,
+    /// so that every state of the game gets to leave.
+    pub fn take_request(&mut self) -> Option<Request> {
+        match self.request {
+            Some(Request::Exit) => Some(Request::Exit),
+            _ => self.request.take(),
+        }
+    }
+
+    /// Stop the simulation, turning the camera to the player if `focus` is set.
+    pub fn pause(&mut self, focus: bool) {
+        self.tick = None;
+        if focus {
+            self.focus_on_player();
+        }
+    }
+
+    /// Continue the simulation, with the camera looking down at the player.
+    pub fn resume(&mut self) {
+        let center = self.player_transform();
+        self.tick = None;
+        self.cam.loc = center.disp + cgmath::vec3(0.0, 0.0, 200.0);
+        self.cam.rot = cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0);
+    }
+
+    pub fn focus_on_player(&mut self) {
+        let center = self.player_transform();
+        self.cam.focus_on(&center);
+    }
+
+    fn reset_player(&mut self) {
+        if let Physics::Cpu {
+            ref mut transform,
+            ref mut dynamo,
+        } = self.player_mut().physics
+        {
+            transform.rot = cgmath::One::one();
+            dynamo.linear_velocity = cgmath::Vector3::zero();
+            dynamo.angular_velocity = cgmath::Vector3::zero();
+        }
+    }
+
+    /// Start moving the player to a flat dry spot nearby, putting it back on the wheels.
+    fn recover_player(&mut self) {
+        let transform = match self.player_mut().physics {
+            Physics::Cpu { ref transform, .. } => transform.clone(),
+            Physics::Gpu { .. } => {
+                log::warn!("Recovery is not supported for GPU physics");
+                return;
+            }
+        };
+        let center = cgmath::Point2::new(transform.disp.x, transform.disp.y);
+        let region = match self.level.find_flat_region_near(
+            center,
+            RECOVER_REGION_SIZE,
+            SPAWN_MAX_SLOPE,
+            RECOVER_MAX_DISTANCE,
+        ) {
+            Some(region) => region,
+            None => {
+                log::warn!("Unable to find a safe spot near {:?}", center);
+                return;
+            }
+        };
+
+        // keep the heading of the vehicle, unless it's looking straight up or down
+        let forward = transform.rot * cgmath::Vector3::unit_y();
+        let yaw = if forward.x == 0.0 && forward.y == 0.0 {
+            0.0
+        } else {
+            (-forward.x).atan2(forward.y)
+        };
+        let to = space::Transform {
+            disp: region.center.to_vec() + cgmath::vec3(0.0, 0.0, RECOVER_HEIGHT),
+            rot: cgmath::Quaternion::from_angle_z(cgmath::Rad(yaw)),
+            scale: transform.scale,
+        };
+        self.recovery = Some(Recovery {
+            from: transform,
+            to,
+            time: 0.0,
+        });
+    }
+
+    fn update_recovery(&mut self, delta: f32) {
+        let mut recovery = match self.recovery.take() {
+            Some(recovery) => recovery,
+            None => return,
+        };
+        recovery.time += delta;
+        let t = (recovery.time / RECOVER_DURATION).min(1.0);
+        if let Physics::Cpu {
+            ref mut transform,
+            ref mut dynamo,
+        } = self.player_mut().physics
+        {
+            transform.disp = recovery.from.disp.lerp(recovery.to.disp, t);
+            transform.rot = recovery.from.rot.nlerp(recovery.to.rot, t);
+            dynamo.linear_velocity = cgmath::Vector3::zero();
+            dynamo.angular_velocity = cgmath::Vector3::zero();
+        }
+        if t < 1.0 {
+            self.recovery = Some(recovery);
+        }
+    }
+
+    fn fire_projectile(&mut self) {
+        let speed = match self.projectile_config {
+            Some(ref config) => config.speed,
+            None => {
+                log::warn!("Projectiles are not configured");
+                return;
+            }
+        };
+        let player = self
+            .agents
+            .iter()
+            .find(|a| a.spirit == Spirit::Player)
+            .unwrap();
+        let projectile = match player.physics {
+            Physics::Cpu { ref transform, .. } => {
+                let forward = transform.rot * cgmath::Vector3::unit_y();
+                physics::Projectile::new(transform.disp, forward * speed)
+            }
+            Physics::Gpu { ref body, .. } => {
+                let transform = match self.gpu.as_ref().unwrap().store.cpu_mirror().get(body) {
+                    Some(transform) => *transform,
+                    None => return,
+                };
+                let forward = transform.rot * cgmath::Vector3::unit_y();
+                physics::Projectile::new(transform.disp, forward * speed)
+            }
+        };
+        self.projectiles.push(projectile);
+    }
+
+    fn collide_vehicles(&mut self) {
+        let config = match self.vehicle_collision {
+            Some(ref config) => config,
+            None => return,
+        };
+        let wrap = cgmath::vec2(self.level.size.0 as f32, (self.level.size.1 >> 1) as f32);
+        let boxes = bounding_boxes(&self.agents);
+        for (i, j) in physics::find_pairs(&boxes, wrap) {
+            let (head, tail) = self.agents.split_at_mut(j);
+            let (first, second) = (&mut head[i], &mut tail[0]);
+            if let (
+                Physics::Cpu {
+                    transform: transform_a,
+                    dynamo: dynamo_a,
+                },
+                Physics::Cpu {
+                    transform: transform_b,
+                    dynamo: dynamo_b,
+                },
+            ) = (&mut first.physics, &mut second.physics)
+            {
+                // bring the second vehicle to the copy of the level with the first one
+                let raw = transform_b.disp - transform_a.disp;
+                let offset = cgmath::vec3(
+                    (raw.x + 0.5 * wrap.x).rem_euclid(wrap.x) - 0.5 * wrap.x - raw.x,
+                    (raw.y + 0.5 * wrap.y).rem_euclid(wrap.y) - 0.5 * wrap.y - raw.y,
+                    0.0,
+                );
+                physics::collide_vehicles(
+                    physics::Vehicle {
+                        dynamo: dynamo_a,
+                        transform: transform_a,
+                        chassis: physics::Chassis::new(&first.car),
+                        hull: &first.hull,
+                    },
+                    physics::Vehicle {
+                        dynamo: dynamo_b,
+                        transform: transform_b,
+                        chassis: physics::Chassis::new(&second.car),
+                        hull: &second.hull,
+                    },
+                    offset,
+                    &self.db.common,
+                    config,
+                );
+            }
+        }
+    }
+
+    fn update_passages(&mut self) {
+        let passages = match self.passages {
+            Some(ref mut passages) => passages,
+            None => return,
+        };
+        let position = self
+            .agents
+            .iter()
+            .find(|a| a.spirit == Spirit::Player)
+            .unwrap()
+            .position();
+        let wrap = cgmath::vec2(self.level.size.0 as f32, self.level.size.1 as f32);
+        if let Some(crossing) = passages.update(&self.level_name, position, wrap) {
+            self.crossing = Some(crossing);
+        }
+    }
+
+    /// Move the player into the destination level, keeping the vehicle state.
+    fn cross(&mut self, crossing: passage::Crossing, device: &wgpu::Device, queue: &wgpu::Queue) {
+        log::info!("Entering level {}", crossing.name);
+        let settings = match config::Settings::try_load(SETTINGS_PATH) {
+            Ok(settings) => settings,
+            Err(e) => {
+                log::error!("Unable to enter level {}: {}", crossing.name, e);
+                // keep the level that was left before, to come back to it later
+                if let (passage::Destination::Resident(resident), Some(passages)) =
+                    (crossing.destination, self.passages.as_mut())
+                {
+                    passages.leave(resident);
+                }
+                return;
+            }
+        };
+        let (mut level, agents, props, weather) = match crossing.destination {
+            passage::Destination::Resident(resident) => (
+                resident.level,
+                resident.agents,
+                resident.props,
+                resident.weather,
+            ),
+            passage::Destination::Loaded(level) => {
+                let props = props::Props::scatter(&settings, &self.db.game, &level, device);
+                let weather = settings
+                    .game
+                    .weather
+                    .get(&crossing.name)
+                    .map(|config| Weather::new(config, self.render.global(), device));
+                (level, Vec::new(), props, weather)
+            }
+        };
+        if let Some(world) = settings.game.physics.world {
+            level.physics = world;
+        }
+        // the level factors are applied on top of the original values
+        self.db.common = config::common::load(settings.open_relative("common.prm"));
+        level.physics.apply_common(&mut self.db.common);
+        self.render
+            .set_level(device, queue, &level, &settings.render);
+
+        let height = physics::get_height(level.get(crossing.exit).top()) + 5.0;
+        let exit = cgmath::vec3(crossing.exit.0 as f32, crossing.exit.1 as f32, height);
+        let (mut players, others): (Vec<_>, Vec<_>) = self
+            .agents
+            .drain(..)
+            .partition(|a| a.spirit == Spirit::Player);
+        if let Physics::Cpu {
+            ref mut transform,
+            ref mut dynamo,
+        } = players[0].physics
+        {
+            // the velocities are in the local space, and carry over as is
+            self.cam.loc += exit - transform.disp;
+            transform.disp = exit;
+            dynamo.sleep.wake();
+        }
+        players.extend(agents);
+        self.agents = players;
+        self.projectiles.clear();
+        self.recovery = None;
+
+        self.terrain_grid = physics::TerrainGrid::new(&level);
+        let resident = passage::Resident {
+            name: mem::replace(&mut self.level_name, crossing.name),
+            level: mem::replace(&mut self.level, level),
+            agents: others,
+            props: mem::replace(&mut self.props, props),
+            weather: mem::replace(&mut self.weather, weather),
+        };
+        if let Some(ref mut passages) = self.passages {
+            passages.leave(resident);
+        }
+    }
+
+    fn update_projectiles(&mut self, delta: f32) {
+        let config = match self.projectile_config {
+            Some(ref config) => config,
+            None => return,
+        };
+        let gravity = self.db.common.nature.gravity;
+        for projectile in self.projectiles.iter_mut() {
+            let start = projectile.pos;
+            if let Some(event) = projectile.step(&self.level, config, gravity, delta) {
+                log::debug!("Projectile hit {:?}", event);
+                self.audio.emit(audio::Event {
+                    material: match event.terrain {
+                        Some(terrain) => audio::Material::from_terrain(terrain),
+                        None => audio::Material::Swamp,
+                    },
+                    speed: event.speed,
+                    position: event.position,
+                });
+            }
+            // Yellow: projectile tracers
+            self.line_buffer
+                .add(start.into(), projectile.pos.into(), 0xFFFF0000);
+        }
+        self.projectiles.retain(|p| p.is_alive(config));
+    }
+
+    /// Move the projectiles with the GPU physics, where the terrain hits
+    /// are cast on GPU and arrive a few frames later, in batches.
+    fn cast_projectiles(&mut self, device: &wgpu::Device, delta: f32) -> wgpu::CommandBuffer {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Projectiles"),
+        });
+        self.line_buffer.clear();
+        let (config, caster) = match (self.projectile_config.as_ref(), self.gpu.as_mut()) {
+            (Some(config), Some(gpu)) => (config, &mut gpu.caster),
+            _ => return encoder.finish(),
+        };
+        let gravity = self.db.common.nature.gravity;
+
+        for result in caster.take_results() {
+            for projectile in self.projectiles.iter_mut() {
+                let hits = projectile
+                    .casts
+                    .iter()
+                    .filter(|&&(batch, _)| batch == result.batch)
+                    .map(|&(_, index)| result.hits[index])
+                    .collect::<Vec<_>>();
+                projectile.casts.retain(|&(batch, _)| batch > result.batch);
+                for hit in hits.into_iter().filter(|hit| hit.is_hit()) {
+                    let position = hit.position().to_vec();
+                    let terrain = hit.info[0] as level::TerrainType;
+                    if let Some(event) = projectile.hit(&self.level, config, position, terrain) {
+                        log::debug!("Projectile hit {:?}", event);
+                        self.audio.emit(audio::Event {
+                            material: audio::Material::from_terrain(terrain),
+                            speed: event.speed,
+                            position: event.position,
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut added = Vec::new();
+        for (i, projectile) in self.projectiles.iter_mut().enumerate() {
+            let start = projectile.pos;
+            if let Some(event) = projectile.fly(&self.level, gravity, delta) {
+                log::debug!("Projectile hit {:?}", event);
+                self.audio.emit(audio::Event {
+                    material: audio::Material::Swamp,
+                    speed: event.speed,
+                    position: event.position,
+                });
+            } else if projectile.is_alive(config) {
+                let offset = projectile.pos - start;
+                let ray = Ray::new(start, offset.normalize(), offset.magnitude());
+                if let Some(index) = caster.add(ray) {
+                    added.push((i, index));
+                }
+            }
+            // Yellow: projectile tracers
+            self.line_buffer
+                .add(start.into(), projectile.pos.into(), 0xFFFF0000);
+        }
+        let batch = caster.cast(device, &mut encoder, &self.render.terrain);
+        for (i, index) in added {
+            self.projectiles[i].casts.push((batch, index));
+        }
+
+        self.projectiles.retain(|p| p.is_alive(config));
+        encoder.finish()
+    }
+
+    fn execute(&mut self, command: Command, device: &wgpu::Device) {
+        log::info!("Executing {:?}", command);
+        match command {
+            Command::Pause => self.request = Some(Request::TogglePause),
+            Command::Reset => self.reset_player(),
+            Command::Recover => self.recover_player(),
+            Command::Teleport { x, y } => {
+                let height = physics::get_height(self.level.get((x, y)).top()) + 5.;
+                match self.player_mut().physics {
+                    Physics::Cpu {
+                        ref mut transform, ..
+                    } => {
+                        transform.disp = cgmath::vec3(x as f32, y as f32, height);
+                    }
+                    Physics::Gpu { .. } => log::warn!("Teleport is not supported on GPU"),
+                }
+            }
+            Command::ToggleDebug(ref layer) => {
+                if !self.render.debug.toggle(layer, device) {
+                    log::warn!("Unknown debug layer '{}'", layer);
+                }
+            }
+            Command::Fire => self.fire_projectile(),
+        }
+    }
+
+    pub fn change_player_car(&mut self, id: &str) {
+        let car = self.db.cars[id].clone();
+        let player = self.player_mut();
+        match player.physics {
+            Physics::Cpu {
+                ref mut transform,
+                ref mut dynamo,
+            } => {
+                transform.scale = car.scale;
+                *dynamo = physics::Dynamo {
+                    manifold: dynamo
+                        .manifold
+                        .take()
+                        .map(|m| physics::Manifold::new(m.config().clone())),
+                    ackermann: dynamo.ackermann,
+                    rigid: dynamo.rigid.take().and_then(|old| {
+                        let mut body = vangers::physics::Body::new(&car.model, *transform)?;
+                        body.params = old.params;
+                        Some(body)
+                    }),
+                    ..physics::Dynamo::default()
+                };
+            }
+            Physics::Gpu { .. } => {
+                log::warn!("Changing the car is not supported on GPU");
+                return;
+            }
         }
+        player.hull = physics::ConvexHull::from_shape(&car.model.shape, car.physics.scale_bound);
+        player.car = car;
+    }
+
+    pub fn car_ids(&self) -> Vec<String> {
+        self.db.cars.keys().cloned().collect()
+    }
+
+    pub fn car_id(&self) -> &str {
+        &self.car_id
+    }
+
+    /// Keep the given car for the player, saving it into the settings.
+    pub fn pick_car(&mut self, id: &str) {
+        self.car_id = id.to_string();
+        log::info!("Picked {}", self.car_id);
+        if let Err(e) = config::settings::Car::save_id(Path::new(SETTINGS_PATH), &self.car_id) {
+            log::warn!("Unable to save the car selection: {}", e);
+        }
+    }
+
+    /// Put the player back into the car that was picked before.
+    pub fn restore_car(&mut self) {
+        let id = self.car_id.clone();
+        self.change_player_car(&id);
     }
 
     fn _move_cam(&mut self, step: f32) {
@@ -496,73 +1292,88 @@ impl Game {
         back.z = 0.0;
         self.cam.loc -= back.normalize() * step;
     }
-}
 
-impl Application for Game {
-    fn on_key(&mut self, input: winit::event::KeyboardInput) -> bool {
-        use winit::event::{ElementState, KeyboardInput, VirtualKeyCode as Key};
+    pub fn status(&self) -> Option<String> {
+        let stats = if self.render.debug.settings().stats {
+            Some(self.render.stats.to_string())
+        } else {
+            None
+        };
+        if !self.show_readout {
+            return stats;
+        }
+        let readout = match readout::Readout::probe(&self.cam, &self.level) {
+            Some(readout) => readout.to_string(),
+            None => "no terrain".to_string(),
+        };
+        Some(match stats {
+            Some(stats) => format!("{} | {}", readout, stats),
+            None => readout,
+        })
+    }
+
+    pub fn on_key(&mut self, input: winit::event::KeyboardInput) {
+        use winit::event::{ElementState, KeyboardInput};
 
         let player = match self.agents.iter_mut().find(|a| a.spirit == Spirit::Player) {
             Some(agent) => agent,
-            None => return false,
+            None => {
+                self.request = Some(Request::Exit);
+                return;
+            }
         };
 
+        #[allow(deprecated)]
         match input {
             KeyboardInput {
                 state: ElementState::Pressed,
                 virtual_keycode: Some(key),
+                ref modifiers,
                 ..
-            } => match key {
-                Key::Escape => return false,
-                Key::P => {
-                    let center = match player.physics {
-                        Physics::Cpu { ref transform, .. } => transform.clone(),
-                        Physics::Gpu { ref body, .. } => self
-                            .gpu
-                            .as_ref()
-                            .unwrap()
-                            .store
-                            .cpu_mirror()
-                            .get(body)
-                            .unwrap()
-                            .clone(),
-                    };
-                    self.tick = None;
-                    if self.is_paused {
-                        self.is_paused = false;
-                        self.cam.loc = center.disp + cgmath::vec3(0.0, 0.0, 200.0);
-                        self.cam.rot = cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0);
-                    } else {
-                        self.is_paused = true;
-                        self.cam.focus_on(&center);
+            } => match (key, self.key_map.action(key)) {
+                (_, Some(Action::Exit)) => {
+                    if let Some(ref session) = self.replay {
+                        session.finish_recording(self.player_state_hash());
                     }
+                    self.request = Some(Request::Exit);
                 }
-                Key::Comma => self.tick = Some(-1.0),
-                Key::Period => self.tick = Some(1.0),
-                Key::LShift => self.turbo = true,
-                Key::LAlt => self.jump = Some(0.0),
-                Key::W => self.spin_ver = 1.0,
-                Key::S => self.spin_ver = -1.0,
-                Key::R => {
-                    if let Physics::Cpu {
-                        ref mut transform,
-                        ref mut dynamo,
-                    } = player.physics
-                    {
-                        transform.rot = cgmath::One::one();
-                        dynamo.linear_velocity = cgmath::Vector3::zero();
-                        dynamo.angular_velocity = cgmath::Vector3::zero();
+                _ if self.tuning.on_key(key, &mut player.car) => {
+                    if let Physics::Gpu { .. } = player.physics {
+                        log::warn!("Tuning is not applied to GPU physics");
+                    }
+                }
+                _ if self.macros.contains_key(&key) => {
+                    let commands = &self.macros[&key];
+                    self.pending_commands.extend(commands.iter().cloned());
+                }
+                (_, None) if bookmarks::slot(key).is_some() => {
+                    let slot = bookmarks::slot(key).unwrap();
+                    if modifiers.ctrl() {
+                        self.bookmarks.save(slot, &self.cam);
+                    } else {
+                        self.recall_bookmark(slot);
                     }
                 }
-                Key::A => self.spin_hor = -1.0,
-                Key::D => self.spin_hor = 1.0,
-                Key::Q => {
+                (_, Some(Action::Pause)) => self.request = Some(Request::TogglePause),
+                (_, Some(Action::Debug)) => self.show_readout = !self.show_readout,
+                (_, Some(Action::Turbo)) => self.turbo = true,
+                (_, Some(Action::Jump)) => self.jump = Some(0.0),
+                (_, Some(Action::Accelerate)) => self.spin_ver = 1.0,
+                (_, Some(Action::Reverse)) => self.spin_ver = -1.0,
+                (_, Some(Action::Reset)) => self.reset_player(),
+                (_, Some(Action::Recover)) => self.recover_player(),
+                (_, Some(Action::TurnLeft)) => self.spin_hor = -1.0,
+                (_, Some(Action::TurnRight)) => self.spin_hor = 1.0,
+                (_, Some(Action::Garage)) => self.request = Some(Request::Garage),
+                (_, Some(Action::StepBack)) => self.tick = Some(-1.0),
+                (_, Some(Action::StepForward)) => self.tick = Some(1.0),
+                (_, Some(Action::RollLeft)) => {
                     self.roll = Some(Roll {
                         dir: -1.0,
                         time: 0.0,
                     })
                 }
-                Key::E => {
+                (_, Some(Action::RollRight)) => {
                     self.roll = Some(Roll {
                         dir: 1.0,
                         time: 0.0,
@@ -574,12 +1385,12 @@ impl Application for Game {
                 state: ElementState::Released,
                 virtual_keycode: Some(key),
                 ..
-            } => match key {
-                Key::W | Key::S => self.spin_ver = 0.0,
-                Key::A | Key::D => self.spin_hor = 0.0,
-                Key::Q | Key::E => self.roll = None,
-                Key::LShift => self.turbo = false,
-                Key::LAlt => player.jump = self.jump.take(),
+            } => match (key, self.key_map.action(key)) {
+                (_, Some(Action::Accelerate)) | (_, Some(Action::Reverse)) => self.spin_ver = 0.0,
+                (_, Some(Action::TurnLeft)) | (_, Some(Action::TurnRight)) => self.spin_hor = 0.0,
+                (_, Some(Action::Turbo)) => self.turbo = false,
+                (_, Some(Action::Jump)) => player.jump = self.jump.take(),
+                (_, Some(Action::RollLeft)) | (_, Some(Action::RollRight)) => self.roll = None,
                 _ => (),
             },
             /*
@@ -598,66 +1409,93 @@ impl Application for Game {
             */
             _ => {}
         }
+    }
+
+    /// Handle the input and the commands, common to every state of the game.
+    fn begin_update(&mut self, device: &wgpu::Device, delta: f32) {
+        #[cfg(feature = "gamepad")]
+        self.poll_gamepad();
+        self.render.stats.record_frame(delta);
 
-        true
+        for command in mem::replace(&mut self.pending_commands, Vec::new()) {
+            self.execute(command, device);
+        }
     }
 
-    fn update(
+    /// Update the paused game, where the player only moves by the explicit steps,
+    /// and the camera rotates around it.
+    pub fn update_paused(&mut self, device: &wgpu::Device, delta: f32) {
+        self.begin_update(device, delta);
+
+        let focus_point = self.cam.intersect_height(level::HEIGHT_SCALE as f32 * 0.3);
+        let target = self.player_transform();
+        if let Some(tick) = self.tick.take() {
+            let player = self
+                .agents
+                .iter_mut()
+                .find(|a| a.spirit == Spirit::Player)
+                .unwrap();
+            self.line_buffer.clear();
+            player.control.roll = 0.0;
+
+            player.cpu_step(
+                tick * self.max_quant,
+                &self.level,
+                &self.terrain_grid,
+                &self.db.common,
+                SimulationStep::Final {
+                    focus_point: &focus_point,
+                    debug: Some(physics::DebugDraw {
+                        lines: &mut self.line_buffer,
+                        layers: self.render.debug.settings(),
+                    }),
+                },
+            );
+        }
+
+        self.cam.rotate_focus(
+            &target,
+            cgmath::Rad(2.0 * delta * self.spin_hor),
+            cgmath::Rad(delta * self.spin_ver),
+        );
+    }
+
+    pub fn update(
         &mut self,
         device: &wgpu::Device,
         delta: f32,
         spawner: &LocalSpawner,
     ) -> Vec<wgpu::CommandBuffer> {
+        self.begin_update(device, delta);
+
+        let replay_frame = self
+            .replay
+            .as_mut()
+            .and_then(|session| session.next_frame());
+        let delta = self
+            .replay
+            .as_ref()
+            .map_or(delta, |session| session.time_step());
+
         let focus_point = self.cam.intersect_height(level::HEIGHT_SCALE as f32 * 0.3);
 
+        if let Some(ref mut weather) = self.weather {
+            weather.update(delta, focus_point.to_vec(), &self.level);
+            self.render.wetness = weather.wetness();
+        }
+
         if let Some(ref mut jump) = self.jump {
             let power = delta * (self.db.common.speed.standard_frame_rate as f32);
             *jump = (*jump + power).min(self.db.common.force.max_jump_power);
         }
 
         {
+            let target = self.player_transform();
             let player = self
                 .agents
                 .iter_mut()
                 .find(|a| a.spirit == Spirit::Player)
                 .unwrap();
-            let target = match player.physics {
-                Physics::Cpu { ref transform, .. } => transform.clone(),
-                Physics::Gpu { ref body, .. } => self
-                    .gpu
-                    .as_ref()
-                    .unwrap()
-                    .store
-                    .cpu_mirror()
-                    .get(body)
-                    .cloned()
-                    .unwrap_or(space::Transform::one()),
-            };
-
-            if self.is_paused {
-                if let Some(tick) = self.tick.take() {
-                    self.line_buffer.clear();
-                    player.control.roll = 0.0;
-
-                    player.cpu_step(
-                        tick * self.max_quant,
-                        &self.level,
-                        &self.db.common,
-                        SimulationStep::Final {
-                            focus_point: &focus_point,
-                            line_buffer: Some(&mut self.line_buffer),
-                        },
-                    );
-                }
-
-                self.cam.rotate_focus(
-                    &target,
-                    cgmath::Rad(2.0 * delta * self.spin_hor),
-                    cgmath::Rad(delta * self.spin_ver),
-                );
-
-                return Vec::new();
-            }
 
             player.control.rudder = self.spin_hor;
             player.control.motor = 1.0 * self.spin_ver;
@@ -679,6 +1517,13 @@ impl Application for Game {
                 None => 0.0,
             };
 
+            if let Some(ref frame) = replay_frame {
+                replay::apply(frame, &mut player.control, &mut player.jump);
+            }
+            if let Some(ref mut session) = self.replay {
+                session.push(replay::record(&player.control, player.jump));
+            }
+
             match self.cam_style {
                 CameraStyle::Simple(ref dir) => {
                     self.cam.look_by(&target, dir);
@@ -688,6 +1533,10 @@ impl Application for Game {
                 }
             }
         }
+        if self.wrap_seam {
+            self.wrap_world();
+        }
+        self.update_recovery(delta);
 
         const TIME_HACK: f32 = 1.0;
         // Note: the equations below make the game absolutely match the original
@@ -701,7 +1550,9 @@ impl Application for Game {
             fps * n.time_delta0 * n.num_calls_analysis as f32
         };
 
-        if let Some(ref mut gpu) = self.gpu {
+        if self.gpu.is_some() {
+            let projectile_commands = self.cast_projectiles(device, physics_dt);
+            let gpu = self.gpu.as_mut().unwrap();
             let mut prep_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Preparation"),
             });
@@ -777,7 +1628,7 @@ impl Application for Game {
             gpu.store.step(device, &mut encoder, physics_dt, ranges);
             gpu.store.produce_gpu_results(device, &mut encoder);
 
-            vec![prep_encoder.finish(), encoder.finish()]
+            vec![prep_encoder.finish(), encoder.finish(), projectile_commands]
         } else {
             use rayon::prelude::*;
 
@@ -785,6 +1636,33 @@ impl Application for Game {
             let max_quant = self.max_quant;
             let common = &self.db.common;
             let level = &self.level;
+            let grid = &self.terrain_grid;
+
+            // wake up the sleeping bodies touched by the awake ones
+            let wrap = cgmath::vec2(level.size.0 as f32, (level.size.1 >> 1) as f32);
+            let boxes = bounding_boxes(&self.agents);
+            for (i, j) in physics::find_pairs(&boxes, wrap) {
+                let asleep = |a: &Agent| match a.physics {
+                    Physics::Cpu { ref dynamo, .. } => dynamo.sleep.is_asleep(),
+                    Physics::Gpu { .. } => false,
+                };
+                let (first, second) = (asleep(&self.agents[i]), asleep(&self.agents[j]));
+                if first != second {
+                    let index = if first { i } else { j };
+                    if let Physics::Cpu { ref mut dynamo, .. } = self.agents[index].physics {
+                        dynamo.sleep.wake();
+                    }
+                }
+            }
+
+            // only the player is drawn by the physics debug layers
+            let layers = self.render.debug.settings();
+            self.line_buffer.clear();
+            let player_lines = Mutex::new(if layers.draws_lines() {
+                Some(&mut self.line_buffer)
+            } else {
+                None
+            });
 
             self.agents.par_iter_mut().for_each(|a| {
                 let mut dt = physics_dt;
@@ -793,7 +1671,7 @@ impl Application for Game {
                 // only go through the full iteration on visible objects
                 if !clipper.clip(&a.position()) {
                     while dt > max_quant {
-                        a.cpu_step(max_quant, level, common, SimulationStep::Intermediate);
+                        a.cpu_step(max_quant, level, grid, common, SimulationStep::Intermediate);
                         dt -= max_quant;
                     }
                 }
@@ -801,49 +1679,128 @@ impl Application for Game {
                 a.cpu_step(
                     dt,
                     level,
+                    grid,
                     common,
                     SimulationStep::Final {
                         focus_point: &focus_point,
-                        line_buffer: None,
+                        debug: match a.spirit {
+                            Spirit::Player => player_lines
+                                .lock()
+                                .unwrap()
+                                .take()
+                                .map(|lines| physics::DebugDraw { lines, layers }),
+                            Spirit::Other(_) => None,
+                        },
                     },
                 );
 
                 a.ai_behavior(delta);
             });
+            drop(player_lines);
+
+            if layers.sleeping {
+                for agent in self.agents.iter() {
+                    if let Physics::Cpu {
+                        ref transform,
+                        ref dynamo,
+                    } = agent.physics
+                    {
+                        // Blue: sleeping bodies, green: awake ones
+                        let color = if dynamo.sleep.is_asleep() {
+                            0x0000FF00
+                        } else {
+                            0x00FF0000
+                        };
+                        let top = transform.disp + cgmath::vec3(0.0, 0.0, 20.0);
+                        self.line_buffer
+                            .add(transform.disp.into(), top.into(), color);
+                    }
+                }
+            }
+
+            for agent in self.agents.iter() {
+                if let Physics::Cpu {
+                    ref transform,
+                    ref dynamo,
+                } = agent.physics
+                {
+                    if let Some(terrain) = dynamo.surface {
+                        self.audio.emit(audio::Event {
+                            material: audio::Material::from_terrain(terrain),
+                            speed: dynamo.linear_velocity.magnitude(),
+                            position: transform.disp,
+                        });
+                    }
+                }
+            }
+            self.collide_vehicles();
+            self.update_projectiles(physics_dt);
+            self.update_passages();
+            self.audio.flush(self.cam.loc);
 
             Vec::new()
         }
     }
 
-    fn resize(&mut self, device: &wgpu::Device, extent: wgpu::Extent3d) {
+    pub fn resize(&mut self, device: &wgpu::Device, extent: wgpu::Extent3d) {
         self.cam
             .proj
             .update(extent.width as u16, extent.height as u16);
         self.render.resize(extent, device);
     }
 
-    fn reload(&mut self, device: &wgpu::Device) {
+    pub fn set_ui_scale(&mut self, scale: f32) {
+        self.render.set_ui_scale(scale);
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device) {
+        // pick up the tweaked shader defines
+        match config::Settings::try_load(SETTINGS_PATH) {
+            Ok(settings) => self
+                .render
+                .terrain
+                .set_defines(&settings.render.terrain_defines, device),
+            Err(e) => log::error!("Keeping the old settings: {}", e),
+        }
         self.render.reload(device);
+        if let Some(ref mut weather) = self.weather {
+            weather.reload(self.render.global(), device);
+        }
         if let Some(Gpu {
             ref mut store,
             ref mut collider,
-            ..
+            ref mut caster,
         }) = self.gpu
         {
             store.reload(device);
             collider.reload(device);
+            caster.reload(device);
         }
     }
 
-    fn draw(
+    pub fn destroy(self) {
+        self.render.destroy();
+    }
+
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if let Some(crossing) = self.crossing.take() {
+            self.cross(crossing, device, queue);
+        }
+    }
+
+    /// Draw the world. The player is drawn with the given transform instead of its own, if any.
+    pub fn draw(
         &mut self,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         targets: ScreenTargets,
         spawner: &LocalSpawner,
+        player: Option<space::Transform>,
     ) -> wgpu::CommandBuffer {
         if let Some(ref mut gpu) = self.gpu {
             //Note: we rely on the fact that updates where submitted separately
             gpu.store.consume_gpu_results(spawner);
+            gpu.caster.consume_results(spawner);
         }
 
         let identity_transform = space::Transform::one();
@@ -856,9 +1813,13 @@ impl Application for Game {
                     if clipper.clip(&transform.disp) {
                         continue;
                     }
+                    let transform = match player {
+                        Some(player) if agent.spirit == Spirit::Player => player,
+                        _ => *transform,
+                    };
                     (&GpuBody::ZERO, transform)
                 }
-                Physics::Gpu { ref body, .. } => (body, &identity_transform),
+                Physics::Gpu { ref body, .. } => (body, identity_transform),
             };
             let debug_shape_scale = match agent.spirit {
                 Spirit::Player => Some(agent.car.physics.scale_bound),
@@ -872,21 +1833,118 @@ impl Application for Game {
                 agent.color,
             );
         }
+        if let Some(player) = self.agents.first() {
+            self.props
+                .draw(&mut self.batcher, player.position(), |pos| {
+                    clipper.clip(pos)
+                });
+        }
 
+        let encode_start = time::Instant::now();
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Draw"),
         });
 
-        self.render
-            .draw_world(&mut encoder, &mut self.batcher, &self.cam, targets, device);
-
-        /*
-        self.render.debug.draw_lines(
+        let mut passes = match self.weather {
+            Some(ref mut weather) => vec![weather as &mut dyn CustomPass],
+            None => Vec::new(),
+        };
+        self.render.draw_world_with(
+            &mut encoder,
+            &mut self.batcher,
+            &self.cam,
+            targets,
+            device,
+            queue,
             &self.line_buffer,
-            self.cam.get_view_proj().into(),
-            encoder,
-        );*/
+            &mut passes,
+        );
+        self.render.stats.record_encode(encode_start.elapsed());
+        self.render.draw_stats(&mut encoder, targets, device, queue);
 
         encoder.finish()
     }
 }
+
+/// Driving state of the game, at the bottom of the stack of its states.
+pub struct Driving {
+    game: Rc<RefCell<Game>>,
+}
+
+impl Driving {
+    pub fn new(game: Game) -> Self {
+        Driving {
+            game: Rc::new(RefCell::new(game)),
+        }
+    }
+}
+
+impl Application for Driving {
+    fn status(&self) -> Option<String> {
+        self.game.borrow().status()
+    }
+
+    fn on_key(&mut self, input: winit::event::KeyboardInput) -> Transition {
+        self.game.borrow_mut().on_key(input);
+        self.transition()
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, extent: wgpu::Extent3d) {
+        self.game.borrow_mut().resize(device, extent);
+    }
+
+    fn set_ui_scale(&mut self, scale: f32) {
+        self.game.borrow_mut().set_ui_scale(scale);
+    }
+
+    fn reload(&mut self, device: &wgpu::Device) {
+        self.game.borrow_mut().reload(device);
+    }
+
+    fn destroy(self: Box<Self>) {
+        // the other states of the game are removed before this one
+        if let Ok(game) = Rc::try_unwrap(self.game) {
+            game.into_inner().destroy();
+        }
+    }
+
+    fn transition(&mut self) -> Transition {
+        let request = self.game.borrow_mut().take_request();
+        match request {
+            None => Transition::None,
+            Some(Request::TogglePause) => {
+                Transition::Push(Box::new(pause::Paused::new(&self.game, true)))
+            }
+            Some(Request::Pause) => {
+                Transition::Push(Box::new(pause::Paused::new(&self.game, false)))
+            }
+            Some(Request::Garage) => garage::Garage::enter(&self.game),
+            Some(Request::Exit) => Transition::Pop,
+        }
+    }
+
+    fn update(
+        &mut self,
+        device: &wgpu::Device,
+        delta: f32,
+        spawner: &LocalSpawner,
+    ) -> Vec<wgpu::CommandBuffer> {
+        self.game.borrow_mut().update(device, delta, spawner)
+    }
+
+    fn prepare(&mut self, screen: &mut Screen) {
+        self.game.borrow_mut().prepare(screen.device, screen.queue);
+    }
+
+    fn draw(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        targets: ScreenTargets,
+        spawner: &LocalSpawner,
+    ) -> wgpu::CommandBuffer {
+        self.game
+            .borrow_mut()
+            .draw(device, queue, targets, spawner, None)
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{
+    boilerplate::{Application, Transition},
+    game::Game,
+};
+use vangers::{render::ScreenTargets, space};
+
+use cgmath::prelude::*;
+use futures::executor::LocalSpawner;
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode as Key};
+
+use std::{cell::RefCell, f32::consts::PI, rc::Rc};
+
+/// Angular speed of the pedestal, in radians per second.
+const SPIN_SPEED: f32 = 0.5 * PI;
+
+/// Vehicle selection mode, on top of the other states of the game.
+///
+/// `Left`/`Right` cycle through the mechouses, `Return` picks one,
+/// and `Escape` goes back to the original one.
+/// The picked car is saved into the settings.
+pub struct Garage {
+    game: Rc<RefCell<Game>>,
+    cars: Vec<String>,
+    selected: usize,
+    angle: cgmath::Rad<f32>,
+}
+
+impl Garage {
+    /// Enter the garage, unless there are no cars to choose from.
+    pub fn enter(game: &Rc<RefCell<Game>>) -> Transition {
+        let (mut cars, current) = {
+            let game = game.borrow();
+            (game.car_ids(), game.car_id().to_string())
+        };
+        if cars.is_empty() {
+            log::warn!("No cars to choose from in the garage");
+            return Transition::None;
+        }
+        cars.sort();
+        let selected = cars.iter().position(|id| *id == current).unwrap_or(0);
+        Transition::Push(Box::new(Garage {
+            game: Rc::clone(game),
+            cars,
+            selected,
+            angle: cgmath::Rad(0.0),
+        }))
+    }
+
+    fn selected(&self) -> &str {
+        &self.cars[self.selected]
+    }
+
+    /// Transform of the car standing on the rotating pedestal.
+    fn pedestal(&self, base: &space::Transform) -> space::Transform {
+        cgmath::Decomposed {
+            disp: base.disp,
+            rot: cgmath::Quaternion::from_angle_z(self.angle),
+            scale: base.scale,
+        }
+    }
+}
+
+impl Application for Garage {
+    fn status(&self) -> Option<String> {
+        Some(format!(
+            "Garage: {} ({}/{}), Left/Right to browse, Return to pick",
+            self.selected(),
+            self.selected + 1,
+            self.cars.len()
+        ))
+    }
+
+    fn on_key(&mut self, input: KeyboardInput) -> Transition {
+        let key = match input {
+            KeyboardInput {
+                state: ElementState::Pressed,
+                virtual_keycode: Some(key),
+                ..
+            } => key,
+            _ => return Transition::None,
+        };
+        let count = self.cars.len();
+        match key {
+            Key::Left => {
+                self.selected = (self.selected + count - 1) % count;
+                self.game.borrow_mut().change_player_car(self.selected());
+            }
+            Key::Right => {
+                self.selected = (self.selected + 1) % count;
+                self.game.borrow_mut().change_player_car(self.selected());
+            }
+            Key::Return => {
+                self.game.borrow_mut().pick_car(self.selected());
+                return Transition::Pop;
+            }
+            Key::Escape => {
+                self.game.borrow_mut().restore_car();
+                return Transition::Pop;
+            }
+            _ => {}
+        }
+        Transition::None
+    }
+
+    // the game is reloaded by the driving state below
+    fn reload(&mut self, _device: &wgpu::Device) {}
+
+    fn update(
+        &mut self,
+        _device: &wgpu::Device,
+        delta: f32,
+        _spawner: &LocalSpawner,
+    ) -> Vec<wgpu::CommandBuffer> {
+        self.angle = (self.angle + cgmath::Rad(SPIN_SPEED * delta)).normalize();
+        self.game.borrow_mut().focus_on_player();
+        Vec::new()
+    }
+
+    fn draw(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        targets: ScreenTargets,
+        spawner: &LocalSpawner,
+    ) -> wgpu::CommandBuffer {
+        let mut game = self.game.borrow_mut();
+        let pedestal = self.pedestal(&game.player_transform());
+        game.draw(device, queue, targets, spawner, Some(pedestal))
+    }
+}
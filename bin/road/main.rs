@@ -1,21 +1,45 @@
 use log::info;
 
+mod audio;
 #[path = "../boilerplate.rs"]
 mod boilerplate;
+mod bookmarks;
+mod command;
 mod game;
+mod garage;
+#[path = "../input/mod.rs"]
+mod input;
+mod passage;
+mod pause;
 mod physics;
+mod props;
+mod readout;
+mod replay;
+mod tuning;
 
 fn main() {
     use std::env;
 
-    let (mut harness, settings) = boilerplate::Harness::init("road");
+    let (harness, settings) = boilerplate::Harness::init("road");
 
     info!("Parsing command line");
     let args: Vec<_> = env::args().collect();
     let mut options = getopts::Options::new();
     options
         .parsing_style(getopts::ParsingStyle::StopAtFirstFree)
-        .optflag("h", "help", "print this help menu");
+        .optflag("h", "help", "print this help menu")
+        .optopt(
+            "",
+            "record",
+            "record the player input into a replay",
+            "FILE",
+        )
+        .optopt(
+            "",
+            "replay",
+            "play a recorded replay and check the golden state",
+            "FILE",
+        );
 
     let matches = options.parse(&args[1..]).unwrap();
     if matches.opt_present("h") || !matches.free.is_empty() {
@@ -25,12 +49,51 @@ fn main() {
         return;
     }
 
-    let game = game::Game::new(
-        &settings,
-        harness.extent,
-        &harness.device,
-        &mut harness.queue,
-    );
+    let (extent, color_format) = (harness.extent, harness.color_format);
+    let telemetry = harness.telemetry();
 
-    harness.main_loop(game);
+    if let Some(path) = matches.opt_str("replay") {
+        // the replay is simulated without running the window loop, as fast as possible
+        let mut game = game::Game::new(
+            &settings,
+            extent,
+            color_format,
+            &harness.device,
+            &harness.queue,
+            &*telemetry,
+            &mut |stage, progress| info!("Loading {} ({:.0}%)", stage, progress * 100.0),
+        );
+        let result = replay::Session::play(path.as_ref()).and_then(|session| {
+            game.start_replay(session);
+            game.play_replay(&harness.device)
+        });
+        match result {
+            Ok(()) => info!("Replay matches the golden"),
+            Err(e) => {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let record = matches.opt_str("record");
+    let loading = harness.loading(&settings.window);
+    let loading = loading.create(Box::new(move |device, queue, progress| {
+        let mut game = game::Game::new(
+            &settings,
+            extent,
+            color_format,
+            device,
+            queue,
+            &*telemetry,
+            progress,
+        );
+        if let Some(path) = record {
+            game.start_replay(replay::Session::record(path.as_ref()));
+        }
+        Box::new(game::Driving::new(game))
+    }));
+
+    harness.main_loop(loading);
 }
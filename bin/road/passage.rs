@@ -0,0 +1,207 @@
+//! Passages between the levels.
+//!
+//! Approaching a passage starts streaming the destination level in the background,
+//! and entering it switches the levels. The level that was left stays resident,
+//! so going back through the opposite passage doesn't load anything.
+
+use crate::{game::Agent, props::Props};
+use vangers::{
+    config::{self, settings},
+    level,
+    render::weather::Weather,
+};
+
+use cgmath::prelude::*;
+
+use std::path::PathBuf;
+
+/// Number of the level rows loaded at once in the background.
+const PRELOAD_CHUNK_ROWS: i32 = 256;
+/// Distance around the exit of a passage that is loaded ahead of the rest, in texels.
+const EXIT_PREWARM_RADIUS: f32 = 128.0;
+
+struct Preload {
+    passage: usize,
+    level: level::Level,
+    stream: level::LevelStream,
+}
+
+/// Level that is not active, but kept in memory.
+pub struct Resident {
+    pub name: String,
+    pub level: level::Level,
+    /// Vehicles other than the player, frozen until the level is back.
+    pub agents: Vec<Agent>,
+    pub props: Props,
+    pub weather: Option<Weather>,
+}
+
+pub enum Destination {
+    /// Level that has just been loaded.
+    Loaded(level::Level),
+    /// Level that was left before.
+    Resident(Resident),
+}
+
+/// Transition of the player into another level.
+pub struct Crossing {
+    pub name: String,
+    pub destination: Destination,
+    /// Where the vehicle comes out, in texels.
+    pub exit: (i32, i32),
+}
+
+pub struct Passages {
+    data_path: PathBuf,
+    worlds: config::worlds::Worlds,
+    passages: Vec<settings::Passage>,
+    preload: Option<Preload>,
+    /// Passage whose destination has failed to load,
+    /// not retried until the player gets away from it.
+    failed: Option<usize>,
+    resident: Option<Resident>,
+    /// False after a crossing, until the player gets out of the passage radius,
+    /// so that coming out next to the opposite passage doesn't bounce back.
+    armed: bool,
+}
+
+impl Passages {
+    pub fn new(settings: &config::Settings) -> Self {
+        let worlds = config::worlds::load(settings.open_relative("wrlds.dat"));
+        let passages = settings
+            .game
+            .passages
+            .iter()
+            .filter(|p| {
+                let known = worlds.contains_key(&p.destination);
+                if !known {
+                    log::warn!("Unknown passage destination '{}'", p.destination);
+                }
+                known
+            })
+            .cloned()
+            .collect();
+        Passages {
+            data_path: settings.data_path.clone(),
+            worlds,
+            passages,
+            preload: None,
+            failed: None,
+            resident: None,
+            armed: true,
+        }
+    }
+
+    fn start_preload(&self, index: usize) -> Result<Preload, String> {
+        let ini_name = &self.worlds[&self.passages[index].destination];
+        log::info!("Preloading level {}", ini_name);
+        let config = level::LevelConfig::load(&self.data_path.join(ini_name));
+        let (level, stream) = level::LevelStream::start(&config, PRELOAD_CHUNK_ROWS)?;
+        let (x, y) = self.passages[index].exit;
+        stream.prewarm(&[cgmath::vec2(x as f32, y as f32)], EXIT_PREWARM_RADIUS);
+        Ok(Preload {
+            passage: index,
+            level,
+            stream,
+        })
+    }
+
+    /// Follow the player `position` in the `current` level, preloading the destination
+    /// of the passage nearby. Returns the crossing once the player is in the passage,
+    /// and the destination is ready.
+    pub fn update(
+        &mut self,
+        current: &str,
+        position: cgmath::Vector3<f32>,
+        wrap: cgmath::Vector2<f32>,
+    ) -> Option<Crossing> {
+        let nearby = self
+            .passages
+            .iter()
+            .enumerate()
+            .filter(|&(_, p)| p.level == current)
+            .map(|(i, p)| {
+                let offset =
+                    cgmath::vec2(p.position.0 as f32, p.position.1 as f32) - position.truncate();
+                let wrapped = cgmath::vec2(
+                    (offset.x + 0.5 * wrap.x).rem_euclid(wrap.x) - 0.5 * wrap.x,
+                    (offset.y + 0.5 * wrap.y).rem_euclid(wrap.y) - 0.5 * wrap.y,
+                );
+                (i, wrapped.magnitude())
+            })
+            .filter(|&(i, distance)| distance < self.passages[i].preload_radius)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+        let (index, distance) = match nearby {
+            Some(nearby) => nearby,
+            None => {
+                // the receiver is dropped, which stops the loading thread
+                if self.preload.take().is_some() {
+                    log::info!("Cancelled preloading");
+                }
+                self.failed = None;
+                self.armed = true;
+                return None;
+            }
+        };
+
+        let passage = &self.passages[index];
+        let is_resident = self
+            .resident
+            .as_ref()
+            .map_or(false, |r| r.name == passage.destination);
+        if !is_resident
+            && self.failed != Some(index)
+            && self.preload.as_ref().map_or(true, |p| p.passage != index)
+        {
+            self.preload = match self.start_preload(index) {
+                Ok(preload) => Some(preload),
+                Err(e) => {
+                    log::error!("{}", e);
+                    self.failed = Some(index);
+                    None
+                }
+            };
+        }
+        if let Some(ref mut preload) = self.preload {
+            preload.stream.poll(&mut preload.level);
+            if let Err(e) = preload.stream.is_done() {
+                log::error!("{}", e);
+                self.failed = Some(preload.passage);
+                self.preload = None;
+            }
+        }
+
+        if distance >= passage.radius {
+            self.armed = true;
+            return None;
+        }
+        if !self.armed {
+            return None;
+        }
+        let destination = if is_resident {
+            Destination::Resident(self.resident.take().unwrap())
+        } else {
+            match self.preload.take() {
+                Some(preload) if preload.stream.is_done() == Ok(true) => {
+                    Destination::Loaded(preload.level)
+                }
+                other => {
+                    self.preload = other;
+                    return None;
+                }
+            }
+        };
+        self.armed = false;
+        Some(Crossing {
+            name: passage.destination.clone(),
+            destination,
+            exit: passage.exit,
+        })
+    }
+
+    /// Keep the level that was left, replacing the one kept before.
+    pub fn leave(&mut self, resident: Resident) {
+        self.resident = Some(resident);
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{
+    boilerplate::{Application, Transition},
+    game::{Game, Request},
+    garage,
+};
+use vangers::render::ScreenTargets;
+
+use futures::executor::LocalSpawner;
+
+use std::{cell::RefCell, rc::Rc};
+
+/// Paused game, on top of the driving state.
+///
+/// The player only moves by the explicit steps back and forth,
+/// and the camera rotates around it.
+pub struct Paused {
+    game: Rc<RefCell<Game>>,
+}
+
+impl Paused {
+    /// Pause the game, turning the camera to the player if `focus` is set.
+    pub fn new(game: &Rc<RefCell<Game>>, focus: bool) -> Self {
+        game.borrow_mut().pause(focus);
+        Paused {
+            game: Rc::clone(game),
+        }
+    }
+}
+
+impl Application for Paused {
+    fn status(&self) -> Option<String> {
+        Some(match self.game.borrow().status() {
+            Some(status) => format!("Paused | {}", status),
+            None => "Paused".to_string(),
+        })
+    }
+
+    fn on_key(&mut self, input: winit::event::KeyboardInput) -> Transition {
+        self.game.borrow_mut().on_key(input);
+        self.transition()
+    }
+
+    // the game is reloaded by the driving state below
+    fn reload(&mut self, _device: &wgpu::Device) {}
+
+    fn transition(&mut self) -> Transition {
+        let request = self.game.borrow_mut().take_request();
+        match request {
+            None | Some(Request::Pause) => Transition::None,
+            Some(Request::TogglePause) => {
+                self.game.borrow_mut().resume();
+                Transition::Pop
+            }
+            Some(Request::Garage) => garage::Garage::enter(&self.game),
+            Some(Request::Exit) => Transition::Pop,
+        }
+    }
+
+    fn update(
+        &mut self,
+        device: &wgpu::Device,
+        delta: f32,
+        _spawner: &LocalSpawner,
+    ) -> Vec<wgpu::CommandBuffer> {
+        self.game.borrow_mut().update_paused(device, delta);
+        Vec::new()
+    }
+
+    fn draw(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        targets: ScreenTargets,
+        spawner: &LocalSpawner,
+    ) -> wgpu::CommandBuffer {
+        self.game
+            .borrow_mut()
+            .draw(device, queue, targets, spawner, None)
+    }
+}
@@ -0,0 +1,159 @@
+use cgmath::prelude::*;
+
+/// Axis-aligned bounding box in world space.
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub center: cgmath::Vector3<f32>,
+    pub half_size: cgmath::Vector3<f32>,
+}
+
+impl Aabb {
+    /// Conservative box around an object with the given bounding radius.
+    pub fn from_sphere(center: cgmath::Vector3<f32>, radius: f32) -> Self {
+        Aabb {
+            center,
+            half_size: cgmath::vec3(radius, radius, radius),
+        }
+    }
+}
+
+/// Wrap the offset into the range of `[-period/2, period/2)`.
+fn wrap_offset(offset: f32, period: f32) -> f32 {
+    (offset + 0.5 * period).rem_euclid(period) - 0.5 * period
+}
+
+/// Check if two boxes overlap on a level that wraps around in X and Y.
+pub fn overlaps(a: &Aabb, b: &Aabb, wrap: cgmath::Vector2<f32>) -> bool {
+    let offset = b.center - a.center;
+    let distance = cgmath::vec3(
+        wrap_offset(offset.x, wrap.x).abs(),
+        wrap_offset(offset.y, wrap.y).abs(),
+        offset.z.abs(),
+    );
+    let reach = a.half_size + b.half_size;
+    distance.x <= reach.x && distance.y <= reach.y && distance.z <= reach.z
+}
+
+/// Find all pairs of overlapping boxes with sweep and prune along X.
+/// The returned pairs are sorted, with the lower index first.
+pub fn find_pairs(boxes: &[Aabb], wrap: cgmath::Vector2<f32>) -> Vec<(usize, usize)> {
+    // X intervals, with the start wrapped into `[0, wrap.x)`
+    let mut intervals = boxes
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            let start = (b.center.x - b.half_size.x).rem_euclid(wrap.x);
+            (start, start + 2.0 * b.half_size.x, i)
+        })
+        .collect::<Vec<_>>();
+    intervals.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    let mut pairs = Vec::new();
+    for (k, &(_, end, i)) in intervals.iter().enumerate() {
+        let candidates = intervals[k + 1..]
+            .iter()
+            .take_while(|&&(start, _, _)| start <= end);
+        // intervals crossing the seam also reach the beginning of the list
+        let wrapped = intervals[..k]
+            .iter()
+            .take_while(|&&(start, _, _)| start + wrap.x <= end);
+        for &(_, _, j) in candidates.chain(wrapped) {
+            if overlaps(&boxes[i], &boxes[j], wrap) {
+                pairs.push((i.min(j), i.max(j)));
+            }
+        }
+    }
+    pairs.sort();
+    pairs.dedup();
+    pairs
+}
+
+/// Tracks the time a body spends at rest, to put it asleep.
+#[derive(Clone, Debug, Default)]
+pub struct Sleep {
+    rest_time: f32,
+}
+
+/// Velocity below which a body is considered resting.
+const SLEEP_LINEAR_VELOCITY: f32 = 0.5;
+const SLEEP_ANGULAR_VELOCITY: f32 = 0.05;
+/// Time a body has to rest before falling asleep.
+const SLEEP_DELAY: f32 = 1.0;
+
+impl Sleep {
+    pub fn is_asleep(&self) -> bool {
+        self.rest_time >= SLEEP_DELAY
+    }
+
+    pub fn wake(&mut self) {
+        self.rest_time = 0.0;
+    }
+
+    /// Account for the velocities after a step, returning true if asleep.
+    pub fn update(
+        &mut self,
+        linear_velocity: cgmath::Vector3<f32>,
+        angular_velocity: cgmath::Vector3<f32>,
+        dt: f32,
+    ) -> bool {
+        if linear_velocity.magnitude2() < SLEEP_LINEAR_VELOCITY * SLEEP_LINEAR_VELOCITY
+            && angular_velocity.magnitude2() < SLEEP_ANGULAR_VELOCITY * SLEEP_ANGULAR_VELOCITY
+        {
+            self.rest_time += dt;
+        } else {
+            self.rest_time = 0.0;
+        }
+        self.is_asleep()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WRAP: cgmath::Vector2<f32> = cgmath::Vector2 { x: 256.0, y: 256.0 };
+
+    fn cube(x: f32, y: f32, z: f32) -> Aabb {
+        Aabb::from_sphere(cgmath::vec3(x, y, z), 2.0)
+    }
+
+    #[test]
+    fn far_apart_boxes_have_no_pairs() {
+        let boxes = [
+            cube(10.0, 10.0, 0.0),
+            cube(100.0, 10.0, 0.0),
+            cube(10.0, 100.0, 0.0),
+            cube(10.0, 10.0, 50.0),
+        ];
+        assert_eq!(find_pairs(&boxes, WRAP), Vec::new());
+    }
+
+    #[test]
+    fn overlapping_boxes_are_paired_once() {
+        let boxes = [
+            cube(13.0, 10.0, 0.0),
+            cube(100.0, 10.0, 0.0),
+            cube(10.0, 10.0, 0.0),
+            cube(10.0, 13.0, 1.0),
+        ];
+        assert_eq!(find_pairs(&boxes, WRAP), vec![(0, 2), (0, 3), (2, 3)]);
+    }
+
+    #[test]
+    fn boxes_are_paired_across_the_seam() {
+        let boxes = [cube(255.0, 10.0, 0.0), cube(1.0, 10.0, 0.0)];
+        assert_eq!(find_pairs(&boxes, WRAP), vec![(0, 1)]);
+        let boxes = [cube(10.0, 255.0, 0.0), cube(10.0, 1.0, 0.0)];
+        assert_eq!(find_pairs(&boxes, WRAP), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn resting_bodies_fall_asleep() {
+        let mut sleep = Sleep::default();
+        let still = cgmath::Vector3::zero();
+        assert!(!sleep.update(still, still, 0.5));
+        assert!(sleep.update(still, still, 0.5));
+        assert!(!sleep.update(cgmath::vec3(1.0, 0.0, 0.0), still, 0.5));
+        assert!(!sleep.is_asleep());
+    }
+}
@@ -0,0 +1,352 @@
+//! Narrow phase between the vehicles, based on GJK for the intersection test
+//! and EPA for the penetration depth.
+
+use vangers::{model, space};
+
+use cgmath::prelude::*;
+
+type Vector = cgmath::Vector3<f32>;
+
+const MAX_GJK_ITERATIONS: usize = 32;
+const MAX_EPA_ITERATIONS: usize = 64;
+/// Precision of the penetration depth found by EPA.
+const EPA_TOLERANCE: f32 = 1.0e-3;
+
+/// Convex hull of a collision shape in the model space.
+///
+/// GJK only needs the support mapping, and the support of a point set
+/// is the same as the support of its hull, so the points are kept as is.
+pub struct ConvexHull {
+    points: Vec<Vector>,
+}
+
+impl ConvexHull {
+    /// Build the hull from the collision samples of the shape,
+    /// or from the polygon centers if there are no samples.
+    pub fn from_shape(shape: &model::Shape, scale: f32) -> Self {
+        let points = if shape.samples.is_empty() {
+            shape
+                .polygons
+                .iter()
+                .map(|p| Vector::from(p.middle))
+                .collect::<Vec<_>>()
+        } else {
+            shape
+                .samples
+                .iter()
+                .map(|s| Vector::from(*s).cast::<f32>().unwrap())
+                .collect::<Vec<_>>()
+        };
+        Self::from_points(points.into_iter().map(|p| p * scale).collect())
+    }
+
+    pub fn from_points(mut points: Vec<Vector>) -> Self {
+        points.sort_by(|a, b| {
+            let (a, b): ([f32; 3], [f32; 3]) = ((*a).into(), (*b).into());
+            a.partial_cmp(&b).unwrap()
+        });
+        points.dedup();
+        ConvexHull { points }
+    }
+
+    /// The farthest point of the hull placed at `transform` in the world direction `dir`.
+    pub fn support(&self, transform: &space::Transform, dir: Vector) -> Vector {
+        let local_dir = transform.rot.invert() * dir;
+        let best = self
+            .points
+            .iter()
+            .max_by(|a, b| a.dot(local_dir).partial_cmp(&b.dot(local_dir)).unwrap())
+            .cloned()
+            .unwrap_or_else(Vector::zero);
+        transform.disp + transform.rot * (best * transform.scale)
+    }
+}
+
+/// Intersection of two hulls.
+#[derive(Debug)]
+pub struct Penetration {
+    /// Direction from the first hull into the second one.
+    pub normal: Vector,
+    pub depth: f32,
+    /// World position between the deepest points of both hulls.
+    pub point: Vector,
+}
+
+fn same_direction(a: Vector, b: Vector) -> bool {
+    a.dot(b) > 0.0
+}
+
+/// Any vector orthogonal to the given one.
+fn orthogonal(v: Vector) -> Vector {
+    let axis = if v.x.abs() < 0.5 {
+        Vector::unit_x()
+    } else {
+        Vector::unit_y()
+    };
+    v.cross(axis)
+}
+
+// The simplex functions keep the newest point first.
+
+fn line(simplex: &mut Vec<Vector>, dir: &mut Vector) {
+    let (a, b) = (simplex[0], simplex[1]);
+    let (ab, ao) = (b - a, -a);
+    if same_direction(ab, ao) {
+        *dir = ab.cross(ao).cross(ab);
+        if dir.magnitude2() == 0.0 {
+            // the origin is on the segment
+            *dir = orthogonal(ab);
+        }
+    } else {
+        simplex.truncate(1);
+        *dir = ao;
+    }
+}
+
+fn triangle(simplex: &mut Vec<Vector>, dir: &mut Vector) {
+    let (a, b, c) = (simplex[0], simplex[1], simplex[2]);
+    let (ab, ac, ao) = (b - a, c - a, -a);
+    let abc = ab.cross(ac);
+    if same_direction(abc.cross(ac), ao) {
+        if same_direction(ac, ao) {
+            *simplex = vec![a, c];
+            *dir = ac.cross(ao).cross(ac);
+        } else {
+            *simplex = vec![a, b];
+            line(simplex, dir);
+        }
+    } else if same_direction(ab.cross(abc), ao) {
+        *simplex = vec![a, b];
+        line(simplex, dir);
+    } else if same_direction(abc, ao) {
+        *dir = abc;
+    } else {
+        *simplex = vec![a, c, b];
+        *dir = -abc;
+    }
+}
+
+/// Returns true if the tetrahedron encloses the origin,
+/// otherwise reduces it to the face closest to the origin.
+fn tetrahedron(simplex: &mut Vec<Vector>, dir: &mut Vector) -> bool {
+    let (a, b, c, d) = (simplex[0], simplex[1], simplex[2], simplex[3]);
+    let (ab, ac, ad, ao) = (b - a, c - a, d - a, -a);
+    let face = if same_direction(ab.cross(ac), ao) {
+        [a, b, c]
+    } else if same_direction(ac.cross(ad), ao) {
+        [a, c, d]
+    } else if same_direction(ad.cross(ab), ao) {
+        [a, d, b]
+    } else {
+        return true;
+    };
+    *simplex = face.to_vec();
+    triangle(simplex, dir);
+    false
+}
+
+/// Check if the Minkowski difference given by the `support` function contains the origin,
+/// returning the enclosing tetrahedron if it does.
+fn gjk(support: &impl Fn(Vector) -> Vector, initial_dir: Vector) -> Option<Vec<Vector>> {
+    let first = support(if initial_dir.magnitude2() > 0.0 {
+        initial_dir
+    } else {
+        Vector::unit_x()
+    });
+    let mut simplex = vec![first];
+    let mut dir = -first;
+    for _ in 0..MAX_GJK_ITERATIONS {
+        if dir.magnitude2() == 0.0 {
+            // touching, not penetrating
+            return None;
+        }
+        let p = support(dir);
+        if !same_direction(p, dir) {
+            return None;
+        }
+        simplex.insert(0, p);
+        match simplex.len() {
+            2 => line(&mut simplex, &mut dir),
+            3 => triangle(&mut simplex, &mut dir),
+            _ => {
+                if tetrahedron(&mut simplex, &mut dir) {
+                    return Some(simplex);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Outward normals of the faces together with their distances to the origin,
+/// and the index of the closest face.
+fn face_normals(polytope: &[Vector], faces: &[usize]) -> (Vec<(Vector, f32)>, usize) {
+    let mut normals = Vec::with_capacity(faces.len() / 3);
+    let mut closest = 0;
+    for (i, face) in faces.chunks(3).enumerate() {
+        let (a, b, c) = (polytope[face[0]], polytope[face[1]], polytope[face[2]]);
+        let cross = (b - a).cross(c - a);
+        let (mut normal, mut distance) = if cross.magnitude2() > 0.0 {
+            let n = cross.normalize();
+            (n, n.dot(a))
+        } else {
+            // degenerate face, never picked as the closest
+            (Vector::unit_z(), std::f32::MAX)
+        };
+        if distance < 0.0 {
+            normal = -normal;
+            distance = -distance;
+        }
+        if distance < normals.get(closest).map_or(std::f32::MAX, |&(_, d)| d) {
+            closest = i;
+        }
+        normals.push((normal, distance));
+    }
+    (normals, closest)
+}
+
+/// Add the edge to the horizon, or remove it if it's shared with another removed face.
+fn add_unique_edge(edges: &mut Vec<(usize, usize)>, a: usize, b: usize) {
+    match edges.iter().position(|&e| e == (b, a)) {
+        Some(index) => {
+            edges.remove(index);
+        }
+        None => edges.push((a, b)),
+    }
+}
+
+/// Expand the tetrahedron found by GJK until the closest face of the Minkowski
+/// difference to the origin is found, returning its normal and distance.
+/// Returns `None` if the polytope is degenerate.
+fn epa(support: &impl Fn(Vector) -> Vector, simplex: Vec<Vector>) -> Option<(Vector, f32)> {
+    let mut polytope = simplex;
+    let mut faces = vec![0, 1, 2, 0, 3, 1, 0, 2, 3, 1, 3, 2];
+    let (mut normals, mut closest) = face_normals(&polytope, &faces);
+
+    for _ in 0..MAX_EPA_ITERATIONS {
+        let (normal, distance) = normals[closest];
+        let p = support(normal);
+        if p.dot(normal) - distance < EPA_TOLERANCE {
+            break;
+        }
+
+        let mut edges = Vec::new();
+        let mut i = 0;
+        while i < normals.len() {
+            if same_direction(normals[i].0, p - polytope[faces[i * 3]]) {
+                let f = i * 3;
+                add_unique_edge(&mut edges, faces[f], faces[f + 1]);
+                add_unique_edge(&mut edges, faces[f + 1], faces[f + 2]);
+                add_unique_edge(&mut edges, faces[f + 2], faces[f]);
+                faces.drain(f..f + 3);
+                normals.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        let new_faces = edges
+            .iter()
+            .flat_map(|&(a, b)| vec![a, b, polytope.len()])
+            .collect::<Vec<_>>();
+        polytope.push(p);
+        faces.extend(new_faces);
+        let (all_normals, all_closest) = face_normals(&polytope, &faces);
+        normals = all_normals;
+        closest = all_closest;
+        if normals.is_empty() {
+            break;
+        }
+    }
+
+    match normals.get(closest) {
+        Some(&(normal, distance)) if distance < std::f32::MAX => {
+            Some((normal, distance + EPA_TOLERANCE))
+        }
+        _ => None,
+    }
+}
+
+/// Find the penetration of two hulls at the given transformations.
+pub fn intersect(
+    hull_a: &ConvexHull,
+    transform_a: &space::Transform,
+    hull_b: &ConvexHull,
+    transform_b: &space::Transform,
+) -> Option<Penetration> {
+    let support =
+        |dir: Vector| hull_a.support(transform_a, dir) - hull_b.support(transform_b, -dir);
+    let simplex = gjk(&support, transform_b.disp - transform_a.disp)?;
+    let (normal, depth) = epa(&support, simplex)?;
+    let deepest_a = hull_a.support(transform_a, normal);
+    let deepest_b = hull_b.support(transform_b, -normal);
+    Some(Penetration {
+        normal,
+        depth,
+        point: (deepest_a + deepest_b) * 0.5,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> ConvexHull {
+        let mut points = Vec::new();
+        for &x in &[-1.0, 1.0] {
+            for &y in &[-1.0, 1.0] {
+                for &z in &[-1.0, 1.0] {
+                    points.push(cgmath::vec3(x, y, z));
+                }
+            }
+        }
+        ConvexHull::from_points(points)
+    }
+
+    fn at(x: f32, y: f32, z: f32) -> space::Transform {
+        cgmath::Decomposed {
+            scale: 1.0,
+            disp: cgmath::vec3(x, y, z),
+            rot: cgmath::Quaternion::one(),
+        }
+    }
+
+    #[test]
+    fn overlapping_boxes_are_pushed_apart() {
+        let hull = unit_box();
+        let pen = intersect(&hull, &at(0.0, 0.0, 0.0), &hull, &at(1.5, 0.25, 0.125)).unwrap();
+        assert!(
+            (pen.normal - Vector::unit_x()).magnitude() < 1.0e-3,
+            "{:?}",
+            pen
+        );
+        assert!((pen.depth - 0.5).abs() < 0.01, "{:?}", pen);
+        assert!(pen.point.x > 0.0 && pen.point.x < 1.5, "{:?}", pen);
+
+        let pen = intersect(&hull, &at(0.0, 0.0, 0.0), &hull, &at(0.25, 0.125, 1.5)).unwrap();
+        assert!(
+            (pen.normal - Vector::unit_z()).magnitude() < 1.0e-3,
+            "{:?}",
+            pen
+        );
+        assert!((pen.depth - 0.5).abs() < 0.01, "{:?}", pen);
+    }
+
+    #[test]
+    fn separated_boxes_do_not_intersect() {
+        let hull = unit_box();
+        for &(x, y, z) in &[(2.5, 0.25, 0.125), (0.0, 3.0, 0.0), (-2.5, -2.5, -2.5)] {
+            let pen = intersect(&hull, &at(0.0, 0.0, 0.0), &hull, &at(x, y, z));
+            assert!(pen.is_none(), "{:?}", pen);
+        }
+    }
+
+    #[test]
+    fn touching_boxes_are_not_pushed() {
+        let hull = unit_box();
+        for &(x, y, z) in &[(2.0, 0.0, 0.0), (2.0, 0.25, 0.125)] {
+            let pen = intersect(&hull, &at(0.0, 0.0, 0.0), &hull, &at(x, y, z));
+            assert!(pen.map_or(true, |p| p.depth < 0.01), "{:?}", pen);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use super::rigid::RigidBody;
+use vangers::{config::settings::ContactSolver, level, model, space};
+
+use cgmath::prelude::*;
+
+/// Maximum number of contacts kept in a manifold.
+pub const MAX_CONTACTS: usize = 4;
+/// Contacts of different samples closer than this are matched between frames.
+const MATCH_DISTANCE: f32 = 2.0;
+/// Fraction of the penetration depth to be resolved per second.
+const BAUMGARTE_FACTOR: f32 = 0.2;
+
+#[derive(Clone, Debug)]
+pub struct Contact {
+    /// Index of the shape sample, persistent between frames.
+    pub id: usize,
+    pub pos: cgmath::Vector3<f32>,
+    pub normal: cgmath::Vector3<f32>,
+    pub depth: f32,
+    pub terrain: level::TerrainType,
+    /// Accumulated normal impulse, carried over for warm starting.
+    pub impulse: f32,
+    /// Accumulated friction impulse along the tangent basis.
+    pub friction: [f32; 2],
+}
+
+/// Lateral constraint of a wheel, keeping it from sliding sideways.
+#[derive(Clone, Debug)]
+pub struct WheelJoint {
+    /// Contact point of the wheel, in local space.
+    pub pos: cgmath::Vector3<f32>,
+    /// Sliding direction to be constrained, in local space.
+    pub axis: cgmath::Vector3<f32>,
+}
+
+fn tangent_basis(n: cgmath::Vector3<f32>) -> [cgmath::Vector3<f32>; 2] {
+    let t0 = if n.x.abs() < 0.6 {
+        n.cross(cgmath::Vector3::unit_x())
+    } else {
+        n.cross(cgmath::Vector3::unit_y())
+    }
+    .normalize();
+    [t0, n.cross(t0)]
+}
+
+/// Collect the samples of the shape that penetrate the terrain.
+pub fn collect(
+    samples: &[model::RawVertex],
+    scale: f32,
+    transform: &space::Transform,
+    level: &level::Level,
+) -> Vec<Contact> {
+    let mut scaled = *transform;
+    scaled.scale *= scale;
+    model::project_samples(samples, level, &scaled)
+        .into_iter()
+        .map(|point| Contact {
+            id: point.sample,
+            pos: point.pos,
+            normal: point.normal,
+            depth: point.depth,
+            terrain: point.terrain,
+            impulse: 0.0,
+            friction: [0.0; 2],
+        })
+        .collect()
+}
+
+fn pick(contacts: &[Contact], score: impl Fn(&Contact) -> f32) -> usize {
+    let mut best = (0, std::f32::MIN);
+    for (i, c) in contacts.iter().enumerate() {
+        let value = score(c);
+        if value > best.1 {
+            best = (i, value);
+        }
+    }
+    best.0
+}
+
+/// Reduce the contacts to at most `MAX_CONTACTS`, keeping the deepest one
+/// and the ones that cover the largest area.
+pub fn reduce(mut contacts: Vec<Contact>) -> Vec<Contact> {
+    if contacts.len() <= MAX_CONTACTS {
+        return contacts;
+    }
+    let mut chosen = Vec::with_capacity(MAX_CONTACTS);
+
+    let first = pick(&contacts, |c| c.depth);
+    chosen.push(contacts.swap_remove(first));
+    let a = chosen[0].pos;
+
+    let second = pick(&contacts, |c| (c.pos - a).magnitude2());
+    chosen.push(contacts.swap_remove(second));
+    let b = chosen[1].pos;
+
+    let third = pick(&contacts, |c| (b - a).cross(c.pos - a).magnitude2());
+    chosen.push(contacts.swap_remove(third));
+    let c = chosen[2].pos;
+
+    // the point furthest outside of the triangle edges adds the most area
+    let normal = (b - a).cross(c - a);
+    let fourth = pick(&contacts, |d| {
+        [(a, b), (b, c), (c, a)]
+            .iter()
+            .map(|&(p, q)| -(q - p).cross(d.pos - p).dot(normal))
+            .fold(std::f32::MIN, f32::max)
+    });
+    chosen.push(contacts.swap_remove(fourth));
+
+    chosen
+}
+
+/// Persistent set of contacts between the car bound and the terrain.
+#[derive(Debug)]
+pub struct Manifold {
+    pub contacts: Vec<Contact>,
+    /// Accumulated lateral impulses of the wheel joints.
+    pub wheel_impulses: Vec<f32>,
+    config: ContactSolver,
+}
+
+impl Manifold {
+    pub fn new(config: ContactSolver) -> Self {
+        Manifold {
+            contacts: Vec::new(),
+            wheel_impulses: Vec::new(),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &ContactSolver {
+        &self.config
+    }
+
+    fn friction_of(&self, terrain: level::TerrainType) -> f32 {
+        self.config.friction[terrain as usize % self.config.friction.len()]
+    }
+
+    /// Replace the contacts with the reduced new ones,
+    /// carrying over the impulses of the matching old contacts.
+    pub fn update(&mut self, candidates: Vec<Contact>) {
+        let mut old = std::mem::replace(&mut self.contacts, reduce(candidates));
+        for contact in self.contacts.iter_mut() {
+            let matched = match old.iter().position(|c| c.id == contact.id) {
+                Some(index) => Some(index),
+                None => old.iter().position(|c| {
+                    (c.pos - contact.pos).magnitude2() < MATCH_DISTANCE * MATCH_DISTANCE
+                }),
+            };
+            if let Some(index) = matched {
+                let c = old.swap_remove(index);
+                contact.impulse = c.impulse;
+                contact.friction = c.friction;
+            }
+        }
+    }
+
+    /// Resolve the contacts and the wheel joints with sequential impulses,
+    /// starting from the previous ones. Friction is bounded by the Coulomb cone
+    /// of the material under each contact.
+    pub fn solve(
+        &mut self,
+        rigid: &mut RigidBody,
+        transform: &space::Transform,
+        dt: f32,
+        wheels: &[WheelJoint],
+        wheel_terrain: Option<level::TerrainType>,
+    ) {
+        let rot_inv = transform.rot.invert();
+        let local = self
+            .contacts
+            .iter()
+            .map(|c| {
+                let n = rot_inv * c.normal;
+                (rot_inv * (c.pos - transform.disp), n, tangent_basis(n))
+            })
+            .collect::<Vec<_>>();
+        let frictions = self
+            .contacts
+            .iter()
+            .map(|c| self.friction_of(c.terrain))
+            .collect::<Vec<_>>();
+        let mu_wheel = wheel_terrain.map_or(0.0, |t| self.friction_of(t));
+        if self.wheel_impulses.len() != wheels.len() {
+            self.wheel_impulses = vec![0.0; wheels.len()];
+        }
+
+        for (c, &(r, n, t)) in self.contacts.iter().zip(local.iter()) {
+            let total = n * c.impulse + t[0] * c.friction[0] + t[1] * c.friction[1];
+            if total != cgmath::Vector3::zero() {
+                rigid.push(r, total);
+            }
+        }
+        for (w, &impulse) in wheels.iter().zip(self.wheel_impulses.iter()) {
+            if impulse != 0.0 {
+                rigid.push(w.pos, w.axis * impulse);
+            }
+        }
+
+        for _ in 0..self.config.iterations {
+            for ((c, &(r, n, t)), &mu) in self
+                .contacts
+                .iter_mut()
+                .zip(local.iter())
+                .zip(frictions.iter())
+            {
+                let bias = BAUMGARTE_FACTOR * c.depth / dt;
+                let delta = bias - rigid.contact_velocity_at(r).dot(n);
+                let impulse = (c.impulse + delta).max(0.0);
+                rigid.push(r, n * (impulse - c.impulse));
+                c.impulse = impulse;
+
+                let vel = rigid.contact_velocity_at(r);
+                let mut friction = [c.friction[0] - vel.dot(t[0]), c.friction[1] - vel.dot(t[1])];
+                let limit = mu * c.impulse;
+                let length = (friction[0] * friction[0] + friction[1] * friction[1]).sqrt();
+                if length > limit {
+                    let ratio = limit / length;
+                    friction = [friction[0] * ratio, friction[1] * ratio];
+                }
+                rigid.push(
+                    r,
+                    t[0] * (friction[0] - c.friction[0]) + t[1] * (friction[1] - c.friction[1]),
+                );
+                c.friction = friction;
+            }
+
+            // the wheels share the load carried by the contacts
+            let load = self.contacts.iter().map(|c| c.impulse).sum::<f32>();
+            let limit = mu_wheel * load / wheels.len().max(1) as f32;
+            for (w, accumulated) in wheels.iter().zip(self.wheel_impulses.iter_mut()) {
+                let delta = -rigid.contact_velocity_at(w.pos).dot(w.axis);
+                let impulse = (*accumulated + delta).max(-limit).min(limit);
+                rigid.push(w.pos, w.axis * (impulse - *accumulated));
+                *accumulated = impulse;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contact(id: usize, x: f32, y: f32, depth: f32) -> Contact {
+        Contact {
+            id,
+            pos: cgmath::vec3(x, y, -1.0),
+            normal: cgmath::Vector3::unit_z(),
+            depth,
+            terrain: 0,
+            impulse: 0.0,
+            friction: [0.0; 2],
+        }
+    }
+
+    /// Contacts under the corners of a body sitting on a flat ground.
+    fn corners() -> Vec<Contact> {
+        vec![
+            contact(0, -1.0, -1.0, 0.0),
+            contact(1, 1.0, -1.0, 0.0),
+            contact(2, -1.0, 1.0, 0.0),
+            contact(3, 1.0, 1.0, 0.0),
+        ]
+    }
+
+    fn manifold(friction: f32) -> Manifold {
+        Manifold::new(ContactSolver {
+            iterations: 8,
+            friction: [friction; 8],
+        })
+    }
+
+    fn body(vel: cgmath::Vector3<f32>) -> RigidBody {
+        RigidBody::new(&cgmath::Matrix3::identity(), vel, cgmath::Vector3::zero())
+    }
+
+    #[test]
+    fn reduce_keeps_the_deepest_and_the_widest() {
+        let mut contacts = Vec::new();
+        for y in 0..3 {
+            for x in 0..3 {
+                let depth = if (x, y) == (1, 1) { 2.0 } else { 1.0 };
+                contacts.push(contact(contacts.len(), x as f32, y as f32, depth));
+            }
+        }
+        let reduced = reduce(contacts);
+        assert_eq!(reduced.len(), MAX_CONTACTS);
+        assert_eq!(reduced[0].id, 4);
+        // the others are spread over the corners
+        for c in reduced[1..].iter() {
+            assert!(c.pos.x != 1.0 && c.pos.y != 1.0, "{:?}", c);
+        }
+        assert_eq!(reduce(corners()).len(), 4);
+    }
+
+    #[test]
+    fn update_carries_over_the_impulses() {
+        let mut manifold = manifold(0.5);
+        manifold.update(corners());
+        for c in manifold.contacts.iter_mut() {
+            c.impulse = 1.0;
+            c.friction = [0.5, 0.0];
+        }
+        let mut moved = corners();
+        // same sample
+        moved[0].pos.x += 3.0;
+        // different sample at the same place
+        moved[1].id = 10;
+        // different sample far away
+        moved[2].id = 11;
+        moved[2].pos.x += 10.0;
+        manifold.update(moved);
+
+        let impulses = manifold
+            .contacts
+            .iter()
+            .map(|c| (c.id, c.impulse))
+            .collect::<Vec<_>>();
+        assert_eq!(impulses, vec![(0, 1.0), (10, 1.0), (11, 0.0), (3, 1.0)]);
+        assert_eq!(manifold.contacts[1].friction, [0.5, 0.0]);
+    }
+
+    #[test]
+    fn resting_contacts_stop_the_approach() {
+        let mut manifold = manifold(0.5);
+        manifold.update(corners());
+        let mut rigid = body(cgmath::vec3(0.0, 0.0, -1.0));
+        manifold.solve(&mut rigid, &space::Transform::one(), 1.0 / 60.0, &[], None);
+
+        for c in manifold.contacts.iter() {
+            let approach = rigid.contact_velocity_at(c.pos).z;
+            assert!(approach.abs() < 1.0e-3, "{:?} at {}", c, approach);
+        }
+        // the symmetric contacts don't make the body spin
+        let (vel, wel) = rigid.finish();
+        assert!(vel.magnitude() < 1.0e-3, "{:?}", vel);
+        assert!(wel.magnitude() < 1.0e-3, "{:?}", wel);
+    }
+
+    #[test]
+    fn sliding_contacts_slow_down_without_spinning() {
+        let mut manifold = manifold(0.5);
+        manifold.update(corners());
+        let mut rigid = body(cgmath::vec3(10.0, 0.0, -1.0));
+        manifold.solve(&mut rigid, &space::Transform::one(), 1.0 / 60.0, &[], None);
+        let (vel, wel) = rigid.finish();
+        assert!(vel.x < 10.0, "{:?}", vel);
+        assert!(vel.z.abs() < 1.0e-3, "{:?}", vel);
+        assert!(wel.magnitude() < 1.0e-3, "{:?}", wel);
+    }
+
+    #[test]
+    fn friction_is_bounded_by_the_coulomb_cone() {
+        let mut manifold = manifold(0.5);
+        manifold.update(corners());
+        let mut rigid = body(cgmath::vec3(10.0, 0.0, -1.0));
+        manifold.solve(&mut rigid, &space::Transform::one(), 1.0 / 60.0, &[], None);
+
+        for c in manifold.contacts.iter() {
+            assert!(c.impulse > 0.0, "{:?}", c);
+            let friction = (c.friction[0] * c.friction[0] + c.friction[1] * c.friction[1]).sqrt();
+            assert!(friction <= 0.5 * c.impulse * 1.001, "{:?}", c);
+        }
+        // the body keeps sliding, just slower
+        let (vel, _) = rigid.finish();
+        assert!(vel.x > 9.0 && vel.x < 10.0, "{:?}", vel);
+    }
+}
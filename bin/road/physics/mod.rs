@@ -1,18 +1,32 @@
 //! Physics port of the original game. Most closely described by the following documents:
 //! - https://people.eecs.berkeley.edu/~jfc/mirtich/thesis/mirtichThesis.pdf
 
-use vangers::{config, level, render::debug::LineBuffer, space};
+use vangers::{
+    config::{self, settings},
+    level, model,
+    render::debug::LineBuffer,
+    space,
+};
 
 use cgmath::prelude::*;
 
-use std::f32::EPSILON;
+use std::{f32::EPSILON, sync::Arc};
 
+mod broad;
+mod collide;
+mod contact;
+mod projectile;
 mod rigid;
 mod terrain;
 
-pub use terrain::get_height;
+pub use broad::{find_pairs, Aabb, Sleep};
+pub use collide::ConvexHull;
+pub use contact::Manifold;
+pub use projectile::{Event as ProjectileEvent, Impact, Projectile};
+pub use terrain::{get_height, TerrainGrid};
 
 const MAX_TRACTION: config::common::Traction = 4.0;
+const SPLASH_DROPLETS: usize = 8;
 
 #[derive(Debug)]
 struct AccelerationVectors {
@@ -25,6 +39,16 @@ pub struct Dynamo {
     pub rudder: cgmath::Rad<f32>,
     pub linear_velocity: cgmath::Vector3<f32>,
     pub angular_velocity: cgmath::Vector3<f32>,
+    pub in_water: bool,
+    /// Terrain type under the wheels, if they touch the ground.
+    pub surface: Option<level::TerrainType>,
+    /// Contacts with the terrain, replacing the per-sample springs if enabled.
+    pub manifold: Option<Manifold>,
+    /// Steering geometry of the front wheels, if enabled.
+    pub ackermann: Option<settings::Ackermann>,
+    /// Rigid body of the library, replacing the original physics if enabled.
+    pub rigid: Option<vangers::physics::Body>,
+    pub sleep: Sleep,
 }
 
 impl Default for Dynamo {
@@ -34,6 +58,46 @@ impl Default for Dynamo {
             rudder: cgmath::Rad(0.),
             linear_velocity: cgmath::Vector3::zero(),
             angular_velocity: cgmath::Vector3::zero(),
+            in_water: false,
+            surface: None,
+            manifold: None,
+            ackermann: None,
+            rigid: None,
+            sleep: Sleep::default(),
+        }
+    }
+}
+
+/// Input of a vehicle, from the player or the AI.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Control {
+    pub motor: f32,
+    pub rudder: f32,
+    pub roll: f32,
+    pub brake: bool,
+    pub turbo: bool,
+}
+
+/// Parts of a car that the CPU physics works with,
+/// which can be put together without loading the model on GPU.
+pub struct Chassis<'a> {
+    pub physics: &'a config::car::CarPhysics,
+    pub mass: &'a m3d::Physics,
+    pub bbox: &'a model::BoundingBox,
+    pub polygons: &'a [model::Polygon],
+    pub samples: &'a [model::RawVertex],
+    pub wheels: &'a [m3d::Wheel<Arc<model::Mesh>>],
+}
+
+impl<'a> Chassis<'a> {
+    pub fn new(car: &'a config::car::CarInfo) -> Self {
+        Chassis {
+            physics: &car.physics,
+            mass: &car.model.body.physics,
+            bbox: &car.model.body.bbox,
+            polygons: &car.model.shape.polygons,
+            samples: &car.model.shape.samples,
+            wheels: &car.model.wheels,
         }
     }
 }
@@ -52,22 +116,140 @@ impl Dynamo {
     }
 }
 
+/// Destination of the physics debug drawing.
+pub struct DebugDraw<'a> {
+    pub lines: &'a mut LineBuffer,
+    pub layers: &'a settings::DebugRender,
+}
+
+impl DebugDraw<'_> {
+    /// Return the line buffer if the selected layer is enabled.
+    fn layer(&mut self, select: fn(&settings::DebugRender) -> bool) -> Option<&mut LineBuffer> {
+        if select(self.layers) {
+            Some(&mut *self.lines)
+        } else {
+            None
+        }
+    }
+}
+
+/// Sliding axis of a steered wheel, in local space.
+/// With the Ackermann geometry, all the wheels turn around a common center
+/// on the rear axle line, so the inner wheel is steered more than the outer one.
+fn steer_axis(
+    wheel_pos: [f32; 3],
+    rear_axle: f32,
+    ackermann: Option<&settings::Ackermann>,
+    rudder: cgmath::Rad<f32>,
+) -> cgmath::Vector3<f32> {
+    let (sin, cos) = rudder.sin_cos();
+    if let Some(ack) = ackermann {
+        let base = ack.wheelbase.unwrap_or(wheel_pos[1] - rear_axle);
+        if base > EPSILON {
+            return cgmath::vec3(base * cos - wheel_pos[0] * sin, -base * sin, 0.0).normalize();
+        }
+    }
+    cgmath::vec3(cos, -sin, 0.0)
+}
+
+fn rigid_body(
+    dynamo: &Dynamo,
+    transform: &space::Transform,
+    chassis: &Chassis,
+) -> rigid::RigidBody {
+    let phys = chassis.mass;
+    let jacobian =
+        cgmath::Matrix3::from(phys.jacobi) * (transform.scale * transform.scale / phys.volume);
+    rigid::RigidBody::new(&jacobian, dynamo.linear_velocity, dynamo.angular_velocity)
+}
+
+/// A vehicle taking part in a collision with another one.
+pub struct Vehicle<'a> {
+    pub dynamo: &'a mut Dynamo,
+    pub transform: &'a mut space::Transform,
+    pub chassis: Chassis<'a>,
+    pub hull: &'a ConvexHull,
+}
+
+impl Vehicle<'_> {
+    fn mass(&self, common: &config::common::Common) -> f32 {
+        common.nature.density
+            * self.chassis.mass.volume
+            * self.transform.scale
+            * self.transform.scale
+    }
+
+    /// World velocity of the body at the given world point.
+    fn velocity_at(&self, point: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        let r = self.transform.rot.invert() * (point - self.transform.disp);
+        self.transform.rot * (self.dynamo.linear_velocity + self.dynamo.angular_velocity.cross(r))
+    }
+
+    /// Change the velocity of the body at the given world point.
+    fn push(&mut self, point: cgmath::Vector3<f32>, delta: cgmath::Vector3<f32>) {
+        let rot_inv = self.transform.rot.invert();
+        let mut rigid = rigid_body(self.dynamo, self.transform, &self.chassis);
+        rigid.push(rot_inv * (point - self.transform.disp), rot_inv * delta);
+        let (v, w) = rigid.finish();
+        self.dynamo.linear_velocity = v;
+        self.dynamo.angular_velocity = w;
+        self.dynamo.sleep.wake();
+    }
+}
+
+/// Separate two intersecting vehicles and exchange the impulse along the contact normal.
+/// `offset` is the shift bringing the second vehicle next to the first one
+/// on a level wrapping around. Returns true if the vehicles collided.
+pub fn collide_vehicles(
+    mut a: Vehicle,
+    mut b: Vehicle,
+    offset: cgmath::Vector3<f32>,
+    common: &config::common::Common,
+    config: &settings::VehicleCollision,
+) -> bool {
+    let mut transform_b = *b.transform;
+    transform_b.disp += offset;
+    let pen = match collide::intersect(a.hull, a.transform, b.hull, &transform_b) {
+        Some(pen) => pen,
+        None => return false,
+    };
+    log::debug!("Vehicle collision {:?}", pen);
+
+    // the lighter vehicle gets the larger share of the response
+    let (mass_a, mass_b) = (a.mass(common), b.mass(common));
+    let share_a = mass_b / (mass_a + mass_b);
+    let share_b = 1.0 - share_a;
+    a.transform.disp -= pen.normal * (pen.depth * share_a);
+    b.transform.disp += pen.normal * (pen.depth * share_b);
+
+    let point_b = pen.point - offset;
+    let approach = (b.velocity_at(point_b) - a.velocity_at(pen.point)).dot(pen.normal);
+    if approach < 0.0 {
+        let delta = pen.normal * (-(1.0 + config.restitution) * approach);
+        a.push(pen.point, -delta * share_a);
+        b.push(point_b, delta * share_b);
+    }
+    true
+}
+
 pub fn jump_dir(power: f32) -> cgmath::Vector3<f32> {
     5.0 * power * cgmath::vec3(0.0, 3.0, 10.0).normalize()
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn step(
     dynamo: &mut Dynamo,
     transform: &mut space::Transform,
     dt: f32,
-    car: &config::car::CarInfo,
+    chassis: &Chassis,
     level: &level::Level,
+    grid: &TerrainGrid,
     common: &config::common::Common,
     f_turbo: f32,
     f_brake: f32,
     jump: Option<f32>,
     roll: f32,
-    mut line_buffer: Option<&mut LineBuffer>,
+    mut debug: Option<DebugDraw>,
 ) {
     let speed_correction_factor = dt / common.nature.time_delta0;
     let acc_global = AccelerationVectors {
@@ -76,24 +258,15 @@ pub fn step(
     };
     let rot_inv = transform.rot.invert();
     log::debug!("dt {}, num {}", dt, common.nature.num_calls_analysis);
-    let flood_level = level.flood_map[0] as f32;
     // Z axis in the local coordinate space
     let z_axis = rot_inv * cgmath::Vector3::unit_z();
     let device_modulation = 1.0;
     let dt_impulse = 1.0;
 
-    let mut rigid = {
-        let phys = &car.model.body.physics;
-        let jacobian =
-            cgmath::Matrix3::from(phys.jacobi) * (transform.scale * transform.scale / phys.volume);
-        rigid::RigidBody::new(&jacobian, dynamo.linear_velocity, dynamo.angular_velocity)
-    };
+    let mut rigid = rigid_body(dynamo, transform, chassis);
 
     if let Some(power) = jump {
-        let mass = common.nature.density
-            * car.model.body.physics.volume
-            * transform.scale
-            * transform.scale;
+        let mass = common.nature.density * chassis.mass.volume * transform.scale * transform.scale;
         let f = device_modulation * common.force.k_distance_to_force * dt_impulse / mass.powf(0.3);
         log::info!("jump mass {:?}, f {:?}", mass, f);
         //DBV dV = A_g2l*DBV(-Sin(Pi/10)*Sin(psi),-Sin(Pi/10)*Cos(psi),Cos(Pi/10));
@@ -102,10 +275,9 @@ pub fn step(
 
     let mut wheels_touch = 0u32;
     let mut spring_touch = 0;
-    //let mut in_water = false;
 
     let mut float_count = 0;
-    let (mut terrain_immersion, mut water_immersion) = (0.0, 0.0);
+    let mut water_immersion = 0.0;
     let stand_on_wheels =
         z_axis.z > 0.0 && (transform.rot * cgmath::Vector3::unit_x()).z.abs() < 0.7;
     let modulation = 1.0;
@@ -120,19 +292,44 @@ pub fn step(
         k: cgmath::Vector3::zero(),
     };
 
+    // the contact solver replaces the penalty impulses and springs
+    let use_solver = dynamo.manifold.is_some();
+    let mut wheel_joints = Vec::new();
+
+    // swept box of the collision shape, to only look at the terrain cells under it,
+    // if it reaches any of the terrain chunks at all
+    let terrain_patch = {
+        let radius = chassis
+            .samples
+            .iter()
+            .map(|s| cgmath::Vector3::from(*s).cast::<f32>().unwrap().magnitude())
+            .fold(0.0, f32::max)
+            * transform.scale
+            * chassis.physics.scale_bound;
+        let sweep = rigid.vel.magnitude() * dt;
+        let aabb = broad::Aabb::from_sphere(transform.disp, radius + sweep);
+        let bottom = aabb.center.z - aabb.half_size.z;
+        if grid.chunks_under(&aabb).is_empty() {
+            None
+        } else {
+            Some(terrain::TerrainPatch::new(level, &aabb)).filter(|p| bottom <= p.max_height())
+        }
+    };
+
     let mut sum_count = 0usize;
     let mut sum_rg0 = cgmath::Vector3::zero();
     let mut sum_df = 0.;
 
-    for (bound_poly_id, poly) in car.model.shape.polygons.iter().enumerate() {
-        let r = cgmath::Vector3::from(poly.middle) * (transform.scale * car.physics.scale_bound);
+    for (bound_poly_id, poly) in chassis.polygons.iter().enumerate() {
+        let r =
+            cgmath::Vector3::from(poly.middle) * (transform.scale * chassis.physics.scale_bound);
         let rg0 = transform.rot * r;
         let rglob = rg0 + transform.disp;
         log::debug!(
             "\t\tpoly[{}]: normal={:?} scale={} mid={:?} r={:?}",
             bound_poly_id,
             poly.normal,
-            transform.scale * car.physics.scale_bound,
+            transform.scale * chassis.physics.scale_bound,
             poly.middle,
             r
         );
@@ -142,7 +339,7 @@ pub fn step(
                 low: level::Point(_, 0),
                 ..
             } => {
-                let dz = flood_level - rglob.z;
+                let dz = level.flood_level(rglob.y as i32) as f32 - rglob.z;
                 if dz > 0.0 {
                     float_count += 1;
                     water_immersion += dz;
@@ -152,24 +349,20 @@ pub fn step(
         };
         let poly_norm = cgmath::Vector3::from(poly.normal).normalize();
         if z_axis.dot(poly_norm) < 0.0 {
-            let cdata = terrain::CollisionData::collide_low(
-                poly,
-                &car.model.shape.samples,
-                car.physics.scale_bound,
-                &transform,
-                level,
-                &common.terrain,
-            );
+            let cdata = match terrain_patch {
+                None => terrain::CollisionData::empty(),
+                Some(ref patch) => terrain::CollisionData::collide_low(
+                    poly,
+                    chassis.samples,
+                    chassis.physics.scale_bound,
+                    &transform,
+                    level,
+                    patch,
+                    &common.terrain,
+                ),
+            };
 
             log::debug!("\t\tcollide_low = {:?}", cdata);
-            terrain_immersion += match cdata.soft {
-                Some(ref cp) => cp.depth.abs(),
-                None => 0.0,
-            };
-            terrain_immersion += match cdata.hard {
-                Some(ref cp) => cp.depth.abs(),
-                None => 0.0,
-            };
 
             let origin = transform.disp;
             let mostly_horisontal = {
@@ -183,7 +376,7 @@ pub fn step(
                     let r1 = rot_inv * cgmath::vec3(cp.pos.x - origin.x, cp.pos.y - origin.y, 0.0); // ignore vertical
                     let pv = rigid.velocity_at(r1);
                     let normal = {
-                        let bm = car.model.body.bbox.max;
+                        let bm = chassis.bbox.max;
                         let n = cgmath::vec3(r1.x / bm[0], r1.y / bm[1], r1.z / bm[2]);
                         n.normalize()
                     };
@@ -194,7 +387,7 @@ pub fn step(
                 }
                 terrain::CollisionData {
                     soft: Some(ref cp), ..
-                } => {
+                } if !use_solver => {
                     //TODO: let r1 = rot_inv * (cp.pos - origin);
                     let r1 =
                         rot_inv * cgmath::vec3(cp.pos.x - origin.x, cp.pos.y - origin.y, rg0.z);
@@ -234,7 +427,7 @@ pub fn step(
                 sum_rg0 += rg0;
                 sum_df += df;
 
-                if let Some(ref mut lbuf) = line_buffer {
+                if let Some(lbuf) = debug.as_mut().and_then(|d| d.layer(|l| l.impulses)) {
                     // Red: center -> collision point
                     lbuf.add(transform.disp.into(), rglob.into(), 0xFF000000);
                     // Yellow: collision point -> linear force
@@ -256,7 +449,11 @@ pub fn step(
         log::debug!("Avg df {} rg0 {:?}", sum_df * kf, sum_rg0 * kf);
     }
 
-    if wheels_touch + spring_touch != 0 {
+    if let Some(ref mut manifold) = dynamo.manifold {
+        let scale = transform.scale * chassis.physics.scale_bound;
+        manifold.update(contact::collect(chassis.samples, scale, transform, level));
+        log::debug!("\tmanifold {:?}", manifold.contacts);
+    } else if wheels_touch + spring_touch != 0 {
         log::debug!("\tsprings total {:?}", acc_springs);
         acc_cur.f += rot_inv * acc_springs.f;
         acc_cur.k += rot_inv * acc_springs.k;
@@ -272,24 +469,92 @@ pub fn step(
             .powf(rigid.angular_velocity().magnitude2()); //why mag2?
     if wheels_touch > 0 {
         //TODO: why `ln()`?
-        let speed =
-            common.drag.wheel_speed.ln() * car.physics.mobility_factor * common.global.speed_factor
-                / car.physics.speed_factor;
+        let speed = common.drag.wheel_speed.ln()
+            * chassis.physics.mobility_factor
+            * common.global.speed_factor
+            / chassis.physics.speed_factor;
         rigid.vel.y *= (1.0 + speed).powf(speed_correction_factor);
     }
 
-    let _ = (float_count, water_immersion, terrain_immersion); //TODO
+    let in_water = float_count != 0;
+    if in_water {
+        // Archimedean force, proportional to the average immersion of the bound
+        let df = chassis.physics.k_archimedean
+            * level.physics.water_density
+            * common.nature.gravity
+            * water_immersion
+            / chassis.polygons.len() as f32;
+        log::debug!("\tfloat count {}, buoyancy {}", float_count, df);
+        acc_cur.f += z_axis * df;
+        v_drag *= common.drag.float.v;
+        w_drag *= common.drag.float.w;
+    }
+    if in_water != dynamo.in_water {
+        log::debug!("\tsplash at {:?}", transform.disp);
+        if let Some(lbuf) = debug.as_mut().and_then(|d| d.layer(|l| l.contacts)) {
+            // Blue: splash droplets around the entry point
+            let speed = rigid.vel.magnitude();
+            for i in 0..SPLASH_DROPLETS {
+                let angle = cgmath::Rad::full_turn() * (i as f32 / SPLASH_DROPLETS as f32);
+                let (sin, cos) = angle.sin_cos();
+                let end = transform.disp + cgmath::vec3(cos, sin, 2.0) * speed;
+                lbuf.add(transform.disp.into(), end.into(), 0x0000FF00);
+            }
+        }
+    }
+    dynamo.in_water = in_water;
     let is_after_collision = false;
+    dynamo.surface = None;
+    let rear_axle = {
+        let (sum, count) = chassis
+            .wheels
+            .iter()
+            .filter(|w| w.steer == 0)
+            .fold((0.0, 0), |(sum, count), w| (sum + w.pos[1], count + 1));
+        if count != 0 {
+            sum / count as f32
+        } else {
+            0.0
+        }
+    };
+    if let Some(lbuf) = debug.as_mut().and_then(|d| d.layer(|l| l.wheels)) {
+        // Green: rays to the ground under touching wheels, red: under the others
+        // Yellow: sliding axes of the steered wheels
+        for wheel in chassis.wheels.iter() {
+            let pw = transform.transform_point(cgmath::Point3::from(wheel.pos));
+            let dist = terrain::get_distance_to_terrain(level, pw);
+            let ground = pw - cgmath::Vector3::unit_z() * dist;
+            let color = if dist <= 0.0 { 0x00FF0000 } else { 0xFF000000 };
+            lbuf.add(pw.into(), ground.into(), color);
+            if wheel.steer != 0 {
+                let axis = steer_axis(
+                    wheel.pos,
+                    rear_axle,
+                    dynamo.ackermann.as_ref(),
+                    dynamo.rudder,
+                );
+                let end = pw + transform.rot * axis * 5.0;
+                lbuf.add(pw.into(), end.into(), 0xFFFF0000);
+            }
+        }
+    }
     if wheels_touch != 0 && stand_on_wheels {
-        let f_traction_per_wheel =
-            car.physics.mobility_factor * common.global.mobility_factor * f_turbo * dynamo.traction
-                / (car.model.wheels.len() as f32);
-        let rudder_vec = {
-            let (sin, cos) = dynamo.rudder.sin_cos();
-            cgmath::vec3(cos, -sin, 0.0)
-        };
-        for wheel in car.model.wheels.iter() {
+        let f_traction_per_wheel = chassis.physics.mobility_factor
+            * common.global.mobility_factor
+            * f_turbo
+            * dynamo.traction
+            / (chassis.wheels.len() as f32);
+        for wheel in chassis.wheels.iter() {
             let pw = transform.transform_point(cgmath::Point3::from(wheel.pos));
+            if dynamo.surface.is_none() {
+                dynamo.surface = Some(match level.get((pw.x as i32, pw.y as i32)) {
+                    level::Texel::Single(level::Point(_, terrain)) => terrain,
+                    level::Texel::Dual {
+                        low: level::Point(_, terrain),
+                        ..
+                    } => terrain,
+                });
+            }
             let detect_wheel_hits = false;
             if detect_wheel_hits {
                 let dist = terrain::get_distance_to_terrain(level, pw);
@@ -299,9 +564,9 @@ pub fn step(
             }
 
             let rx_max = if wheel.pos[0] > 0.0 {
-                car.model.body.bbox.max[0]
+                chassis.bbox.max[0]
             } else {
-                car.model.body.bbox.min[0]
+                chassis.bbox.min[0]
             };
             let pos = cgmath::vec3(rx_max, wheel.pos[1], wheel.pos[2]) * transform.scale;
             let pv = rigid.velocity_at(pos);
@@ -311,14 +576,23 @@ pub fn step(
 
             if !is_after_collision {
                 let dir = if wheel.steer != 0 {
-                    rudder_vec
+                    steer_axis(
+                        wheel.pos,
+                        rear_axle,
+                        dynamo.ackermann.as_ref(),
+                        dynamo.rudder,
+                    )
                 } else {
                     cgmath::Vector3::unit_x()
                 };
 
+                if use_solver {
+                    wheel_joints.push(contact::WheelJoint { pos, axis: dir });
+                    continue;
+                }
                 let dot = dir.dot(pv);
                 let pulse = rigid.push(pos, dir * (dot * -common.impulse.k_wheel));
-                if let Some(ref mut lbuf) = line_buffer {
+                if let Some(lbuf) = debug.as_mut().and_then(|d| d.layer(|l| l.forces)) {
                     let dest = pw + transform.transform_vector(pulse) * 10.0;
                     lbuf.add(pw.into(), dest.into(), 0xFFFFFF00);
                 }
@@ -326,12 +600,23 @@ pub fn step(
         }
     }
 
+    if let Some(ref mut manifold) = dynamo.manifold {
+        manifold.solve(&mut rigid, transform, dt, &wheel_joints, dynamo.surface);
+        if let Some(lbuf) = debug.as_mut().and_then(|d| d.layer(|l| l.contacts)) {
+            // White: contact normals, scaled by the impulse
+            for c in manifold.contacts.iter() {
+                let end = c.pos + c.normal * (1.0 + c.impulse);
+                lbuf.add(c.pos.into(), end.into(), 0xFFFFFF00);
+            }
+        }
+    }
+
     if spring_touch + wheels_touch != 0 {
         //|| in_water
         let tmp = cgmath::Vector3::new(
             0.0,
             0.0,
-            car.physics.z_offset_of_mass_center * transform.scale,
+            chassis.physics.z_offset_of_mass_center * transform.scale,
         );
         acc_cur.k -= common.nature.gravity * tmp.cross(z_axis);
         let vz = z_axis.dot(rigid.vel);
@@ -343,9 +628,9 @@ pub fn step(
     if roll != 0.0 && wheels_touch == 0 && spring_touch != 0 {
         let df = common.force.f_spring_impulse * speed_correction_factor;
         let x_edge = if roll > 0.0 {
-            car.model.body.bbox.max[0]
+            chassis.bbox.max[0]
         } else {
-            car.model.body.bbox.min[0]
+            chassis.bbox.min[0]
         };
         rigid.add_raw(
             cgmath::vec3(0.0, 0.0, df),
@@ -372,7 +657,7 @@ pub fn step(
     }
 
     if v_mag * v_drag > common.drag.abs_stop.v || w_mag * w_drag > common.drag.abs_stop.w {
-        let radius = car.model.body.bbox.radius; //approx?
+        let radius = chassis.bbox.radius; //approx?
         let local_z_scaled = z_axis * (radius * common.impulse.rolling_scale);
         let r_diff_sign = down_minus_up.signum() as f32;
         let vs = v_vel - r_diff_sign * local_z_scaled.cross(w_vel);
@@ -395,8 +680,8 @@ pub fn step(
     v_vel *= v_drag.powf(speed_correction_factor);
     w_vel *= w_drag.powf(speed_correction_factor);
 
-    if let Some(ref mut lbuf) = line_buffer {
-        // Note: velocity and acceleration are in local space
+    if let Some(lbuf) = debug.as_mut().and_then(|d| d.layer(|l| l.forces)) {
+        // Note: acceleration is in local space
         let rot = transform.rot;
         let ba = transform.disp + cgmath::vec3(3.0, 0.0, 10.0);
         let xf = ba + rot * acc_cur.f;
@@ -405,6 +690,10 @@ pub fn step(
         lbuf.add(ba.into(), xk.into(), 0xFF00FF00);
         // Yellow: center -> angular springs total
         lbuf.add(ba.into(), (ba + acc_springs.k).into(), 0xFFFF0000);
+    }
+    if let Some(lbuf) = debug.as_mut().and_then(|d| d.layer(|l| l.velocities)) {
+        // Note: velocity is in local space
+        let rot = transform.rot;
         let bv = transform.disp + cgmath::vec3(-3.0, 0.0, 10.0);
         let xv = bv + rot * v_vel;
         let xw = bv + rot * w_vel * 10.0; //TEMP
@@ -422,3 +711,93 @@ pub fn step(
     // slow down
     dynamo.slow_down(dt * common.car.traction_decr);
 }
+
+/// Turn the controls into the rudder and the traction of the vehicle.
+pub fn apply_control(
+    dynamo: &mut Dynamo,
+    control: &Control,
+    jump: bool,
+    dt: f32,
+    common: &config::common::Common,
+) {
+    if *control != Control::default() || jump {
+        dynamo.sleep.wake();
+    }
+    if control.rudder != 0.0 {
+        let angle = dynamo.rudder.0 + common.car.rudder_step * 2.0 * dt * control.rudder;
+        dynamo.rudder.0 = angle.min(common.car.rudder_max).max(-common.car.rudder_max);
+    }
+    if control.motor != 0.0 {
+        dynamo.change_traction(control.motor * dt * common.car.traction_incr);
+    }
+    if control.brake && dynamo.traction != 0.0 {
+        dynamo.traction *= (-dt).exp2();
+    }
+}
+
+/// Move a vehicle unless it's asleep, with either the original physics
+/// or the rigid body of the library, and put it to sleep once it rests.
+#[allow(clippy::too_many_arguments)]
+pub fn step_vehicle(
+    dynamo: &mut Dynamo,
+    transform: &mut space::Transform,
+    dt: f32,
+    chassis: &Chassis,
+    level: &level::Level,
+    grid: &TerrainGrid,
+    common: &config::common::Common,
+    control: &Control,
+    jump: Option<f32>,
+    roll: f32,
+    debug: Option<DebugDraw>,
+) {
+    if dynamo.sleep.is_asleep() {
+        return;
+    }
+    if let Some(ref mut body) = dynamo.rigid {
+        // the body velocities are in the world space, unlike the dynamo ones
+        body.transform = *transform;
+        body.vel = transform.rot * dynamo.linear_velocity;
+        body.wel = transform.rot * dynamo.angular_velocity;
+        body.control = vangers::physics::Control {
+            throttle: control.motor,
+            steer: control.rudder,
+        };
+        let contacts = body.step(dt, level);
+        *transform = body.transform;
+        let rot_inv = transform.rot.invert();
+        dynamo.linear_velocity = rot_inv * body.vel;
+        dynamo.angular_velocity = rot_inv * body.wel;
+        dynamo.surface = contacts.first().map(|c| c.terrain);
+    } else {
+        step(
+            dynamo,
+            transform,
+            dt,
+            chassis,
+            level,
+            grid,
+            common,
+            if control.turbo {
+                common.global.k_traction_turbo
+            } else {
+                1.0
+            },
+            if control.brake {
+                common.global.f_brake_max
+            } else {
+                0.0
+            },
+            jump,
+            roll,
+            debug,
+        );
+    }
+    if dynamo
+        .sleep
+        .update(dynamo.linear_velocity, dynamo.angular_velocity, dt)
+    {
+        dynamo.linear_velocity = cgmath::Vector3::zero();
+        dynamo.angular_velocity = cgmath::Vector3::zero();
+    }
+}
@@ -0,0 +1,157 @@
+use super::terrain;
+use vangers::{
+    config::settings::{ProjectileResponse, Projectiles},
+    level,
+    render::raycast::GpuBatch,
+};
+
+use cgmath::prelude::*;
+
+use std::f32::EPSILON;
+
+/// Outcome of a projectile hitting the level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Impact {
+    /// Bounced off the ground at a grazing angle.
+    Ricochet,
+    /// Got stuck in the soft ground.
+    Penetration,
+    /// Hit the hard ground too steeply to bounce.
+    Stop,
+    /// Fell into the water.
+    Splash,
+}
+
+/// Projectile hit, to be followed by the effects and sounds.
+#[derive(Debug)]
+pub struct Event {
+    pub impact: Impact,
+    /// Terrain type that was hit, `None` for the water.
+    pub terrain: Option<level::TerrainType>,
+    pub position: cgmath::Vector3<f32>,
+    pub normal: cgmath::Vector3<f32>,
+    /// Speed of the projectile before the hit.
+    pub speed: f32,
+}
+
+pub struct Projectile {
+    pub pos: cgmath::Vector3<f32>,
+    pub vel: cgmath::Vector3<f32>,
+    age: f32,
+    stopped: bool,
+    /// Rays cast on GPU along the flight, which results are not read back yet.
+    pub casts: Vec<(GpuBatch, usize)>,
+}
+
+impl Projectile {
+    pub fn new(pos: cgmath::Vector3<f32>, vel: cgmath::Vector3<f32>) -> Self {
+        Projectile {
+            pos,
+            vel,
+            age: 0.0,
+            stopped: false,
+            casts: Vec::new(),
+        }
+    }
+
+    /// Returns true if the projectile is still flying within its lifetime.
+    pub fn is_alive(&self, config: &Projectiles) -> bool {
+        !self.stopped && self.age < config.lifetime
+    }
+
+    /// Move the projectile under the gravity, and respond to hitting the terrain
+    /// according to the material table of the `config`.
+    pub fn step(
+        &mut self,
+        level: &level::Level,
+        config: &Projectiles,
+        gravity: f32,
+        dt: f32,
+    ) -> Option<Event> {
+        if let Some(event) = self.fly(level, gravity, dt) {
+            return Some(event);
+        }
+        match terrain::get_surface(level, self.pos) {
+            Some((height, terrain)) if self.pos.z <= height => {
+                let position = cgmath::vec3(self.pos.x, self.pos.y, height);
+                self.hit(level, config, position, terrain)
+            }
+            _ => None,
+        }
+    }
+
+    /// Move the projectile under the gravity, only checking for the water.
+    /// Returns the splash if it fell into the water.
+    pub fn fly(&mut self, level: &level::Level, gravity: f32, dt: f32) -> Option<Event> {
+        if self.stopped {
+            return None;
+        }
+        self.age += dt;
+        self.vel.z -= gravity * dt;
+        let old_z = self.pos.z;
+        self.pos += self.vel * dt;
+
+        let surface = terrain::get_surface(level, self.pos);
+        let water = terrain::get_height(level.flood_level(self.pos.y as i32));
+        let over_water = surface.map_or(true, |(height, _)| height < water);
+        if over_water && old_z > water && self.pos.z <= water {
+            self.stopped = true;
+            self.pos.z = water;
+            return Some(Event {
+                impact: Impact::Splash,
+                terrain: None,
+                position: self.pos,
+                normal: cgmath::Vector3::unit_z(),
+                speed: self.vel.magnitude(),
+            });
+        }
+        None
+    }
+
+    /// Respond to hitting the `terrain` at `position`, which may be found
+    /// after the projectile has already flown past it.
+    pub fn hit(
+        &mut self,
+        level: &level::Level,
+        config: &Projectiles,
+        position: cgmath::Vector3<f32>,
+        terrain: level::TerrainType,
+    ) -> Option<Event> {
+        let speed = self.vel.magnitude();
+        if self.stopped || speed < EPSILON {
+            return None;
+        }
+        let normal = level.surface_normal(position);
+        let response = config.terrain[terrain as usize % config.terrain.len()];
+        let impact = match response {
+            ProjectileResponse::Ricochet {
+                max_angle,
+                restitution,
+            } => {
+                // angle between the velocity and the surface plane
+                let grazing = cgmath::Rad((-self.vel.dot(normal) / speed).min(1.0).asin());
+                self.pos = position;
+                if grazing < cgmath::Deg(max_angle).into() {
+                    self.vel = (self.vel - normal * 2.0 * self.vel.dot(normal)) * restitution;
+                    Impact::Ricochet
+                } else {
+                    self.stopped = true;
+                    Impact::Stop
+                }
+            }
+            ProjectileResponse::Penetrate { depth } => {
+                self.pos = position + self.vel * (depth / speed);
+                self.stopped = true;
+                Impact::Penetration
+            }
+        };
+
+        Some(Event {
+            impact,
+            terrain: Some(terrain),
+            position,
+            normal,
+            speed,
+        })
+    }
+}
@@ -70,7 +70,17 @@ impl RigidBody {
         self.wel_orig
     }
 
+    /// Velocity of a point, with all the pushes applied so far.
+    /// Used by the contact solver, which iterates on the impulses.
+    pub fn contact_velocity_at(&self, point: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        self.vel + self.pushed_angular_velocity().cross(point)
+    }
+
+    fn pushed_angular_velocity(&self) -> cgmath::Vector3<f32> {
+        self.wel_orig + self.j_inv * self.wel_raw
+    }
+
     pub fn finish(self) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
-        (self.vel, self.wel_orig + self.j_inv * self.wel_raw)
+        (self.vel, self.pushed_angular_velocity())
     }
 }
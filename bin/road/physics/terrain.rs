@@ -1,4 +1,11 @@
-use vangers::{config, level, model, space};
+use super::broad::Aabb;
+use vangers::{
+    config,
+    level::{self, get_middle},
+    model, space,
+};
+
+pub use vangers::level::get_height;
 
 use cgmath::prelude::*;
 
@@ -45,20 +52,6 @@ impl HitAccumulator {
     }
 }
 
-pub fn get_height(altitude: u8) -> f32 {
-    altitude as f32 * (level::HEIGHT_SCALE as f32) / 255.0
-}
-
-// see `GET_MIDDLE_HIGHT` macro
-fn get_middle(low: u8, high: u8) -> f32 {
-    let extra_room = if high.saturating_sub(low) > 130 {
-        110
-    } else {
-        48
-    };
-    get_height(low.saturating_add(extra_room))
-}
-
 pub fn get_distance_to_terrain(level: &level::Level, point: cgmath::Point3<f32>) -> f32 {
     let altitude = match level.get((point.x as i32, point.y as i32)) {
         level::Texel::Single(p) => p.0,
@@ -74,35 +67,186 @@ pub fn get_distance_to_terrain(level: &level::Level, point: cgmath::Point3<f32>)
     point.z - get_height(altitude)
 }
 
+/// Height and terrain type of the surface that a point at `pos` would collide with,
+/// or `None` if it's in the gap under the upper layer.
+pub fn get_surface(
+    level: &level::Level,
+    pos: cgmath::Vector3<f32>,
+) -> Option<(f32, level::TerrainType)> {
+    level.collision_surface(pos)
+}
+
+/// Height of the surface that a point at `pos` would collide with,
+/// or `None` if it's in the gap under the upper layer.
+pub fn get_surface_height(level: &level::Level, pos: cgmath::Vector3<f32>) -> Option<f32> {
+    get_surface(level, pos).map(|(height, _)| height)
+}
+
+/// Size of the side of a terrain grid chunk, in cells.
+const CHUNK_SIZE: i32 = 16;
+
+/// Highest surfaces of the level, gathered per chunk of cells.
+///
+/// Built once per level, it's the broadphase of the terrain collision:
+/// only the bodies reaching below the top of a chunk they overlap
+/// need to look at the cells under them.
+pub struct TerrainGrid {
+    /// Size of a chunk, smaller than `CHUNK_SIZE` for the tiny levels.
+    chunk: (i32, i32),
+    /// Number of the chunks along X and Y.
+    size: (i32, i32),
+    tops: Vec<f32>,
+}
+
+impl TerrainGrid {
+    pub fn new(level: &level::Level) -> Self {
+        let chunk = (CHUNK_SIZE.min(level.size.0), CHUNK_SIZE.min(level.size.1));
+        let size = (
+            (level.size.0 + chunk.0 - 1) / chunk.0,
+            (level.size.1 + chunk.1 - 1) / chunk.1,
+        );
+        let mut tops = vec![0.0f32; (size.0 * size.1) as usize];
+        for y in 0..level.size.1 {
+            for x in 0..level.size.0 {
+                let top = &mut tops[((y / chunk.1) * size.0 + x / chunk.0) as usize];
+                *top = top.max(get_height(level.get((x, y)).top()));
+            }
+        }
+        TerrainGrid { chunk, size, tops }
+    }
+
+    /// Chunks overlapped by the box that reach above its bottom,
+    /// wrapping around the level.
+    pub fn chunks_under(&self, aabb: &Aabb) -> Vec<(i32, i32)> {
+        let min = aabb.center - aabb.half_size;
+        let max = aabb.center + aabb.half_size;
+        // conservative range, matching the truncation of `Level::get` lookups
+        let start = (
+            (min.x.floor() as i32).div_euclid(self.chunk.0),
+            (min.y.floor() as i32).div_euclid(self.chunk.1),
+        );
+        let end = (
+            (max.x as i32 + 1)
+                .div_euclid(self.chunk.0)
+                .min(start.0 + self.size.0 - 1),
+            (max.y as i32 + 1)
+                .div_euclid(self.chunk.1)
+                .min(start.1 + self.size.1 - 1),
+        );
+        let mut chunks = Vec::new();
+        for y in start.1..=end.1 {
+            for x in start.0..=end.0 {
+                let coord = (x.rem_euclid(self.size.0), y.rem_euclid(self.size.1));
+                if self.tops[(coord.1 * self.size.0 + coord.0) as usize] >= min.z {
+                    chunks.push(coord);
+                }
+            }
+        }
+        chunks
+    }
+
+    /// Pairs of the box indices and the chunks they may collide with.
+    pub fn find_pairs(&self, boxes: &[Aabb]) -> Vec<(usize, (i32, i32))> {
+        boxes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, aabb)| {
+                self.chunks_under(aabb)
+                    .into_iter()
+                    .map(move |chunk| (i, chunk))
+            })
+            .collect()
+    }
+}
+
+/// Highest surfaces of the level cells overlapped by a box.
+///
+/// Gathered once per body and step, so that the collision sampling
+/// only touches the cells under the body, and is skipped entirely
+/// when the body is above all of them.
+pub struct TerrainPatch {
+    origin: (i32, i32),
+    size: (i32, i32),
+    tops: Vec<f32>,
+    max: f32,
+}
+
+impl TerrainPatch {
+    pub fn new(level: &level::Level, aabb: &Aabb) -> Self {
+        let min = aabb.center - aabb.half_size;
+        let max = aabb.center + aabb.half_size;
+        let origin = (min.x.floor() as i32, min.y.floor() as i32);
+        // conservative upper bound, matching the truncation of `Level::get` lookups
+        let size = (
+            max.x as i32 + 1 - origin.0 + 1,
+            max.y as i32 + 1 - origin.1 + 1,
+        );
+        let mut tops = Vec::with_capacity((size.0 * size.1) as usize);
+        for y in origin.1..origin.1 + size.1 {
+            for x in origin.0..origin.0 + size.0 {
+                let altitude = match level.get((x, y)) {
+                    level::Texel::Single(p) => p.0,
+                    level::Texel::Dual { high, .. } => high.0,
+                };
+                tops.push(get_height(altitude));
+            }
+        }
+        let max = tops.iter().cloned().fold(0.0, f32::max);
+        TerrainPatch {
+            origin,
+            size,
+            tops,
+            max,
+        }
+    }
+
+    /// Height of the highest surface in the patch.
+    pub fn max_height(&self) -> f32 {
+        self.max
+    }
+
+    /// Height of the highest surface in the cell of `pos`,
+    /// or `None` if it's outside of the patch.
+    pub fn top_at(&self, pos: cgmath::Vector3<f32>) -> Option<f32> {
+        let x = pos.x as i32 - self.origin.0;
+        let y = pos.y as i32 - self.origin.1;
+        if x >= 0 && y >= 0 && x < self.size.0 && y < self.size.1 {
+            Some(self.tops[(y * self.size.0 + x) as usize])
+        } else {
+            None
+        }
+    }
+}
+
 impl CollisionData {
+    pub fn empty() -> Self {
+        CollisionData {
+            soft: None,
+            hard: None,
+        }
+    }
+
     pub fn collide_low(
         poly: &model::Polygon,
         samples: &[model::RawVertex],
         scale: f32,
         transform: &space::Transform,
         level: &level::Level,
+        patch: &TerrainPatch,
         terraconf: &config::common::Terrain,
     ) -> Self {
         let (mut soft, mut hard) = (HitAccumulator::new(), HitAccumulator::new());
         for s in samples[poly.samples.clone()].iter() {
             let sp = cgmath::Point3::from(*s).cast::<f32>().unwrap();
             let pos = transform.transform_point(sp * scale).to_vec();
-            let texel = level.get((pos.x as i32, pos.y as i32));
-            let height = match texel {
-                level::Texel::Single(point) => get_height(point.0),
-                level::Texel::Dual { high, low, .. } => {
-                    let middle = get_middle(low.0, high.0);
-                    if pos.z > middle {
-                        let top = get_height(high.0);
-                        if pos.z - middle > top - pos.z {
-                            top
-                        } else {
-                            continue;
-                        }
-                    } else {
-                        get_height(low.0)
-                    }
-                }
+            match patch.top_at(pos) {
+                // above the highest surface of the cell, no contact possible
+                Some(top) if pos.z >= top => continue,
+                _ => {}
+            }
+            let height = match get_surface_height(level, pos) {
+                Some(height) => height,
+                None => continue,
             };
             let dz = height - pos.z;
             //log::debug!("\t\t\tSample h={:?} at {:?}, dz={}", height, pos, dz);
@@ -120,3 +264,54 @@ impl CollisionData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Flat level of 64x64 cells, with a tower in the chunk at `(1, 2)`.
+    fn tower_level() -> level::Level {
+        let size = (64, 64);
+        let mut level = level::Level::new_test();
+        level.size = size;
+        level.height = (0..size.1)
+            .flat_map(|y| (0..size.0).map(move |x| (x, y)))
+            .map(|(x, y)| if x / 16 == 1 && y / 16 == 2 { 200 } else { 10 })
+            .collect();
+        level.meta = vec![0; (size.0 * size.1) as usize];
+        level
+    }
+
+    fn body(x: f32, y: f32, z: f32) -> Aabb {
+        Aabb::from_sphere(cgmath::vec3(x, y, z), 2.0)
+    }
+
+    #[test]
+    fn bodies_far_from_the_terrain_have_no_pairs() {
+        let grid = TerrainGrid::new(&tower_level());
+        let above_ground = get_height(10) + 10.0;
+        let boxes = [body(40.0, 8.0, above_ground), body(8.0, 56.0, above_ground)];
+        assert_eq!(grid.find_pairs(&boxes), Vec::new());
+    }
+
+    #[test]
+    fn bodies_pair_with_the_chunks_they_reach() {
+        let grid = TerrainGrid::new(&tower_level());
+        let above_ground = get_height(10) + 10.0;
+        // next to the tower, only reaching its chunk
+        let boxes = [
+            body(31.0, 40.0, above_ground),
+            body(8.0, 8.0, get_height(10)),
+        ];
+        assert_eq!(grid.find_pairs(&boxes), vec![(0, (1, 2)), (1, (0, 0))]);
+    }
+
+    #[test]
+    fn chunks_wrap_around_the_level() {
+        let grid = TerrainGrid::new(&tower_level());
+        let on_ground = get_height(10);
+        let mut chunks = grid.chunks_under(&body(-1.0, 64.0, on_ground));
+        chunks.sort();
+        assert_eq!(chunks, vec![(0, 0), (0, 3), (3, 0), (3, 3)]);
+    }
+}
@@ -0,0 +1,158 @@
+use m3d::Mesh;
+use vangers::{
+    config::{game::Registry, settings},
+    level, model,
+    render::{body::GpuBody, object, Batcher},
+    space,
+};
+
+use cgmath::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use std::sync::Arc;
+
+/// Side of the square areas the props are grouped in, in texels.
+/// The distance culling is done per area.
+const AREA_SIZE: i32 = 64;
+
+struct Prop {
+    mesh: Arc<model::Mesh>,
+    transform: space::Transform,
+}
+
+struct Area {
+    center: cgmath::Vector2<f32>,
+    props: Vec<Prop>,
+}
+
+/// Small static meshes scattered over the level.
+pub struct Props {
+    areas: Vec<Area>,
+    max_distance: f32,
+    wrap: cgmath::Vector2<f32>,
+}
+
+impl Props {
+    /// Place the props of all the kinds over the level.
+    /// The result only depends on the level and the `seed`.
+    pub fn scatter(
+        settings: &settings::Settings,
+        registry: &Registry,
+        level: &level::Level,
+        device: &wgpu::Device,
+    ) -> Self {
+        let config = &settings.game.props;
+        let kinds = config
+            .kinds
+            .iter()
+            .filter_map(|kind| match registry.model_infos.get(&kind.model) {
+                Some(info) => {
+                    let raw = Mesh::load(&mut settings.open_relative(&info.path));
+                    let mesh = model::load_c3d(raw, device, !settings.render.debug.raw_vertices);
+                    Some((kind, mesh, info.scale))
+                }
+                None => {
+                    log::warn!("Unknown prop model {}", kind.model);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let mut areas = Vec::new();
+        for ay in (0..level.size.1).step_by(AREA_SIZE as usize) {
+            for ax in (0..level.size.0).step_by(AREA_SIZE as usize) {
+                let mut props = Vec::new();
+                for &(kind, ref mesh, model_scale) in kinds.iter() {
+                    // density is given per a million texels
+                    let expected = kind.density * (AREA_SIZE * AREA_SIZE) as f32 * 1.0e-6;
+                    let count = (expected + rng.gen::<f32>()) as usize;
+                    for _ in 0..count {
+                        let x = ax + rng.gen_range(0, AREA_SIZE);
+                        let y = ay + rng.gen_range(0, AREA_SIZE);
+                        let angle = rng.gen_range(0.0, 1.0);
+                        let scale = rng.gen_range(kind.scale.0, kind.scale.1 + 1.0e-6);
+                        // the top surface, as seen from above the level
+                        let sky = cgmath::vec3(x as f32, y as f32, level::HEIGHT_SCALE as f32);
+                        let (height, terrain) = match level.collision_surface(sky) {
+                            Some(surface) => surface,
+                            None => continue,
+                        };
+                        if !kind.terrains.contains(&terrain) {
+                            continue;
+                        }
+                        props.push(Prop {
+                            mesh: Arc::clone(mesh),
+                            transform: space::Transform {
+                                disp: cgmath::vec3(x as f32, y as f32, height),
+                                rot: cgmath::Quaternion::from_angle_z(
+                                    cgmath::Rad::full_turn() * angle,
+                                ),
+                                scale: scale * model_scale,
+                            },
+                        });
+                    }
+                }
+                if !props.is_empty() {
+                    let half = 0.5 * AREA_SIZE as f32;
+                    areas.push(Area {
+                        center: cgmath::vec2(ax as f32 + half, ay as f32 + half),
+                        props,
+                    });
+                }
+            }
+        }
+        log::info!(
+            "Scattered {} props",
+            areas.iter().map(|a| a.props.len()).sum::<usize>()
+        );
+
+        Props {
+            areas,
+            max_distance: config.max_distance,
+            wrap: cgmath::vec2(level.size.0 as f32, level.size.1 as f32),
+        }
+    }
+
+    /// Add the props within the distance from `center` to the batch.
+    /// `clip` is called with the world position of each prop,
+    /// returning true for the ones not to be drawn.
+    pub fn draw(
+        &self,
+        batcher: &mut Batcher,
+        center: cgmath::Vector3<f32>,
+        clip: impl Fn(&cgmath::Vector3<f32>) -> bool,
+    ) {
+        let reach = self.max_distance + AREA_SIZE as f32;
+        for area in self.areas.iter() {
+            // shift the area to the copy of the level closest to the center
+            let offset = area.center - center.truncate();
+            let shift = cgmath::vec2(
+                -(offset.x / self.wrap.x).round() * self.wrap.x,
+                -(offset.y / self.wrap.y).round() * self.wrap.y,
+            );
+            if (offset + shift).magnitude2() > reach * reach {
+                continue;
+            }
+            for prop in area.props.iter() {
+                let mut transform = prop.transform;
+                transform.disp += shift.extend(0.0);
+                if (transform.disp - center).truncate().magnitude2()
+                    > self.max_distance * self.max_distance
+                    || clip(&transform.disp)
+                {
+                    continue;
+                }
+                batcher.add_mesh(
+                    &prop.mesh,
+                    object::Instance::new(
+                        &transform,
+                        0.0,
+                        &GpuBody::ZERO,
+                        object::BodyColor::Dummy,
+                    ),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use vangers::{level, space};
+
+use cgmath::prelude::*;
+
+use std::fmt;
+
+/// Maximum distance of the ray cast, in world units.
+const MAX_DISTANCE: f32 = 2000.0;
+
+/// Terrain information at the point under the screen center.
+pub struct Readout {
+    pub position: cgmath::Point3<f32>,
+    pub terrain: level::TerrainType,
+    pub water_depth: f32,
+}
+
+impl Readout {
+    /// Cast a ray from the camera on the CPU, returning the first terrain hit.
+    pub fn probe(cam: &space::Camera, level: &level::Level) -> Option<Self> {
+        let dir = cam.dir();
+        let mut pos = cgmath::Point3::from_vec(cam.loc);
+        let mut distance = 0.0;
+        while distance < MAX_DISTANCE {
+            // the gap under the upper layer of a double level has no surface
+            if let Some((height, terrain)) = level
+                .collision_surface(pos.to_vec())
+                .filter(|&(height, _)| pos.z <= height)
+            {
+                let flood = level::get_height(level.flood_level(pos.y as i32));
+                return Some(Readout {
+                    position: cgmath::Point3::new(pos.x, pos.y, height),
+                    terrain,
+                    water_depth: if terrain == 0 {
+                        (flood - height).max(0.0)
+                    } else {
+                        0.0
+                    },
+                });
+            }
+            pos += dir;
+            distance += 1.0;
+        }
+        None
+    }
+}
+
+impl fmt::Display for Readout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "pos ({:.0}, {:.0}), height {:.1}, type {}, water {:.1}",
+            self.position.x, self.position.y, self.position.z, self.terrain, self.water_depth
+        )
+    }
+}
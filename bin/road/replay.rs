@@ -0,0 +1,455 @@
+use crate::physics;
+use vangers::replay::{self, Frame, Replay};
+
+use std::path::{Path, PathBuf};
+
+/// Duration of a recorded step. The game runs a single step per frame
+/// while recording, so that the replay doesn't depend on the frame rate.
+const TIME_STEP: f32 = 1.0 / 60.0;
+
+/// Put the recorded input of a frame into the controls of the vehicle.
+pub fn apply(frame: &Frame, control: &mut physics::Control, jump: &mut Option<f32>) {
+    control.motor = frame.motor;
+    control.rudder = frame.rudder;
+    control.roll = frame.roll;
+    control.turbo = frame.turbo;
+    *jump = frame.jump;
+}
+
+/// Input of the vehicle to be recorded for a frame.
+pub fn record(control: &physics::Control, jump: Option<f32>) -> Frame {
+    Frame {
+        motor: control.motor,
+        rudder: control.rudder,
+        roll: control.roll,
+        turbo: control.turbo,
+        jump,
+    }
+}
+
+pub enum Session {
+    Record {
+        path: PathBuf,
+        replay: Replay,
+    },
+    Play {
+        path: PathBuf,
+        replay: Replay,
+        position: usize,
+    },
+}
+
+impl Session {
+    pub fn record(path: &Path) -> Self {
+        Session::Record {
+            path: path.to_path_buf(),
+            replay: Replay::new(TIME_STEP),
+        }
+    }
+
+    pub fn play(path: &Path) -> Result<Self, String> {
+        let replay = Replay::load(path)
+            .map_err(|e| format!("Unable to load the replay {}: {}", path.display(), e))?;
+        log::info!(
+            "Playing {} frames from {}",
+            replay.frames.len(),
+            path.display()
+        );
+        Ok(Session::Play {
+            path: path.to_path_buf(),
+            replay,
+            position: 0,
+        })
+    }
+
+    /// Fixed duration of the simulated steps.
+    pub fn time_step(&self) -> f32 {
+        match *self {
+            Session::Record { ref replay, .. } | Session::Play { ref replay, .. } => {
+                replay.time_step
+            }
+        }
+    }
+
+    /// Returns true if there are frames left to play.
+    pub fn is_playing(&self) -> bool {
+        match *self {
+            Session::Record { .. } => false,
+            Session::Play {
+                ref replay,
+                position,
+                ..
+            } => position < replay.frames.len(),
+        }
+    }
+
+    /// Returns the next frame to play, or `None` if the replay is over.
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        match *self {
+            Session::Record { .. } => None,
+            Session::Play {
+                ref replay,
+                ref mut position,
+                ..
+            } => {
+                let frame = replay.frames.get(*position).cloned();
+                *position += 1;
+                frame
+            }
+        }
+    }
+
+    pub fn push(&mut self, frame: Frame) {
+        if let Session::Record { ref mut replay, .. } = *self {
+            replay.frames.push(frame);
+        }
+    }
+
+    /// Saves the recorded frames, together with the golden hash of the final state.
+    pub fn finish_recording(&self, state_hash: u64) {
+        if let Session::Record {
+            ref path,
+            ref replay,
+        } = *self
+        {
+            log::info!(
+                "Saving {} frames into {}",
+                replay.frames.len(),
+                path.display()
+            );
+            let result = replay
+                .save(path)
+                .and_then(|()| replay::write_golden(path, state_hash));
+            if let Err(e) = result {
+                log::error!("Unable to save the replay {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Compares the final state hash against the golden of the played replay.
+    pub fn check_golden(&self, state_hash: u64) -> Result<(), String> {
+        let path = match *self {
+            Session::Play { ref path, .. } => path,
+            Session::Record { .. } => return Ok(()),
+        };
+        let golden = replay::read_golden(path)
+            .map_err(|e| format!("Unable to read the golden of {}: {}", path.display(), e))?;
+        if golden == state_hash {
+            Ok(())
+        } else {
+            Err(format!(
+                "State hash {:016x} doesn't match the golden {:016x}",
+                state_hash, golden
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vangers::{
+        config::{
+            car::CarPhysics,
+            common::{self, VelocityPair},
+        },
+        level, model, space,
+    };
+
+    use cgmath::One as _;
+    use std::sync::Arc;
+
+    const SIZE: (i32, i32) = (128, 128);
+    const GROUND: u8 = 64;
+    const MAX_QUANT: f32 = 0.1;
+
+    fn pair(v: f32, w: f32) -> VelocityPair {
+        VelocityPair { v, w }
+    }
+
+    /// Made-up parameters standing in for `common.prm`,
+    /// which is not available without the resources of the game.
+    fn parameters() -> common::Common {
+        common::Common {
+            nature: common::Nature {
+                gravity: 10.0,
+                density: 1.0,
+                time_delta0: 0.5,
+                scale_general: 1.0,
+                num_calls_analysis: 1,
+                movement_detection_threshold: 1,
+            },
+            impulse: common::Impulse {
+                elastic_restriction: 20.0,
+                elastic_time_scale_factor: 1.0,
+                rolling_scale: 0.5,
+                normal_threshold: 0.3,
+                k_wheel: 0.5,
+                factors: [0.5, 0.5],
+                k_friction: 0.5,
+            },
+            car: common::Car {
+                rudder_step: 0.05,
+                rudder_max: 0.5,
+                rudder_k_decr: 0.01,
+                traction_incr: 0.1,
+                traction_decr: 0.05,
+            },
+            global: common::Global {
+                speed_factor: 1.0,
+                mobility_factor: 1.0,
+                water_speed_factor: 1.0,
+                air_speed_factor: 1.0,
+                underground_speed_factor: 1.0,
+                k_traction_turbo: 2.0,
+                f_brake_max: 1.0,
+            },
+            heli: common::Helicopter {
+                max_height: 100,
+                height_incr: 1,
+                height_decr: 1,
+                k_thrust: 1.0,
+                k_rotate: 1.0,
+                k_strife: 1.0,
+                max_time: 100,
+                convert: [1.0; 2],
+                rudder_decr: 1.0,
+                traction_decr: 1.0,
+                z_offset: 0.0,
+                ampl: 1.0,
+                dphi: 1,
+                circle_radius: [1.0; 2],
+                circle_dphi: 1,
+            },
+            drag: common::Drag {
+                speed: pair(0.999, 0.99),
+                wheel_speed: 0.95,
+                z: 0.99,
+                free: pair(0.95, 0.9),
+                wheel: pair(0.9, 0.9),
+                spring: pair(0.9, 0.8),
+                coll: pair(0.9, 0.9),
+                helicopter: pair(0.9, 0.9),
+                float: pair(0.8, 0.8),
+                friction: pair(0.9, 0.9),
+                abs_stop: pair(0.01, 0.001),
+                stuff: 0.9,
+                swamp: 0.9,
+                mole: 0.9,
+                abs_min: pair(0.5, 0.05),
+            },
+            terrain: common::Terrain {
+                dz_max: 20.0,
+                min_wall_delta: 8.0,
+            },
+            mole: common::Mole {
+                k_elastic_mole: 1.0,
+                k_mole: 1.0,
+                k_mole_rudder: 1.0,
+                mole_emerging_fz: 1.0,
+                mole_submerging_fz: 1.0,
+            },
+            contact: common::Contact {
+                k_elastic_wheel: 1.0,
+                k_elastic_spring: 1.0,
+                k_elastic_xy: 1.0,
+                k_elastic_db_coll: 1.0,
+                k_destroy_level: 1.0,
+                strong_ground_collision_threshold: 10.0,
+                strong_double_collision_threshold: 10.0,
+                k_friction_wheel_x: 1.0,
+                k_friction_wheel_x_back: 1.0,
+                k_friction_wheel_y: 1.0,
+                k_friction_wheel_z: 1.0,
+                k_friction_spring: 1.0,
+            },
+            force: common::Force {
+                f_spring_impulse: 5.0,
+                k_spring_impulse: 1.0,
+                f_traction_impulse: 1.0,
+                k_distance_to_force: 50.0,
+                explosion: pair(1.0, 1.0),
+                max_jump_power: 1.0,
+                side_impulse_delay: 10,
+                side_impulse_duration: 4,
+            },
+            speed: common::Speed {
+                standard_frame_rate: 14,
+                speed_correction_tau: 1.6e-2,
+            },
+        }
+    }
+
+    fn car_physics() -> CarPhysics {
+        CarPhysics {
+            name: "test".to_string(),
+            scale_size: 1.0,
+            scale_bound: 1.0,
+            scale_box: 1.0,
+            z_offset_of_mass_center: 0.0,
+            speed_factor: 1.0,
+            mobility_factor: 1.0,
+            water_speed_factor: 1.0,
+            air_speed_factor: 1.0,
+            underground_speed_factor: 1.0,
+            k_archimedean: 1.0,
+            k_water_traction: 1.0,
+            k_water_rudder: 1.0,
+            terra_mover_sx: [0.0; 3],
+            defence: [0; 5],
+            ram_power: [0; 5],
+        }
+    }
+
+    /// Box of 24x40x12 standing on four wheels, with the bottom split into
+    /// a polygon per quarter, each having a sample in every corner.
+    fn shape() -> (Vec<model::Polygon>, Vec<model::RawVertex>) {
+        let mut polygons = Vec::new();
+        let mut samples = Vec::new();
+        for &(sx, sy) in &[(-1i8, -1i8), (1, -1), (-1, 1), (1, 1)] {
+            let start = samples.len();
+            for &(x, y) in &[(3, 5), (9, 5), (3, 15), (9, 15)] {
+                samples.push([sx * x, sy * y, -6]);
+            }
+            polygons.push(model::Polygon {
+                middle: [sx as f32 * 6.0, sy as f32 * 10.0, -6.0],
+                normal: [0.0, 0.0, -1.0],
+                samples: start..samples.len(),
+            });
+        }
+        (polygons, samples)
+    }
+
+    fn wheels() -> Vec<m3d::Wheel<Arc<model::Mesh>>> {
+        [(-12.0, -14.0), (12.0, -14.0), (-12.0, 14.0), (12.0, 14.0)]
+            .iter()
+            .map(|&(x, y)| m3d::Wheel {
+                mesh: None,
+                // the front wheels are steered
+                steer: if y > 0.0 { 1 } else { 0 },
+                pos: [x, y, -4.0],
+                width: 4,
+                radius: 6,
+                bound_index: 0,
+            })
+            .collect()
+    }
+
+    /// Play the replay on a single vehicle without any window or GPU,
+    /// stepping it the same way `Game::update` does on CPU.
+    fn run(replay: &Replay) -> u64 {
+        let common = parameters();
+        let car_physics = car_physics();
+        let mass = m3d::Physics {
+            volume: 11520.0,
+            rcm: [0.0; 3],
+            jacobi: [
+                [1674240.0, 0.0, 0.0],
+                [0.0, 691200.0, 0.0],
+                [0.0, 0.0, 2088960.0],
+            ],
+        };
+        let bbox = model::BoundingBox {
+            min: [-12.0, -20.0, -6.0],
+            max: [12.0, 20.0, 6.0],
+            radius: 24.0,
+        };
+        let (polygons, samples) = shape();
+        let wheels = wheels();
+        let chassis = physics::Chassis {
+            physics: &car_physics,
+            mass: &mass,
+            bbox: &bbox,
+            polygons: &polygons,
+            samples: &samples,
+            wheels: &wheels,
+        };
+        let level = level::Level::new_flat(SIZE, GROUND, 1);
+        let grid = physics::TerrainGrid::new(&level);
+
+        let mut dynamo = physics::Dynamo::default();
+        let mut transform = space::Transform {
+            scale: 1.0,
+            disp: cgmath::vec3(64.0, 64.0, level::get_height(GROUND) + 8.0),
+            rot: cgmath::Quaternion::one(),
+        };
+        let mut control = physics::Control::default();
+        let mut jump = None;
+
+        let input_factor = replay.time_step / common::MAIN_LOOP_TIME;
+        let physics_dt = replay.time_step * {
+            let n = &common.nature;
+            let fps = common.speed.standard_frame_rate as f32;
+            fps * n.time_delta0 * n.num_calls_analysis as f32
+        };
+        for frame in replay.frames.iter() {
+            apply(frame, &mut control, &mut jump);
+            physics::apply_control(&mut dynamo, &control, jump.is_some(), input_factor, &common);
+            let mut dt = physics_dt;
+            while dt > MAX_QUANT {
+                physics::step_vehicle(
+                    &mut dynamo,
+                    &mut transform,
+                    MAX_QUANT,
+                    &chassis,
+                    &level,
+                    &grid,
+                    &common,
+                    &control,
+                    None,
+                    0.0,
+                    None,
+                );
+                dt -= MAX_QUANT;
+            }
+            physics::step_vehicle(
+                &mut dynamo,
+                &mut transform,
+                dt,
+                &chassis,
+                &level,
+                &grid,
+                &common,
+                &control,
+                jump.take(),
+                control.roll,
+                None,
+            );
+        }
+        replay::hash_state(&transform, dynamo.linear_velocity, dynamo.angular_velocity)
+    }
+
+    fn drive() -> Replay {
+        Replay::load(Path::new("tests/replays/drive.ron")).unwrap()
+    }
+
+    #[test]
+    fn replay_is_deterministic() {
+        let replay = drive();
+        assert_eq!(run(&replay), run(&replay));
+    }
+
+    #[test]
+    fn replay_depends_on_the_input() {
+        let replay = drive();
+        let idle = Replay {
+            time_step: replay.time_step,
+            frames: vec![Frame::default(); replay.frames.len()],
+        };
+        assert_ne!(run(&replay), run(&idle));
+    }
+
+    #[test]
+    fn replay_matches_golden() {
+        let path = Path::new("tests/replays/drive.ron");
+        let state_hash = run(&drive());
+        match replay::read_golden(path) {
+            Ok(golden) => assert_eq!(format!("{:016x}", state_hash), format!("{:016x}", golden)),
+            // the first run records the golden, to be checked in
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+                replay::write_golden(path, state_hash).unwrap()
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use vangers::config::car::CarInfo;
+use winit::event::VirtualKeyCode as Key;
+
+/// Relative change of a parameter per key press.
+const STEP: f32 = 0.05;
+
+/// Keyboard-driven panel for live tuning of the vehicle physics.
+///
+/// `Tab` toggles the panel, `Up`/`Down` select a parameter,
+/// `Left`/`Right` change it, and `Return` saves the changes into a copy of the PRM file
+/// in `config/physics`, which is loaded instead of the original from then on.
+#[derive(Default)]
+pub struct Panel {
+    active: bool,
+    selected: usize,
+}
+
+impl Panel {
+    /// Handles a key press, returns `true` if the key was consumed by the panel.
+    pub fn on_key(&mut self, key: Key, car: &mut CarInfo) -> bool {
+        if key == Key::Tab {
+            self.active = !self.active;
+            if self.active {
+                log::info!("Tuning {}:", car.physics.name);
+                for (name, value) in car.physics.tunables_mut().iter() {
+                    log::info!("\t{} {}", name, value);
+                }
+            }
+            return true;
+        }
+        if !self.active {
+            return false;
+        }
+        if key == Key::Return {
+            log::info!("Saving physics to {}", car.tuned_path.display());
+            match car.physics.save(&car.physics_path, &car.tuned_path) {
+                Ok(()) => car.physics_path = car.tuned_path.clone(),
+                Err(e) => log::error!("Unable to save physics: {}", e),
+            }
+            return true;
+        }
+
+        let mut tunables = car.physics.tunables_mut();
+        let count = tunables.len();
+        match key {
+            Key::Up => self.selected = (self.selected + count - 1) % count,
+            Key::Down => self.selected = (self.selected + 1) % count,
+            Key::Left | Key::Right => {
+                let value = &mut *tunables[self.selected].1;
+                let delta = if *value == 0.0 {
+                    STEP
+                } else {
+                    value.abs() * STEP
+                };
+                *value += if key == Key::Right { delta } else { -delta };
+            }
+            _ => return false,
+        }
+        let (name, ref value) = tunables[self.selected];
+        log::info!("\t{} {}", name, value);
+        true
+    }
+}
@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 pub const NORMALIZER: f32 = 124.0;
 pub const NUM_COLOR_IDS: u32 = 25;
 
@@ -31,7 +33,7 @@ pub enum ColorId {
     RottenItem = 24,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Vertex {
     pub pos: u16,
     pub normal: u16,
@@ -44,19 +46,21 @@ impl Vertex {
     };
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct DrawTriangle {
     pub vertices: [Vertex; 3],
     pub flat_normal: [i8; 3],
     pub material: [u32; 2],
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct CollisionQuad {
     pub vertices: [u16; 4],
     pub middle: [i8; 3],
     pub flat_normal: [i8; 3],
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct Geometry<P> {
     pub positions: Vec<[i8; 3]>,
     pub normals: Vec<[i8; 3]>,
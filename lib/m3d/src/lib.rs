@@ -6,10 +6,34 @@ pub use self::geometry::{
 
 use byteorder::{LittleEndian as E, ReadBytesExt, WriteBytesExt};
 use serde::{Deserialize, Serialize};
-use std::{fs::File, io::Write};
+use std::fmt;
+use std::io::{Read, Write};
 
 const MAX_SLOTS: usize = 3;
-const MAGIC_VERSION: u32 = 8;
+
+/// Model that can't be written in the M3D format.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InvalidModel {
+    /// Wheel at this index has a mesh without steering, or steering without a mesh,
+    /// since the format only stores the meshes of the steering wheels.
+    WheelMesh(usize),
+}
+
+impl fmt::Display for InvalidModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvalidModel::WheelMesh(index) => write!(
+                f,
+                "wheel {} has to have a mesh if and only if it's steering",
+                index
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvalidModel {}
+/// Version of the meshes in M3D and A3D files.
+pub const MAGIC_VERSION: u32 = 8;
 
 fn read_vec_i32<I: ReadBytesExt>(source: &mut I) -> [i32; 3] {
     [
@@ -310,7 +334,11 @@ impl<G> Mesh<G> {
 impl<P: Polygon> Mesh<Geometry<P>> {
     pub fn load<I: ReadBytesExt>(source: &mut I) -> Self {
         let version = source.read_u32::<E>().unwrap();
-        assert_eq!(version, MAGIC_VERSION);
+        assert_eq!(
+            version, MAGIC_VERSION,
+            "Unsupported mesh version {}",
+            version
+        );
         let num_positions = source.read_u32::<E>().unwrap();
         let num_normals = source.read_u32::<E>().unwrap();
         let num_polygons = source.read_u32::<E>().unwrap();
@@ -459,7 +487,7 @@ pub struct AnimatedMesh<G> {
 }
 
 impl<P: Polygon> AnimatedMesh<Geometry<P>> {
-    pub fn load(mut input: File) -> Self {
+    pub fn load<I: Read>(mut input: I) -> Self {
         let count = input.read_u32::<E>().unwrap();
         AnimatedMesh {
             bound: UpperBound::read(&mut input),
@@ -468,7 +496,7 @@ impl<P: Polygon> AnimatedMesh<Geometry<P>> {
         }
     }
 
-    pub fn save(&self, mut output: File) {
+    pub fn save<W: Write>(&self, mut output: W) {
         output.write_u32::<E>(self.meshes.len() as u32).unwrap();
         self.bound.write(&mut output);
         self.color.write(&mut output);
@@ -481,7 +509,7 @@ impl<P: Polygon> AnimatedMesh<Geometry<P>> {
 pub type FullModel = Model<DrawMesh, CollisionMesh>;
 
 impl FullModel {
-    pub fn load(mut input: File) -> Self {
+    pub fn load<I: Read>(mut input: I) -> Self {
         log::debug!("\tReading the body...");
         let body: DrawMesh = Mesh::load(&mut input);
 
@@ -552,7 +580,17 @@ impl FullModel {
         }
     }
 
-    pub fn save(&self, mut output: File) {
+    /// Write the model, after checking that the format can describe it.
+    pub fn save<W: Write>(&self, mut output: W) -> Result<(), InvalidModel> {
+        // the mesh is only stored for the steering wheels
+        if let Some(index) = self
+            .wheels
+            .iter()
+            .position(|wheel| (wheel.steer != 0) != wheel.mesh.is_some())
+        {
+            return Err(InvalidModel::WheelMesh(index));
+        }
+
         self.body.save(&mut output);
         self.bound.write(&mut output);
         output.write_u32::<E>(self.wheels.len() as u32).unwrap();
@@ -579,7 +617,12 @@ impl FullModel {
 
         self.shape.save(&mut output);
 
-        let slot_mask = 0; //TODO?
+        let slot_mask = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.mesh.is_some() || slot.pos != [0; 3] || slot.angle != 0)
+            .fold(0u32, |mask, (i, _)| mask | 1 << i);
         output.write_u32::<E>(slot_mask).unwrap();
         for slot in &self.slots {
             for p in &slot.pos {
@@ -587,5 +630,6 @@ impl FullModel {
             }
             output.write_i32::<E>(slot.angle).unwrap()
         }
+        Ok(())
     }
 }
@@ -1,5 +1,9 @@
 use byteorder::{LittleEndian as E, ReadBytesExt, WriteBytesExt};
-use std::io::{Read, Write};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io::{Read, Write},
+};
 
 pub struct Splay {
     tree1: [i32; 512],
@@ -36,6 +40,92 @@ impl Splay {
         }
     }
 
+    /// Build the trees from the frequencies of the symbols in the rows
+    /// of heights and metas, the same way the original encoder does.
+    pub fn from_rows<'a, I>(rows: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    {
+        let mut freq1 = [0u64; 256];
+        let mut freq2 = [0u64; 256];
+        for (input1, input2) in rows {
+            let mut last_char = 0u8;
+            for &b in input1 {
+                freq1[b.wrapping_sub(last_char) as usize] += 1;
+                last_char = b;
+            }
+            last_char = 0;
+            for &b in input2 {
+                freq2[(b ^ last_char) as usize] += 1;
+                last_char = b;
+            }
+        }
+        Splay {
+            tree1: Self::build_tree(&freq1),
+            tree2: Self::build_tree(&freq2),
+        }
+    }
+
+    /// Construct a Huffman tree in the layout of the decoder: the internal node `c`
+    /// has its children at `2c` and `2c + 1`, which are either the positive indices
+    /// of other internal nodes, or the negated symbols of the leaves.
+    fn build_tree(freq: &[u64; 256]) -> [i32; 512] {
+        enum Node {
+            Leaf(u8),
+            Internal(usize, usize),
+        }
+        let mut nodes = Vec::new();
+        let mut heap = BinaryHeap::new();
+        for (symbol, &count) in freq.iter().enumerate() {
+            if count != 0 {
+                heap.push(Reverse((count, nodes.len())));
+                nodes.push(Node::Leaf(symbol as u8));
+            }
+        }
+        // the root has to be an internal node, even if there are less than 2 symbols
+        for symbol in 0..2 {
+            if heap.len() < 2 {
+                heap.push(Reverse((0, nodes.len())));
+                nodes.push(Node::Leaf(symbol));
+            }
+        }
+        while heap.len() > 1 {
+            let Reverse((count0, id0)) = heap.pop().unwrap();
+            let Reverse((count1, id1)) = heap.pop().unwrap();
+            heap.push(Reverse((count0 + count1, nodes.len())));
+            nodes.push(Node::Internal(id0, id1));
+        }
+
+        // number the internal nodes in the breadth-first order, starting with the root
+        let mut tree = [0i32; 512];
+        let mut queue = vec![heap.pop().unwrap().0 .1];
+        let mut next_index = 1;
+        while next_index <= queue.len() {
+            let (left, right) = match nodes[queue[next_index - 1]] {
+                Node::Internal(left, right) => (left, right),
+                Node::Leaf(_) => unreachable!(),
+            };
+            for (bit, &child) in [left, right].iter().enumerate() {
+                tree[(next_index << 1) + bit] = match nodes[child] {
+                    Node::Leaf(symbol) => -(symbol as i32),
+                    Node::Internal(..) => {
+                        queue.push(child);
+                        queue.len() as i32
+                    }
+                };
+            }
+            next_index += 1;
+        }
+        tree
+    }
+
+    /// Write the trees, for `Splay::new` to read them back.
+    pub fn write<O: WriteBytesExt>(&self, output: &mut O) {
+        for &v in self.tree1.iter().chain(self.tree2.iter()) {
+            output.write_i32::<E>(v).unwrap();
+        }
+    }
+
     pub fn tree_size() -> u64 {
         512 * 2 * 4
     }
@@ -105,6 +195,60 @@ impl Splay {
         assert_eq!(off1 + off2, input.len());
     }
 
+    /// Collect the codes of the symbols as (bits, length), by walking the tree.
+    fn codes(tree: &[i32]) -> Vec<Option<(u64, u8)>> {
+        let mut codes = vec![None; 256];
+        let mut stack = vec![(1usize, 0u64, 0u8)];
+        while let Some((index, bits, length)) = stack.pop() {
+            for bit in 0..2 {
+                let code = (bits << 1) | bit as u64;
+                match tree[(index << 1) + bit] {
+                    child if child > 0 => stack.push((child as usize, code, length + 1)),
+                    leaf => codes[-leaf as usize] = Some((code, length + 1)),
+                }
+            }
+        }
+        codes
+    }
+
+    fn compress<F: Fn(u8, u8) -> u8>(
+        codes: &[Option<(u64, u8)>],
+        input: &[u8],
+        output: &mut Vec<u8>,
+        fun: F,
+    ) {
+        let mut last_char = 0u8;
+        let mut cur = 0u8;
+        let mut bit = 8;
+        for &b in input {
+            let (code, length) = codes[fun(last_char, b) as usize]
+                .expect("Symbol is missing from the compression tree");
+            last_char = b;
+            for i in (0..length).rev() {
+                bit -= 1;
+                cur |= ((code >> i) as u8 & 1) << bit;
+                if bit == 0 {
+                    output.push(cur);
+                    cur = 0;
+                    bit = 8;
+                }
+            }
+        }
+        // each stream starts at a byte boundary
+        if bit != 8 {
+            output.push(cur);
+        }
+    }
+
+    /// Compress a row of heights and metas, which `expand` turns back.
+    /// The symbols have to be present in the rows the trees were built from.
+    pub fn shrink(&self, input1: &[u8], input2: &[u8], output: &mut Vec<u8>) {
+        Self::compress(&Self::codes(&self.tree1), input1, output, |b, c| {
+            c.wrapping_sub(b)
+        });
+        Self::compress(&Self::codes(&self.tree2), input2, output, |b, c| b ^ c);
+    }
+
     pub fn compress_trivial<O: Write>(input1: &[u8], input2: &[u8], output: &mut O) {
         let mut last_char = 0;
         for &b in input1 {
@@ -8,10 +8,29 @@ layout(set = 1, binding = 5) uniform utexture1D t_Table;
 // corresponds to SDL palette
 layout(set = 1, binding = 6) uniform texture1D t_Palette;
 layout(set = 1, binding = 8) uniform sampler s_FloodSampler;
+// Filtering of the final palette lookup, configured by the user.
+layout(set = 1, binding = 10) uniform sampler s_ColorSampler;
 
 layout(set = 0, binding = 1) uniform sampler s_PaletteSampler;
+// Color provider and the procedural detail of the close-up terrain.
+layout(set = 1, binding = 11) uniform c_Color {
+    uvec4 u_ColorProvider;  // X = provider, see `settings::TerrainColor`, Y = debug palette, Z = linear filter
+    vec4 u_DetailCamera;    // XYZ = camera position, W = fade distance
+    vec4 u_DetailParams[8]; // per terrain type: X = scale, Y = intensity
+    vec4 u_Weather;         // X = wetness
+};
+// Custom colors, indexed by the altitude along X and by the terrain type along Y.
+layout(set = 1, binding = 12) uniform texture2D t_ColorLut;
+
+const uint
+    c_ProviderPalette = 0U,
+    c_ProviderHypsometric = 1U,
+    c_ProviderMeta = 2U,
+    c_ProviderLut = 3U;
 
 const float c_HorFactor = 0.5; //H_CORRECTION
+// Light lost by the fully wet terrain.
+const float c_WetDarkening = 0.3;
 const float c_DiffuseScale = 8.0;
 const float c_ShadowDepthScale = 2.0 / 3.0;
 
@@ -26,6 +45,12 @@ float evaluate_light(vec3 mat, float height_diff) {
     return clamp(v, 0.0, 1.0);
 }
 
+// Height of the water above the point, negative if the point is above the water.
+float water_depth(vec3 pos) {
+    float flood = texture(sampler1D(t_Flood, s_FloodSampler), pos.y / u_TextureScale.y).x;
+    return flood * u_TextureScale.z - pos.z;
+}
+
 float evaluate_palette(uint type, float value, float ycoord) {
     value = clamp(value, 0.0, 1.0);
     vec4 terr = vec4(texelFetch(usampler1D(t_Table, s_PaletteSampler), int(type), 0));
@@ -37,17 +62,123 @@ float evaluate_palette(uint type, float value, float ycoord) {
     return (mix(terr.z, terr.w, value) + 0.5) / 256.0;
 }
 
-float evaluate_color_id(uint type, vec2 tex_coord, float height_normalized, float lit_factor) {
+float detail_hash(vec2 p) {
+    return fract(sin(dot(p, vec2(127.1, 311.7))) * 43758.5453);
+}
+
+float detail_noise(vec2 p) {
+    vec2 i = floor(p);
+    vec2 f = fract(p);
+    vec2 u = f * f * (3.0 - 2.0 * f);
+    return mix(
+        mix(detail_hash(i), detail_hash(i + vec2(1.0, 0.0)), u.x),
+        mix(detail_hash(i + vec2(0.0, 1.0)), detail_hash(i + vec2(1.0, 1.0)), u.x),
+        u.y
+    );
+}
+
+// Signed change of the lighting value, made of two cascades of noise,
+// with the finer one fading out at half the distance.
+float evaluate_detail(uint type, vec3 world_pos) {
+    vec4 params = u_DetailParams[type & 7U];
+    if (params.y == 0.0) {
+        return 0.0;
+    }
+    float dist = distance(world_pos, u_DetailCamera.xyz);
+    float coarse_fade = 1.0 - smoothstep(0.0, u_DetailCamera.w, dist);
+    float fine_fade = 1.0 - smoothstep(0.0, 0.5 * u_DetailCamera.w, dist);
+    vec2 p = world_pos.xy * params.x;
+    float noise =
+        (detail_noise(p) - 0.5) * coarse_fade +
+        0.5 * (detail_noise(p * 4.0) - 0.5) * fine_fade;
+    return noise * params.y;
+}
+
+// Lighting value of the terrain, as used for the palette lookup.
+float evaluate_light_value(uint type, vec2 tex_coord, float height_normalized, float lit_factor) {
     float diff =
         textureLodOffset(sampler2D(t_Height, s_MainSampler), tex_coord, 0.0, ivec2(1, 0)).x -
         textureLodOffset(sampler2D(t_Height, s_MainSampler), tex_coord, 0.0, ivec2(-1, 0)).x;
     vec3 mat = type == 0U ? vec3(5.0, 1.25, 0.5) : vec3(1.0);
     float light_clr = evaluate_light(mat, diff);
-    float tmp = light_clr - c_HorFactor * (1.0 - height_normalized);
-    return evaluate_palette(type, lit_factor * tmp, tex_coord.y);
+    vec3 world_pos = vec3(tex_coord * u_TextureScale.xy, height_normalized * u_TextureScale.z);
+    float tmp = light_clr - c_HorFactor * (1.0 - height_normalized) + evaluate_detail(type, world_pos);
+    float wet = type == 0U ? 0.0 : c_WetDarkening * u_Weather.x;
+    return lit_factor * tmp * (1.0 - wet);
 }
 
-vec4 evaluate_color(uint type, vec2 tex_coord, float height_normalized, float lit_factor) {
+// Palette coordinate of the terrain color, used by the palette provider.
+float evaluate_color_id(uint type, vec2 tex_coord, float height_normalized, float lit_factor) {
+    float value = evaluate_light_value(type, tex_coord, height_normalized, lit_factor);
+    return evaluate_palette(type, value, tex_coord.y);
+}
+
+vec3 evaluate_hypsometric(uint type, float height_normalized) {
+    if (type == 0U) {
+        return vec3(0.1, 0.3, 0.6);
+    }
+    vec3 low = vec3(0.2, 0.45, 0.15), mid = vec3(0.55, 0.4, 0.2), high = vec3(0.95);
+    return height_normalized < 0.5 ?
+        mix(low, mid, height_normalized * 2.0) :
+        mix(mid, high, height_normalized * 2.0 - 1.0);
+}
+
+// Colors of the terrain types for the color-blind friendly debug palettes,
+// from the palette of Okabe and Ito, with the black replaced by gray.
+const vec3 c_SafeMetaColors[8] = vec3[8](
+    vec3(0.0, 0.447, 0.698),
+    vec3(0.902, 0.624, 0.0),
+    vec3(0.337, 0.706, 0.914),
+    vec3(0.0, 0.620, 0.451),
+    vec3(0.941, 0.894, 0.259),
+    vec3(0.835, 0.369, 0.0),
+    vec3(0.8, 0.475, 0.655),
+    vec3(0.6, 0.6, 0.6)
+);
+
+vec3 evaluate_meta_color(uint type) {
+    if (u_ColorProvider.y != 0U) {
+        return c_SafeMetaColors[type & 7U];
+    }
+    // evenly spaced hues
+    float hue = float(type & 7U) / 8.0;
+    vec3 rgb = clamp(abs(mod(hue * 6.0 + vec3(0.0, 4.0, 2.0), 6.0) - 3.0) - 1.0, 0.0, 1.0);
+    return mix(vec3(0.2), rgb, 0.8);
+}
+
+// Palette color of the terrain. The neighboring palette entries are unrelated,
+// so the lookup itself is never filtered.
+vec4 fetch_palette_color(uint type, vec2 tex_coord, float height_normalized, float lit_factor) {
     float color_id = evaluate_color_id(type, tex_coord, height_normalized, lit_factor);
-    return texture(sampler1D(t_Palette, s_PaletteSampler), color_id);
+    return texelFetch(sampler1D(t_Palette, s_PaletteSampler), int(color_id * 256.0), 0);
+}
+
+vec4 evaluate_color(uint type, vec2 tex_coord, float height_normalized, float lit_factor) {
+    if (u_ColorProvider.x == c_ProviderPalette) {
+        if (u_ColorProvider.z == 0U) {
+            return fetch_palette_color(type, tex_coord, height_normalized, lit_factor);
+        }
+        // blend the colors looked up at the centers of the neighboring texels
+        vec2 texel = tex_coord * u_TextureScale.xy - 0.5;
+        vec2 t = fract(texel);
+        vec2 base = (floor(texel) + 0.5) / u_TextureScale.xy;
+        vec2 step = 1.0 / u_TextureScale.xy;
+        vec4 c00 = fetch_palette_color(type, base, height_normalized, lit_factor);
+        vec4 c10 = fetch_palette_color(type, base + vec2(step.x, 0.0), height_normalized, lit_factor);
+        vec4 c01 = fetch_palette_color(type, base + vec2(0.0, step.y), height_normalized, lit_factor);
+        vec4 c11 = fetch_palette_color(type, base + step, height_normalized, lit_factor);
+        return mix(mix(c00, c10, t.x), mix(c01, c11, t.x), t.y);
+    }
+
+    float light = clamp(evaluate_light_value(type, tex_coord, height_normalized, lit_factor), 0.0, 1.0);
+    vec3 base;
+    if (u_ColorProvider.x == c_ProviderHypsometric) {
+        base = evaluate_hypsometric(type, height_normalized);
+    } else if (u_ColorProvider.x == c_ProviderMeta) {
+        base = evaluate_meta_color(type);
+    } else {
+        vec2 lut_coord = vec2(height_normalized, (float(type & 7U) + 0.5) / 8.0);
+        base = texture(sampler2D(t_ColorLut, s_ColorSampler), lut_coord).xyz;
+    }
+    return vec4(base * (0.25 + 0.75 * light), 1.0);
 }
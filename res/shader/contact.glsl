@@ -0,0 +1,69 @@
+//!include fs:globals.inc vs:fullscreen.inc
+//!specialization MULTISAMPLED
+// Screen-space contact shadows, marched through the depth buffer.
+
+#ifdef SHADER_FS
+
+layout(set = 1, binding = 0) uniform c_Contact {
+    vec4 u_ScreenSize;  // XY = size
+    vec4 u_Params;      // X = length, Y = intensity, Z = steps, W = thickness
+};
+#if MULTISAMPLED
+layout(set = 1, binding = 1) uniform texture2DMS t_Depth;
+#else
+layout(set = 1, binding = 1) uniform texture2D t_Depth;
+#endif
+layout(set = 1, binding = 2) uniform sampler s_Depth;
+
+layout(location = 0) out vec4 o_Color;
+
+float fetch_depth(ivec2 tc) {
+#if MULTISAMPLED
+    return texelFetch(sampler2DMS(t_Depth, s_Depth), tc, 0).x;
+#else
+    return texelFetch(sampler2D(t_Depth, s_Depth), tc, 0).x;
+#endif
+}
+
+vec3 unproject(vec2 frag_coord, float depth) {
+    // note the Y-flip here
+    vec2 ndc = ((frag_coord / u_ScreenSize.xy) * 2.0 - 1.0) * vec2(1.0, -1.0);
+    vec4 homogeneous = u_InvViewProj * vec4(ndc, depth, 1.0);
+    return homogeneous.xyz / homogeneous.w;
+}
+
+void main() {
+    float depth = fetch_depth(ivec2(gl_FragCoord.xy));
+    if (depth >= 1.0) {
+        o_Color = vec4(1.0);
+        return;
+    }
+
+    vec3 pos = unproject(gl_FragCoord.xy, depth);
+    vec3 light_dir = normalize(u_LightPos.xyz - pos * u_LightPos.w);
+    int steps = int(u_Params.z);
+    vec3 step = light_dir * (u_Params.x / u_Params.z);
+
+    float occlusion = 0.0;
+    for (int i = 1; i <= steps; ++i) {
+        vec3 point = pos + float(i) * step;
+        vec4 clip = u_ViewProj * vec4(point, 1.0);
+        vec2 ndc = clip.xy / clip.w;
+        vec2 frag_coord = (ndc * vec2(0.5, -0.5) + 0.5) * u_ScreenSize.xy;
+        if (any(lessThan(frag_coord, vec2(0.0))) || any(greaterThanEqual(frag_coord, u_ScreenSize.xy))) {
+            break;
+        }
+        float scene_depth = fetch_depth(ivec2(frag_coord));
+        vec3 scene_point = unproject(frag_coord, scene_depth);
+        // the ray is behind the scene, but not by more than the assumed thickness
+        float behind = distance(point, u_CameraPos.xyz) - distance(scene_point, u_CameraPos.xyz);
+        if (behind > 0.0 && behind < u_Params.w) {
+            // closer occluders cast darker shadows
+            occlusion = 1.0 - float(i - 1) / float(steps);
+            break;
+        }
+    }
+
+    o_Color = vec4(vec3(1.0 - u_Params.y * occlusion), 1.0);
+}
+#endif //FS
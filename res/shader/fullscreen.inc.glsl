@@ -0,0 +1,9 @@
+// Full-screen triangle, with the texture coordinates going down the screen.
+
+layout(location = 0) out vec2 v_TexCoord;
+
+void main() {
+    vec2 pos = vec2(gl_VertexIndex == 1 ? 3.0 : -1.0, gl_VertexIndex == 2 ? 3.0 : -1.0);
+    v_TexCoord = pos * vec2(0.5, -0.5) + 0.5;
+    gl_Position = vec4(pos, 0.0, 1.0);
+}
@@ -5,4 +5,7 @@ layout(set = 0, binding = 0) uniform Globals {
     mat4 u_LightViewProj;
     vec4 u_LightPos;
     vec4 u_LightColor; // not used
+    vec4 u_CameraDir; // W = 0 for orthographic, 1 for perspective
+    mat4 u_InvView;
+    mat4 u_InvProj;
 };
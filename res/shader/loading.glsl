@@ -0,0 +1,30 @@
+//!include vs:fullscreen.inc
+// Loading screen: background image with a progress bar.
+
+#ifdef SHADER_FS
+
+layout(location = 0) in vec2 v_TexCoord;
+
+layout(set = 0, binding = 0) uniform texture2D t_Background;
+layout(set = 0, binding = 1) uniform sampler s_Background;
+layout(set = 0, binding = 2) uniform c_Params {
+    vec4 u_Params; // X = progress, Y = bar height
+};
+
+layout(location = 0) out vec4 o_Color;
+
+const vec3 c_BarRect = vec3(0.1, 0.9, 0.9); // min X, min Y, max X
+const vec3 c_BarEmpty = vec3(0.2);
+const vec3 c_BarFull = vec3(0.9, 0.8, 0.5);
+
+void main() {
+    vec2 tc = v_TexCoord;
+    vec2 bar_max = vec2(c_BarRect.z, c_BarRect.y + u_Params.y);
+    if (all(greaterThanEqual(tc, c_BarRect.xy)) && all(lessThanEqual(tc, bar_max))) {
+        float fill = (tc.x - c_BarRect.x) / (c_BarRect.z - c_BarRect.x);
+        o_Color = vec4(fill <= u_Params.x ? c_BarFull : c_BarEmpty, 1.0);
+    } else {
+        o_Color = texture(sampler2D(t_Background, s_Background), tc);
+    }
+}
+#endif //FS
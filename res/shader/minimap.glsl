@@ -0,0 +1,21 @@
+//!include fs:globals.inc fs:surface.inc fs:color.inc vs:fullscreen.inc
+
+// Top-down view of the whole level, for the HUD.
+
+#ifdef SHADER_FS
+//imported: Surface, u_TextureScale, get_surface, evaluate_color
+
+layout(location = 0) in vec2 v_TexCoord;
+
+layout(location = 0) out vec4 o_Color;
+
+const float c_ShadowLit = 0.5;
+
+void main() {
+    // north is up, while the texture rows go down
+    vec2 pos = vec2(v_TexCoord.x, 1.0 - v_TexCoord.y) * u_TextureScale.xy;
+    Surface suf = get_surface(pos);
+    float lit_factor = suf.is_shadowed ? c_ShadowLit : 1.0;
+    o_Color = evaluate_color(suf.high_type, suf.tex_coord, suf.high_alt / u_TextureScale.z, lit_factor);
+}
+#endif //FS
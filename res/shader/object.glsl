@@ -56,8 +56,20 @@ void main() {
 #if COLOR
 layout(set = 0, binding = 1) uniform sampler s_PaletteSampler;
 layout(set = 1, binding = 1) uniform texture1D t_Palette;
+layout(set = 1, binding = 3) uniform texture2D t_LightProbes;
+layout(set = 1, binding = 4) uniform sampler s_LightProbes;
+layout(set = 1, binding = 5) uniform c_LightProbes {
+    vec4 u_ProbeParams; // XY = world size covered, Z = strength
+};
 
 layout(location = 0) out vec4 o_Color;
+
+// Hue of the ambient light around the point, normalized to keep the brightness.
+vec3 ambient_tint(vec3 pos) {
+    vec3 probe = texture(sampler2D(t_LightProbes, s_LightProbes), pos.xy / u_ProbeParams.xy).rgb;
+    float luma = dot(probe, vec3(0.299, 0.587, 0.114));
+    return min(probe / max(luma, 0.01), vec3(2.0));
+}
 #endif
 
 void main() {
@@ -69,6 +81,9 @@ void main() {
     float tc_raw = mix(v_PaletteRange.x, v_PaletteRange.y, n_dot_l);
     float tc = clamp(tc_raw, v_PaletteRange.x + 0.5, v_PaletteRange.y - 0.5) / 256.0;
     o_Color = texture(sampler1D(t_Palette, s_PaletteSampler), tc);
+    // the unlit parts are lit by the surroundings
+    float ambient = u_ProbeParams.z * (1.0 - n_dot_l);
+    o_Color.rgb *= mix(vec3(1.0), ambient_tint(v_Position), ambient);
     #endif
 }
 #endif //FS
@@ -0,0 +1,81 @@
+//!include cs:surface.inc
+
+#ifdef SHADER_CS
+//imported: Surface, get_surface
+
+struct Ray {
+    vec4 origin;
+    vec4 dir; // W = max distance
+};
+
+struct Hit {
+    vec4 pos; // W = distance, negative if nothing is hit
+    uvec4 info; // X = terrain type, Y = 1 for the upper layer
+};
+
+layout(set = 0, binding = 0) uniform c_Locals {
+    uvec4 u_Count; // X = number of rays
+};
+layout(set = 0, binding = 1, std430) readonly buffer Rays {
+    Ray s_Rays[];
+};
+layout(set = 0, binding = 2, std430) buffer Hits {
+    Hit s_Hits[];
+};
+
+const float c_Step = 1.0;
+const int c_NumBinary = 6;
+
+// Returns 0 for the empty space, 1 for the lower layer, and 2 for the upper one.
+uint classify(vec3 pos, out Surface suf) {
+    suf = get_surface(pos.xy);
+    if (pos.z <= suf.low_alt) {
+        return 1U;
+    }
+    // the gap of `delta` above the lower layer is empty
+    if (suf.delta != 0.0 && pos.z >= suf.low_alt + suf.delta && pos.z <= suf.high_alt) {
+        return 2U;
+    }
+    return 0U;
+}
+
+void main() {
+    uint index = gl_GlobalInvocationID.x;
+    if (index >= u_Count.x) {
+        return;
+    }
+
+    Ray ray = s_Rays[index];
+    vec3 dir = normalize(ray.dir.xyz);
+    Hit hit = Hit(vec4(ray.origin.xyz, -1.0), uvec4(0U));
+    Surface suf;
+    float t_free = 0.0;
+
+    for (float t = c_Step; t <= ray.dir.w; t += c_Step) {
+        uint layer = classify(ray.origin.xyz + t * dir, suf);
+        if (layer == 0U) {
+            t_free = t;
+            continue;
+        }
+        // refine the hit between the last empty point and the solid one
+        float t_solid = t;
+        for (int i = 0; i < c_NumBinary; ++i) {
+            float t_mid = 0.5 * (t_free + t_solid);
+            Surface suf_mid;
+            uint layer_mid = classify(ray.origin.xyz + t_mid * dir, suf_mid);
+            if (layer_mid != 0U) {
+                t_solid = t_mid;
+                layer = layer_mid;
+                suf = suf_mid;
+            } else {
+                t_free = t_mid;
+            }
+        }
+        hit.pos = vec4(ray.origin.xyz + t_solid * dir, t_solid);
+        hit.info = uvec4(layer == 2U ? suf.high_type : suf.low_type, layer - 1U, 0U, 0U);
+        break;
+    }
+
+    s_Hits[index] = hit;
+}
+#endif //CS
@@ -0,0 +1,34 @@
+//!include vs:fullscreen.inc
+// CRT filter: screen curvature, scanlines, and vignette.
+
+#ifdef SHADER_FS
+
+layout(location = 0) in vec2 v_TexCoord;
+
+layout(set = 0, binding = 0) uniform texture2D t_Source;
+layout(set = 0, binding = 1) uniform sampler s_Source;
+layout(set = 0, binding = 3) uniform c_Params {
+    vec4 u_Params; // X = curvature, Y = scanlines, Z = vignette
+};
+
+layout(location = 0) out vec4 o_Color;
+
+void main() {
+    // barrel distortion around the center
+    vec2 centered = v_TexCoord * 2.0 - 1.0;
+    centered *= 1.0 + u_Params.x * dot(centered, centered);
+    vec2 tc = centered * 0.5 + 0.5;
+    if (any(lessThan(tc, vec2(0.0))) || any(greaterThan(tc, vec2(1.0)))) {
+        o_Color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    vec3 color = texture(sampler2D(t_Source, s_Source), tc).rgb;
+    if ((int(gl_FragCoord.y) & 1) != 0) {
+        color *= 1.0 - u_Params.y;
+    }
+    float edge = dot(centered, centered) * 0.5;
+    color *= 1.0 - u_Params.z * edge;
+    o_Color = vec4(color, 1.0);
+}
+#endif //FS
@@ -0,0 +1,43 @@
+//!include vs:fullscreen.inc
+// Quantizes the image to the level palette with ordered dithering.
+
+#ifdef SHADER_FS
+
+layout(location = 0) in vec2 v_TexCoord;
+
+layout(set = 0, binding = 0) uniform texture2D t_Source;
+layout(set = 0, binding = 1) uniform sampler s_Source;
+layout(set = 0, binding = 2) uniform texture1D t_Palette;
+
+layout(location = 0) out vec4 o_Color;
+
+// 4x4 Bayer matrix
+const float c_Bayer[16] = float[16](
+    0.0, 8.0, 2.0, 10.0,
+    12.0, 4.0, 14.0, 6.0,
+    3.0, 11.0, 1.0, 9.0,
+    15.0, 7.0, 13.0, 5.0
+);
+// Amplitude of the dithering noise, roughly the palette step.
+const float c_Spread = 1.0 / 16.0;
+
+void main() {
+    vec3 color = texture(sampler2D(t_Source, s_Source), v_TexCoord).rgb;
+    ivec2 cell = ivec2(gl_FragCoord.xy) & 3;
+    float threshold = (c_Bayer[cell.y * 4 + cell.x] + 0.5) / 16.0 - 0.5;
+    color += threshold * c_Spread;
+
+    vec3 best = vec3(0.0);
+    float best_dist = 1.0e10;
+    for (int i = 0; i < 256; ++i) {
+        vec3 entry = texelFetch(sampler1D(t_Palette, s_Source), i, 0).rgb;
+        vec3 diff = entry - color;
+        float dist = dot(diff, diff);
+        if (dist < best_dist) {
+            best_dist = dist;
+            best = entry;
+        }
+    }
+    o_Color = vec4(best, 1.0);
+}
+#endif //FS
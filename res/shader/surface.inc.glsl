@@ -38,6 +38,11 @@ int modulo(int a, int b) {
     return c < 0 ? c + b : c;
 }
 
+// Position inside the original copy of the level.
+vec2 wrap_position(vec2 pos) {
+    return mod(pos, u_TextureScale.xy);
+}
+
 float get_lod_height(ivec2 ipos, int lod) {
     int x = modulo(ipos.x, int(u_TextureScale.x));
     int y = modulo(ipos.y, int(u_TextureScale.y));
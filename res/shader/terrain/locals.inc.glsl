@@ -23,6 +23,19 @@ vec3 get_frag_world(float z) {
     return homogeneous.xyz / homogeneous.w;
 }
 
+struct Ray {
+    vec3 origin;
+    vec3 dir;
+};
+
+// View ray of the fragment, starting at the near plane.
+Ray get_frag_ray() {
+    Ray ray;
+    ray.origin = get_frag_world(0.0);
+    ray.dir = u_CameraDir.w == 0.0 ? u_CameraDir.xyz : normalize(ray.origin - u_CameraPos.xyz);
+    return ray;
+}
+
 vec4 apply_fog(vec4 terrain_color, vec2 world_pos) {
     float cam_distance = clamp(length(world_pos - u_CamOriginDir.xy), u_FogParams.x, u_FogParams.y);
     float fog_amount = smoothstep(u_FogParams.x, u_FogParams.y, cam_distance);
@@ -18,12 +18,40 @@ void main() {
 #ifdef SHADER_FS
 //imported: Surface, u_TextureScale, get_surface, evaluate_color
 
+// Tunables, can be overridden by `render.terrain_defines` in the settings.
+#ifndef RAY_FORWARD_STEPS
+#define RAY_FORWARD_STEPS 8
+#endif
+#ifndef RAY_BINARY_STEPS
+#define RAY_BINARY_STEPS 4
+#endif
+#ifndef RAY_UNDERGROUND_FORWARD_STEPS
+#define RAY_UNDERGROUND_FORWARD_STEPS 6
+#endif
+#ifndef RAY_UNDERGROUND_BINARY_STEPS
+#define RAY_UNDERGROUND_BINARY_STEPS 3
+#endif
+#ifndef REFLECTIONS
+#define REFLECTIONS 1
+#endif
+#ifndef SHADOWS
+#define SHADOWS 1
+#endif
+#ifndef REFRACTIONS
+#define REFRACTIONS 1
+#endif
+
 const float c_DepthBias = COLOR != 0 ? 0.0 : 0.01;
 
 #if COLOR
 const float
     c_ReflectionVariance = 0.5,
     c_ReflectionPower = 0.2;
+// Water shallower than this shows the bottom through, bent by the waves.
+const float
+    c_ShallowDepth = 8.0,
+    c_RefractionStrength = 1.5,
+    c_RefractionFrequency = 0.7;
 
 #define TERRAIN_WATER   0U
 
@@ -93,7 +121,7 @@ CastPoint cast_ray_to_map(vec3 base, vec3 dir) {
     vec3 c = cast_ray_to_plane(0.0, base, dir);
     vec3 b = c;
 
-    Surface suf = cast_ray_impl(a, b, true, 8, 4);
+    Surface suf = cast_ray_impl(a, b, true, RAY_FORWARD_STEPS, RAY_BINARY_STEPS);
     result.type = suf.high_type;
     result.is_underground = false;
 
@@ -101,7 +129,7 @@ CastPoint cast_ray_to_map(vec3 base, vec3 dir) {
         // continue the cast underground, but reserve
         // the right to re-appear above the surface.
         a = b; b = c;
-        suf = cast_ray_impl(a, b, false, 6, 3);
+        suf = cast_ray_impl(a, b, false, RAY_UNDERGROUND_FORWARD_STEPS, RAY_UNDERGROUND_BINARY_STEPS);
         if (b.z >= suf.low_alt + suf.delta) {
             result.type = suf.high_type;
         } else {
@@ -122,19 +150,53 @@ CastPoint cast_ray_to_map(vec3 base, vec3 dir) {
 vec4 color_point(CastPoint pt, float lit_factor) {
     return evaluate_color(pt.type, pt.tex_coord, pt.pos.z / u_TextureScale.z, lit_factor);
 }
+
+// Static ripples, displacing the view of the bottom.
+vec2 refraction_offset(vec2 pos) {
+    vec2 phase = pos * c_RefractionFrequency;
+    return vec2(sin(phase.y + 1.7 * sin(phase.x)), cos(phase.x + 1.3 * cos(phase.y)));
+}
+
+// Blend the bottom seen through the shallow water with the water color,
+// which takes over as the depth grows.
+vec4 refract_shallow(CastPoint pt, vec4 water_color, float lit_factor) {
+    float depth = water_depth(pt.pos);
+    if (depth <= 0.0 || depth >= c_ShallowDepth) {
+        return water_color;
+    }
+    float shallowness = 1.0 - depth / c_ShallowDepth;
+    vec2 pos = pt.pos.xy + c_RefractionStrength * shallowness * refraction_offset(pt.pos.xy);
+    // the bottom under the water is typed as water itself
+    Surface suf = get_surface(pos);
+    CastPoint bottom;
+    bottom.pos = vec3(pos, suf.high_alt);
+    bottom.type = suf.high_type;
+    bottom.tex_coord = suf.tex_coord;
+    vec4 bottom_color = color_point(bottom, lit_factor);
+    // attenuated by the depth of the water above the refracted bottom
+    float bottom_depth = clamp(water_depth(bottom.pos), 0.0, c_ShallowDepth);
+    float clarity = 1.0 - bottom_depth / c_ShallowDepth;
+    return mix(water_color, bottom_color, clarity * clarity);
+}
 #endif
 
 void main() {
-    vec3 sp_near_world = get_frag_world(0.0);
-    vec3 sp_far_world = get_frag_world(1.0);
-    vec3 view = normalize(sp_far_world - sp_near_world);
-    CastPoint pt = cast_ray_to_map(sp_near_world, view);
+    Ray ray = get_frag_ray();
+    vec3 view = ray.dir;
+    CastPoint pt = cast_ray_to_map(ray.origin, view);
 
     #if COLOR
+#if SHADOWS
     float lit_factor = fetch_shadow(pt.pos);
+#else
+    float lit_factor = 1.0;
+#endif
     vec4 frag_color = color_point(pt, lit_factor);
 
-    if (pt.type == TERRAIN_WATER) {
+    if (REFRACTIONS != 0 && pt.type == TERRAIN_WATER) {
+        frag_color = refract_shallow(pt, frag_color, lit_factor);
+    }
+    if (REFLECTIONS != 0 && pt.type == TERRAIN_WATER) {
         vec3 a = pt.pos;
         vec2 variance = mod(a.xy, c_ReflectionVariance);
         vec3 reflected = normalize(view * vec3(1.0 + variance, -1.0));
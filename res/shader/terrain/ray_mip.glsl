@@ -109,11 +109,10 @@ vec3 cast_ray(vec3 point, vec3 dir) {
 }
 
 void main() {
-    vec3 sp_near_world = get_frag_world(0.0);
-    vec3 sp_far_world = get_frag_world(1.0);
-    vec3 view = normalize(sp_far_world - sp_near_world);
-    vec3 point = cast_ray(sp_near_world, view);
-    //vec3 point = cast_ray_to_plane(0.0, sp_near_world, view);
+    Ray ray = get_frag_ray();
+    vec3 view = ray.dir;
+    vec3 point = cast_ray(ray.origin, view);
+    //vec3 point = cast_ray_to_plane(0.0, ray.origin, view);
 
     #if COLOR
     if (u_Params.w == 0U) {
@@ -1,7 +1,7 @@
 //!include cs:globals.inc cs:terrain/locals.inc cs:surface.inc cs:color.inc
 
 #ifdef SHADER_CS
-//imported: Surface, get_surface, evaluate_color_id
+//imported: Surface, get_surface, wrap_position, evaluate_color_id
 
 layout(set = 2, binding = 0, std430) buffer Storage {
     uint w_Data[];
@@ -55,7 +55,9 @@ void main() {
         vec2(gl_NumWorkGroups.xy * gl_WorkGroupSize.xy - vec2(1));
     vec2 pos = generate_scatter_pos(source_coord);
 
-    Surface suf = get_surface(pos);
+    // the surface is fetched from the original copy of the level,
+    // while the voxels are placed around the camera
+    Surface suf = get_surface(wrap_position(pos));
     float base = 0.0;
     float t = float(gl_GlobalInvocationID.z) / float(gl_NumWorkGroups.z * gl_WorkGroupSize.z);
 
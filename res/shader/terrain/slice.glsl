@@ -1,4 +1,5 @@
 //!include vs:globals.inc vs:surface.inc fs:surface.inc fs:color.inc
+//!specialization SMOOTH_HEIGHT WRAP_SEAM
 
 layout(location = 0) varying vec4 v_Pos;
 
@@ -7,7 +8,14 @@ layout(location = 0) varying vec4 v_Pos;
 layout(location = 0) attribute ivec4 a_Pos;
 
 void main() {
-    v_Pos = vec4(a_Pos.xy * u_TextureScale.xy, u_TextureScale.z - float(gl_InstanceIndex + 1), 1.0);
+#if WRAP_SEAM
+    // center the slices at the level copy closest to the camera,
+    // so that there is at least half of the level visible around
+    vec2 origin = floor(u_CameraPos.xy / u_TextureScale.xy + 0.5) * u_TextureScale.xy;
+#else
+    vec2 origin = vec2(0.0);
+#endif
+    v_Pos = vec4(origin + a_Pos.xy * u_TextureScale.xy, u_TextureScale.z - float(gl_InstanceIndex + 1), 1.0);
     gl_Position = u_ViewProj * v_Pos;
 }
 #endif //VS
@@ -18,8 +26,29 @@ void main() {
 
 layout(location = 0) out vec4 o_Color;
 
+#if SMOOTH_HEIGHT
+// Bilinear interpolation of the heights around the point,
+// hiding the steps between the slices on the gentle slopes.
+float get_smooth_height(vec2 pos) {
+    vec2 base = pos - 0.5;
+    ivec2 ipos = ivec2(floor(base));
+    vec2 f = base - floor(base);
+    float h00 = get_lod_height(ipos, 0);
+    float h10 = get_lod_height(ipos + ivec2(1, 0), 0);
+    float h01 = get_lod_height(ipos + ivec2(0, 1), 0);
+    float h11 = get_lod_height(ipos + ivec2(1, 1), 0);
+    return mix(mix(h00, h10, f.x), mix(h01, h11, f.x), f.y);
+}
+#endif
+
 void main() {
     Surface surface = get_surface(v_Pos.xy);
+#if SMOOTH_HEIGHT
+    // the heights of double level texels alternate between the layers
+    if (surface.delta == 0.0) {
+        surface.low_alt = surface.high_alt = get_smooth_height(v_Pos.xy);
+    }
+#endif
     uint type = 0U;
     if (v_Pos.z <= surface.low_alt) {
         type = surface.low_type;
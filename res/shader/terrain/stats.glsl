@@ -0,0 +1,36 @@
+//!include cs:surface.inc
+
+#ifdef SHADER_CS
+//imported: get_lod_height
+
+layout(set = 0, binding = 0) uniform c_Locals {
+    uvec4 u_Tile; // XY = number of tiles, Z = tile size
+};
+layout(set = 0, binding = 1, rgba32f) uniform writeonly image2D i_Stats;
+
+// Reduces a tile of the height map into (min, max, mean, variance).
+void main() {
+    uvec2 tile = gl_GlobalInvocationID.xy;
+    if (any(greaterThanEqual(tile, u_Tile.xy))) {
+        return;
+    }
+
+    ivec2 base = ivec2(tile * u_Tile.z);
+    float lo = u_TextureScale.z, hi = 0.0;
+    float sum = 0.0, sum_sq = 0.0;
+    for (int y = 0; y < int(u_Tile.z); ++y) {
+        for (int x = 0; x < int(u_Tile.z); ++x) {
+            float alt = get_lod_height(base + ivec2(x, y), 0);
+            lo = min(lo, alt);
+            hi = max(hi, alt);
+            sum += alt;
+            sum_sq += alt * alt;
+        }
+    }
+
+    float count = float(u_Tile.z * u_Tile.z);
+    float mean = sum / count;
+    float variance = max(sum_sq / count - mean * mean, 0.0);
+    imageStore(i_Stats, ivec2(tile), vec4(lo, hi, mean, variance));
+}
+#endif //CS
@@ -0,0 +1,24 @@
+//!include vs:globals.inc
+
+layout(location = 0) varying vec4 v_Color;
+
+#ifdef SHADER_VS
+
+layout(location = 0) in vec4 a_Pos;
+layout(location = 1) in vec4 a_Color;
+
+void main() {
+    gl_Position = u_ViewProj * a_Pos;
+    v_Color = a_Color;
+}
+#endif //VS
+
+
+#ifdef SHADER_FS
+
+layout(location = 0) out vec4 o_Color;
+
+void main() {
+    o_Color = v_Color;
+}
+#endif //FS
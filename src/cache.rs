@@ -0,0 +1,101 @@
+//! Cache of the parsed assets, stored in a fast binary format.
+//! Entries are keyed by the hash of the source file contents,
+//! so that modified sources are picked up automatically.
+
+use crate::hash::Fnv;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use std::{
+    fs::{self, File},
+    hash::Hasher,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// Bump this whenever the layout of any of the cached types changes.
+const FORMAT_VERSION: u32 = 1;
+
+pub struct Cache {
+    dir: Option<PathBuf>,
+}
+
+impl Cache {
+    pub fn new(dir: &Path) -> Self {
+        if dir.as_os_str().is_empty() {
+            return Self::disabled();
+        }
+        match fs::create_dir_all(dir) {
+            Ok(()) => Cache {
+                dir: Some(dir.to_path_buf()),
+            },
+            Err(e) => {
+                warn!("Unable to create the cache directory {:?}: {:?}", dir, e);
+                Self::disabled()
+            }
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Cache { dir: None }
+    }
+
+    fn hash_source(source: &mut File) -> Option<u64> {
+        let mut hasher = Fnv::default();
+        hasher.write(&FORMAT_VERSION.to_le_bytes());
+        let mut reader = BufReader::new(&mut *source);
+        let mut buf = [0u8; 0x10000];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(count) => hasher.write(&buf[..count]),
+                Err(_) => return None,
+            }
+        }
+        drop(reader);
+        source.seek(SeekFrom::Start(0)).ok()?;
+        Some(hasher.finish())
+    }
+
+    /// Load the parsed representation of the `source` file from the cache,
+    /// or parse it with `parse` and store the result.
+    pub fn load_or_parse<T, F>(&self, kind: &str, mut source: File, parse: F) -> T
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce(File) -> T,
+    {
+        let dir = match self.dir {
+            Some(ref dir) => dir,
+            None => return parse(source),
+        };
+        let hash = match Self::hash_source(&mut source) {
+            Some(hash) => hash,
+            None => return parse(source),
+        };
+        let path = dir.join(format!("{}-{:016x}.bin", kind, hash));
+
+        if let Ok(file) = File::open(&path) {
+            match bincode::deserialize_from(BufReader::new(file)) {
+                Ok(value) => {
+                    debug!("Loaded {} from cache {:?}", kind, path);
+                    return value;
+                }
+                Err(e) => warn!("Corrupted cache entry {:?}: {:?}", path, e),
+            }
+        }
+
+        let value = parse(source);
+        // written aside and renamed, so that an interrupted write
+        // or a concurrent process never leaves a partial entry
+        let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        let result = File::create(&temp_path)
+            .map_err(bincode::Error::from)
+            .and_then(|file| bincode::serialize_into(BufWriter::new(file), &value))
+            .and_then(|()| fs::rename(&temp_path, &path).map_err(bincode::Error::from));
+        if let Err(e) = result {
+            warn!("Unable to store cache entry {:?}: {:?}", path, e);
+            let _ = fs::remove_file(&temp_path);
+        }
+        value
+    }
+}
@@ -1,10 +1,16 @@
 use crate::{
-    config::text::Reader, config::Settings, model, render::object::Context as ObjectContext,
+    cache::Cache, config::text::Reader, config::Settings, model,
+    render::object::Context as ObjectContext,
 };
 
 use wgpu;
 
-use std::{collections::HashMap, fs::File};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+};
 
 pub type BoxSize = u8;
 pub type Price = u32;
@@ -142,6 +148,59 @@ impl CarPhysics {
             ],
         }
     }
+
+    /// Returns the parameters that can be tuned at run-time, keyed by their PRM names.
+    pub fn tunables_mut(&mut self) -> [(&'static str, &mut f32); 14] {
+        let [tx, ty, tz] = &mut self.terra_mover_sx;
+        [
+            ("scale_bound:", &mut self.scale_bound),
+            ("scale_box:", &mut self.scale_box),
+            (
+                "z_offset_of_mass_center:",
+                &mut self.z_offset_of_mass_center,
+            ),
+            ("speed_factor:", &mut self.speed_factor),
+            ("mobility_factor:", &mut self.mobility_factor),
+            ("water_speed_factor:", &mut self.water_speed_factor),
+            ("air_speed_factor:", &mut self.air_speed_factor),
+            (
+                "underground_speed_factor:",
+                &mut self.underground_speed_factor,
+            ),
+            ("k_archimedean:", &mut self.k_archimedean),
+            ("k_water_traction:", &mut self.k_water_traction),
+            ("k_water_rudder:", &mut self.k_water_rudder),
+            ("TerraMoverSx:", tx),
+            ("TerraMoverSy:", ty),
+            ("TerraMoverSz:", tz),
+        ]
+    }
+
+    /// Writes the tunable parameters into a copy of the PRM file at `source`,
+    /// preserving the comments and the rest of its contents.
+    pub fn save(&self, source: &Path, dest: &Path) -> io::Result<()> {
+        let text = fs::read_to_string(source)?;
+        if let Some(dir) = dest.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut copy = self.clone();
+        let tunables = copy.tunables_mut();
+        let mut file = File::create(dest)?;
+        for line in text.lines() {
+            let key = line.split_whitespace().next().unwrap_or("");
+            match tunables.iter().find(|&&(name, _)| name == key) {
+                Some(&(_, ref value)) => writeln!(file, "{}\t{}", key, value)?,
+                None => writeln!(file, "{}", line)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Path of the tuned copy of the vehicle parameters,
+/// which takes precedence over the PRM file of the game.
+pub fn tuned_physics_path(name: &str) -> PathBuf {
+    Path::new("config/physics").join(name).with_extension("prm")
 }
 
 #[derive(Clone)]
@@ -149,17 +208,25 @@ pub struct CarInfo {
     pub kind: Kind,
     pub stats: CarStats,
     pub physics: CarPhysics,
+    /// PRM file the physics was loaded from.
+    pub physics_path: PathBuf,
+    /// Tuned copy of the PRM file, where the tuning is saved.
+    pub tuned_path: PathBuf,
     pub model: model::VisualModel,
     pub scale: f32,
 }
 
+const UPLOAD_BATCH_SIZE: usize = 8;
+
 pub fn load_registry(
     settings: &Settings,
     reg: &super::game::Registry,
     device: &wgpu::Device,
     object: &ObjectContext,
+    progress: &mut dyn FnMut(usize, usize),
 ) -> HashMap<String, CarInfo> {
     let mut map = HashMap::new();
+    let mut sources = Vec::new();
     let mut fi = Reader::new(settings.open_relative("car.prm"));
     fi.advance();
     assert_eq!(fi.cur(), "uniVang-ParametersFile_Ver_1");
@@ -181,31 +248,65 @@ pub fn load_registry(
             warn!("Vehicle {} doesn't have parameters, using defaults", name);
             prm_path.set_file_name("default");
         }
-        let physics = CarPhysics::load(File::open(prm_path).unwrap());
+        let tuned_path = tuned_physics_path(name);
+        if tuned_path.exists() {
+            info!("Using the tuned parameters of {}", name);
+            prm_path = tuned_path;
+        }
+        let physics = CarPhysics::load(File::open(&prm_path).unwrap());
         let scale = if is_default {
             mi.scale
         } else {
             physics.scale_size
         };
-        let file = settings.open_relative(&mi.path);
-        let model = model::load_m3d(file, device, object, settings.game.physics.shape_sampling);
+        sources.push((name.to_owned(), settings.open_relative(&mi.path)));
         map.insert(
             name.to_owned(),
-            CarInfo {
-                kind: if i < num_main {
+            (
+                if i < num_main {
                     Kind::Main
                 } else if i < num_main + num_ruffa {
                     Kind::Ruffa
                 } else {
                     Kind::Constructor
                 },
-                stats: CarStats::new(&data),
+                CarStats::new(&data),
                 physics,
-                model,
+                prm_path,
                 scale,
-            },
+            ),
+        );
+    }
+
+    let mut pending = model::PendingModels::parse(sources, &Cache::new(&settings.cache_path));
+    let mut registry = HashMap::new();
+    while !pending.is_done() {
+        let batch = pending.upload_batch(
+            UPLOAD_BATCH_SIZE,
+            device,
+            object,
+            settings.game.physics.shape_sampling,
+            !settings.render.debug.raw_vertices,
         );
+        for (name, model) in batch {
+            let (kind, stats, physics, physics_path, scale) = map.remove(&name).unwrap();
+            let tuned_path = tuned_physics_path(&name);
+            registry.insert(
+                name,
+                CarInfo {
+                    kind,
+                    stats,
+                    physics,
+                    physics_path,
+                    tuned_path,
+                    model,
+                    scale,
+                },
+            );
+        }
+        let (done, total) = pending.progress();
+        progress(done, total);
     }
 
-    map
+    registry
 }
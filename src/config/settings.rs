@@ -1,7 +1,10 @@
-use crate::render::object::BodyColor;
+use crate::{level::WorldPhysics, render::object::BodyColor};
 
-use std::fs::File;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::ops;
+use std::path::{Path, PathBuf};
 
 #[derive(Deserialize)]
 pub struct Car {
@@ -10,6 +13,84 @@ pub struct Car {
     pub slots: Vec<String>,
 }
 
+impl Car {
+    /// Replace the car id in the settings file, keeping the rest of it intact.
+    pub fn save_id(path: &Path, id: &str) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        let range = find_car_id(&text).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "Unable to find the car id")
+        })?;
+        let output = format!("{}{:?}{}", &text[..range.start], id, &text[range.end..]);
+        fs::write(path, output)
+    }
+}
+
+/// Find the string literal of `car.id` in the settings text,
+/// skipping over the comments and other strings.
+fn find_car_id(text: &str) -> Option<ops::Range<usize>> {
+    // the field that opened each of the nested scopes
+    let mut scopes = Vec::<Option<&str>>::new();
+    let mut key = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '/' if text[start..].starts_with("//") => {
+                while let Some(&(_, c)) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+            '/' if text[start..].starts_with("/*") => {
+                let end = text[start + 2..].find("*/")? + start + 4;
+                while chars.peek().map_or(false, |&(pos, _)| pos < end) {
+                    chars.next();
+                }
+            }
+            '"' => {
+                let mut end = None;
+                while let Some((pos, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => {
+                            end = Some(pos + 1);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                if scopes.len() == 2 && scopes[1] == Some("car") && key == Some("id") {
+                    return end.map(|end| start..end);
+                }
+            }
+            '(' | '[' | '{' => scopes.push(key.take()),
+            ')' | ']' | '}' => {
+                scopes.pop();
+                key = None;
+            }
+            ',' => key = None,
+            _ if c.is_alphabetic() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(pos, c)) = chars.peek() {
+                    if !c.is_alphanumeric() && c != '_' {
+                        break;
+                    }
+                    end = pos + c.len_utf8();
+                    chars.next();
+                }
+                if text[end..].trim_start().starts_with(':') {
+                    key = Some(&text[start..end]);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
 #[derive(Deserialize)]
 pub enum View {
     Flat,
@@ -44,11 +125,129 @@ pub struct GpuCollision {
     pub max_raster_size: (u32, u32),
 }
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct ContactSolver {
+    pub iterations: usize,
+    /// Coulomb friction coefficient per terrain type.
+    pub friction: [f32; 8],
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct VehicleCollision {
+    /// Part of the approaching speed kept after the collision.
+    pub restitution: f32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct RigidBody {
+    /// Torque of the engine at the full throttle, per unit of the wheel radius.
+    pub engine_torque: f32,
+    /// Lateral grip of a wheel, per unit of its width.
+    pub grip: f32,
+    /// Friction coefficient of the body sliding on the terrain.
+    pub friction: f32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Ackermann {
+    /// Distance from the steered wheels to the rear axle, in model units.
+    /// Derived from the wheel positions if not set.
+    pub wheelbase: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum ProjectileResponse {
+    /// Bounce off when hitting at an angle below `max_angle` degrees to the surface,
+    /// keeping the `restitution` part of the speed. Stop otherwise.
+    Ricochet { max_angle: f32, restitution: f32 },
+    /// Get stuck `depth` units deep into the ground.
+    Penetrate { depth: f32 },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Projectiles {
+    /// Muzzle speed of the fired projectiles.
+    pub speed: f32,
+    /// Time after which a projectile disappears, in seconds.
+    pub lifetime: f32,
+    /// Response to hitting the ground per terrain type.
+    pub terrain: [ProjectileResponse; 8],
+}
+
 #[derive(Deserialize)]
 pub struct Physics {
     pub max_quant: f32,
     pub shape_sampling: u8,
+    pub contact_solver: Option<ContactSolver>,
+    /// Drive the vehicles with the rigid body dynamics of `vangers::physics`,
+    /// instead of the port of the original physics.
+    pub rigid_body: Option<RigidBody>,
+    /// Steer the front wheels around a common turning center.
+    pub ackermann: Option<Ackermann>,
+    /// Collide the vehicles with each other, using the hulls of their shapes.
+    pub vehicle_collision: Option<VehicleCollision>,
+    /// Overrides the physical parameters of the level.
+    pub world: Option<WorldPhysics>,
     pub gpu_collision: Option<GpuCollision>,
+    /// Projectiles fired by the "fire" command, colliding with the terrain.
+    pub projectiles: Option<Projectiles>,
+}
+
+#[derive(Deserialize)]
+pub struct KeyMacro {
+    pub key: String,
+    pub commands: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Audio {
+    pub volume: f32,
+    /// Distance at which the sound gets attenuated by half.
+    pub falloff_distance: f32,
+}
+
+#[derive(Deserialize)]
+pub struct PropKind {
+    /// Model name, see `game.lst` for the list.
+    pub model: String,
+    /// Terrain types to place the prop on.
+    pub terrains: Vec<u8>,
+    /// Average number of props per million texels of the level.
+    pub density: f32,
+    /// Range of the random scale.
+    pub scale: (f32, f32),
+}
+
+#[derive(Deserialize)]
+pub struct Props {
+    /// Seed of the placement, the same seed gives the same props.
+    pub seed: u64,
+    /// Distance from the player beyond which the props are not drawn.
+    pub max_distance: f32,
+    pub kinds: Vec<PropKind>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Precipitation {
+    /// Fast drops splashing on impact and wetting the terrain.
+    Rain,
+    /// Slow grains blown by the wind, raising dust on impact.
+    Sand,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Weather {
+    pub kind: Precipitation,
+    /// Number of the particles around the camera.
+    pub count: usize,
+    /// Horizontal distance from the camera focus that the particles are kept in.
+    pub radius: f32,
+    /// Height above the camera focus that the particles are spawned at.
+    pub height: f32,
+    /// Falling speed of the particles.
+    pub speed: f32,
+    /// Horizontal velocity added by the wind.
+    pub wind: (f32, f32),
 }
 
 #[derive(Deserialize)]
@@ -59,13 +258,116 @@ pub struct Game {
     pub camera: Camera,
     pub other: Other,
     pub physics: Physics,
+    pub macros: Vec<KeyMacro>,
+    pub audio: Audio,
+    pub props: Props,
+    /// Weather per level name, the levels not listed have none.
+    pub weather: HashMap<String, Weather>,
+    pub passages: Vec<Passage>,
+}
+
+/// One-way passage from one level into another, entered by driving into it.
+#[derive(Clone, Deserialize)]
+pub struct Passage {
+    /// Name of the level with the entrance.
+    pub level: String,
+    /// Center of the entrance, in texels.
+    pub position: (i32, i32),
+    /// Distance to the center that gets the vehicle through.
+    pub radius: f32,
+    /// Distance to the center that starts loading the destination in the background.
+    pub preload_radius: f32,
+    /// Name of the level to go to.
+    pub destination: String,
+    /// Where the vehicle comes out in the destination level, in texels.
+    pub exit: (i32, i32),
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub enum ColorFormat {
+    /// Pick the format preferred by the surface, which may have 10 bits per channel.
+    Auto,
+    /// Regular 8-bit per channel output.
+    Ldr,
+}
+
+impl ColorFormat {
+    pub fn to_wgpu(&self) -> Option<wgpu::TextureFormat> {
+        match *self {
+            ColorFormat::Auto => None,
+            ColorFormat::Ldr => Some(wgpu::TextureFormat::Bgra8Unorm),
+        }
+    }
 }
 
 #[derive(Deserialize)]
 pub struct Window {
     pub title: String,
+    /// Logical size, which is multiplied by the scale factor of the display
+    /// to get the size of the framebuffer in pixels.
     pub size: [u32; 2],
+    /// Scale of the UI, in pixels per logical unit. Follows the display if not set.
+    pub ui_scale: Option<f32>,
     pub reload_on_focus: bool,
+    pub color_format: ColorFormat,
+    pub loading_image: String,
+}
+
+/// Keys of the actions shared by the binaries, named after `winit::event::VirtualKeyCode`,
+/// e.g. "W", "LShift", "F1", or "Snapshot".
+#[derive(Clone, Deserialize)]
+pub struct Bindings {
+    pub accelerate: String,
+    pub reverse: String,
+    pub turn_left: String,
+    pub turn_right: String,
+    pub turbo: String,
+    pub jump: String,
+    pub reset: String,
+    pub recover: String,
+    pub pause: String,
+    /// Toggle the debug readout of the terrain.
+    pub debug: String,
+    /// Write the diagnostic bundle, with a screenshot if enabled.
+    pub screenshot: String,
+    /// Capture the next frame with RenderDoc, see the `capture` feature.
+    pub capture: String,
+    pub exit: String,
+    pub road: RoadBindings,
+    pub level: LevelBindings,
+    pub model: ModelBindings,
+}
+
+/// Keys of the game, taking over the shared ones.
+#[derive(Clone, Deserialize)]
+pub struct RoadBindings {
+    pub garage: String,
+    pub roll_left: String,
+    pub roll_right: String,
+    /// Step the physics back during the pause.
+    pub step_back: String,
+    /// Step the physics forward during the pause.
+    pub step_forward: String,
+}
+
+/// Keys of the level viewer, taking over the shared ones.
+#[derive(Clone, Deserialize)]
+pub struct LevelBindings {
+    pub descend: String,
+    pub ascend: String,
+    /// Export the scattered terrain points.
+    pub export: String,
+}
+
+/// Keys of the model viewer, taking over the shared ones.
+#[derive(Clone, Deserialize)]
+pub struct ModelBindings {
+    pub wheels: String,
+    pub debris: String,
+    pub slots: String,
+    pub samples: String,
+    pub export: String,
+    pub measure: String,
 }
 
 #[derive(Deserialize)]
@@ -89,12 +391,87 @@ impl Backend {
     }
 }
 
+#[derive(Deserialize)]
+pub struct Validation {
+    /// Enable the API validation layers of the backend (Vulkan only for now).
+    pub backend: bool,
+    /// Validate shader interfaces against the pipeline layouts.
+    pub shaders: bool,
+    /// Abort on the first uncaptured GPU error instead of logging it.
+    pub fatal_errors: bool,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Diagnostics {
+    /// Directory where the bundles are written.
+    pub path: PathBuf,
+    /// Number of the latest log lines to keep for a bundle.
+    pub log_lines: usize,
+    /// Number of the latest frame times to keep for a bundle.
+    pub frame_count: usize,
+    /// Write a bundle when the application panics.
+    pub on_panic: bool,
+    /// Include a screenshot into the bundles requested by the user.
+    pub screenshot: bool,
+}
+
+/// Colors of the debug overlays.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum DebugPalette {
+    /// Pure red, green, and blue.
+    Default,
+    /// Friendly to the weak green perception.
+    Deuteranopia,
+    /// Friendly to the weak red perception.
+    Protanopia,
+}
+
 #[derive(Clone, Deserialize)]
 pub struct DebugRender {
+    pub palette: DebugPalette,
     pub max_vertices: usize,
     pub collision_shapes: bool,
     pub collision_map: bool,
     pub impulses: bool,
+    pub contacts: bool,
+    pub forces: bool,
+    pub velocities: bool,
+    pub sleeping: bool,
+    pub wheels: bool,
+    /// Frame time and pass statistics, as a graph and in the window title.
+    pub stats: bool,
+    /// Upload the model vertices as they are, without merging the identical ones.
+    pub raw_vertices: bool,
+}
+
+impl DebugRender {
+    /// Returns `true` if any of the layers drawn with lines is enabled.
+    pub fn draws_lines(&self) -> bool {
+        self.impulses
+            || self.contacts
+            || self.forces
+            || self.velocities
+            || self.sleeping
+            || self.wheels
+    }
+
+    /// Toggle a debug layer by name, returns `false` if there is no such layer.
+    pub fn toggle(&mut self, layer: &str) -> bool {
+        let value = match layer {
+            "collision_shapes" => &mut self.collision_shapes,
+            "collision_map" => &mut self.collision_map,
+            "impulses" => &mut self.impulses,
+            "contacts" => &mut self.contacts,
+            "forces" => &mut self.forces,
+            "velocities" => &mut self.velocities,
+            "sleeping" => &mut self.sleeping,
+            "wheels" => &mut self.wheels,
+            "stats" => &mut self.stats,
+            _ => return false,
+        };
+        *value = !*value;
+        true
+    }
 }
 
 #[derive(Clone, Deserialize)]
@@ -108,6 +485,16 @@ pub struct Shadow {
     pub terrain: ShadowTerrain,
 }
 
+/// Handling of the level edges, where the world repeats itself.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum WorldSeam {
+    /// The world is drawn around the origin, the camera can fly out of it.
+    Fixed,
+    /// The camera is moved back by whole level sizes when crossing the edges,
+    /// and the world is drawn around the camera.
+    Wrap,
+}
+
 #[derive(Clone, Deserialize)]
 pub struct Light {
     pub pos: [f32; 4],
@@ -119,6 +506,7 @@ pub struct Light {
 pub enum Terrain {
     RayTraced,
     RayMipTraced {
+        /// Number of the max-filtered height mips, 0 for the full chain.
         mip_count: u32,
         max_jumps: u32,
         max_steps: u32,
@@ -127,62 +515,215 @@ pub enum Terrain {
     Tessellated {
         screen_space: bool,
     },
-    Sliced,
+    Sliced {
+        quality: SliceQuality,
+    },
     Painted,
     Scattered {
+        /// Points per screen pixel along each axis, and the number of layers.
+        /// Bounds the dispatch when `texel_density` is set.
         density: [u32; 3],
+        /// Points per texel of the visible terrain along each axis.
+        /// Sizes the dispatch by the area under the camera frustum if set.
+        texel_density: Option<f32>,
     },
 }
 
+impl Terrain {
+    /// Name of the technique, as written in the settings.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Terrain::RayTraced => "RayTraced",
+            Terrain::RayMipTraced { .. } => "RayMipTraced",
+            Terrain::Tessellated { .. } => "Tessellated",
+            Terrain::Sliced { .. } => "Sliced",
+            Terrain::Painted => "Painted",
+            Terrain::Scattered { .. } => "Scattered",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum SliceQuality {
+    /// Flat texels, showing the steps between the slices.
+    Low,
+    /// Heights interpolated per fragment, smoothing the gentle slopes.
+    High,
+}
+
+/// Filtering of the terrain colors. The palette indices are never filtered,
+/// so `Linear` blends the colors of the neighboring texels after the lookup.
+#[derive(Clone, Copy, Deserialize)]
+pub enum TerrainFilter {
+    Nearest = 0,
+    Linear = 1,
+}
+
+impl TerrainFilter {
+    pub fn to_wgpu(&self) -> wgpu::FilterMode {
+        match *self {
+            TerrainFilter::Nearest => wgpu::FilterMode::Nearest,
+            TerrainFilter::Linear => wgpu::FilterMode::Linear,
+        }
+    }
+}
+
+/// Precision of the terrain heights in the GPU texture.
+#[derive(Clone, Copy, Deserialize)]
+pub enum TerrainHeight {
+    /// Original 8-bit heights, terraced when looked at closely.
+    Low,
+    /// Heights smoothed into 16-bit floats at load, taking twice the memory.
+    Smooth,
+}
+
+impl TerrainHeight {
+    pub fn to_wgpu(&self) -> wgpu::TextureFormat {
+        match *self {
+            TerrainHeight::Low => wgpu::TextureFormat::R8Unorm,
+            TerrainHeight::Smooth => wgpu::TextureFormat::R16Float,
+        }
+    }
+}
+
+/// Source of the terrain colors.
+#[derive(Clone, Deserialize)]
+pub enum TerrainColor {
+    /// Palette of the level, as in the original.
+    Palette,
+    /// Gradient over the altitude.
+    Hypsometric,
+    /// Distinct color per terrain type, for inspecting the meta data.
+    Meta,
+    /// PNG table, indexed by the altitude along X and by the terrain type along Y.
+    Lut { path: PathBuf },
+}
+
+/// Procedural noise breaking up the flat texels of the terrain up close.
+/// Two cascades of noise are used, the finer one fading out at half the distance.
+#[derive(Clone, Deserialize)]
+pub struct TerrainDetail {
+    /// Distance at which the detail fades out completely.
+    pub fade_distance: f32,
+    /// Frequency of the coarse noise per terrain type, in cycles per texel.
+    pub scale: [f32; 8],
+    /// Amplitude of the noise per terrain type, in the units of the lighting.
+    pub intensity: [f32; 8],
+}
+
 #[derive(Clone, Deserialize)]
 pub struct Fog {
     pub color: [f32; 4],
     pub depth: f32,
 }
 
+#[derive(Clone, Deserialize)]
+pub struct Crt {
+    /// Amount of the screen barrel distortion.
+    pub curvature: f32,
+    /// Darkening of every other line, from 0 to 1.
+    pub scanlines: f32,
+    /// Darkening of the screen corners, from 0 to 1.
+    pub vignette: f32,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Post {
+    /// Quantize the image to the level palette with ordered dithering.
+    pub palette_dither: bool,
+    /// Retro CRT filter, applied at the very end.
+    pub crt: Option<Crt>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ContactShadow {
+    /// Distance to march towards the light.
+    pub length: f32,
+    /// Darkening of the fully occluded points, from 0 to 1.
+    pub intensity: f32,
+    /// Number of the depth samples along the ray.
+    pub steps: u32,
+    /// Assumed thickness of the occluders in the depth buffer.
+    pub thickness: f32,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct LightProbes {
+    /// Side of the level area covered by a probe, in texels, a power of two.
+    pub cell_size: u32,
+    /// Contribution of the fog color, as the sky, from 0 to 1.
+    pub sky_weight: f32,
+    /// Tinting of the unlit parts of the objects, from 0 to 1.
+    pub strength: f32,
+}
+
 #[derive(Deserialize)]
 pub struct Render {
     pub light: Light,
     pub terrain: Terrain,
+    pub terrain_filter: TerrainFilter,
+    pub terrain_height: TerrainHeight,
+    pub terrain_color: TerrainColor,
+    pub terrain_detail: Option<TerrainDetail>,
+    /// Values defined ahead of the ray-traced terrain shaders, e.g. the number of the ray steps.
+    pub terrain_defines: Vec<(String, String)>,
+    pub world_seam: WorldSeam,
+    /// Size of the texture data submitted at once when loading a level, 0 for no limit.
+    pub upload_chunk_megabytes: u32,
+    /// Size of the top-down level map texture for the HUD, 0 to disable.
+    pub minimap_size: u32,
+    /// Number of samples per pixel of the world rendering, 1 to disable MSAA.
+    pub msaa: u32,
+    /// Screen-space contact shadows, working without the shadow map.
+    pub contact_shadow: Option<ContactShadow>,
+    /// Ambient light probes, tinting the objects with the colors around them.
+    pub light_probes: Option<LightProbes>,
     pub fog: Fog,
+    pub post: Post,
     pub debug: DebugRender,
 }
 
 #[derive(Deserialize)]
 pub struct Settings {
     pub data_path: PathBuf,
+    pub cache_path: PathBuf,
     pub car: Car,
     pub game: Game,
     pub window: Window,
+    pub bindings: Bindings,
     pub backend: Backend,
+    /// Part of the name of the adapter to use, or empty to pick automatically.
+    pub adapter: String,
+    pub validation: Validation,
+    pub diagnostics: Diagnostics,
     pub render: Render,
 }
 
 impl Settings {
     pub fn load(path: &str) -> Self {
-        use std::io::Read;
-
-        let mut string = String::new();
-        File::open(path)
-            .expect("Unable to open the settings file")
-            .read_to_string(&mut string)
-            .unwrap();
-        let set: Settings = match ron::de::from_str(&string) {
-            Ok(set) => set,
-            Err(e) => panic!("Unable to parse settings RON.\n\t{}\n\tError: {:?}",
+        Self::try_load(path).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Load the settings, returning the error instead of panicking,
+    /// e.g. for reloading them while running.
+    pub fn try_load(path: &str) -> Result<Self, String> {
+        let string = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to open the settings file {}: {}", path, e))?;
+        let set: Settings = ron::de::from_str(&string).map_err(|e| {
+            format!("Unable to parse settings RON.\n\t{}\n\tError: {:?}",
                 "Please check if `config/settings.template.ron` has changed and your local config needs to be adjusted.",
                 e,
-            ),
-        };
+            )
+        })?;
 
         if !set.check_path("options.dat") {
-            panic!(
+            return Err(format!(
                 "Can't find the resources of the original Vangers game at {:?}, {}",
                 set.data_path, "please check your `config/settings.ron`"
-            );
+            ));
         }
 
-        set
+        Ok(set)
     }
 
     pub fn open_relative(&self, path: &str) -> File {
@@ -0,0 +1,233 @@
+//! Diagnostic bundles, making the bug reports actionable.
+//!
+//! A bundle is a directory with a report of the current settings, adapter,
+//! the latest log lines and frame times, and optionally a screenshot.
+//! It's written on panic, or when requested by the user.
+
+use crate::config::settings;
+
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    fs::{self, File},
+    io, panic,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+type History<T> = Arc<Mutex<VecDeque<T>>>;
+
+fn push_limited<T>(history: &Mutex<VecDeque<T>>, value: T, capacity: usize) {
+    let mut queue = history.lock().unwrap();
+    if queue.len() == capacity {
+        queue.pop_front();
+    }
+    queue.push_back(value);
+}
+
+/// Logger that forwards everything to `env_logger`,
+/// while remembering the latest lines for the bundles.
+struct HistoryLogger {
+    inner: env_logger::Logger,
+    lines: History<String>,
+    capacity: usize,
+}
+
+impl log::Log for HistoryLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.inner.matches(record) {
+            return;
+        }
+        self.inner.log(record);
+        if self.capacity != 0 {
+            let line = format!("[{} {}] {}", record.level(), record.target(), record.args());
+            push_limited(&self.lines, line, self.capacity);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+pub struct Diagnostics {
+    config: settings::Diagnostics,
+    settings_path: PathBuf,
+    adapter: Mutex<String>,
+    log_lines: History<String>,
+    frame_times: Mutex<VecDeque<f32>>,
+}
+
+impl Diagnostics {
+    /// Install the logger, which has to happen before anything is logged.
+    pub fn init(config: &settings::Diagnostics, settings_path: &Path) -> Arc<Self> {
+        let log_lines = Arc::new(Mutex::new(VecDeque::with_capacity(config.log_lines)));
+        let inner = env_logger::Builder::from_default_env().build();
+        let max_level = inner.filter();
+        let logger = HistoryLogger {
+            inner,
+            lines: Arc::clone(&log_lines),
+            capacity: config.log_lines,
+        };
+        match log::set_boxed_logger(Box::new(logger)) {
+            Ok(()) => log::set_max_level(max_level),
+            Err(e) => eprintln!("Unable to install the logger: {}", e),
+        }
+
+        let diagnostics = Arc::new(Diagnostics {
+            config: config.clone(),
+            settings_path: settings_path.to_path_buf(),
+            adapter: Mutex::new(String::new()),
+            log_lines,
+            frame_times: Mutex::new(VecDeque::with_capacity(config.frame_count)),
+        });
+        if config.on_panic {
+            diagnostics.install_panic_hook();
+        }
+        diagnostics
+    }
+
+    fn install_panic_hook(self: &Arc<Self>) {
+        let previous = panic::take_hook();
+        let diagnostics = Arc::clone(self);
+        panic::set_hook(Box::new(move |info| {
+            previous(info);
+            match diagnostics.write_bundle(&info.to_string()) {
+                Ok(dir) => eprintln!("Diagnostic bundle is written to {:?}", dir),
+                Err(e) => eprintln!("Unable to write the diagnostic bundle: {}", e),
+            }
+        }));
+    }
+
+    pub fn wants_screenshot(&self) -> bool {
+        self.config.screenshot
+    }
+
+    pub fn set_adapter(&self, info: &wgpu::AdapterInfo) {
+        *self.adapter.lock().unwrap() = format!("{:#?}", info);
+    }
+
+    pub fn record_frame(&self, delta: f32) {
+        if self.config.frame_count != 0 {
+            push_limited(&self.frame_times, delta, self.config.frame_count);
+        }
+    }
+
+    fn report(&self, reason: &str) -> String {
+        let mut text = String::new();
+        writeln!(text, "Reason: {}\n", reason).unwrap();
+
+        // the panic may happen while any of the locks is held,
+        // in which case the corresponding section is skipped
+        writeln!(text, "== Adapter ==").unwrap();
+        match self.adapter.try_lock() {
+            Ok(adapter) => writeln!(text, "{}\n", adapter).unwrap(),
+            Err(_) => writeln!(text, "<unavailable>\n").unwrap(),
+        }
+
+        writeln!(text, "== Frames ==").unwrap();
+        match self.frame_times.try_lock() {
+            Ok(ref times) if !times.is_empty() => {
+                let total = times.iter().sum::<f32>();
+                let min = times.iter().cloned().fold(f32::INFINITY, f32::min);
+                let max = times.iter().cloned().fold(0.0, f32::max);
+                writeln!(
+                    text,
+                    "count: {}, average: {:.2} ms, min: {:.2} ms, max: {:.2} ms",
+                    times.len(),
+                    total * 1000.0 / times.len() as f32,
+                    min * 1000.0,
+                    max * 1000.0,
+                )
+                .unwrap();
+                for time in times.iter() {
+                    write!(text, "{:.2} ", time * 1000.0).unwrap();
+                }
+                writeln!(text, "\n").unwrap();
+            }
+            Ok(_) => writeln!(text, "<none>\n").unwrap(),
+            Err(_) => writeln!(text, "<unavailable>\n").unwrap(),
+        }
+
+        writeln!(text, "== Log ==").unwrap();
+        match self.log_lines.try_lock() {
+            Ok(lines) => {
+                for line in lines.iter() {
+                    writeln!(text, "{}", line).unwrap();
+                }
+            }
+            Err(_) => writeln!(text, "<unavailable>").unwrap(),
+        }
+        text
+    }
+
+    /// Write a new bundle, returning the directory it's in.
+    pub fn write_bundle(&self, reason: &str) -> io::Result<PathBuf> {
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let dir = self.config.path.join(format!("bundle-{}", stamp));
+        fs::create_dir_all(&dir)?;
+        if let Err(e) = fs::copy(&self.settings_path, dir.join("settings.ron")) {
+            log::warn!("Unable to copy the settings: {}", e);
+        }
+        fs::write(dir.join("report.txt"), self.report(reason))?;
+        Ok(dir)
+    }
+}
+
+/// Size of a texel of the color target format, in bytes.
+pub fn texel_size(format: wgpu::TextureFormat) -> Result<u32, String> {
+    use wgpu::TextureFormat as Tf;
+
+    match format {
+        Tf::Rgba8Unorm
+        | Tf::Rgba8UnormSrgb
+        | Tf::Bgra8Unorm
+        | Tf::Bgra8UnormSrgb
+        | Tf::Rgb10a2Unorm => Ok(4),
+        Tf::Rgba16Float => Ok(8),
+        Tf::Rgba32Float => Ok(16),
+        other => Err(format!("unsupported format {:?}", other)),
+    }
+}
+
+/// Write the tightly packed texels of a screenshot as PNG.
+/// Only the 8-bit RGBA and BGRA formats, and the 10-bit RGB, are supported.
+pub fn write_screenshot(
+    path: &Path,
+    extent: wgpu::Extent3d,
+    format: wgpu::TextureFormat,
+    data: &[u8],
+) -> Result<(), String> {
+    use wgpu::TextureFormat as Tf;
+
+    let rgba = match format {
+        Tf::Rgba8Unorm | Tf::Rgba8UnormSrgb => data.to_vec(),
+        Tf::Bgra8Unorm | Tf::Bgra8UnormSrgb => data
+            .chunks(4)
+            .flat_map(|c| vec![c[2], c[1], c[0], 0xFF])
+            .collect(),
+        Tf::Rgb10a2Unorm => data
+            .chunks(4)
+            .flat_map(|c| {
+                let texel = u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+                // keep the upper 8 bits of each channel
+                let channel = |shift: u32| (texel >> (shift + 2)) as u8;
+                vec![channel(0), channel(10), channel(20), 0xFF]
+            })
+            .collect(),
+        other => return Err(format!("unsupported format {:?}", other)),
+    };
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(file, extent.width, extent.height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&rgba).map_err(|e| e.to_string())
+}
@@ -0,0 +1,62 @@
+//! Components and systems for using the crate with the `hecs` ECS.
+//!
+//! An entity with a `space::Transform` and a `ModelHandle` is drawn by `extract_render`.
+//! Adding a `PhysicsBody` makes its transform follow the GPU physics with `sync_physics`.
+
+use crate::{
+    model,
+    render::{
+        body::{GpuBody, GpuStoreMirror},
+        object::BodyColor,
+        Batcher,
+    },
+    space,
+};
+
+use std::sync::Arc;
+
+/// Visual model of an entity, shared between the entities using it.
+#[derive(Clone)]
+pub struct ModelHandle {
+    pub model: Arc<model::VisualModel>,
+    pub color: BodyColor,
+}
+
+/// Body of an entity in the GPU physics store.
+/// The transform of the entity is taken from the store by `sync_physics`.
+pub struct PhysicsBody {
+    pub body: GpuBody,
+}
+
+/// Spawn an entity drawn with the given model.
+pub fn spawn_model(
+    world: &mut hecs::World,
+    model: Arc<model::VisualModel>,
+    color: BodyColor,
+    transform: space::Transform,
+) -> hecs::Entity {
+    world.spawn((transform, ModelHandle { model, color }))
+}
+
+/// Copy the transforms of the physics bodies from the CPU mirror of the store.
+pub fn sync_physics(world: &mut hecs::World, mirror: &GpuStoreMirror) {
+    for (_, (transform, body)) in world
+        .query::<(&mut space::Transform, &PhysicsBody)>()
+        .iter()
+    {
+        if let Some(t) = mirror.get(&body.body) {
+            *transform = *t;
+        }
+    }
+}
+
+/// Add the models of all the entities into the batch for drawing.
+pub fn extract_render(world: &hecs::World, batcher: &mut Batcher) {
+    for (_, (transform, handle, body)) in world
+        .query::<(&space::Transform, &ModelHandle, Option<&PhysicsBody>)>()
+        .iter()
+    {
+        let gpu_body = body.map_or(&GpuBody::ZERO, |b| &b.body);
+        batcher.add_model(&handle.model, transform, None, gpu_body, handle.color);
+    }
+}
@@ -0,0 +1,138 @@
+//! Identification of the original asset files before parsing them.
+//!
+//! The parsers assume well-formed input and panic in the middle otherwise,
+//! so the tools sniff the files first to report the unsupported ones precisely.
+//! VPR files have no recognizable header, and are only checked for the size
+//! expected by the level configuration when loading.
+
+use byteorder::{ByteOrder as _, LittleEndian as E};
+
+use std::{
+    fmt,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+/// Mesh versions supported in the M3D and A3D files.
+pub const MESH_VERSIONS: &[u32] = &[m3d::MAGIC_VERSION];
+/// Size of the Splay trees in front of the VMC rows.
+const VMC_TREE_SIZE: u32 = 512 * 2 * 4;
+/// Size of a VMC row table entry: a 32-bit offset and a 16-bit size.
+const VMC_ROW_ENTRY: u32 = 6;
+/// Offset of the mesh in an A3D file, after the frame count, the bound, and the color.
+const A3D_MESH_OFFSET: usize = 4 + 16 + 8;
+/// Number of bytes needed to identify any of the formats.
+const HEADER_SIZE: u64 = (A3D_MESH_OFFSET + 5 * 4) as u64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Model with the body, wheels, debris, and the collision shape.
+    M3d { version: u32 },
+    /// Animated mesh with the given number of frames.
+    A3d { version: u32, frames: u32 },
+    /// Compressed level data with the given number of rows.
+    Vmc { rows: u32 },
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Format::M3d { .. } => "M3D",
+            Format::A3d { .. } => "A3D",
+            Format::Vmc { .. } => "VMC",
+        }
+    }
+
+    pub fn version(&self) -> Option<u32> {
+        match *self {
+            Format::M3d { version } | Format::A3d { version, .. } => Some(version),
+            Format::Vmc { .. } => None,
+        }
+    }
+
+    /// Check if the file can be loaded by this crate.
+    pub fn check(&self) -> Result<(), UnsupportedVersion> {
+        match self.version() {
+            Some(version) if !MESH_VERSIONS.contains(&version) => Err(UnsupportedVersion {
+                format: *self,
+                supported: MESH_VERSIONS,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnsupportedVersion {
+    pub format: Format,
+    pub supported: &'static [u32],
+}
+
+impl fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unsupported {} version {}, expected one of {:?}",
+            self.format.name(),
+            self.format.version().unwrap_or(0),
+            self.supported,
+        )
+    }
+}
+
+/// Check if the mesh header at `offset` looks sane, returning its version.
+fn sniff_mesh(data: &[u8], offset: usize) -> Option<u32> {
+    let header = data.get(offset..offset + 5 * 4)?;
+    let version = E::read_u32(&header[0..]);
+    let num_positions = E::read_u32(&header[4..]);
+    let num_normals = E::read_u32(&header[8..]);
+    let num_polygons = E::read_u32(&header[12..]);
+    let total_verts = E::read_u32(&header[16..]);
+    // the polygons are triangles or quads, indexing the positions by 16 bits
+    let sane = version < 0x100
+        && num_positions != 0
+        && num_positions <= 0x10000
+        && num_normals <= 0x10000
+        && num_polygons != 0
+        && num_polygons <= 0x10000
+        && total_verts >= 3 * num_polygons
+        && total_verts <= 4 * num_polygons;
+    if sane {
+        Some(version)
+    } else {
+        None
+    }
+}
+
+/// Check if the data starts with a VMC row table, returning the number of rows.
+fn sniff_vmc(data: &[u8]) -> Option<u32> {
+    let first = E::read_i32(data.get(0..4)?);
+    let second = E::read_i32(data.get(VMC_ROW_ENTRY as usize..VMC_ROW_ENTRY as usize + 4)?);
+    // the first row follows the row table and the trees, the others go after it
+    let table_size = (first as u32).checked_sub(VMC_TREE_SIZE)?;
+    if first <= 0 || table_size == 0 || table_size % VMC_ROW_ENTRY != 0 || second < first {
+        return None;
+    }
+    Some(table_size / VMC_ROW_ENTRY)
+}
+
+/// Identify the format of the data in `reader`, without consuming it.
+/// Returns `None` if the format is not recognized.
+pub fn detect_format<R: Read + Seek>(reader: &mut R) -> io::Result<Option<Format>> {
+    let start = reader.seek(SeekFrom::Current(0))?;
+    let mut data = Vec::new();
+    reader.by_ref().take(HEADER_SIZE).read_to_end(&mut data)?;
+    reader.seek(SeekFrom::Start(start))?;
+
+    if let Some(version) = sniff_mesh(&data, 0) {
+        return Ok(Some(Format::M3d { version }));
+    }
+    if data.len() >= 4 {
+        let frames = E::read_u32(&data);
+        if frames != 0 && frames <= 0x100 {
+            if let Some(version) = sniff_mesh(&data, A3D_MESH_OFFSET) {
+                return Ok(Some(Format::A3d { version, frames }));
+            }
+        }
+    }
+    Ok(sniff_vmc(&data).map(|rows| Format::Vmc { rows }))
+}
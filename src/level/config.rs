@@ -1,15 +1,79 @@
+use super::Rect;
+use crate::config::common::Common;
+
 use ini::Ini;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
 use std::ops::Range;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// Power of two that is out of the supported range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InvalidPower(pub i32);
+
+impl fmt::Display for InvalidPower {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "power {} is out of the range 0..={}", self.0, Power::MAX)
+    }
+}
+
+/// Size of the level or its parts, given by the power of two,
+/// as the level INI files describe them.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[serde(try_from = "i32")]
+pub struct Power(i32);
 
-pub struct Power(pub i32);
 impl Power {
+    /// Largest supported power, so that the area of a level fits into `i32`.
+    pub const MAX: i32 = 15;
+
+    pub fn new(power: i32) -> Result<Self, InvalidPower> {
+        if power >= 0 && power <= Self::MAX {
+            Ok(Power(power))
+        } else {
+            Err(InvalidPower(power))
+        }
+    }
+
+    /// Power of the given size, if it's a power of two.
+    pub fn from_value(value: i32) -> Option<Self> {
+        if value > 0 && value & (value - 1) == 0 {
+            Power::new(value.trailing_zeros() as i32).ok()
+        } else {
+            None
+        }
+    }
+
     pub fn as_value(&self) -> i32 {
         1 << self.0
     }
     pub fn as_power(&self) -> i32 {
         self.0
     }
+
+    /// Index of the cell of this size that contains the coordinate.
+    pub fn index_of(&self, coord: i32) -> i32 {
+        coord >> self.0
+    }
+
+    /// Coordinate of the start of the cell with the given index.
+    pub fn offset_of(&self, index: i32) -> i32 {
+        index << self.0
+    }
+
+    /// Number of the cells of this size needed to cover the `size`,
+    /// counting the partial cell at the end.
+    pub fn count_in(&self, size: i32) -> i32 {
+        (size + self.as_value() - 1) >> self.0
+    }
+}
+
+impl TryFrom<i32> for Power {
+    type Error = InvalidPower;
+    fn try_from(power: i32) -> Result<Self, InvalidPower> {
+        Power::new(power)
+    }
 }
 
 #[derive(Clone)]
@@ -19,6 +83,35 @@ pub struct TerrainConfig {
     pub colors: Range<u8>, // note: actually, this is inclusive range
 }
 
+/// Physical parameters of a world, as factors to the ones in `common.prm`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct WorldPhysics {
+    pub gravity: f32,
+    pub air_drag: f32,
+    pub water_density: f32,
+}
+
+impl Default for WorldPhysics {
+    fn default() -> Self {
+        WorldPhysics {
+            gravity: 1.0,
+            air_drag: 1.0,
+            water_density: 1.0,
+        }
+    }
+}
+
+impl WorldPhysics {
+    /// Adjust the common parameters to this world.
+    /// The water density isn't there, it's applied by the physics directly.
+    pub fn apply_common(&self, common: &mut Common) {
+        common.nature.gravity *= self.gravity;
+        // drag is a multiplier applied every frame, so scaling the drag means raising it
+        common.drag.free.v = common.drag.free.v.powf(self.air_drag);
+        common.drag.free.w = common.drag.free.w.powf(self.air_drag);
+    }
+}
+
 pub struct LevelConfig {
     //pub name: String,
     pub path_palette: PathBuf,
@@ -29,9 +122,15 @@ pub struct LevelConfig {
     pub section: Power,
     pub min_square: Power,
     pub terrains: Box<[TerrainConfig]>,
+    pub physics: WorldPhysics,
 }
 
 impl LevelConfig {
+    /// Size of the level, in texels.
+    pub fn texel_size(&self) -> (i32, i32) {
+        (self.size.0.as_value(), self.size.1.as_value())
+    }
+
     pub fn load(ini_path: &PathBuf) -> Self {
         let ini = Ini::load_from_file(ini_path).expect(&format!(
             "Unable to read the level's INI description: {:?}",
@@ -40,6 +139,12 @@ impl LevelConfig {
         let global = &ini["Global Parameters"];
         let storage = &ini["Storage"];
         let render = &ini["Rendering Parameters"];
+        let power = |key: &str| {
+            let value = global[key]
+                .parse()
+                .unwrap_or_else(|e| panic!("Bad {}: {:?}", key, e));
+            Power::new(value).unwrap_or_else(|e| panic!("Bad {}: {}", key, e))
+        };
 
         let terra_count = render
             .get("Terrain Max")
@@ -77,20 +182,109 @@ impl LevelConfig {
             t.colors.end = val.parse().unwrap();
         }
 
+        let mut physics = WorldPhysics::default();
+        if let Some(section) = ini.section(Some("Physics Parameters")) {
+            for (key, value) in vec![
+                ("Gravity", &mut physics.gravity),
+                ("Air Drag", &mut physics.air_drag),
+                ("Water Density", &mut physics.water_density),
+            ] {
+                if let Some(string) = section.get(key) {
+                    *value = string.parse().unwrap();
+                }
+            }
+        }
+
         let path_data = ini_path.with_file_name(&storage["File Name"]);
         LevelConfig {
             path_data,
             path_palette: ini_path.with_file_name(&storage["Palette File"]),
             is_compressed: &storage["Compressed Format Using"] != "0",
             //name: self.game.level.clone(),
-            size: (
-                Power(global["Map Power X"].parse().unwrap()),
-                Power(global["Map Power Y"].parse().unwrap()),
-            ),
-            geo: Power(global["GeoNet Power"].parse().unwrap()),
-            section: Power(global["Section Size Power"].parse().unwrap()),
-            min_square: Power(global["Minimal Square Power"].parse().unwrap()),
+            size: (power("Map Power X"), power("Map Power Y")),
+            geo: power("GeoNet Power"),
+            section: power("Section Size Power"),
+            min_square: power("Minimal Square Power"),
             terrains,
+            physics,
+        }
+    }
+
+    /// Derive the config of a mini-level carved out by `super::extract_region`.
+    /// The data is expected to be saved as VMP next to the `ini_path`.
+    pub fn extract_region(&self, rect: &Rect, ini_path: &Path) -> Self {
+        let size = (power_of(rect.w), power_of(rect.h));
+        let max_power = size.0.min(size.1);
+        LevelConfig {
+            path_data: ini_path.with_extension("vmp"),
+            path_palette: ini_path.with_extension("pal"),
+            is_compressed: false,
+            size,
+            geo: self.geo.min(max_power),
+            section: self.section.min(size.1),
+            min_square: self.min_square.min(max_power),
+            terrains: self.terrains.clone(),
+            physics: self.physics,
+        }
+    }
+
+    /// Write the INI description of the level, copying the palette if needed.
+    pub fn save(&self, ini_path: &Path, source_palette: &Path) -> io::Result<()> {
+        fn join<I: Iterator<Item = u8>>(values: I) -> String {
+            values.map(|v| v.to_string()).collect::<Vec<_>>().join(" ")
         }
+
+        if source_palette != self.path_palette.as_path() {
+            std::fs::copy(source_palette, &self.path_palette)?;
+        }
+
+        let file_name = |path: &PathBuf| path.file_name().unwrap().to_str().unwrap().to_string();
+        let mut ini = Ini::new();
+        ini.with_section(Some("Global Parameters"))
+            .set("Map Power X", self.size.0.as_power().to_string())
+            .set("Map Power Y", self.size.1.as_power().to_string())
+            .set("GeoNet Power", self.geo.as_power().to_string())
+            .set("Section Size Power", self.section.as_power().to_string())
+            .set(
+                "Minimal Square Power",
+                self.min_square.as_power().to_string(),
+            );
+        ini.with_section(Some("Storage"))
+            .set("File Name", file_name(&self.path_data))
+            .set("Palette File", file_name(&self.path_palette))
+            .set(
+                "Compressed Format Using",
+                if self.is_compressed { "1" } else { "0" },
+            );
+        ini.with_section(Some("Rendering Parameters"))
+            .set("Terrain Max", self.terrains.len().to_string())
+            .set(
+                "Shadow Offsets",
+                join(self.terrains.iter().map(|t| t.shadow_offset)),
+            )
+            .set(
+                "Height Shifts",
+                join(self.terrains.iter().map(|t| t.height_shift)),
+            )
+            .set(
+                "Begin Colors",
+                join(self.terrains.iter().map(|t| t.colors.start)),
+            )
+            .set(
+                "End Colors",
+                join(self.terrains.iter().map(|t| t.colors.end)),
+            );
+        if self.physics != WorldPhysics::default() {
+            ini.with_section(Some("Physics Parameters"))
+                .set("Gravity", self.physics.gravity.to_string())
+                .set("Air Drag", self.physics.air_drag.to_string())
+                .set("Water Density", self.physics.water_density.to_string());
+        }
+        ini.write_to_file(ini_path)
     }
 }
+
+fn power_of(value: i32) -> Power {
+    Power::from_value(value)
+        .unwrap_or_else(|| panic!("Level dimension {} is not a supported power of two", value))
+}
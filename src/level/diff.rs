@@ -0,0 +1,133 @@
+//! Differences between two versions of a level, for reviewing the map edits.
+
+use super::{Level, Rect, TerrainBits};
+
+use std::fmt;
+
+/// Sizes of the levels that can't be compared.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SizeMismatch {
+    pub old: (i32, i32),
+    pub new: (i32, i32),
+}
+
+impl fmt::Display for SizeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "level size {:?} doesn't match the original {:?}",
+            self.new, self.old
+        )
+    }
+}
+
+/// Summary of the changes between the levels.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiffStats {
+    /// Number of the texels with a different height.
+    pub height_changed: usize,
+    /// Number of the texels with a different terrain type.
+    pub terrain_changed: usize,
+    /// Largest increase of the height, in altitude units.
+    pub max_raise: u8,
+    /// Largest decrease of the height, in altitude units.
+    pub max_drop: u8,
+    /// Average height change over the texels with a different height.
+    pub mean_change: f32,
+    /// Bounding rectangle of all the changed texels.
+    pub bounds: Option<Rect>,
+}
+
+/// Per-texel differences between the old and the new versions of a level.
+pub struct LevelDiff {
+    pub size: (i32, i32),
+    /// Height of the new level minus the old one.
+    pub height_delta: Vec<i16>,
+    /// True for the texels with a different terrain type.
+    pub terrain_changed: Vec<bool>,
+    pub stats: DiffStats,
+}
+
+impl LevelDiff {
+    /// Compare the raw texels of the levels, which need to be of the same size.
+    pub fn new(old: &Level, new: &Level) -> Result<Self, SizeMismatch> {
+        if old.size != new.size {
+            return Err(SizeMismatch {
+                old: old.size,
+                new: new.size,
+            });
+        }
+        let old_bits = TerrainBits::new(old.terrains.len() as u8);
+        let new_bits = TerrainBits::new(new.terrains.len() as u8);
+        let mut stats = DiffStats::default();
+        let mut total_change = 0u64;
+        let (mut min, mut max) = ((new.size.0, new.size.1), (-1, -1));
+
+        let mut height_delta = Vec::with_capacity(new.height.len());
+        let mut terrain_changed = Vec::with_capacity(new.meta.len());
+        for i in 0..new.height.len() {
+            let (h0, h1) = (old.height[i], new.height[i]);
+            let (m0, m1) = (old.meta[i], new.meta[i]);
+            let delta = h1 as i16 - h0 as i16;
+            let is_terrain_changed = old_bits.read(m0) != new_bits.read(m1);
+            if delta != 0 {
+                stats.height_changed += 1;
+                total_change += delta.abs() as u64;
+                if delta > 0 {
+                    stats.max_raise = stats.max_raise.max(delta as u8);
+                } else {
+                    stats.max_drop = stats.max_drop.max(-delta as u8);
+                }
+            }
+            if is_terrain_changed {
+                stats.terrain_changed += 1;
+            }
+            if delta != 0 || is_terrain_changed {
+                let (x, y) = (i as i32 % new.size.0, i as i32 / new.size.0);
+                min = (min.0.min(x), min.1.min(y));
+                max = (max.0.max(x), max.1.max(y));
+            }
+            height_delta.push(delta);
+            terrain_changed.push(is_terrain_changed);
+        }
+
+        if stats.height_changed != 0 {
+            stats.mean_change = total_change as f32 / stats.height_changed as f32;
+        }
+        if max.0 >= 0 {
+            stats.bounds = Some(Rect {
+                x: min.0,
+                y: min.1,
+                w: max.0 + 1 - min.0,
+                h: max.1 + 1 - min.1,
+            });
+        }
+        Ok(LevelDiff {
+            size: new.size,
+            height_delta,
+            terrain_changed,
+            stats,
+        })
+    }
+
+    /// Visualize the differences as RGB texels on top of the dimmed heights of the `level`:
+    /// the raised texels are red, the lowered ones are blue, the brighter the larger
+    /// the change, and the texels with a different terrain type are green.
+    pub fn to_rgb(&self, level: &Level) -> Vec<u8> {
+        let mut data = Vec::with_capacity(3 * self.height_delta.len());
+        for ((&delta, &is_terrain_changed), &height) in self
+            .height_delta
+            .iter()
+            .zip(&self.terrain_changed)
+            .zip(&level.height)
+        {
+            let base = height / 4;
+            let change = 0x80 + delta.abs().min(0x7F) as u8;
+            let red = if delta > 0 { change } else { base };
+            let green = if is_terrain_changed { 0xFF } else { base };
+            let blue = if delta < 0 { change } else { base };
+            data.extend_from_slice(&[red, green, blue]);
+        }
+        data
+    }
+}
@@ -0,0 +1,169 @@
+//! Modification of the level data at run time, e.g. digging the craters.
+//!
+//! The modified areas are tracked, so that only they get uploaded to GPU.
+
+use super::{Altitude, Level, Rect, DOUBLE_LEVEL};
+
+use std::{fs::File, io, path::Path};
+
+/// Areas of the level modified since they were last taken.
+#[derive(Debug, Default)]
+pub struct DirtyRegions {
+    rects: Vec<Rect>,
+}
+
+fn touches(a: &Rect, b: &Rect) -> bool {
+    a.x <= b.x + b.w && b.x <= a.x + a.w && a.y <= b.y + b.h && b.y <= a.y + a.h
+}
+
+fn union(a: &Rect, b: &Rect) -> Rect {
+    let (x, y) = (a.x.min(b.x), a.y.min(b.y));
+    Rect {
+        x,
+        y,
+        w: (a.x + a.w).max(b.x + b.w) - x,
+        h: (a.y + a.h).max(b.y + b.h) - y,
+    }
+}
+
+impl DirtyRegions {
+    /// Add an area, merging it with the ones it overlaps or touches.
+    pub fn add(&mut self, rect: Rect) {
+        let mut merged = rect;
+        while let Some(index) = self.rects.iter().position(|r| touches(r, &merged)) {
+            merged = union(&self.rects.swap_remove(index), &merged);
+        }
+        self.rects.push(merged);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn take(&mut self) -> Vec<Rect> {
+        std::mem::replace(&mut self.rects, Vec::new())
+    }
+}
+
+/// How the brush is combined with the height map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Blend {
+    /// Raise the terrain by the brush, or lower it with a negative strength.
+    Add,
+    /// Raise the terrain up to the brush, never lowering it.
+    Max,
+    /// Move the terrain towards the strength, as much as the brush covers it.
+    Set,
+}
+
+/// Grayscale image to be stamped onto the height map.
+pub struct Brush {
+    pub size: (i32, i32),
+    /// Coverage of the texels, row by row.
+    pub values: Vec<u8>,
+}
+
+impl Brush {
+    /// Load the brush from a PNG image. Only the first channel is used,
+    /// multiplied by the alpha if there is one.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let (info, mut reader) = decoder.read_info().map_err(invalid)?;
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).map_err(invalid)?;
+        let (stride, alpha) = match info.color_type {
+            png::ColorType::Grayscale => (1, None),
+            png::ColorType::GrayscaleAlpha => (2, Some(1)),
+            png::ColorType::RGB => (3, None),
+            png::ColorType::RGBA => (4, Some(3)),
+            png::ColorType::Indexed => unreachable!("Palette is expanded"),
+        };
+        let values = data
+            .chunks(stride)
+            .map(|c| match alpha {
+                Some(a) => (c[0] as u32 * c[a] as u32 / 0xFF) as u8,
+                None => c[0],
+            })
+            .collect();
+        Ok(Brush {
+            size: (info.width as i32, info.height as i32),
+            values,
+        })
+    }
+}
+
+impl Level {
+    fn edit_index(&mut self, x: i32, y: i32) -> usize {
+        let (x, y) = (x.rem_euclid(self.size.0), y.rem_euclid(self.size.1));
+        // double level texels are stored in pairs, which are uploaded together
+        self.dirty.add(Rect {
+            x: x & !1,
+            y,
+            w: 2,
+            h: 1,
+        });
+        (y * self.size.0 + x) as usize
+    }
+
+    /// Set the raw altitude of a texel. For the double level texels,
+    /// the even ones store the lower layer, and the odd ones the upper layer.
+    pub fn set_height(&mut self, x: i32, y: i32, height: Altitude) {
+        let i = self.edit_index(x, y);
+        self.height[i] = height;
+    }
+
+    /// Set the raw meta data of a texel, see `get` for the layout.
+    pub fn set_meta(&mut self, x: i32, y: i32, meta: u8) {
+        let i = self.edit_index(x, y);
+        self.meta[i] = meta;
+    }
+
+    /// Stamp the brush centered at the texel `center`, which is also the world
+    /// position in the horizontal plane. The `strength` is the altitude change
+    /// at the full coverage of the brush. For the double level texels,
+    /// only the upper layer is modified, once per pair with the larger coverage
+    /// of the two, and it's kept above the gap. The stamped area is marked as dirty,
+    /// so that it gets uploaded to GPU with the other edits.
+    pub fn stamp(&mut self, brush: &Brush, center: (i32, i32), strength: f32, blend: Blend) {
+        let origin = (center.0 - brush.size.0 / 2, center.1 - brush.size.1 / 2);
+        let coverage_at = |x: i32, y: i32| {
+            let (bx, by) = (x - origin.0, y - origin.1);
+            if bx < 0 || by < 0 || bx >= brush.size.0 || by >= brush.size.1 {
+                0.0
+            } else {
+                brush.values[(by * brush.size.0 + bx) as usize] as f32 / 255.0
+            }
+        };
+        for y in origin.1..origin.1 + brush.size.1 {
+            // start at the pair boundary, so that the double level pairs are whole
+            let mut x = origin.0 & !1;
+            while x < origin.0 + brush.size.0 {
+                let raw =
+                    (y.rem_euclid(self.size.1) * self.size.0 + x.rem_euclid(self.size.0)) as usize;
+                // the upper layer is stored at the odd texel of a pair
+                let (target_x, coverage, min_altitude) = if self.meta[raw] & DOUBLE_LEVEL != 0 {
+                    let coverage = coverage_at(x & !1, y).max(coverage_at(x | 1, y));
+                    (x | 1, coverage, self.ceiling((x, y)).unwrap_or(0))
+                } else {
+                    (x, coverage_at(x, y), 0)
+                };
+                x = target_x + 1;
+                if coverage > 0.0 {
+                    let index = self.edit_index(target_x, y);
+                    let old = self.height[index] as f32;
+                    let new = match blend {
+                        Blend::Add => old + strength * coverage,
+                        Blend::Max => old.max(strength * coverage),
+                        Blend::Set => old + (strength - old) * coverage,
+                    };
+                    self.height[index] =
+                        new.round()
+                            .max(min_altitude as f32)
+                            .min(Altitude::max_value() as f32) as Altitude;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Levels authored as standard images.
+//!
+//! The height map is a grayscale image, where the gray value is the altitude.
+//! The terrain types come from a second image of the same size, with the gray
+//! range split evenly between the types. Color images are read by their red
+//! channel, so the maps can be painted in any tool.
+//!
+//! The existing levels are exported in the same layout, with the extra
+//! channels of the terrain image carrying the double level data,
+//! which is read back on import.
+
+use super::{
+    DirtyRegions, Level, Power, TerrainBits, TerrainConfig, TerrainType, WorldPhysics, DELTA_MASK,
+    DOUBLE_LEVEL,
+};
+
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+/// Names of the images written by `export_images`.
+pub const HEIGHT_IMAGE: &str = "height.png";
+pub const META_IMAGE: &str = "meta.png";
+pub const FLOOD_IMAGE: &str = "flood.png";
+
+/// Number of the terrain types of an imported level.
+pub const IMAGE_TERRAINS: u8 = 8;
+
+/// Terrain configuration of an imported level. The types get 16 palette
+/// entries each, from 96 to 223, and the grays above are left to the palette.
+pub fn image_terrains() -> Box<[TerrainConfig]> {
+    (0..IMAGE_TERRAINS)
+        .map(|t| TerrainConfig {
+            shadow_offset: 0,
+            height_shift: 0,
+            colors: 96 + t * 16..96 + t * 16 + 15,
+        })
+        .collect()
+}
+
+/// Texels of a PNG image as the red, green, and blue values.
+/// The gray images only have the red channel, and the alpha is ignored.
+fn load_rgb(path: &Path) -> io::Result<((i32, i32), Vec<[u8; 3]>)> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info().map_err(invalid)?;
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data).map_err(invalid)?;
+    let stride = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => unreachable!("Palette is expanded"),
+    };
+    let texels = data
+        .chunks(stride)
+        .map(|c| {
+            if stride < 3 {
+                [c[0], 0, 0]
+            } else {
+                [c[0], c[1], c[2]]
+            }
+        })
+        .collect();
+    Ok(((info.width as i32, info.height as i32), texels))
+}
+
+/// Terrain type of a gray value in the terrain image.
+pub fn terrain_of_gray(gray: u8) -> TerrainType {
+    (gray as u32 * IMAGE_TERRAINS as u32 / 0x100) as TerrainType
+}
+
+/// Gray value in the middle of the range of a terrain type,
+/// out of `count` types.
+pub fn gray_of_terrain(terrain: TerrainType, count: u8) -> u8 {
+    ((terrain as u32 * 2 + 1) * 0x80 / count as u32) as u8
+}
+
+fn write_png(path: &Path, size: (i32, i32), color: png::ColorType, data: &[u8]) -> io::Result<()> {
+    let to_io = |e| io::Error::new(io::ErrorKind::Other, e);
+    let mut encoder = png::Encoder::new(File::create(path)?, size.0 as u32, size.1 as u32);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .map_err(to_io)?
+        .write_image_data(data)
+        .map_err(to_io)
+}
+
+/// Write the level as images into a directory:
+///   - `height.png`: raw altitudes in gray. The double level texels come in
+///     pairs, with the lower layer in the even texel and the upper in the odd one.
+///   - `meta.png`: terrain type in red, spread over the gray range
+///     of the `IMAGE_TERRAINS` types like `from_image` expects,
+///     the double level flag in green, and the delta bits in blue.
+///   - `flood.png`: water level of each flood section in gray, one row per section.
+pub fn export_images(level: &Level, dir: &Path) -> io::Result<()> {
+    if level.terrains.len() > IMAGE_TERRAINS as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Level has {} terrain types, but the images only have {}",
+                level.terrains.len(),
+                IMAGE_TERRAINS
+            ),
+        ));
+    }
+    fs::create_dir_all(dir)?;
+    write_png(
+        &dir.join(HEIGHT_IMAGE),
+        level.size,
+        png::ColorType::Grayscale,
+        &level.height,
+    )?;
+
+    let bits = TerrainBits::new(level.terrains.len() as u8);
+    let meta = level
+        .meta
+        .iter()
+        .flat_map(|&meta| {
+            let double = if meta & DOUBLE_LEVEL != 0 { 0xFF } else { 0 };
+            let delta = (meta & DELTA_MASK) * (0xFF / DELTA_MASK);
+            vec![
+                gray_of_terrain(bits.read(meta), IMAGE_TERRAINS),
+                double,
+                delta,
+            ]
+        })
+        .collect::<Vec<_>>();
+    write_png(
+        &dir.join(META_IMAGE),
+        level.size,
+        png::ColorType::RGB,
+        &meta,
+    )?;
+
+    write_png(
+        &dir.join(FLOOD_IMAGE),
+        (1, level.flood_map.len() as i32),
+        png::ColorType::Grayscale,
+        &level.flood_map,
+    )
+}
+
+/// Construct a level from a grayscale height map, an image of the terrain
+/// types, and a palette file. Both images need to have the same size,
+/// which has to be a power of two on each side, like the original levels.
+/// The green and blue channels of the terrain image, if any, are read
+/// as the double level flag and the delta bits, as written by `export_images`.
+pub fn from_image(height_png: &Path, meta_png: &Path, palette: &Path) -> io::Result<Level> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let (size, height) = load_rgb(height_png)?;
+    let (meta_size, meta) = load_rgb(meta_png)?;
+    if size != meta_size {
+        return Err(invalid(format!(
+            "Height map is {:?}, but the terrain image is {:?}",
+            size, meta_size
+        )));
+    }
+    let (width, rows) = size;
+    if Power::from_value(width).is_none() {
+        return Err(invalid(format!("Width {} is not a power of two", width)));
+    }
+    // the whole level is a single flood section
+    let flood_section_power = Power::from_value(rows)
+        .ok_or_else(|| invalid(format!("Height {} is not a power of two", rows)))?;
+
+    let terrains = image_terrains();
+    let bits = TerrainBits::new(IMAGE_TERRAINS);
+    let palette = super::read_palette(File::open(palette)?, Some(&terrains));
+
+    Ok(Level {
+        size,
+        flood_map: vec![0],
+        flood_section_power,
+        height: height.into_iter().map(|[gray, _, _]| gray).collect(),
+        meta: meta
+            .into_iter()
+            .map(|[gray, double, delta]| {
+                let flag = if double >= 0x80 { DOUBLE_LEVEL } else { 0 };
+                // rounded to the nearest of the values written by `export_images`
+                let bits_delta = ((delta as u32 * DELTA_MASK as u32 + 0x7F) / 0xFF) as u8;
+                bits.write(terrain_of_gray(gray)) | flag | bits_delta
+            })
+            .collect(),
+        palette,
+        terrains,
+        physics: WorldPhysics::default(),
+        dirty: DirtyRegions::default(),
+    })
+}
@@ -0,0 +1,127 @@
+//! CPU reference of the terrain ray march, following `terrain/ray.glsl`.
+//! It's not used for rendering, but allows validating the algorithm
+//! against the `Level` queries without a GPU.
+
+use super::{get_height, Level, TerrainType, Texel, HEIGHT_SCALE};
+
+/// Same as `Surface` in `surface.inc.glsl`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Surface {
+    pub low_alt: f32,
+    pub high_alt: f32,
+    pub delta: f32,
+    pub low_type: TerrainType,
+    pub high_type: TerrainType,
+}
+
+pub fn get_surface(level: &Level, pos: cgmath::Vector2<f32>) -> Surface {
+    match level.get((pos.x.floor() as i32, pos.y.floor() as i32)) {
+        Texel::Single(point) => Surface {
+            low_alt: get_height(point.0),
+            high_alt: get_height(point.0),
+            delta: 0.0,
+            low_type: point.1,
+            high_type: point.1,
+        },
+        Texel::Dual { low, high, delta } => Surface {
+            low_alt: get_height(low.0),
+            high_alt: get_height(high.0),
+            delta: get_height(delta),
+            low_type: low.1,
+            high_type: high.1,
+        },
+    }
+}
+
+fn cast_ray_to_plane(
+    level: f32,
+    base: cgmath::Vector3<f32>,
+    dir: cgmath::Vector3<f32>,
+) -> cgmath::Vector3<f32> {
+    let t = (level - base.z) / dir.z;
+    base + dir * t
+}
+
+fn cast_ray_impl(
+    level: &Level,
+    a: &mut cgmath::Vector3<f32>,
+    b: &mut cgmath::Vector3<f32>,
+    mut high: bool,
+    num_forward: usize,
+    num_binary: usize,
+) -> Surface {
+    let step = (*b - *a) / (num_forward + 1) as f32;
+
+    for _ in 0..num_forward {
+        let c = *a + step;
+        let suf = get_surface(level, c.truncate());
+
+        if c.z > suf.high_alt {
+            high = true; // re-appear on the surface
+            *a = c;
+        } else {
+            let height = if high { suf.high_alt } else { suf.low_alt };
+            if c.z <= height {
+                *b = c;
+                break;
+            } else {
+                *a = c;
+            }
+        }
+    }
+
+    let mut result = get_surface(level, b.truncate());
+
+    for _ in 0..num_binary {
+        let c = (*a + *b) * 0.5;
+        let suf = get_surface(level, c.truncate());
+
+        let height = if high { suf.high_alt } else { suf.low_alt };
+        if c.z <= height {
+            *b = c;
+            result = suf;
+        } else {
+            *a = c;
+        }
+    }
+
+    result
+}
+
+/// Same as `CastPoint` in `terrain/ray.glsl`.
+#[derive(Clone, Copy, Debug)]
+pub struct CastPoint {
+    pub pos: cgmath::Vector3<f32>,
+    pub terrain: TerrainType,
+}
+
+/// Cast a ray against the level, as `cast_ray_to_map` does.
+/// The direction is expected to point downwards.
+pub fn cast_ray(level: &Level, base: cgmath::Vector3<f32>, dir: cgmath::Vector3<f32>) -> CastPoint {
+    let max_alt = HEIGHT_SCALE as f32;
+    let mut a = if base.z <= max_alt {
+        base
+    } else {
+        cast_ray_to_plane(max_alt, base, dir)
+    };
+    let c = cast_ray_to_plane(0.0, base, dir);
+    let mut b = c;
+
+    let mut suf = cast_ray_impl(level, &mut a, &mut b, true, 8, 4);
+    let mut terrain = suf.high_type;
+
+    if suf.delta != 0.0 && b.z < suf.low_alt + suf.delta {
+        // continue the cast underground, but reserve
+        // the right to re-appear above the surface.
+        a = b;
+        b = c;
+        suf = cast_ray_impl(level, &mut a, &mut b, false, 6, 3);
+        terrain = if b.z >= suf.low_alt + suf.delta {
+            suf.high_type
+        } else {
+            suf.low_type
+        };
+    }
+
+    CastPoint { pos: b, terrain }
+}
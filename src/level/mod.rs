@@ -1,13 +1,34 @@
+use crate::cache::Cache;
+
 use byteorder::{LittleEndian as E, ReadBytesExt, WriteBytesExt};
 
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 mod config;
-
-pub use self::config::{LevelConfig, TerrainConfig};
+mod diff;
+mod edit;
+mod image;
+mod march;
+mod search;
+mod stream;
+#[cfg(feature = "hot-reload")]
+mod watch;
+
+pub use self::config::{InvalidPower, LevelConfig, Power, TerrainConfig, WorldPhysics};
+pub use self::diff::{DiffStats, LevelDiff, SizeMismatch};
+pub use self::edit::{Blend, Brush, DirtyRegions};
+pub use self::image::{
+    export_images, from_image, gray_of_terrain, image_terrains, terrain_of_gray, FLOOD_IMAGE,
+    HEIGHT_IMAGE, IMAGE_TERRAINS, META_IMAGE,
+};
+pub use self::march::{cast_ray, get_surface, CastPoint, Surface};
+pub use self::search::FlatRegion;
+pub use self::stream::{route_rows, LevelStream};
+#[cfg(feature = "hot-reload")]
+pub use self::watch::LevelWatcher;
 
 pub type TerrainType = u8;
 
@@ -22,11 +43,40 @@ pub const HEIGHT_SCALE: u32 = 128;
 pub struct Level {
     pub size: (i32, i32),
     pub flood_map: Vec<u8>,
-    pub flood_section_power: usize,
+    /// Size of a flood section, in rows.
+    pub flood_section_power: Power,
     pub height: Vec<u8>,
     pub meta: Vec<u8>,
     pub palette: [[u8; 4]; 0x100],
     pub terrains: Box<[TerrainConfig]>,
+    pub physics: WorldPhysics,
+    /// Areas modified by `set_height` and `set_meta`.
+    pub dirty: DirtyRegions,
+}
+
+/// Rectangular area of a level, in texels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+/// Height of the raw altitude, in world units.
+pub fn get_height(altitude: Altitude) -> f32 {
+    altitude as f32 * (HEIGHT_SCALE as f32) / 255.0
+}
+
+/// Height separating the lower and the upper layers of a double level texel,
+/// see `GET_MIDDLE_HIGHT` macro.
+pub fn get_middle(low: Altitude, high: Altitude) -> f32 {
+    let extra_room = if high.saturating_sub(low) > 130 {
+        110
+    } else {
+        48
+    };
+    get_height(low.saturating_add(extra_room))
 }
 
 pub struct Point(pub Altitude, pub TerrainType);
@@ -60,8 +110,14 @@ impl TerrainBits {
     }
 }
 
+/// Terrain column at a texel, decoded from the height and meta data.
 pub enum Texel {
     Single(Point),
+    /// Upper shelf over a lower floor, e.g. a bridge or a cave ceiling.
+    /// The pair of texels at the even and odd X share the column:
+    /// the even one stores the floor, and the odd one the shelf.
+    /// The gap between the floor and the bottom of the shelf is `delta`,
+    /// combined from the delta bits of both.
     Dual {
         low: Point,
         high: Point,
@@ -80,22 +136,47 @@ impl Texel {
 
 impl Level {
     pub fn new_test() -> Self {
+        Self::new_flat((2, 1), 0, 0)
+    }
+
+    /// Flat level of the given size, with all the texels at the `altitude`
+    /// and of the `terrain` type, under a single flood section.
+    /// Both sides have to be powers of two.
+    pub fn new_flat(size: (i32, i32), altitude: Altitude, terrain: TerrainType) -> Self {
         let tc = TerrainConfig {
             shadow_offset: 0,
             height_shift: 0,
             colors: 0..1,
         };
+        let terrains: Box<[TerrainConfig]> = (0..8).map(|_| tc.clone()).collect();
+        let count = (size.0 * size.1) as usize;
         Level {
-            size: (2, 1),
+            size,
             flood_map: vec![0],
-            flood_section_power: 0,
-            height: vec![0, 0],
-            meta: vec![0, 0],
+            flood_section_power: Power::from_value(size.1)
+                .expect("Level height has to be a power of two"),
+            height: vec![altitude; count],
+            meta: vec![TerrainBits::new(terrains.len() as u8).write(terrain); count],
             palette: [[0xFF; 4]; 0x100],
-            terrains: (0..8).map(|_| tc.clone()).collect(),
+            terrains,
+            physics: WorldPhysics::default(),
+            dirty: DirtyRegions::default(),
         }
     }
 
+    /// Returns the water level of the flood section containing the given row.
+    pub fn flood_level(&self, y: i32) -> Altitude {
+        let section = self.flood_section_power.index_of(y.rem_euclid(self.size.1)) as usize;
+        self.flood_map[section % self.flood_map.len()]
+    }
+
+    /// Returns the shift by whole level sizes that brings the position
+    /// into the original copy of the level. The world looks the same after it.
+    pub fn wrap_offset(&self, pos: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        let (w, h) = (self.size.0 as f32, self.size.1 as f32);
+        cgmath::vec3(-(pos.x / w).floor() * w, -(pos.y / h).floor() * h, 0.0)
+    }
+
     pub fn get(&self, mut coord: (i32, i32)) -> Texel {
         let bits = TerrainBits::new(self.terrains.len() as u8);
         while coord.0 < 0 {
@@ -121,6 +202,74 @@ impl Level {
         }
     }
 
+    /// Height and terrain type of the surface that a point at `pos` would collide with,
+    /// or `None` if it's in the gap under the upper layer.
+    pub fn collision_surface(&self, pos: cgmath::Vector3<f32>) -> Option<(f32, TerrainType)> {
+        match self.get((pos.x as i32, pos.y as i32)) {
+            Texel::Single(point) => Some((get_height(point.0), point.1)),
+            Texel::Dual { high, low, .. } => {
+                let middle = get_middle(low.0, high.0);
+                if pos.z > middle {
+                    let top = get_height(high.0);
+                    if pos.z - middle > top - pos.z {
+                        Some((top, high.1))
+                    } else {
+                        None
+                    }
+                } else {
+                    Some((get_height(low.0), low.1))
+                }
+            }
+        }
+    }
+
+    /// Normal of the collision surface at `pos`, estimated from the neighbor heights.
+    pub fn surface_normal(&self, pos: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        use cgmath::InnerSpace as _;
+
+        let surface_height = |p: cgmath::Vector3<f32>| self.collision_surface(p).map(|(h, _)| h);
+        let center = surface_height(pos).unwrap_or(pos.z);
+        let height_at =
+            |dx: f32, dy: f32| surface_height(pos + cgmath::vec3(dx, dy, 0.0)).unwrap_or(center);
+        cgmath::vec3(
+            height_at(-1.0, 0.0) - height_at(1.0, 0.0),
+            height_at(0.0, -1.0) - height_at(0.0, 1.0),
+            2.0,
+        )
+        .normalize()
+    }
+
+    /// Altitude of the bottom of the upper layer at the given texel,
+    /// or `None` if there is only one layer.
+    pub fn ceiling(&self, coord: (i32, i32)) -> Option<Altitude> {
+        match self.get(coord) {
+            Texel::Single(_) => None,
+            Texel::Dual { low, delta, .. } => Some(low.0.saturating_add(delta)),
+        }
+    }
+
+    /// Vertical room between the floor and the ceiling at the given texel,
+    /// or `None` if there is no ceiling.
+    pub fn clearance(&self, coord: (i32, i32)) -> Option<Altitude> {
+        match self.get(coord) {
+            Texel::Single(_) => None,
+            Texel::Dual { delta, .. } => Some(delta),
+        }
+    }
+
+    /// Decode the delta bits of the meta map into a separate map of the ceiling
+    /// altitudes, matching the height map. Texels without the upper layer
+    /// get the maximum altitude, as if the ceiling was at the sky.
+    pub fn extract_ceiling(&self) -> Vec<Altitude> {
+        let mut ceiling = Vec::with_capacity(self.height.len());
+        for y in 0..self.size.1 {
+            for x in 0..self.size.0 {
+                ceiling.push(self.ceiling((x, y)).unwrap_or(Altitude::max_value()));
+            }
+        }
+        ceiling
+    }
+
     pub fn export(&self) -> Vec<u8> {
         let mut data = vec![0; self.size.0 as usize * self.size.1 as usize * 4];
         for y in 0..self.size.1 {
@@ -210,9 +359,20 @@ fn report_time(start: Instant) {
     );
 }
 
+/// Returns the offset of the flood map in the VPR file, and the number of sections.
+fn vpr_flood_layout(config: &LevelConfig) -> (u64, i32) {
+    let size = config.texel_size();
+    let flood_size = config.section.count_in(size.1);
+    let geo_pow = config.geo.as_power();
+    let net_size = config.geo.count_in(size.0) * config.geo.count_in(size.1);
+    let flood_offset =
+        (2 * 4 + (1 + 4 + 4) * 4 + 2 * net_size + 2 * geo_pow * 4 + 2 * flood_size * geo_pow * 4)
+            as u64;
+    (flood_offset, flood_size)
+}
+
 pub fn load_flood(config: &LevelConfig) -> Vec<u8> {
-    let size = (config.size.0.as_value(), config.size.1.as_value());
-    let flood_size = size.1 >> config.section.as_power();
+    let (flood_offset, flood_size) = vpr_flood_layout(config);
 
     let instant = Instant::now();
     let flood_map = {
@@ -222,13 +382,6 @@ pub fn load_flood(config: &LevelConfig) -> Vec<u8> {
         };
 
         info!("Loading flood map...");
-        let geo_pow = config.geo.as_power();
-        let net_size = size.0 * size.1 >> (2 * geo_pow);
-        let flood_offset = (2 * 4
-            + (1 + 4 + 4) * 4
-            + 2 * net_size
-            + 2 * geo_pow * 4
-            + 2 * flood_size * geo_pow * 4) as u64;
         let expected_file_size = flood_offset + (flood_size * 4) as u64;
         assert_eq!(vpr_file.metadata().unwrap().len(), expected_file_size,);
         let mut vpr = BufReader::new(vpr_file);
@@ -242,6 +395,47 @@ pub fn load_flood(config: &LevelConfig) -> Vec<u8> {
     flood_map
 }
 
+/// Write the flood map into the VPR file next to the level data.
+/// The rest of an existing file is preserved, and a new file gets it zeroed,
+/// since the other sections of VPR are not used here.
+pub fn save_flood(level: &Level, config: &LevelConfig) {
+    let (flood_offset, flood_size) = vpr_flood_layout(config);
+    assert_eq!(level.flood_map.len(), flood_size as usize);
+
+    let path = config.path_data.with_extension("vpr");
+    let vpr_file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .open(&path)
+        .expect("Unable to open VPR");
+    vpr_file
+        .set_len(flood_offset + (flood_size * 4) as u64)
+        .unwrap();
+    let mut vpr = BufWriter::new(vpr_file);
+    vpr.seek(SeekFrom::Start(flood_offset)).unwrap();
+    for &altitude in level.flood_map.iter() {
+        vpr.write_u32::<E>(altitude as u32).unwrap();
+    }
+}
+
+/// Write the level data and the flood map at the paths of the config,
+/// compressed or raw depending on it, so that `load` can read them back.
+pub fn save(level: &Level, config: &LevelConfig) {
+    assert_eq!(level.size, config.texel_size());
+    let data = LevelData {
+        height: level.height.clone(),
+        meta: level.meta.clone(),
+        size: level.size,
+    };
+    if config.is_compressed {
+        data.save_vmc(&config.path_data.with_extension("vmc"));
+    } else {
+        data.save_vmp(&config.path_data.with_extension("vmp"));
+    }
+    save_flood(level, config);
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct LevelData {
     pub height: Vec<u8>,
     pub meta: Vec<u8>,
@@ -278,22 +472,35 @@ impl LevelData {
         use splay::Splay;
         let mut vmc = BufWriter::new(File::create(path).unwrap());
 
+        let rows = || {
+            self.height
+                .chunks(self.size.0 as _)
+                .zip(self.meta.chunks(self.size.0 as _))
+        };
+        let splay = Splay::from_rows(rows());
+        let mut data = Vec::new();
+        let mut ranges = Vec::with_capacity(self.size.1 as usize);
+        for (h_row, m_row) in rows() {
+            let start = data.len();
+            splay.shrink(h_row, m_row, &mut data);
+            ranges.push(start..data.len());
+        }
+
         let base_offset = self.size.1 as u64 * (2 + 4) + Splay::tree_size();
-        for i in 0..self.size.1 {
-            vmc.write_i32::<E>(base_offset as i32 + i * self.size.0 * 2)
+        for range in ranges {
+            let size = range.end - range.start;
+            assert!(
+                size <= i16::max_value() as usize,
+                "Row is too large to compress"
+            );
+            vmc.write_i32::<E>(base_offset as i32 + range.start as i32)
                 .unwrap();
-            vmc.write_i16::<E>(self.size.0 as i16 * 2).unwrap();
+            vmc.write_i16::<E>(size as i16).unwrap();
         }
 
-        Splay::write_trivial(&mut vmc);
+        splay.write(&mut vmc);
         assert_eq!(vmc.seek(SeekFrom::Current(0)).unwrap(), base_offset);
-
-        self.height
-            .chunks(self.size.0 as _)
-            .zip(self.meta.chunks(self.size.0 as _))
-            .for_each(|(h_row, m_row)| {
-                Splay::compress_trivial(h_row, m_row, &mut vmc);
-            });
+        vmc.write_all(&data).unwrap();
     }
 
     pub fn import(data: &[u8], size: (i32, i32), terrain_shift: u8) -> Self {
@@ -395,27 +602,111 @@ pub fn load_vmp(path: &Path, size: (i32, i32)) -> LevelData {
         size,
     };
 
-    let mut vmp = BufReader::new(File::open(path).expect("Unable to open VMP"));
+    let vmp_file = File::open(path).expect("Unable to open VMP");
+    // the rows are stored without the section tables, each height row followed by meta
+    assert_eq!(
+        vmp_file.metadata().unwrap().len(),
+        total as u64 * 2,
+        "Unexpected VMP size of {:?}",
+        path,
+    );
+    let mut vmp = BufReader::new(vmp_file);
     level
         .height
         .chunks_mut(size.0 as _)
         .zip(level.meta.chunks_mut(size.0 as _))
         .for_each(|(h_row, m_row)| {
-            vmp.read(h_row).unwrap();
-            vmp.read(m_row).unwrap();
+            vmp.read_exact(h_row).unwrap();
+            vmp.read_exact(m_row).unwrap();
         });
 
     level
 }
 
-pub fn load(config: &LevelConfig) -> Level {
-    info!("Loading data map...");
-    let size = (config.size.0.as_value(), config.size.1.as_value());
-    let LevelData { height, meta, size } = if config.is_compressed {
-        load_vmc(&config.path_data.with_extension("vmc"), size)
-    } else {
-        load_vmp(&config.path_data.with_extension("vmp"), size)
+/// Carve out a region of the level into a standalone one.
+/// Coordinates wrap around the level edges, same as in `Level::get`.
+pub fn extract_region(level: &Level, rect: &Rect) -> Level {
+    // double-level texels are stored in pairs, so the region can't split them
+    assert_eq!(rect.x & 1, 0, "Region has to start at an even X");
+    assert_eq!(rect.w & 1, 0, "Region has to be of even width");
+    let section = level.flood_section_power;
+    assert_eq!(
+        section.offset_of(section.index_of(rect.y)),
+        rect.y,
+        "Region has to be aligned to flood sections"
+    );
+
+    let total = (rect.w * rect.h) as usize;
+    let mut height = Vec::with_capacity(total);
+    let mut meta = Vec::with_capacity(total);
+    for y in rect.y..rect.y + rect.h {
+        let base = y.rem_euclid(level.size.1) * level.size.0;
+        for x in rect.x..rect.x + rect.w {
+            let i = (base + x.rem_euclid(level.size.0)) as usize;
+            height.push(level.height[i]);
+            meta.push(level.meta[i]);
+        }
+    }
+
+    let first_section = section.index_of(rect.y);
+    let flood_map = (0..section.count_in(rect.h))
+        .map(|i| level.flood_map[((first_section + i) as usize) % level.flood_map.len()])
+        .collect();
+
+    Level {
+        size: (rect.w, rect.h),
+        flood_map,
+        flood_section_power: if rect.h < section.as_value() {
+            Power::from_value(rect.h).expect("Region height has to be a power of two")
+        } else {
+            section
+        },
+        height,
+        meta,
+        palette: level.palette,
+        terrains: level.terrains.clone(),
+        physics: level.physics,
+        dirty: DirtyRegions::default(),
+    }
+}
+
+/// Returns the path to the level data, and whether it's compressed.
+fn data_path(config: &LevelConfig) -> (PathBuf, bool) {
+    let (vmc_path, vmp_path) = (
+        config.path_data.with_extension("vmc"),
+        config.path_data.with_extension("vmp"),
+    );
+    // some of the levels are shipped in the other format than configured
+    let is_compressed = match (vmc_path.exists(), vmp_path.exists()) {
+        (true, false) if !config.is_compressed => {
+            warn!("Raw level data is not found, using {:?}", vmc_path);
+            true
+        }
+        (false, true) if config.is_compressed => {
+            warn!("Compressed level data is not found, using {:?}", vmp_path);
+            false
+        }
+        _ => config.is_compressed,
     };
+    if is_compressed {
+        (vmc_path, true)
+    } else {
+        (vmp_path, false)
+    }
+}
+
+pub fn load(config: &LevelConfig, cache: &Cache) -> Level {
+    info!("Loading data map...");
+    let size = config.texel_size();
+    let (path, is_compressed) = data_path(config);
+    let file = File::open(&path).expect("Unable to open the level data");
+    let LevelData { height, meta, size } = cache.load_or_parse("level", file, |_| {
+        if is_compressed {
+            load_vmc(&path, size)
+        } else {
+            load_vmp(&path, size)
+        }
+    });
 
     info!("Loading flood map...");
     let flood_map = load_flood(config);
@@ -424,10 +715,12 @@ pub fn load(config: &LevelConfig) -> Level {
     Level {
         size,
         flood_map,
-        flood_section_power: config.section.as_power() as usize,
+        flood_section_power: config.section,
         height,
         meta,
         palette: read_palette(palette, Some(&config.terrains)),
         terrains: config.terrains.clone(),
+        physics: config.physics,
+        dirty: DirtyRegions::default(),
     }
 }
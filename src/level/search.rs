@@ -0,0 +1,116 @@
+//! Map-wide analysis of the level surface, for spawn placement, AI, and tooling.
+
+use super::{get_height, Altitude, Level};
+
+/// Square area of the level with a nearly flat top surface above the water.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlatRegion {
+    /// Center of the region on the surface, in world space.
+    pub center: cgmath::Point3<f32>,
+    /// Side of the region, in texels.
+    pub size: i32,
+    /// Height difference across the region per texel of its side.
+    pub slope: f32,
+}
+
+impl Level {
+    /// Split the level into square blocks of `min_size` texels, and return
+    /// the ones above the water with the slope not exceeding `max_slope`,
+    /// ordered from the flattest.
+    pub fn find_flat_regions(&self, min_size: i32, max_slope: f32) -> Vec<FlatRegion> {
+        assert!(min_size > 0);
+        let mut regions = Vec::new();
+        for by in 0..self.size.1 / min_size {
+            for bx in 0..self.size.0 / min_size {
+                if let Some(region) = self.evaluate_block(bx * min_size, by * min_size, min_size) {
+                    if region.slope <= max_slope {
+                        regions.push(region);
+                    }
+                }
+            }
+        }
+        regions.sort_by(|a, b| a.slope.partial_cmp(&b.slope).unwrap());
+        regions
+    }
+
+    /// Find the closest block of `size` texels to the given point, not further
+    /// than `max_distance` from it, which is above the water and has the slope
+    /// not exceeding `max_slope`. Unlike `find_flat_regions`, the blocks are not
+    /// aligned to the grid, and they wrap around the level edges.
+    pub fn find_flat_region_near(
+        &self,
+        center: cgmath::Point2<f32>,
+        size: i32,
+        max_slope: f32,
+        max_distance: f32,
+    ) -> Option<FlatRegion> {
+        assert!(size > 0);
+        let step = (size / 2).max(1);
+        let reach = (max_distance / step as f32) as i32;
+        let mut offsets = Vec::new();
+        for dy in -reach..=reach {
+            for dx in -reach..=reach {
+                let distance = ((dx * dx + dy * dy) as f32).sqrt() * step as f32;
+                if distance <= max_distance {
+                    offsets.push((distance, dx * step, dy * step));
+                }
+            }
+        }
+        offsets.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let origin = (
+            center.x.floor() as i32 - size / 2,
+            center.y.floor() as i32 - size / 2,
+        );
+        offsets.into_iter().find_map(|(_, dx, dy)| {
+            self.evaluate_block(origin.0 + dx, origin.1 + dy, size)
+                .filter(|region| region.slope <= max_slope)
+        })
+    }
+
+    /// Evaluate the square block of texels, returning `None` if any of them
+    /// is below the water.
+    fn evaluate_block(&self, x0: i32, y0: i32, size: i32) -> Option<FlatRegion> {
+        let (mut low, mut high) = (Altitude::max_value(), 0);
+        for y in y0..y0 + size {
+            let flood = self.flood_level(y);
+            for x in x0..x0 + size {
+                let top = self.get((x, y)).top();
+                if top < flood {
+                    return None;
+                }
+                low = low.min(top);
+                high = high.max(top);
+            }
+        }
+        let half = 0.5 * size as f32;
+        Some(FlatRegion {
+            center: cgmath::Point3::new(x0 as f32 + half, y0 as f32 + half, get_height(high)),
+            size,
+            slope: (get_height(high) - get_height(low)) / size as f32,
+        })
+    }
+
+    /// Return the centers of the dry texels bordering the water,
+    /// placed at the water level, in world space.
+    pub fn water_shoreline(&self) -> Vec<cgmath::Point3<f32>> {
+        let is_wet = |x: i32, y: i32| self.get((x, y)).top() < self.flood_level(y);
+        let mut shore = Vec::new();
+        for y in 0..self.size.1 {
+            let flood = self.flood_level(y);
+            for x in 0..self.size.0 {
+                if is_wet(x, y) {
+                    continue;
+                }
+                if is_wet(x - 1, y) || is_wet(x + 1, y) || is_wet(x, y - 1) || is_wet(x, y + 1) {
+                    shore.push(cgmath::Point3::new(
+                        x as f32 + 0.5,
+                        y as f32 + 0.5,
+                        get_height(flood),
+                    ));
+                }
+            }
+        }
+        shore
+    }
+}
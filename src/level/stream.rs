@@ -0,0 +1,308 @@
+//! Progressive loading of the level data.
+//!
+//! The rows are decompressed on a background thread and sent over in chunks,
+//! so that the level can be shown while it's still loading, instead of
+//! stalling until the whole map is in memory. The rows along a planned
+//! route can be requested ahead of the others, see `LevelStream::prewarm`.
+//! A failure to read the data is sent over instead of a chunk, and reported
+//! by `LevelStream::is_done`.
+
+use super::{data_path, load_flood, read_palette, DirtyRegions, Level, LevelConfig};
+
+use byteorder::{LittleEndian as E, ReadBytesExt};
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+    sync::mpsc,
+    thread,
+};
+
+struct Chunk {
+    rows: Range<i32>,
+    height: Vec<u8>,
+    meta: Vec<u8>,
+}
+
+/// Level data being loaded on a background thread.
+pub struct LevelStream {
+    receiver: mpsc::Receiver<Result<Chunk, String>>,
+    requests: mpsc::Sender<Range<i32>>,
+    loaded_rows: i32,
+    total_rows: i32,
+    failure: Option<String>,
+}
+
+/// Order of the chunks to load: the requested ones first, then the rest from the top.
+struct Schedule {
+    chunk_rows: i32,
+    total_rows: i32,
+    sent: Vec<bool>,
+    next: usize,
+    priority: VecDeque<usize>,
+    requests: mpsc::Receiver<Range<i32>>,
+}
+
+impl Schedule {
+    fn new(total_rows: i32, chunk_rows: i32, requests: mpsc::Receiver<Range<i32>>) -> Self {
+        let num_chunks = (total_rows + chunk_rows - 1) / chunk_rows;
+        Schedule {
+            chunk_rows,
+            total_rows,
+            sent: vec![false; num_chunks as usize],
+            next: 0,
+            priority: VecDeque::new(),
+            requests,
+        }
+    }
+
+    fn next_rows(&mut self) -> Option<Range<i32>> {
+        for rows in self.requests.try_iter() {
+            let first = rows.start / self.chunk_rows;
+            let last = (rows.end - 1) / self.chunk_rows;
+            self.priority
+                .extend((first..=last).map(|index| index as usize));
+        }
+        let index = loop {
+            match self.priority.pop_front() {
+                Some(index) if self.sent.get(index) == Some(&false) => break index,
+                Some(_) => continue,
+                None => {
+                    while self.next < self.sent.len() && self.sent[self.next] {
+                        self.next += 1;
+                    }
+                    if self.next == self.sent.len() {
+                        return None;
+                    }
+                    break self.next;
+                }
+            }
+        };
+        self.sent[index] = true;
+        let start = index as i32 * self.chunk_rows;
+        Some(start..(start + self.chunk_rows).min(self.total_rows))
+    }
+}
+
+/// Ranges of the rows within the `radius` of the route going through the `waypoints`,
+/// wrapped around the level of the given number of rows.
+pub fn route_rows(
+    total_rows: i32,
+    waypoints: &[cgmath::Vector2<f32>],
+    radius: f32,
+) -> Vec<Range<i32>> {
+    let mut needed = vec![false; total_rows as usize];
+    let segments = waypoints
+        .iter()
+        .zip(waypoints.iter().skip(1))
+        .chain(waypoints.last().map(|w| (w, w)));
+    for (a, b) in segments {
+        let low = (a.y.min(b.y) - radius).floor() as i32;
+        let high = (a.y.max(b.y) + radius).ceil() as i32;
+        // a route longer than the level needs all of it
+        for y in low..=high.min(low + total_rows - 1) {
+            needed[y.rem_euclid(total_rows) as usize] = true;
+        }
+    }
+
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for (y, &is_needed) in needed.iter().enumerate() {
+        match (start, is_needed) {
+            (None, true) => start = Some(y as i32),
+            (Some(s), false) => {
+                ranges.push(s..y as i32);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        ranges.push(s..total_rows);
+    }
+    ranges
+}
+
+/// Sends the chunks until all of them are sent, or the receiver is gone.
+fn send_vmc(
+    path: &Path,
+    size: (i32, i32),
+    mut schedule: Schedule,
+    sender: &mpsc::Sender<Result<Chunk, String>>,
+) -> io::Result<()> {
+    use splay::Splay;
+
+    let mut vmc = BufReader::new(File::open(path)?);
+    let mut table = Vec::with_capacity(size.1 as usize);
+    for _ in 0..size.1 {
+        let offset = vmc.read_i32::<E>()?;
+        let length = vmc.read_i16::<E>()?;
+        table.push((offset, length));
+    }
+    let splay = Splay::new(&mut vmc);
+
+    let mut data = Vec::new();
+    while let Some(rows) = schedule.next_rows() {
+        let total = ((rows.end - rows.start) * size.0) as usize;
+        let (mut height, mut meta) = (vec![0u8; total], vec![0u8; total]);
+        for ((h_row, m_row), &(offset, length)) in height
+            .chunks_mut(size.0 as usize)
+            .zip(meta.chunks_mut(size.0 as usize))
+            .zip(&table[rows.start as usize..rows.end as usize])
+        {
+            data.resize(length as usize, 0);
+            vmc.seek(SeekFrom::Start(offset as u64))?;
+            vmc.read_exact(&mut data)?;
+            splay.expand(&data, h_row, m_row);
+        }
+        // the receiver is gone if the level is no longer needed
+        if sender.send(Ok(Chunk { rows, height, meta })).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn send_vmp(
+    path: &Path,
+    size: (i32, i32),
+    mut schedule: Schedule,
+    sender: &mpsc::Sender<Result<Chunk, String>>,
+) -> io::Result<()> {
+    let mut vmp = BufReader::new(File::open(path)?);
+    while let Some(rows) = schedule.next_rows() {
+        // each row is the height followed by the meta
+        let offset = rows.start as u64 * size.0 as u64 * 2;
+        vmp.seek(SeekFrom::Start(offset))?;
+        let total = ((rows.end - rows.start) * size.0) as usize;
+        let (mut height, mut meta) = (vec![0u8; total], vec![0u8; total]);
+        for (h_row, m_row) in height
+            .chunks_mut(size.0 as usize)
+            .zip(meta.chunks_mut(size.0 as usize))
+        {
+            vmp.read_exact(h_row)?;
+            vmp.read_exact(m_row)?;
+        }
+        if sender.send(Ok(Chunk { rows, height, meta })).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl LevelStream {
+    /// Start loading the level data, `chunk_rows` at a time.
+    /// Returns the level with everything but the height and meta data loaded,
+    /// which is filled by `poll` as the chunks arrive.
+    pub fn start(config: &LevelConfig, chunk_rows: i32) -> Result<(Level, Self), String> {
+        assert!(chunk_rows > 0);
+        let size = config.texel_size();
+        let palette = File::open(&config.path_palette).map_err(|e| {
+            format!(
+                "Unable to open the palette {}: {}",
+                config.path_palette.display(),
+                e
+            )
+        })?;
+        let (path, is_compressed) = data_path(config);
+        let (sender, receiver) = mpsc::channel();
+        let (request_sender, request_receiver) = mpsc::channel();
+        let schedule = Schedule::new(size.1, chunk_rows, request_receiver);
+        thread::Builder::new()
+            .name("level-stream".to_string())
+            .spawn(move || {
+                let result = if is_compressed {
+                    send_vmc(&path, size, schedule, &sender)
+                } else {
+                    send_vmp(&path, size, schedule, &sender)
+                };
+                if let Err(e) = result {
+                    let message = format!("Unable to read {}: {}", path.display(), e);
+                    let _ = sender.send(Err(message));
+                }
+            })
+            .map_err(|e| format!("Unable to start the level stream: {}", e))?;
+
+        let total = (size.0 * size.1) as usize;
+        let level = Level {
+            size,
+            flood_map: load_flood(config),
+            flood_section_power: config.section,
+            height: vec![0; total],
+            meta: vec![0; total],
+            palette: read_palette(palette, Some(&config.terrains)),
+            terrains: config.terrains.clone(),
+            physics: config.physics,
+            dirty: DirtyRegions::default(),
+        };
+        let stream = LevelStream {
+            receiver,
+            requests: request_sender,
+            loaded_rows: 0,
+            total_rows: size.1,
+            failure: None,
+        };
+        Ok((level, stream))
+    }
+
+    /// Copy the chunks received so far into the level,
+    /// returning the ranges of rows that got loaded.
+    pub fn poll(&mut self, level: &mut Level) -> Vec<Range<i32>> {
+        let mut loaded = Vec::new();
+        while self.failure.is_none() {
+            let chunk = match self.receiver.try_recv() {
+                Ok(Ok(chunk)) => chunk,
+                Ok(Err(message)) => {
+                    self.failure = Some(message);
+                    break;
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    if self.loaded_rows != self.total_rows {
+                        self.failure = Some(format!(
+                            "Level stream has stopped after {} of {} rows",
+                            self.loaded_rows, self.total_rows
+                        ));
+                    }
+                    break;
+                }
+            };
+            let range = (chunk.rows.start * level.size.0) as usize
+                ..(chunk.rows.end * level.size.0) as usize;
+            level.height[range.clone()].copy_from_slice(&chunk.height);
+            level.meta[range].copy_from_slice(&chunk.meta);
+            self.loaded_rows += chunk.rows.end - chunk.rows.start;
+            loaded.push(chunk.rows);
+        }
+        loaded
+    }
+
+    /// Load the rows along the route ahead of the others, e.g. for a scripted
+    /// camera flight or the path of a vehicle, so that they are ready when needed.
+    /// The waypoints are in texels, and the `radius` is the distance around them.
+    pub fn prewarm(&self, waypoints: &[cgmath::Vector2<f32>], radius: f32) {
+        for rows in route_rows(self.total_rows, waypoints, radius) {
+            // the loading thread is gone once everything is sent
+            if self.requests.send(rows).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Fraction of the rows loaded, from 0 to 1.
+    pub fn progress(&self) -> f32 {
+        self.loaded_rows as f32 / self.total_rows as f32
+    }
+
+    /// Returns true once all the rows are loaded, or the error if the loading
+    /// has failed, including the loading thread stopping before sending all of them.
+    pub fn is_done(&self) -> Result<bool, String> {
+        match self.failure {
+            Some(ref message) => Err(message.clone()),
+            None => Ok(self.loaded_rows == self.total_rows),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Watching the level files on disk, for reloading them as they are edited.
+//!
+//! The directories are watched instead of the files, since the editors
+//! tend to replace the files on save, which would stop a watch on the file itself.
+
+use super::{data_path, LevelConfig};
+
+use notify::{DebouncedEvent, RecursiveMode, Watcher as _};
+
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+/// Time for the changes to settle before they are reported.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Watcher of the level data, flood map, and palette files.
+pub struct LevelWatcher {
+    _watcher: notify::RecommendedWatcher,
+    receiver: mpsc::Receiver<DebouncedEvent>,
+    paths: Vec<PathBuf>,
+}
+
+impl LevelWatcher {
+    pub fn new(config: &LevelConfig) -> notify::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::watcher(sender, DEBOUNCE)?;
+        let paths = vec![
+            data_path(config).0,
+            config.path_data.with_extension("vpr"),
+            config.path_palette.clone(),
+        ];
+        let mut dirs = paths
+            .iter()
+            .filter_map(|path| path.parent())
+            .collect::<Vec<_>>();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            let dir = if dir == Path::new("") {
+                Path::new(".")
+            } else {
+                dir
+            };
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+        Ok(LevelWatcher {
+            _watcher: watcher,
+            receiver,
+            paths,
+        })
+    }
+
+    // the events come with the absolute paths, while the config ones may be relative
+    fn is_watched(&self, path: &Path) -> bool {
+        self.paths.iter().any(|p| p.file_name() == path.file_name())
+    }
+
+    /// Check if any of the files has changed since the last call.
+    pub fn changed(&self) -> bool {
+        let mut changed = false;
+        for event in self.receiver.try_iter() {
+            match event {
+                DebouncedEvent::Create(ref path)
+                | DebouncedEvent::Write(ref path)
+                | DebouncedEvent::Rename(_, ref path) => {
+                    if self.is_watched(path) {
+                        info!("Level file {:?} has changed", path);
+                        changed = true;
+                    }
+                }
+                DebouncedEvent::Error(ref e, _) => warn!("Level watch error: {}", e),
+                _ => {}
+            }
+        }
+        changed
+    }
+}
@@ -10,9 +10,18 @@ extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
+pub mod cache;
 pub mod config;
+pub mod diagnostics;
+#[cfg(feature = "ecs")]
+pub mod ecs;
+pub mod formats;
 mod freelist;
+mod hash;
 pub mod level;
 pub mod model;
+pub mod physics;
 pub mod render;
+pub mod replay;
 pub mod space;
+pub mod telemetry;
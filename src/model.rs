@@ -1,12 +1,26 @@
-use crate::render::{
-    debug::Position as DebugPos,
-    object::{Context as ObjectContext, Vertex as ObjectVertex},
-    ShapePolygon,
+use crate::{
+    cache::Cache,
+    level,
+    render::{
+        debug::Position as DebugPos,
+        label,
+        object::{Context as ObjectContext, Vertex as ObjectVertex},
+        ShapePolygon,
+    },
+    space::Transform,
 };
 use m3d;
 use wgpu::util::DeviceExt as _;
 
-use std::{fs::File, mem, ops::Range, slice, sync::Arc};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    mem,
+    ops::Range,
+    path::Path,
+    slice,
+    sync::Arc,
+};
 
 pub struct BoundingBox {
     pub min: [f32; 3],
@@ -14,9 +28,19 @@ pub struct BoundingBox {
     pub radius: f32,
 }
 
+/// GPU buffers holding the geometry of one or more meshes.
+pub struct MeshBuffers {
+    pub vertex_buf: wgpu::Buffer,
+    pub index_buf: wgpu::Buffer,
+}
+
 pub struct Mesh {
     pub num_vertices: usize,
-    pub vertex_buf: wgpu::Buffer,
+    pub num_indices: usize,
+    /// Buffers that may be shared with the other meshes of the same pack.
+    pub buffers: Arc<MeshBuffers>,
+    pub base_vertex: i32,
+    pub first_index: u32,
     pub offset: [f32; 3],
     pub bbox: BoundingBox,
     pub physics: m3d::Physics,
@@ -42,8 +66,69 @@ pub struct Shape {
 pub type RawVertex = [i8; 3];
 pub type ShapeVertex = [i8; 4];
 
+/// Collision sample of a shape, penetrating the terrain.
+#[derive(Clone, Debug)]
+pub struct ContactPoint {
+    /// Index of the sample in the shape, persistent between frames.
+    pub sample: usize,
+    /// Position of the sample in the level space.
+    pub pos: cgmath::Vector3<f32>,
+    /// Normal of the surface above the sample.
+    pub normal: cgmath::Vector3<f32>,
+    /// Distance from the sample up to the surface.
+    pub depth: f32,
+    pub terrain: level::TerrainType,
+}
+
+impl Shape {
+    /// Map the collision samples into the level space with the `transform`,
+    /// and collect the ones under the surface of the height map.
+    pub fn project_onto_terrain(
+        &self,
+        level: &level::Level,
+        transform: &Transform,
+    ) -> Vec<ContactPoint> {
+        project_samples(&self.samples, level, transform)
+    }
+}
+
+/// Map the collision samples into the level space with the `transform`,
+/// and collect the ones under the surface of the height map.
+/// The samples in the gaps under the upper layers don't touch anything.
+pub fn project_samples(
+    samples: &[RawVertex],
+    level: &level::Level,
+    transform: &Transform,
+) -> Vec<ContactPoint> {
+    use cgmath::{EuclideanSpace as _, Transform as _};
+
+    samples
+        .iter()
+        .enumerate()
+        .filter_map(|(sample, s)| {
+            let sp = cgmath::Point3::new(s[0] as f32, s[1] as f32, s[2] as f32);
+            let pos = transform.transform_point(sp).to_vec();
+            let (height, terrain) = level.collision_surface(pos)?;
+            let depth = height - pos.z;
+            if depth <= 0.0 {
+                return None;
+            }
+            Some(ContactPoint {
+                sample,
+                pos,
+                normal: level.surface_normal(pos),
+                depth,
+                terrain,
+            })
+        })
+        .collect()
+}
+
+/// Generates the collision samples of polygons,
+/// reusing the storage between them.
 struct Tessellator {
     samples: Vec<RawVertex>,
+    ring: Vec<RawVertex>,
     depth: u8,
 }
 
@@ -51,9 +136,19 @@ impl Tessellator {
     fn new(depth: u8) -> Self {
         Tessellator {
             samples: Vec::new(),
+            ring: Vec::new(),
             depth,
         }
     }
+
+    fn num_samples(&self, num_corners: usize) -> usize {
+        if self.depth != 0 {
+            2 * num_corners + 1
+        } else {
+            num_corners
+        }
+    }
+
     fn tessellate(&mut self, corners: &[RawVertex], _middle: RawVertex) -> &[RawVertex] {
         self.samples.clear();
         //self.samples.push(middle);
@@ -72,16 +167,15 @@ impl Tessellator {
                 (mid_sum[1] * div) as i8,
                 (mid_sum[2] * div) as i8,
             ];
-            let ring1 = corners
-                .iter()
-                .map(|c| {
-                    [
-                        (corner_ratio * c[0] as f32) as i8 + mid_rationed[0],
-                        (corner_ratio * c[1] as f32) as i8 + mid_rationed[1],
-                        (corner_ratio * c[2] as f32) as i8 + mid_rationed[2],
-                    ]
-                })
-                .collect::<Vec<_>>();
+            self.ring.clear();
+            self.ring.extend(corners.iter().map(|c| {
+                [
+                    (corner_ratio * c[0] as f32) as i8 + mid_rationed[0],
+                    (corner_ratio * c[1] as f32) as i8 + mid_rationed[1],
+                    (corner_ratio * c[2] as f32) as i8 + mid_rationed[2],
+                ]
+            }));
+            let ring1 = &self.ring;
             self.samples.extend((0..corners.len()).map(|i| {
                 let c0 = &ring1[i];
                 let c1 = &ring1[(i + 1) % corners.len()];
@@ -91,7 +185,7 @@ impl Tessellator {
                     c0[2] / 2 + c1[2] / 2,
                 ]
             }));
-            self.samples.extend(ring1);
+            self.samples.extend_from_slice(ring1);
             self.samples.push([
                 (mid_sum[0] / corners.len() as f32) as i8,
                 (mid_sum[1] / corners.len() as f32) as i8,
@@ -120,43 +214,79 @@ fn vec_i2f(v: [i32; 3]) -> [f32; 3] {
     [v[0] as f32, v[1] as f32, v[2] as f32]
 }
 
-pub fn load_c3d(
-    raw: m3d::Mesh<m3d::Geometry<m3d::DrawTriangle>>,
-    device: &wgpu::Device,
-) -> Arc<Mesh> {
-    let num_vertices = raw.geometry.polygons.len() * 3;
-    debug!("\tGot {} GPU vertices...", num_vertices);
-    let vertex_size = mem::size_of::<ObjectVertex>();
-    let vertex_buf = device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("C3D"),
-        size: (num_vertices * vertex_size) as wgpu::BufferAddress,
-        usage: wgpu::BufferUsage::VERTEX,
-        mapped_at_creation: true,
-    });
-    {
-        let mut mapping = vertex_buf.slice(..).get_mapped_range_mut();
-        for (chunk, tri) in mapping
-            .chunks_mut(3 * vertex_size)
-            .zip(&raw.geometry.polygons)
-        {
-            let out_vertices =
-                unsafe { slice::from_raw_parts_mut(chunk.as_mut_ptr() as *mut ObjectVertex, 3) };
-            for (vo, v) in out_vertices.iter_mut().zip(&tri.vertices) {
-                let p = raw.geometry.positions[v.pos as usize];
-                let n = raw.geometry.normals[v.normal as usize];
-                *vo = ObjectVertex {
-                    pos: [p[0], p[1], p[2], 1],
-                    color: tri.material[0],
-                    normal: [n[0], n[1], n[2], 0],
-                };
-            }
+/// Expand the triangles into GPU vertices and indices, merging the identical
+/// vertices if `dedup` is set. The unique vertices are ordered by their contents,
+/// so the result only depends on the geometry.
+pub fn build_vertices(
+    geometry: &m3d::Geometry<m3d::DrawTriangle>,
+    dedup: bool,
+) -> (Vec<ObjectVertex>, Vec<u32>) {
+    let mut vertices = Vec::with_capacity(geometry.polygons.len() * 3);
+    for tri in &geometry.polygons {
+        for v in &tri.vertices {
+            let p = geometry.positions[v.pos as usize];
+            let n = geometry.normals[v.normal as usize];
+            vertices.push(ObjectVertex {
+                pos: [p[0], p[1], p[2], 1],
+                color: tri.material[0],
+                normal: [n[0], n[1], n[2], 0],
+            });
         }
     }
-    vertex_buf.unmap();
+    if !dedup {
+        let indices = (0..vertices.len() as u32).collect();
+        return (vertices, indices);
+    }
+
+    let key = |v: &ObjectVertex| (v.pos, v.normal, v.color);
+    let mut order = (0..vertices.len()).collect::<Vec<_>>();
+    // the key covers all of the vertex data, so equal keys are identical vertices
+    order.sort_by_key(|&i| key(&vertices[i]));
+    let mut unique = Vec::<ObjectVertex>::with_capacity(vertices.len());
+    let mut indices = vec![0; vertices.len()];
+    for i in order {
+        let v = vertices[i];
+        if unique.last().map_or(true, |u| key(u) != key(&v)) {
+            unique.push(v);
+        }
+        indices[i] = unique.len() as u32 - 1;
+    }
+    (unique, indices)
+}
+
+fn create_mesh_buffers(
+    vertices: &[ObjectVertex],
+    indices: &[u32],
+    device: &wgpu::Device,
+) -> Arc<MeshBuffers> {
+    Arc::new(MeshBuffers {
+        vertex_buf: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&label::resource("model", "vertices")),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsage::VERTEX,
+        }),
+        index_buf: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&label::resource("model", "indices")),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsage::INDEX,
+        }),
+    })
+}
 
+fn pack_mesh(
+    raw: m3d::Mesh<m3d::Geometry<m3d::DrawTriangle>>,
+    buffers: &Arc<MeshBuffers>,
+    num_vertices: usize,
+    num_indices: usize,
+    base_vertex: i32,
+    first_index: u32,
+) -> Arc<Mesh> {
     Arc::new(Mesh {
         num_vertices,
-        vertex_buf,
+        num_indices,
+        buffers: Arc::clone(buffers),
+        base_vertex,
+        first_index,
         offset: vec_i2f(raw.parent_off),
         bbox: BoundingBox {
             min: vec_i2f(raw.bounds.coord_min),
@@ -167,6 +297,87 @@ pub fn load_c3d(
     })
 }
 
+pub fn load_c3d(
+    raw: m3d::Mesh<m3d::Geometry<m3d::DrawTriangle>>,
+    device: &wgpu::Device,
+    dedup: bool,
+) -> Arc<Mesh> {
+    let (vertices, indices) = build_vertices(&raw.geometry, dedup);
+    debug!(
+        "\tGot {} GPU vertices and {} indices...",
+        vertices.len(),
+        indices.len()
+    );
+    let buffers = create_mesh_buffers(&vertices, &indices, device);
+    pack_mesh(raw, &buffers, vertices.len(), indices.len(), 0, 0)
+}
+
+/// Upload several meshes into the shared buffers, so that they can be drawn
+/// without switching the buffers, e.g. all the wheels of a model.
+/// The meshes with identical geometry share the same range of the buffers,
+/// and the `Batcher` merges their instances into a single draw.
+pub fn load_c3d_pack(
+    raws: Vec<m3d::Mesh<m3d::Geometry<m3d::DrawTriangle>>>,
+    device: &wgpu::Device,
+    dedup: bool,
+) -> Vec<Arc<Mesh>> {
+    if raws.is_empty() {
+        return Vec::new();
+    }
+    let mut vertices = Vec::<ObjectVertex>::new();
+    let mut indices = Vec::<u32>::new();
+    let mut placement = Vec::<(usize, usize, i32, u32)>::with_capacity(raws.len());
+    for raw in raws.iter() {
+        let (mesh_vertices, mesh_indices) = build_vertices(&raw.geometry, dedup);
+        let bytes = bytemuck::cast_slice::<_, u8>(&mesh_vertices);
+        let same =
+            |&(num_vertices, num_indices, base_vertex, first_index): &(usize, usize, i32, u32)| {
+                let v = base_vertex as usize..base_vertex as usize + num_vertices;
+                let i = first_index as usize..first_index as usize + num_indices;
+                bytemuck::cast_slice::<_, u8>(&vertices[v]) == bytes
+                    && indices[i] == mesh_indices[..]
+            };
+        let entry = match placement.iter().find(|entry| same(entry)) {
+            Some(&entry) => entry,
+            None => {
+                let entry = (
+                    mesh_vertices.len(),
+                    mesh_indices.len(),
+                    vertices.len() as i32,
+                    indices.len() as u32,
+                );
+                vertices.extend_from_slice(&mesh_vertices);
+                indices.extend_from_slice(&mesh_indices);
+                entry
+            }
+        };
+        placement.push(entry);
+    }
+    debug!(
+        "\tPacked {} meshes into {} GPU vertices and {} indices...",
+        raws.len(),
+        vertices.len(),
+        indices.len()
+    );
+
+    let buffers = create_mesh_buffers(&vertices, &indices, device);
+    raws.into_iter()
+        .zip(placement)
+        .map(
+            |(raw, (num_vertices, num_indices, base_vertex, first_index))| {
+                pack_mesh(
+                    raw,
+                    &buffers,
+                    num_vertices,
+                    num_indices,
+                    base_vertex,
+                    first_index,
+                )
+            },
+        )
+        .collect()
+}
+
 pub fn load_c3d_shape(
     raw: m3d::Mesh<m3d::Geometry<m3d::CollisionQuad>>,
     device: &wgpu::Device,
@@ -175,11 +386,18 @@ pub fn load_c3d_shape(
     object: &ObjectContext,
 ) -> Arc<Shape> {
     debug!("\tTessellating polygons...");
-    let mut polygons = Vec::new();
-    let mut polygon_data = Vec::with_capacity(raw.geometry.polygons.len());
-    let mut samples = Vec::new();
-    let mut sample_data = Vec::new();
+    let num_polygons = raw.geometry.polygons.len();
     let mut tess = Tessellator::new(shape_sampling);
+    // collision polygons are quads
+    let samples_per_polygon = tess.num_samples(4);
+    let mut polygons = Vec::with_capacity(num_polygons);
+    let mut polygon_data = Vec::with_capacity(num_polygons);
+    let mut samples = Vec::with_capacity(num_polygons * samples_per_polygon);
+    let mut sample_data = if with_sample_buf {
+        Vec::with_capacity(num_polygons * 2 * (samples_per_polygon + 1))
+    } else {
+        Vec::new()
+    };
 
     for quad in &raw.geometry.polygons {
         let corners = [
@@ -245,11 +463,11 @@ pub fn load_c3d_shape(
             normal,
             samples: samples.len()..samples.len() + cur_samples.len(),
         });
-        samples.extend(cur_samples);
+        samples.extend_from_slice(cur_samples);
     }
 
     let vertex_buf = device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("Shape"),
+        label: Some(&label::resource("shape", "vertices")),
         size: (raw.geometry.positions.len() * mem::size_of::<ShapeVertex>()) as wgpu::BufferAddress,
         usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::STORAGE,
         mapped_at_creation: true,
@@ -263,7 +481,7 @@ pub fn load_c3d_shape(
     };
     vertex_buf.unmap();
     let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: Some("Shape"),
+        label: Some(&label::resource("shape", "vertices")),
         layout: &object.shape_bind_group_layout,
         entries: &[wgpu::BindGroupEntry {
             binding: 0,
@@ -277,13 +495,13 @@ pub fn load_c3d_shape(
         vertex_buf,
         bind_group,
         polygon_buf: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("polygons"),
+            label: Some(&label::resource("shape", "polygons")),
             contents: bytemuck::cast_slice(&polygon_data),
             usage: wgpu::BufferUsage::VERTEX,
         }),
         sample_buf: if with_sample_buf {
             let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("samples"),
+                label: Some(&label::resource("shape", "samples")),
                 contents: bytemuck::cast_slice(&sample_data),
                 usage: wgpu::BufferUsage::VERTEX,
             });
@@ -297,34 +515,675 @@ pub fn load_c3d_shape(
 
 pub type VisualModel = m3d::Model<Arc<Mesh>, Arc<Shape>>;
 
+/// Stable reference to a part of a model, the same for all of its instances.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Part {
+    Body,
+    Wheel(usize),
+    Debrie(usize),
+    Slot(usize),
+}
+
+/// Part of a particular entity, e.g. "wheel 2 of the player's mechous",
+/// for attaching the physics, damage, or rendering overrides to it.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct PartHandle<E> {
+    pub entity: E,
+    pub part: Part,
+}
+
+/// All the parts of the model, including the ones without meshes.
+pub fn parts(model: &VisualModel) -> impl Iterator<Item = Part> {
+    let wheels = (0..model.wheels.len()).map(Part::Wheel);
+    let debris = (0..model.debris.len()).map(Part::Debrie);
+    let slots = (0..model.slots.len()).map(Part::Slot);
+    Some(Part::Body)
+        .into_iter()
+        .chain(wheels)
+        .chain(debris)
+        .chain(slots)
+}
+
+pub fn part_mesh(model: &VisualModel, part: Part) -> Option<&Arc<Mesh>> {
+    match part {
+        Part::Body => Some(&model.body),
+        Part::Wheel(index) => model.wheels.get(index)?.mesh.as_ref(),
+        Part::Debrie(index) => Some(&model.debris.get(index)?.mesh),
+        Part::Slot(index) => model.slots.get(index)?.mesh.as_ref(),
+    }
+}
+
+/// World transform of the part mesh, given the transform of the model.
+/// Returns `None` if the part doesn't have a mesh.
+pub fn part_transform(model: &VisualModel, part: Part, base: &Transform) -> Option<Transform> {
+    use cgmath::{One as _, Rotation3 as _, Transform as _};
+
+    let mesh = part_mesh(model, part)?;
+    let local = match part {
+        Part::Body => return Some(base.clone()),
+        Part::Wheel(_) | Part::Debrie(_) => Transform {
+            disp: mesh.offset.into(),
+            rot: cgmath::Quaternion::one(),
+            scale: 1.0,
+        },
+        Part::Slot(index) => {
+            let s = &model.slots[index];
+            let mut local = Transform {
+                disp: cgmath::vec3(s.pos[0] as f32, s.pos[1] as f32, s.pos[2] as f32),
+                rot: cgmath::Quaternion::from_angle_y(cgmath::Deg(s.angle as f32)),
+                scale: s.scale / base.scale,
+            };
+            local.disp -= local.transform_vector(cgmath::Vector3::from(mesh.offset));
+            local
+        }
+    };
+    Some(base.concat(&local))
+}
+
 pub fn load_m3d(
     file: File,
     device: &wgpu::Device,
     object: &ObjectContext,
     shape_sampling: u8,
+    dedup: bool,
 ) -> VisualModel {
     let raw = m3d::FullModel::load(file);
+    upload_m3d(raw, device, object, shape_sampling, dedup)
+}
+
+/// Write the model in the M3D format, the inverse of `m3d::FullModel::load`.
+/// The uploaded `VisualModel` keeps no CPU copy of the geometry,
+/// so the model is saved before the upload.
+pub fn save_m3d<W: Write>(raw: &m3d::FullModel, output: W) -> Result<(), m3d::InvalidModel> {
+    raw.save(output)
+}
+
+/// Write the polygons of a mesh as an OBJ object, moved by the `offset`.
+/// `base` counts the positions and normals written before, since the indices are global.
+fn write_obj_mesh<W: Write>(
+    dest: &mut W,
+    name: &str,
+    mesh: &m3d::Mesh<m3d::Geometry<m3d::DrawTriangle>>,
+    offset: [f32; 3],
+    base: &mut (usize, usize),
+) -> io::Result<()> {
+    let geometry = &mesh.geometry;
+    writeln!(dest, "o {}", name)?;
+    for p in geometry.positions.iter() {
+        writeln!(
+            dest,
+            "v {} {} {}",
+            p[0] as f32 + offset[0],
+            p[1] as f32 + offset[1],
+            p[2] as f32 + offset[2]
+        )?;
+    }
+    for n in geometry.normals.iter() {
+        writeln!(
+            dest,
+            "vn {} {} {}",
+            n[0] as f32 / m3d::NORMALIZER,
+            n[1] as f32 / m3d::NORMALIZER,
+            n[2] as f32 / m3d::NORMALIZER
+        )?;
+    }
+
+    let mut color_ids = geometry
+        .polygons
+        .iter()
+        .map(|p| p.material[0])
+        .collect::<Vec<_>>();
+    color_ids.sort();
+    color_ids.dedup();
+    for color_id in color_ids {
+        writeln!(dest, "usemtl color{}", color_id)?;
+        for p in geometry
+            .polygons
+            .iter()
+            .filter(|p| p.material[0] == color_id)
+        {
+            write!(dest, "f")?;
+            for v in p.vertices.iter() {
+                write!(
+                    dest,
+                    " {}//{}",
+                    base.0 + v.pos as usize + 1,
+                    base.1 + v.normal as usize + 1
+                )?;
+            }
+            writeln!(dest)?;
+        }
+    }
 
-    let model = VisualModel {
-        body: load_c3d(raw.body, device),
-        shape: load_c3d_shape(raw.shape, device, shape_sampling, true, object),
-        bound: raw.bound,
-        color: raw.color,
-        wheels: raw
+    base.0 += geometry.positions.len();
+    base.1 += geometry.normals.len();
+    Ok(())
+}
+
+/// Export the body, wheels, and debris of the model into a Wavefront OBJ file.
+/// Each mesh is a separate object placed as in the game, and the polygons
+/// are grouped by the color IDs into materials named `color<id>`.
+/// Like `save_m3d`, this works on the model before the upload.
+pub fn export_obj(raw: &m3d::FullModel, path: &Path) -> io::Result<()> {
+    let mut dest = BufWriter::new(File::create(path)?);
+    let mut base = (0, 0);
+    write_obj_mesh(&mut dest, "body", &raw.body, [0.0; 3], &mut base)?;
+    for (i, wheel) in raw.wheels.iter().enumerate() {
+        if let Some(ref mesh) = wheel.mesh {
+            let name = format!("wheel{}", i);
+            write_obj_mesh(&mut dest, &name, mesh, vec_i2f(mesh.parent_off), &mut base)?;
+        }
+    }
+    for (i, debrie) in raw.debris.iter().enumerate() {
+        let name = format!("debrie{}", i);
+        let offset = vec_i2f(debrie.mesh.parent_off);
+        write_obj_mesh(&mut dest, &name, &debrie.mesh, offset, &mut base)?;
+    }
+    dest.flush()
+}
+
+/// Estimate the mass properties of a closed mesh with the unit density:
+/// the volume, the center of mass, and the inertia tensor around it.
+/// The polygons are summed up as tetrahedra with the origin,
+/// so the orientation of the triangles has to be consistent.
+/// Fails if the mesh has no volume, since the bodies need an invertible inertia.
+pub fn estimate_physics(geometry: &m3d::Geometry<m3d::DrawTriangle>) -> io::Result<m3d::Physics> {
+    use cgmath::{SquareMatrix as _, Zero as _};
+
+    let mut volume = 0.0;
+    let mut moment = cgmath::Vector3::zero();
+    let mut covariance = cgmath::Matrix3::zero();
+    for polygon in geometry.polygons.iter() {
+        let [a, b, c] = polygon.vertices;
+        let v = |vertex: m3d::Vertex| {
+            let p = geometry.positions[vertex.pos as usize];
+            cgmath::vec3(p[0] as f32, p[1] as f32, p[2] as f32)
+        };
+        let (a, b, c) = (v(a), v(b), v(c));
+        let det = cgmath::Matrix3::from_cols(a, b, c).determinant();
+        let sum = a + b + c;
+        volume += det / 6.0;
+        moment += sum * (det / 24.0);
+        let outer = |x: cgmath::Vector3<f32>| cgmath::Matrix3::from_cols(x * x.x, x * x.y, x * x.z);
+        covariance += (outer(a) + outer(b) + outer(c) + outer(sum)) * (det / 120.0);
+    }
+    if volume <= 0.0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("mesh is not closed, or inside out, volume {}", volume),
+        ));
+    }
+
+    let rcm = moment / volume;
+    let centered =
+        covariance - cgmath::Matrix3::from_cols(rcm * rcm.x, rcm * rcm.y, rcm * rcm.z) * volume;
+    let inertia =
+        cgmath::Matrix3::from_value(centered.x.x + centered.y.y + centered.z.z) - centered;
+    if !inertia.is_invertible() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("inertia tensor {:?} is not invertible", inertia),
+        ));
+    }
+    Ok(m3d::Physics {
+        volume,
+        rcm: rcm.into(),
+        jacobi: inertia.into(),
+    })
+}
+
+fn quantize(value: f32) -> i8 {
+    value.round().max(-128.0).min(127.0) as i8
+}
+
+fn flat_normal(positions: &[[i8; 3]], corners: [u16; 3]) -> [i8; 3] {
+    use cgmath::InnerSpace as _;
+
+    let v = |i: u16| {
+        let p = positions[i as usize];
+        cgmath::vec3(p[0] as f32, p[1] as f32, p[2] as f32)
+    };
+    let (a, b, c) = (v(corners[0]), v(corners[1]), v(corners[2]));
+    let normal = (b - a).cross(c - a);
+    if normal.magnitude2() == 0.0 {
+        return [0; 3];
+    }
+    let n = normal.normalize() * m3d::NORMALIZER;
+    [quantize(n.x), quantize(n.y), quantize(n.z)]
+}
+
+/// Collision shape made of the draw triangles, each as a quad with the last corner repeated.
+pub fn collision_shape(mesh: &m3d::DrawMesh) -> m3d::CollisionMesh {
+    let geometry = &mesh.geometry;
+    let polygons = geometry
+        .polygons
+        .iter()
+        .map(|p| {
+            let [a, b, c] = p.vertices;
+            let corners = [a.pos, b.pos, c.pos];
+            let mut middle = [0; 3];
+            for (axis, m) in middle.iter_mut().enumerate() {
+                let sum = corners
+                    .iter()
+                    .map(|&i| geometry.positions[i as usize][axis] as f32)
+                    .sum::<f32>();
+                *m = quantize(sum / 3.0);
+            }
+            m3d::CollisionQuad {
+                vertices: [a.pos, b.pos, c.pos, c.pos],
+                middle,
+                flat_normal: p.flat_normal,
+            }
+        })
+        .collect();
+    m3d::Mesh {
+        geometry: m3d::Geometry {
+            positions: geometry.positions.clone(),
+            normals: geometry.normals.clone(),
+            polygons,
+        },
+        bounds: mesh.bounds.clone(),
+        parent_off: mesh.parent_off,
+        parent_rot: mesh.parent_rot,
+        max_radius: mesh.max_radius,
+        physics: mesh.physics.clone(),
+    }
+}
+
+/// Polygon of an OBJ file, as the global position and normal indices of the corners.
+struct ObjFace {
+    corners: Vec<(usize, Option<usize>)>,
+    color_id: u32,
+}
+
+fn parse_obj_index(text: &str, count: usize) -> Option<usize> {
+    let index = text.parse::<isize>().ok()?;
+    // negative indices are relative to the end of the list
+    let absolute = if index < 0 {
+        count as isize + index
+    } else {
+        index - 1
+    };
+    if absolute >= 0 && (absolute as usize) < count {
+        Some(absolute as usize)
+    } else {
+        None
+    }
+}
+
+/// Build the mesh out of the faces, moved by the center of its bounds
+/// unless it's the body, so that the offset becomes the parent offset.
+fn build_obj_mesh(
+    faces: &[ObjFace],
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    is_body: bool,
+) -> io::Result<m3d::DrawMesh> {
+    use std::collections::HashMap;
+
+    let mut min = [std::f32::MAX; 3];
+    let mut max = [std::f32::MIN; 3];
+    for face in faces.iter() {
+        for &(pi, _) in face.corners.iter() {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(positions[pi][axis]);
+                max[axis] = max[axis].max(positions[pi][axis]);
+            }
+        }
+    }
+    let mut offset = [0i32; 3];
+    if !is_body {
+        for axis in 0..3 {
+            offset[axis] = (0.5 * (min[axis] + max[axis])).round() as i32;
+        }
+    }
+
+    let mut geometry = m3d::Geometry::default();
+    let mut position_map = HashMap::new();
+    let mut normal_map = HashMap::new();
+    for face in faces.iter() {
+        let mut corners = Vec::with_capacity(face.corners.len());
+        for &(pi, ni) in face.corners.iter() {
+            let pos = *position_map.entry(pi).or_insert_with(|| {
+                let p = positions[pi];
+                geometry.positions.push([
+                    quantize(p[0] - offset[0] as f32),
+                    quantize(p[1] - offset[1] as f32),
+                    quantize(p[2] - offset[2] as f32),
+                ]);
+                geometry.positions.len() as u16 - 1
+            });
+            let normal = ni.map(|ni| {
+                *normal_map.entry(ni).or_insert_with(|| {
+                    let n = normals[ni];
+                    geometry.normals.push([
+                        quantize(n[0] * m3d::NORMALIZER),
+                        quantize(n[1] * m3d::NORMALIZER),
+                        quantize(n[2] * m3d::NORMALIZER),
+                    ]);
+                    geometry.normals.len() as u16 - 1
+                })
+            });
+            corners.push((pos, normal));
+        }
+        // the polygons are split into triangle fans
+        for i in 1..corners.len().saturating_sub(1) {
+            let triangle = [corners[0], corners[i], corners[i + 1]];
+            let flat = flat_normal(
+                &geometry.positions,
+                [triangle[0].0, triangle[1].0, triangle[2].0],
+            );
+            let mut vertices = [m3d::Vertex::DUMMY; 3];
+            for (vertex, &(pos, normal)) in vertices.iter_mut().zip(triangle.iter()) {
+                let normal = match normal {
+                    Some(normal) => normal,
+                    None => {
+                        geometry.normals.push(flat);
+                        geometry.normals.len() as u16 - 1
+                    }
+                };
+                *vertex = m3d::Vertex { pos, normal };
+            }
+            geometry.polygons.push(m3d::DrawTriangle {
+                vertices,
+                flat_normal: flat,
+                material: [face.color_id, 0],
+            });
+        }
+    }
+
+    // the vertices are referenced by 16-bit indices
+    let count = geometry.positions.len().max(geometry.normals.len());
+    if count > u16::max_value() as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} vertices don't fit into 16-bit indices", count),
+        ));
+    }
+
+    let mut bounds = m3d::Bounds {
+        coord_min: [0; 3],
+        coord_max: [0; 3],
+    };
+    let mut max_radius = 0.0f32;
+    for p in geometry.positions.iter() {
+        for axis in 0..3 {
+            bounds.coord_min[axis] = bounds.coord_min[axis].min(p[axis] as i32);
+            bounds.coord_max[axis] = bounds.coord_max[axis].max(p[axis] as i32);
+        }
+        let (x, y, z) = (p[0] as f32, p[1] as f32, p[2] as f32);
+        max_radius = max_radius.max((x * x + y * y + z * z).sqrt());
+    }
+
+    Ok(m3d::Mesh {
+        physics: estimate_physics(&geometry)?,
+        geometry,
+        bounds,
+        parent_off: offset,
+        parent_rot: [0; 3],
+        max_radius: max_radius.ceil() as u32,
+    })
+}
+
+/// Import a Wavefront OBJ file into a full model, the inverse of `export_obj`.
+///
+/// The objects are recognized by their names: `body`, `wheel<i>`, and `debrie<i>`,
+/// and the faces outside of any object belong to the body. The color IDs are taken
+/// from the materials named `color<id>`. The positions are scaled down uniformly
+/// if they exceed the byte range of the format, and rounded to its integer grid.
+/// The physics parameters are estimated from the geometry, which is also used
+/// for the collision shapes, so the meshes have to be closed.
+pub fn import_obj(path: &Path) -> io::Result<m3d::FullModel> {
+    use std::io::BufRead as _;
+
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut objects: Vec<(String, Vec<ObjFace>)> = vec![("body".to_string(), Vec::new())];
+    let mut current = 0;
+    let mut color_id = 0;
+
+    for (line_index, line) in io::BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        let parse_vector = |words: &mut std::str::SplitWhitespace| {
+            let mut v = [0.0f32; 3];
+            for value in v.iter_mut() {
+                *value = words
+                    .next()
+                    .and_then(|w| w.parse().ok())
+                    .ok_or_else(|| invalid(format!("bad vector at line {}", line_index + 1)))?;
+            }
+            Ok::<_, io::Error>(v)
+        };
+        match words.next() {
+            Some("v") => positions.push(parse_vector(&mut words)?),
+            Some("vn") => normals.push(parse_vector(&mut words)?),
+            Some("o") | Some("g") => {
+                let name = words.next().unwrap_or("").to_string();
+                current = match objects.iter().position(|&(ref n, _)| *n == name) {
+                    Some(index) => index,
+                    None => {
+                        objects.push((name, Vec::new()));
+                        objects.len() - 1
+                    }
+                };
+            }
+            Some("usemtl") => {
+                color_id = words
+                    .next()
+                    .and_then(|name| name.trim_start_matches("color").parse().ok())
+                    .unwrap_or(0);
+            }
+            Some("f") => {
+                let mut corners = Vec::new();
+                for word in words {
+                    let mut parts = word.split('/');
+                    let pi = parts
+                        .next()
+                        .and_then(|p| parse_obj_index(p, positions.len()))
+                        .ok_or_else(|| invalid(format!("bad face at line {}", line_index + 1)))?;
+                    let ni = parts.nth(1).and_then(|n| parse_obj_index(n, normals.len()));
+                    corners.push((pi, ni));
+                }
+                objects[current].1.push(ObjFace { corners, color_id });
+            }
+            _ => {}
+        }
+    }
+
+    let extent = positions
+        .iter()
+        .flat_map(|p| p.iter())
+        .fold(0.0f32, |extent, v| extent.max(v.abs()));
+    if extent > i8::max_value() as f32 {
+        let scale = i8::max_value() as f32 / extent;
+        info!("Scaling the model by {} to fit the format", scale);
+        for p in positions.iter_mut() {
+            for v in p.iter_mut() {
+                *v *= scale;
+            }
+        }
+    }
+
+    let mut body = None;
+    let mut wheels = Vec::new();
+    let mut debris = Vec::new();
+    for (name, faces) in objects {
+        if faces.is_empty() {
+            continue;
+        }
+        if name == "body" {
+            body = Some(build_obj_mesh(&faces, &positions, &normals, true)?);
+        } else if name.starts_with("wheel") {
+            let mesh = build_obj_mesh(&faces, &positions, &normals, false)?;
+            let b = &mesh.bounds;
+            wheels.push(m3d::Wheel {
+                // only the steering wheels have their own meshes
+                steer: 1,
+                pos: vec_i2f(mesh.parent_off),
+                width: (b.coord_max[0] - b.coord_min[0]) as u32,
+                radius: ((b.coord_max[2] - b.coord_min[2]) / 2) as u32,
+                bound_index: 0,
+                mesh: Some(mesh),
+            });
+        } else if name.starts_with("debrie") {
+            let mesh = build_obj_mesh(&faces, &positions, &normals, false)?;
+            let shape = collision_shape(&mesh);
+            debris.push(m3d::Debrie { mesh, shape });
+        } else {
+            warn!("Skipping unknown object '{}'", name);
+        }
+    }
+
+    let body = body.ok_or_else(|| invalid("no body is found".to_string()))?;
+    let mut dimensions = [0; 3];
+    for axis in 0..3 {
+        dimensions[axis] = body.bounds.coord_min[axis]
+            .abs()
+            .max(body.bounds.coord_max[axis].abs()) as u32;
+    }
+    Ok(m3d::FullModel {
+        shape: collision_shape(&body),
+        bound: m3d::UpperBound {
+            dimensions,
+            radius: body.max_radius,
+        },
+        color: m3d::BodyColor {
+            offset: 0,
+            shift: 0,
+        },
+        body,
+        wheels,
+        debris,
+        slots: [m3d::Slot::EMPTY, m3d::Slot::EMPTY, m3d::Slot::EMPTY],
+    })
+}
+
+pub fn upload_m3d(
+    raw: m3d::FullModel,
+    device: &wgpu::Device,
+    object: &ObjectContext,
+    shape_sampling: u8,
+    dedup: bool,
+) -> VisualModel {
+    upload_m3d_batch(vec![raw], device, object, shape_sampling, dedup)
+        .pop()
+        .unwrap()
+}
+
+/// Upload the models with the meshes of all of them packed into shared buffers,
+/// so that the `Batcher` draws the whole batch without switching the buffers,
+/// and merges the instances of the identical meshes across the models.
+pub fn upload_m3d_batch(
+    raws: Vec<m3d::FullModel>,
+    device: &wgpu::Device,
+    object: &ObjectContext,
+    shape_sampling: u8,
+    dedup: bool,
+) -> Vec<VisualModel> {
+    // the meshes go in the order of the body, the wheels, and the debris of each model
+    let mut meshes = Vec::new();
+    let mut rest = Vec::with_capacity(raws.len());
+    for raw in raws {
+        meshes.push(raw.body);
+        let wheels = raw
             .wheels
             .into_iter()
-            .map(|wheel| wheel.map(|mesh| load_c3d(mesh, device)))
-            .collect(),
-        debris: raw
+            .map(|wheel| wheel.map(|mesh| meshes.push(mesh)))
+            .collect::<Vec<_>>();
+        let debrie_shapes = raw
             .debris
             .into_iter()
-            .map(|debrie| m3d::Debrie {
-                mesh: load_c3d(debrie.mesh, device),
-                shape: load_c3d_shape(debrie.shape, device, 0, false, object),
+            .map(|debrie| {
+                meshes.push(debrie.mesh);
+                debrie.shape
             })
-            .collect(),
-        slots: m3d::Slot::map_all(raw.slots, |_, _| unreachable!()),
-    };
+            .collect::<Vec<_>>();
+        rest.push((
+            raw.shape,
+            raw.bound,
+            raw.color,
+            wheels,
+            debrie_shapes,
+            raw.slots,
+        ));
+    }
+    let mut meshes = load_c3d_pack(meshes, device, dedup).into_iter();
 
-    model
+    rest.into_iter()
+        .map(|(shape, bound, color, wheels, debrie_shapes, slots)| {
+            let body = meshes.next().unwrap();
+            let wheels = wheels
+                .into_iter()
+                .map(|wheel| wheel.map(|()| meshes.next().unwrap()))
+                .collect();
+            let debris = debrie_shapes
+                .into_iter()
+                .map(|shape| m3d::Debrie {
+                    mesh: meshes.next().unwrap(),
+                    shape: load_c3d_shape(shape, device, 0, false, object),
+                })
+                .collect();
+            VisualModel {
+                body,
+                shape: load_c3d_shape(shape, device, shape_sampling, true, object),
+                bound,
+                color,
+                wheels,
+                debris,
+                slots: m3d::Slot::map_all(slots, |_, _| unreachable!()),
+            }
+        })
+        .collect()
+}
+
+/// Models parsed on the worker threads, waiting for the GPU upload.
+/// The upload has to happen on the main thread, and it's done in batches,
+/// so that the caller can spread the work across frames.
+pub struct PendingModels<K> {
+    parsed: Vec<(K, m3d::FullModel)>,
+    total: usize,
+}
+
+impl<K: Send> PendingModels<K> {
+    pub fn parse(sources: Vec<(K, File)>, cache: &Cache) -> Self {
+        use rayon::prelude::*;
+
+        debug!("Parsing {} models in parallel", sources.len());
+        let mut parsed = sources
+            .into_par_iter()
+            .map(|(key, file)| (key, cache.load_or_parse("m3d", file, m3d::FullModel::load)))
+            .collect::<Vec<_>>();
+        // uploading happens from the back
+        parsed.reverse();
+        PendingModels {
+            total: parsed.len(),
+            parsed,
+        }
+    }
+
+    /// Returns the number of uploaded models and the total.
+    pub fn progress(&self) -> (usize, usize) {
+        (self.total - self.parsed.len(), self.total)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.parsed.is_empty()
+    }
+
+    pub fn upload_batch(
+        &mut self,
+        batch_size: usize,
+        device: &wgpu::Device,
+        object: &ObjectContext,
+        shape_sampling: u8,
+        dedup: bool,
+    ) -> Vec<(K, VisualModel)> {
+        let count = batch_size.min(self.parsed.len());
+        let start = self.parsed.len() - count;
+        let (keys, raws): (Vec<_>, Vec<_>) = self.parsed.drain(start..).rev().unzip();
+        let models = upload_m3d_batch(raws, device, object, shape_sampling, dedup);
+        keys.into_iter().zip(models).collect()
+    }
 }
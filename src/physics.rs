@@ -0,0 +1,278 @@
+//! Rigid body dynamics of the vehicles, driven by the mass properties of the model.
+//!
+//! It's a simpler alternative to the port of the original physics in the `road` binary,
+//! which depends on the game configuration. Here the body only needs the model:
+//! the inertia comes from `Mesh::physics`, the terrain contacts from the collision
+//! samples pushed along the terrain normals, and the traction from the wheels. The body has the unit mass,
+//! so all the forces are accelerations.
+
+use crate::{
+    level,
+    model::{self, ContactPoint},
+    space::Transform,
+};
+
+use cgmath::prelude::*;
+
+/// Tunable constants of the dynamics.
+#[derive(Clone, Debug)]
+pub struct Params {
+    /// Downward acceleration, scaled by the gravity of the level.
+    pub gravity: f32,
+    /// Torque of the engine at the full throttle, per unit of the wheel radius.
+    pub engine_torque: f32,
+    /// Lateral grip of a wheel, per unit of its width.
+    pub grip: f32,
+    /// Friction coefficient of the body sliding on the terrain.
+    pub friction: f32,
+    /// Fraction of the approaching speed kept after bouncing off the terrain.
+    pub restitution: f32,
+    /// Fraction of the penetration depth resolved per second.
+    pub correction: f32,
+    /// Fraction of the linear and angular velocity lost per second.
+    pub drag: [f32; 2],
+    /// Maximum angle of the steering wheels.
+    pub max_steer: cgmath::Rad<f32>,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            gravity: 100.0,
+            engine_torque: 1000.0,
+            grip: 2.0,
+            friction: 0.5,
+            restitution: 0.2,
+            correction: 5.0,
+            drag: [0.3, 0.8],
+            max_steer: cgmath::Deg(30.0).into(),
+        }
+    }
+}
+
+/// Input of the driver.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Control {
+    /// From -1 for the full reverse to 1 for the full throttle.
+    pub throttle: f32,
+    /// From -1 for the full left to 1 for the full right.
+    pub steer: f32,
+}
+
+struct Wheel {
+    /// Center of the wheel, in the model space.
+    pos: cgmath::Vector3<f32>,
+    radius: f32,
+    width: f32,
+    steer: bool,
+}
+
+/// Vehicle moving under the gravity, the terrain contacts, and the wheel traction.
+pub struct Body {
+    pub transform: Transform,
+    /// Velocity of the center of mass.
+    pub vel: cgmath::Vector3<f32>,
+    /// Angular velocity, in the world space.
+    pub wel: cgmath::Vector3<f32>,
+    pub control: Control,
+    pub params: Params,
+    /// Center of mass, in the model space.
+    rcm: cgmath::Vector3<f32>,
+    /// Inverse of the inertia tensor per unit mass, in the model space.
+    inertia_inv: cgmath::Matrix3<f32>,
+    /// Collision samples of the shape, in the model space.
+    samples: Vec<model::RawVertex>,
+    wheels: Vec<Wheel>,
+}
+
+impl Body {
+    /// Create a resting body of the model, placed with the `transform`.
+    /// Returns `None` if the model has no volume to derive the inertia from.
+    pub fn new(model: &model::VisualModel, transform: Transform) -> Option<Self> {
+        let mut body =
+            Self::from_physics(&model.body.physics, model.shape.samples.clone(), transform)?;
+        body.wheels = model
+            .wheels
+            .iter()
+            .map(|w| Wheel {
+                pos: cgmath::Vector3::from(w.pos),
+                radius: w.radius as f32,
+                width: w.width as f32,
+                steer: w.steer != 0,
+            })
+            .collect();
+        Some(body)
+    }
+
+    /// Create a resting body without wheels from the mass properties
+    /// and the collision samples, which doesn't need the model to be uploaded.
+    /// Returns `None` if there is no volume, or the inertia can't be inverted.
+    pub fn from_physics(
+        physics: &m3d::Physics,
+        samples: Vec<model::RawVertex>,
+        transform: Transform,
+    ) -> Option<Self> {
+        if physics.volume <= 0.0 {
+            return None;
+        }
+        let jacobi = cgmath::Matrix3::from(physics.jacobi) / physics.volume;
+        Some(Body {
+            transform,
+            vel: cgmath::Vector3::zero(),
+            wel: cgmath::Vector3::zero(),
+            control: Control::default(),
+            params: Params::default(),
+            rcm: cgmath::Vector3::from(physics.rcm),
+            inertia_inv: jacobi.invert()?,
+            samples,
+            wheels: Vec::new(),
+        })
+    }
+
+    /// Center of mass, in the world space.
+    pub fn center(&self) -> cgmath::Vector3<f32> {
+        self.transform
+            .transform_point(cgmath::Point3::from_vec(self.rcm))
+            .to_vec()
+    }
+
+    fn world_inertia_inv(&self) -> cgmath::Matrix3<f32> {
+        let rot = cgmath::Matrix3::from(self.transform.rot);
+        let scale2 = self.transform.scale * self.transform.scale;
+        rot * self.inertia_inv * rot.transpose() / scale2
+    }
+
+    /// Apply the impulse at the `offset` from the center of mass.
+    fn apply_impulse(
+        &mut self,
+        inertia_inv: &cgmath::Matrix3<f32>,
+        offset: cgmath::Vector3<f32>,
+        impulse: cgmath::Vector3<f32>,
+    ) {
+        self.vel += impulse;
+        self.wel += inertia_inv * offset.cross(impulse);
+    }
+
+    /// Impulse along the `dir` that changes the velocity at the `offset` by `dv`.
+    fn impulse_for(
+        inertia_inv: &cgmath::Matrix3<f32>,
+        offset: cgmath::Vector3<f32>,
+        dir: cgmath::Vector3<f32>,
+        dv: f32,
+    ) -> f32 {
+        let angular = (inertia_inv * offset.cross(dir)).cross(offset);
+        dv / (1.0 + dir.dot(angular))
+    }
+
+    fn resolve_contacts(&mut self, contacts: &[ContactPoint]) {
+        if contacts.is_empty() {
+            return;
+        }
+        let center = self.center();
+        let inertia_inv = self.world_inertia_inv();
+        // the contacts are resolved together, sharing the response between them
+        let share = 1.0 / contacts.len() as f32;
+        let mut normal_impulses = Vec::with_capacity(contacts.len());
+        for contact in contacts {
+            let offset = contact.pos - center;
+            let approach = (self.vel + self.wel.cross(offset)).dot(contact.normal);
+            let bias = self.params.correction * contact.depth;
+            let target = if approach < 0.0 {
+                -self.params.restitution * approach + bias
+            } else {
+                bias
+            };
+            let impulse = if target > approach {
+                Self::impulse_for(&inertia_inv, offset, contact.normal, target - approach) * share
+            } else {
+                0.0
+            };
+            self.apply_impulse(&inertia_inv, offset, contact.normal * impulse);
+            normal_impulses.push(impulse);
+        }
+
+        // the friction is bounded by the normal impulses, so it goes after all of them,
+        // otherwise the partial responses keep the body creeping on the flat ground
+        for (contact, &impulse) in contacts.iter().zip(&normal_impulses) {
+            let offset = contact.pos - center;
+            let vel = self.vel + self.wel.cross(offset);
+            let tangent = vel - contact.normal * vel.dot(contact.normal);
+            let slide = tangent.magnitude();
+            if slide > 1.0e-3 {
+                let dir = tangent / slide;
+                let max = self.params.friction * impulse;
+                let friction =
+                    (Self::impulse_for(&inertia_inv, offset, dir, slide) * share).min(max);
+                self.apply_impulse(&inertia_inv, offset, -dir * friction);
+            }
+        }
+    }
+
+    fn apply_traction(&mut self, level: &level::Level, dt: f32) {
+        let center = self.center();
+        let inertia_inv = self.world_inertia_inv();
+        let steer_angle = self.params.max_steer * self.control.steer;
+        let num_wheels = self.wheels.len().max(1) as f32;
+        for i in 0..self.wheels.len() {
+            let (pos, radius, width, steer) = {
+                let w = &self.wheels[i];
+                (w.pos, w.radius, w.width, w.steer)
+            };
+            let bottom = pos - cgmath::Vector3::unit_z() * radius;
+            let world = self
+                .transform
+                .transform_point(cgmath::Point3::from_vec(bottom))
+                .to_vec();
+            match level.collision_surface(world) {
+                Some((height, _)) if height >= world.z => {}
+                _ => continue,
+            }
+
+            let local_forward = if steer {
+                cgmath::Matrix3::from_angle_z(-steer_angle) * cgmath::Vector3::unit_y()
+            } else {
+                cgmath::Vector3::unit_y()
+            };
+            let forward = self.transform.rot * local_forward;
+            let side = forward.cross(cgmath::Vector3::unit_z()).normalize();
+            let offset = world - center;
+
+            // the engine torque is turned into the force at the wheel radius
+            let drive =
+                self.control.throttle * self.params.engine_torque / (radius.max(1.0) * num_wheels);
+            self.apply_impulse(&inertia_inv, offset, forward * drive * dt);
+
+            // the grip is cancelling the sliding sideways
+            let lateral = (self.vel + self.wel.cross(offset)).dot(side);
+            let grip = (self.params.grip * width * dt).min(1.0) / num_wheels;
+            let impulse = Self::impulse_for(&inertia_inv, offset, side, lateral) * grip;
+            self.apply_impulse(&inertia_inv, offset, -side * impulse);
+        }
+    }
+
+    /// Advance the body by `dt` seconds on the level,
+    /// returning the contacts of the collision shape with the terrain.
+    pub fn step(&mut self, dt: f32, level: &level::Level) -> Vec<ContactPoint> {
+        self.vel.z -= self.params.gravity * level.physics.gravity * dt;
+
+        let contacts = model::project_samples(&self.samples, level, &self.transform);
+        self.resolve_contacts(&contacts);
+        self.apply_traction(level, dt);
+
+        let [drag_v, drag_w] = self.params.drag;
+        self.vel *= (1.0 - drag_v * dt).max(0.0);
+        self.wel *= (1.0 - drag_w * dt).max(0.0);
+
+        // rotate around the center of mass, keeping it in place
+        let center = self.center() + self.vel * dt;
+        let angle = self.wel.magnitude() * dt;
+        if angle > 0.0 {
+            let spin =
+                cgmath::Quaternion::from_axis_angle(self.wel.normalize(), cgmath::Rad(angle));
+            self.transform.rot = (spin * self.transform.rot).normalize();
+        }
+        self.transform.disp = center - self.transform.rot * (self.rcm * self.transform.scale);
+
+        contacts
+    }
+}
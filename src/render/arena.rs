@@ -0,0 +1,91 @@
+//! Storage of the transient data of a frame.
+//!
+//! The data is only needed until it's uploaded, so the memory is kept
+//! and reused by the next frame, instead of allocating it every time.
+//! The same goes for the GPU buffer it's uploaded into, which only grows
+//! until it fits the largest frame.
+
+use bytemuck::Pod;
+
+use std::ops::Range;
+
+/// Alignment of the data within the arena, required by `write_buffer`
+/// and the offsets of the vertex buffers.
+const ALIGNMENT: usize = 4;
+
+/// Bump allocator of the plain data, reset at the start of a frame,
+/// and uploaded into a GPU buffer that is kept between the frames.
+pub struct FrameArena {
+    bytes: Vec<u8>,
+    name: String,
+    usage: wgpu::BufferUsage,
+    buffer: Option<wgpu::Buffer>,
+    /// Size of the GPU buffer, in bytes.
+    buffer_size: wgpu::BufferAddress,
+}
+
+impl FrameArena {
+    /// Create an empty arena, with the GPU buffer of the given name and usage.
+    pub fn new(name: String, usage: wgpu::BufferUsage) -> Self {
+        FrameArena {
+            bytes: Vec::new(),
+            name,
+            usage: usage | wgpu::BufferUsage::COPY_DST,
+            buffer: None,
+            buffer_size: 0,
+        }
+    }
+
+    /// Forget the data of the previous frame, keeping the memory.
+    pub fn reset(&mut self) {
+        self.bytes.clear();
+    }
+
+    /// Copy the data into the arena, returning its range of bytes.
+    /// The memory only grows until it fits the largest frame.
+    pub fn push<T: Pod>(&mut self, data: &[T]) -> Range<usize> {
+        let start = self.bytes.len();
+        self.bytes.extend_from_slice(bytemuck::cast_slice(data));
+        let end = self.bytes.len();
+        let padding = (ALIGNMENT - end % ALIGNMENT) % ALIGNMENT;
+        self.bytes.resize(end + padding, 0);
+        start..end
+    }
+
+    /// Number of the bytes pushed this frame so far.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Write the data of the frame into the GPU buffer,
+    /// re-creating it first if the data doesn't fit.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.bytes.is_empty() {
+            return;
+        }
+        let size = self.bytes.len() as wgpu::BufferAddress;
+        if size > self.buffer_size {
+            self.buffer_size = size.next_power_of_two();
+            self.buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(&self.name),
+                size: self.buffer_size,
+                usage: self.usage,
+                mapped_at_creation: false,
+            }));
+        }
+        queue.write_buffer(self.buffer.as_ref().unwrap(), 0, &self.bytes);
+    }
+
+    /// Slice of the GPU buffer with the data pushed at the `range`.
+    /// Only valid after the data is uploaded.
+    pub fn slice(&self, range: Range<usize>) -> wgpu::BufferSlice {
+        self.buffer
+            .as_ref()
+            .expect("Frame arena is not uploaded")
+            .slice(range.start as wgpu::BufferAddress..range.end as wgpu::BufferAddress)
+    }
+}
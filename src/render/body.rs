@@ -2,13 +2,18 @@ use crate::{
     config::{car::CarPhysics, common::Common, settings},
     freelist::{self, FreeList},
     model::VisualModel,
-    render::{collision::GpuRange, GpuTransform, Shaders},
+    render::{
+        collision::GpuRange,
+        label,
+        readback::{Readback, Throttle},
+        GpuTransform, Shaders,
+    },
     space::Transform,
 };
 
 use bytemuck::{Pod, Zeroable};
 use cgmath::SquareMatrix as _;
-use futures::{executor::LocalSpawner, task::LocalSpawn as _, FutureExt};
+use futures::executor::LocalSpawner;
 use wgpu::util::DeviceExt as _;
 
 use std::{
@@ -18,6 +23,8 @@ use std::{
 
 const WORK_GROUP_WIDTH: u32 = 32;
 const MAX_WHEELS: usize = 4;
+/// Number of frames the GPU results can be read back behind.
+const MAX_RESULTS_IN_FLIGHT: usize = 2;
 
 pub type GpuControl = [f32; 4];
 
@@ -145,7 +152,7 @@ impl Pipelines {
     ) -> Self {
         Pipelines {
             step: device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: Some("body-step"),
+                label: Some(&label::pipeline("body", "step")),
                 layout: Some(layout_step),
                 compute_stage: wgpu::ProgrammableStageDescriptor {
                     module: &Shaders::new_compute(
@@ -159,7 +166,7 @@ impl Pipelines {
                 },
             }),
             gather: device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: Some("body-gather"),
+                label: Some(&label::pipeline("body", "gather")),
                 layout: Some(layout_gather),
                 compute_stage: wgpu::ProgrammableStageDescriptor {
                     module: &Shaders::new_compute(
@@ -173,7 +180,7 @@ impl Pipelines {
                 },
             }),
             push: device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: Some("body-push"),
+                label: Some(&label::pipeline("body", "push")),
                 layout: Some(layout_push),
                 compute_stage: wgpu::ProgrammableStageDescriptor {
                     module: &Shaders::new_compute(
@@ -209,7 +216,7 @@ impl GpuStoreInit {
         };
 
         let buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("GpuStore"),
+            label: Some(&label::resource("body", "store")),
             size: (rounded_max_objects * mem::size_of::<Data>()) as wgpu::BufferAddress,
             usage: wgpu::BufferUsage::STORAGE
                 | wgpu::BufferUsage::COPY_SRC
@@ -226,7 +233,7 @@ impl GpuStoreInit {
 
     pub fn new_dummy(device: &wgpu::Device) -> Self {
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("dummy"),
+            label: Some(&label::resource("body", "dummy store")),
             contents: bytemuck::bytes_of(&Data::DUMMY),
             usage: wgpu::BufferUsage::STORAGE,
         });
@@ -249,7 +256,7 @@ enum Update {
 }
 
 struct GpuResult {
-    buffer: wgpu::Buffer,
+    readback: Readback,
     count: usize,
 }
 
@@ -282,6 +289,7 @@ pub struct GpuStore {
     update_control: Vec<GpuControl>,
     pending_pushes: Vec<GpuPush>,
     gpu_result: Option<GpuResult>,
+    result_throttle: Throttle,
     cpu_mirror: Arc<Mutex<GpuStoreMirror>>,
 }
 
@@ -293,7 +301,7 @@ impl GpuStore {
         collider_buffer: wgpu::BindingResource,
     ) -> Self {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Body"),
+            label: Some(&label::resource("body", "step")),
             entries: &[
                 // data
                 wgpu::BindGroupLayoutEntry {
@@ -329,14 +337,14 @@ impl GpuStore {
             ],
         });
         let pipeline_layout_step = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("body-step"),
+            label: Some(&label::pipeline("body", "step")),
             bind_group_layouts: &[&bind_group_layout],
             push_constant_ranges: &[],
         });
 
         let bind_group_layout_gather =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Gather"),
+                label: Some(&label::resource("body", "gather")),
                 entries: &[
                     // collisions
                     wgpu::BindGroupLayoutEntry {
@@ -364,14 +372,14 @@ impl GpuStore {
             });
         let pipeline_layout_gather =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("body-gather"),
+                label: Some(&label::pipeline("body", "gather")),
                 bind_group_layouts: &[&bind_group_layout, &bind_group_layout_gather],
                 push_constant_ranges: &[],
             });
 
         let bind_group_layout_push =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Push"),
+                label: Some(&label::resource("body", "push")),
                 entries: &[
                     // pushes
                     wgpu::BindGroupLayoutEntry {
@@ -387,7 +395,7 @@ impl GpuStore {
                 ],
             });
         let pipeline_layout_push = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("body-push"),
+            label: Some(&label::pipeline("body", "push")),
             bind_group_layouts: &[&bind_group_layout, &bind_group_layout_push],
             push_constant_ranges: &[],
         });
@@ -398,27 +406,24 @@ impl GpuStore {
             &pipeline_layout_push,
             device,
         );
-        let desc_uniforms = wgpu::BufferDescriptor {
-            label: Some("Uniforms"),
+        let buf_uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&label::resource("body", "uniforms")),
             size: mem::size_of::<Uniforms>() as wgpu::BufferAddress,
             usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
             mapped_at_creation: false,
-        };
-        let buf_uniforms = device.create_buffer(&desc_uniforms);
-        let desc_ranges = wgpu::BufferDescriptor {
-            label: Some("Ranges"),
+        });
+        let buf_ranges = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&label::resource("body", "ranges")),
             size: (init.rounded_max_objects * mem::size_of::<GpuRange>()) as wgpu::BufferAddress,
             usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
             mapped_at_creation: false,
-        };
-        let buf_ranges = device.create_buffer(&desc_ranges);
-        let desc_pushes = wgpu::BufferDescriptor {
-            label: Some("Pushes"),
+        });
+        let buf_pushes = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&label::resource("body", "pushes")),
             size: (WORK_GROUP_WIDTH as usize * mem::size_of::<GpuPush>()) as wgpu::BufferAddress,
             usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
             mapped_at_creation: false,
-        };
-        let buf_pushes = device.create_buffer(&desc_pushes);
+        });
 
         let constants = Constants {
             nature: [
@@ -478,13 +483,13 @@ impl GpuStore {
             force: [common.force.k_distance_to_force, 0.0, 0.0, 0.0],
         };
         let buf_constants = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("body-constants"),
+            label: Some(&label::resource("body", "constants")),
             contents: bytemuck::bytes_of(&constants),
             usage: wgpu::BufferUsage::UNIFORM,
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Body"),
+            label: Some(&label::resource("body", "step")),
             layout: &bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -502,7 +507,7 @@ impl GpuStore {
             ],
         });
         let bind_group_gather = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Gather"),
+            label: Some(&label::resource("body", "gather")),
             layout: &bind_group_layout_gather,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -516,7 +521,7 @@ impl GpuStore {
             ],
         });
         let bind_group_push = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Push"),
+            label: Some(&label::resource("body", "push")),
             layout: &bind_group_layout_push,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
@@ -543,6 +548,7 @@ impl GpuStore {
             update_control: Vec::new(),
             pending_pushes: Vec::with_capacity(WORK_GROUP_WIDTH as usize),
             gpu_result: None,
+            result_throttle: Throttle::new(MAX_RESULTS_IN_FLIGHT),
             cpu_mirror: Arc::new(Mutex::new(GpuStoreMirror {
                 transforms: Vec::new(),
             })),
@@ -652,7 +658,7 @@ impl GpuStore {
             None
         } else {
             let buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("temp-data"),
+                label: Some(&label::temp("data")),
                 contents: bytemuck::cast_slice(&self.update_data),
                 usage: wgpu::BufferUsage::COPY_SRC,
             });
@@ -663,7 +669,7 @@ impl GpuStore {
             None
         } else {
             let buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("temp-control"),
+                label: Some(&label::temp("control")),
                 contents: bytemuck::cast_slice(&self.update_control),
                 usage: wgpu::BufferUsage::COPY_SRC,
             });
@@ -711,7 +717,7 @@ impl GpuStore {
                     .resize_with(WORK_GROUP_WIDTH as usize, || GpuPush { dir_id: [-1.0; 4] });
             }
             let temp = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("temp-step"),
+                label: Some(&label::temp("step")),
                 contents: bytemuck::cast_slice(&self.pending_pushes[..WORK_GROUP_WIDTH as usize]),
                 usage: wgpu::BufferUsage::COPY_SRC,
             });
@@ -735,7 +741,7 @@ impl GpuStore {
         {
             let sub_range = &raw_ranges[..(num_groups * WORK_GROUP_WIDTH) as usize];
             let temp = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("temp-range"),
+                label: Some(&label::temp("range")),
                 contents: bytemuck::cast_slice(sub_range),
                 usage: wgpu::BufferUsage::COPY_SRC,
             });
@@ -754,7 +760,7 @@ impl GpuStore {
                 delta: [delta, 0.0, 0.0, 0.0],
             };
             let temp = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("temp-uniforms"),
+                label: Some(&label::temp("uniforms")),
                 contents: bytemuck::bytes_of(&uniforms),
                 usage: wgpu::BufferUsage::COPY_SRC,
             });
@@ -770,6 +776,7 @@ impl GpuStore {
         // compute all the things
         let do_gather = true;
         let mut pass = encoder.begin_compute_pass();
+        pass.push_debug_group(&label::group("body", "step"));
         pass.set_bind_group(0, &self.bind_group, &[]);
         if do_gather {
             pass.set_pipeline(&self.pipelines.gather);
@@ -783,6 +790,7 @@ impl GpuStore {
         }
         pass.set_pipeline(&self.pipelines.step);
         pass.dispatch(num_groups, 1, 1);
+        pass.pop_debug_group();
 
         // remove the first N pushes
         if !self.pending_pushes.is_empty() {
@@ -795,43 +803,48 @@ impl GpuStore {
         device: &wgpu::Device,
         encoder: &mut wgpu::CommandEncoder,
     ) {
+        if !self.result_throttle.is_ready() {
+            return;
+        }
         let count = self.free_list.length();
-        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Gpu Results"),
-            size: (count * mem::size_of::<GpuTransform>()) as wgpu::BufferAddress,
-            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
-            mapped_at_creation: false,
-        });
+        let readback = Readback::new(
+            device,
+            &label::temp("body-results"),
+            (count * mem::size_of::<GpuTransform>()) as wgpu::BufferAddress,
+        );
 
         let offset = mem::size_of::<GpuControl>() + mem::size_of::<[f32; 4]>(); // skip control & engine
         for i in 0..count {
             encoder.copy_buffer_to_buffer(
                 &self.buf_data,
                 (i * mem::size_of::<Data>() + offset) as wgpu::BufferAddress,
-                &buffer,
+                readback.buffer(),
                 (i * mem::size_of::<GpuTransform>()) as wgpu::BufferAddress,
                 mem::size_of::<GpuTransform>() as wgpu::BufferAddress,
             );
         }
 
-        self.gpu_result = Some(GpuResult { buffer, count })
+        self.gpu_result = Some(GpuResult { readback, count })
     }
 
     pub fn consume_gpu_results(&mut self, spawner: &LocalSpawner) {
-        let GpuResult { buffer, count } = match self.gpu_result.take() {
+        let GpuResult { readback, count } = match self.gpu_result.take() {
             Some(gr) => gr,
             None => return,
         };
 
         let latest = Arc::clone(&self.cpu_mirror);
-        let end = (count * mem::size_of::<GpuTransform>()) as wgpu::BufferAddress;
-        let future = buffer
-            .slice(..end)
-            .map_async(wgpu::MapMode::Read)
-            .map(move |_| {
-                let mapping = buffer.slice(..end).get_mapped_range();
+        self.result_throttle
+            .consume(readback, spawner, move |result| {
+                let mapping = match result {
+                    Ok(mapping) => mapping,
+                    Err(e) => {
+                        error!("Unable to map the GPU results: {:?}", e);
+                        return;
+                    }
+                };
                 let data = unsafe {
-                    slice::from_raw_parts(*mapping.as_ptr() as *const GpuTransform, count)
+                    slice::from_raw_parts(mapping.as_ptr() as *const GpuTransform, count)
                 };
 
                 let transforms = data.iter().map(|gt| Transform {
@@ -849,7 +862,6 @@ impl GpuStore {
                 storage.transforms.clear();
                 storage.transforms.extend(transforms);
             });
-        spawner.spawn_local_obj(Box::new(future).into()).unwrap();
     }
 
     pub fn cpu_mirror(&self) -> MutexGuard<GpuStoreMirror> {
@@ -0,0 +1,90 @@
+//! Frame captures with RenderDoc, triggered from the application.
+//!
+//! With the `capture` feature, the in-application API of RenderDoc is loaded
+//! if the process is started from RenderDoc, and a requested capture covers
+//! exactly the next frame. Without the feature, the requests are ignored.
+
+#[cfg(feature = "capture")]
+use renderdoc::{RenderDoc, V110};
+
+pub struct FrameCapture {
+    #[cfg(feature = "capture")]
+    renderdoc: Option<RenderDoc<V110>>,
+    requested: bool,
+    active: bool,
+}
+
+impl FrameCapture {
+    pub fn new() -> Self {
+        FrameCapture {
+            #[cfg(feature = "capture")]
+            renderdoc: match RenderDoc::new() {
+                Ok(rd) => {
+                    info!("RenderDoc API is loaded");
+                    Some(rd)
+                }
+                Err(e) => {
+                    info!("RenderDoc is not available: {}", e);
+                    None
+                }
+            },
+            requested: false,
+            active: false,
+        }
+    }
+
+    /// Returns `true` if the captures can be taken.
+    pub fn is_available(&self) -> bool {
+        #[cfg(feature = "capture")]
+        {
+            self.renderdoc.is_some()
+        }
+        #[cfg(not(feature = "capture"))]
+        {
+            false
+        }
+    }
+
+    /// Capture the next frame, between `begin_frame` and `end_frame`.
+    pub fn request(&mut self) {
+        if self.is_available() {
+            info!("Capturing the next frame");
+            self.requested = true;
+        } else if cfg!(feature = "capture") {
+            warn!("Unable to capture: the application is not started from RenderDoc");
+        } else {
+            warn!("Unable to capture: the `capture` feature is not enabled");
+        }
+    }
+
+    /// Called before any work of a frame is recorded.
+    pub fn begin_frame(&mut self) {
+        if !self.requested {
+            return;
+        }
+        self.requested = false;
+        #[cfg(feature = "capture")]
+        {
+            if let Some(ref mut rd) = self.renderdoc {
+                // any device and window
+                rd.start_frame_capture(std::ptr::null(), std::ptr::null());
+                self.active = true;
+            }
+        }
+    }
+
+    /// Called after the work of a frame is submitted.
+    pub fn end_frame(&mut self) {
+        if !self.active {
+            return;
+        }
+        self.active = false;
+        #[cfg(feature = "capture")]
+        {
+            if let Some(ref mut rd) = self.renderdoc {
+                rd.end_frame_capture(std::ptr::null(), std::ptr::null());
+                info!("Frame capture is finished");
+            }
+        }
+    }
+}
@@ -2,7 +2,7 @@ use crate::{
     config::{common::Common, settings},
     model::Shape,
     render::{
-        object::Context as ObjectContext, terrain::Context as TerrainContext, Shaders,
+        label, object::Context as ObjectContext, terrain::Context as TerrainContext, Shaders,
         ShapeVertexDesc,
     },
 };
@@ -104,7 +104,7 @@ impl GpuCollider {
     ) -> (wgpu::RenderPipeline, wgpu::ComputePipeline) {
         let shaders = Shaders::new("physics/collision_add", &[], device).unwrap();
         let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("collision"),
+            label: Some(&label::pipeline("collision", "gather")),
             layout: Some(layout),
             vertex_stage: wgpu::ProgrammableStageDescriptor {
                 module: &shaders.vs,
@@ -148,7 +148,7 @@ impl GpuCollider {
         )
         .unwrap();
         let clear_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("collision-clear"),
+            label: Some(&label::pipeline("collision", "clear")),
             layout: Some(clear_layout),
             compute_stage: wgpu::ProgrammableStageDescriptor {
                 module: &clear_shader,
@@ -168,7 +168,7 @@ impl GpuCollider {
         store_buffer: wgpu::BindingResource,
     ) -> Self {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Collision"),
+            label: Some(&label::resource("collision", "globals")),
             entries: &[
                 // global uniforms
                 wgpu::BindGroupLayoutEntry {
@@ -206,7 +206,7 @@ impl GpuCollider {
         });
         let dynamic_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Collision dynamic"),
+                label: Some(&label::resource("collision", "dynamic")),
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
                     visibility: wgpu::ShaderStage::VERTEX,
@@ -218,7 +218,7 @@ impl GpuCollider {
                 }],
             });
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("collision"),
+            label: Some(&label::pipeline("collision", "gather")),
             bind_group_layouts: &[
                 &bind_group_layout,
                 &terrain.bind_group_layout,
@@ -230,7 +230,7 @@ impl GpuCollider {
 
         let clear_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("collision-clear"),
+                label: Some(&label::pipeline("collision", "clear")),
                 bind_group_layouts: &[&bind_group_layout],
                 push_constant_ranges: &[],
             });
@@ -249,14 +249,14 @@ impl GpuCollider {
             penetration: [common.terrain.min_wall_delta, 0.0, 0.0, 0.0],
         };
         let global_uniforms = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("collision-globals"),
+            label: Some(&label::resource("collision", "globals")),
             contents: bytemuck::bytes_of(&globals),
             usage: wgpu::BufferUsage::UNIFORM,
         });
         let locals_size = mem::size_of::<Locals>().max(wgpu::BIND_BUFFER_ALIGNMENT as usize);
         let locals_total_size = (settings.max_objects * locals_size) as wgpu::BufferAddress;
         let local_uniforms = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Collision Locals"),
+            label: Some(&label::resource("collision", "locals")),
             size: locals_total_size,
             usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::UNIFORM,
             mapped_at_creation: false,
@@ -265,7 +265,7 @@ impl GpuCollider {
             (settings.max_polygons_total - 1) | (CLEAR_WORK_GROUP_WIDTH - 1) as usize + 1;
         let buf_size = (max_polygons_total * mem::size_of::<PolygonData>()) as wgpu::BufferAddress;
         let collision_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Collision"),
+            label: Some(&label::resource("collision", "buffer")),
             size: buf_size,
             usage: wgpu::BufferUsage::STORAGE
                 | wgpu::BufferUsage::STORAGE
@@ -274,7 +274,7 @@ impl GpuCollider {
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Collision"),
+            label: Some(&label::resource("collision", "globals")),
             layout: &bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -292,7 +292,7 @@ impl GpuCollider {
             ],
         });
         let dynamic_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Collision dynamic"),
+            label: Some(&label::resource("collision", "dynamic")),
             layout: &dynamic_bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
@@ -302,7 +302,7 @@ impl GpuCollider {
 
         let dummy_target = device
             .create_texture(&wgpu::TextureDescriptor {
-                label: Some("Dummy"),
+                label: Some(&label::resource("collision", "dummy")),
                 size: wgpu::Extent3d {
                     width: settings.max_raster_size.0,
                     height: settings.max_raster_size.1,
@@ -352,9 +352,11 @@ impl GpuCollider {
     ) -> GpuSession<'pass, 'this> {
         if self.dirty_group_count != 0 {
             let mut pass = encoder.begin_compute_pass();
+            pass.push_debug_group(&label::group("collision", "clear"));
             pass.set_pipeline(&self.clear_pipeline);
             pass.set_bind_group(0, &self.bind_group, &[]);
             pass.dispatch(self.dirty_group_count, 1, 1);
+            pass.pop_debug_group();
             self.dirty_group_count = 0;
         }
 
@@ -369,6 +371,8 @@ impl GpuCollider {
             }],
             depth_stencil_attachment: None,
         });
+        // the pass is left open for the session, so it's only marked
+        pass.insert_debug_marker(&label::group("collision", "gather"));
         pass.set_pipeline(&self.pipeline);
         pass.set_bind_group(0, &self.bind_group, &[]);
         pass.set_bind_group(1, &terrain.bind_group, &[]);
@@ -428,7 +432,7 @@ impl<'pass, 'this: 'pass> GpuSession<'pass, 'this> {
         *self.dirty_group_count = (*self.dirty_group_count).max(num_groups);
 
         let temp = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("temp-collision"),
+            label: Some(&label::temp("collision")),
             contents: bytemuck::cast_slice(&self.object_locals),
             usage: wgpu::BufferUsage::COPY_SRC,
         });
@@ -0,0 +1,235 @@
+//! Screen-space contact shadows.
+//!
+//! Marches a short ray from every visible point towards the light through the depth buffer,
+//! darkening the points that are occluded. Grounds the vehicles on the terrain
+//! even when the shadow map is disabled.
+
+use crate::{
+    config::settings,
+    render::{global::Context as GlobalContext, label, ScreenTargets, Shaders},
+};
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt as _;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Constants {
+    screen_size: [f32; 4],
+    params: [f32; 4],
+}
+unsafe impl Pod for Constants {}
+unsafe impl Zeroable for Constants {}
+
+pub struct ContactShadow {
+    settings: settings::ContactShadow,
+    color_format: wgpu::TextureFormat,
+    sample_count: u32,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+}
+
+impl ContactShadow {
+    fn create_pipeline(
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        device: &wgpu::Device,
+    ) -> wgpu::RenderPipeline {
+        let features: &[&str] = if sample_count > 1 {
+            &["MULTISAMPLED"]
+        } else {
+            &[]
+        };
+        let shaders = Shaders::new("contact", features, device).unwrap();
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&label::pipeline("contact", "shadow")),
+            layout: Some(layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &shaders.vs,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &shaders.fs,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                ..Default::default()
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: color_format,
+                // multiply the color by the lit factor
+                alpha_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::SrcColor,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                write_mask: wgpu::ColorWrite::all(),
+            }],
+            depth_stencil_state: None,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[],
+            },
+            sample_count,
+            alpha_to_coverage_enabled: false,
+            sample_mask: !0,
+        })
+    }
+
+    pub fn new(
+        settings: &settings::ContactShadow,
+        global: &GlobalContext,
+        device: &wgpu::Device,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&label::resource("contact", "shadow")),
+            entries: &[
+                // constants
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // depth
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Float,
+                        multisampled: global.sample_count > 1,
+                    },
+                    count: None,
+                },
+                // depth sampler
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&label::pipeline("contact", "shadow")),
+            bind_group_layouts: &[&global.bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(
+            &pipeline_layout,
+            global.color_format,
+            global.sample_count,
+            device,
+        );
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(&label::resource("contact", "shadow depth")),
+            ..Default::default()
+        });
+
+        ContactShadow {
+            settings: settings.clone(),
+            color_format: global.color_format,
+            sample_count: global.sample_count,
+            bind_group_layout,
+            pipeline_layout,
+            pipeline,
+            sampler,
+        }
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(
+            &self.pipeline_layout,
+            self.color_format,
+            self.sample_count,
+            device,
+        );
+    }
+
+    /// Re-create the pipeline for the color format of the global context.
+    pub fn set_target_formats(&mut self, global: &GlobalContext, device: &wgpu::Device) {
+        self.color_format = global.color_format;
+        self.reload(device);
+    }
+
+    /// Darken the world color by the shadows found in the world depth.
+    pub fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        global: &GlobalContext,
+        targets: ScreenTargets,
+        resolve_target: Option<&wgpu::TextureView>,
+    ) {
+        let constants = Constants {
+            screen_size: [
+                targets.extent.width as f32,
+                targets.extent.height as f32,
+                0.0,
+                0.0,
+            ],
+            params: [
+                self.settings.length,
+                self.settings.intensity,
+                self.settings.steps as f32,
+                self.settings.thickness,
+            ],
+        };
+        let uniform_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&label::resource("contact", "shadow uniforms")),
+            contents: bytemuck::bytes_of(&constants),
+            usage: wgpu::BufferUsage::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&label::resource("contact", "shadow")),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(targets.depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: targets.color,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        pass.push_debug_group(&label::group("contact", "shadow"));
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &global.bind_group, &[]);
+        pass.set_bind_group(1, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        pass.pop_debug_group();
+    }
+}
@@ -0,0 +1,53 @@
+//! Extension points for the passes recorded by the users of the crate.
+//!
+//! A custom pass is called at the selected stage of `Render::draw_world_with`,
+//! and records its own commands into the frame encoder. It can bind the global
+//! bind group to reuse the camera and lighting constants of the frame.
+
+use crate::{
+    render::{global::Context as GlobalContext, ScreenTargets},
+    space::Camera,
+};
+
+/// Point of the frame where a custom pass is recorded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PassStage {
+    /// After the terrain and the objects are drawn,
+    /// before the post-processing is applied.
+    World,
+    /// After the post-processing, on top of the final image.
+    Post,
+}
+
+/// Resources of the frame that a custom pass can use.
+pub struct FrameContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub encoder: &'a mut wgpu::CommandEncoder,
+    /// Global uniforms and samplers, for binding at slot 0.
+    pub global: &'a GlobalContext,
+    pub camera: &'a Camera,
+    /// Targets of the stage. At `PassStage::World`, the color is the input
+    /// of the post-processing if it's enabled, and both targets are
+    /// multisampled with `global.sample_count` if MSAA is enabled.
+    /// At `PassStage::Post`, the targets are always single-sampled.
+    pub targets: ScreenTargets<'a>,
+    /// Target to resolve the multisampled color into, if any.
+    pub resolve_target: Option<&'a wgpu::TextureView>,
+}
+
+pub trait CustomPass {
+    fn stage(&self) -> PassStage;
+    fn record(&mut self, frame: &mut FrameContext);
+}
+
+pub(super) fn record_stage(
+    passes: &mut [&mut dyn CustomPass],
+    stage: PassStage,
+    frame: &mut FrameContext,
+) {
+    for pass in passes.iter_mut() {
+        if pass.stage() == stage {
+            pass.record(frame);
+        }
+    }
+}
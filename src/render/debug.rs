@@ -2,16 +2,23 @@ use crate::{
     config::settings,
     model,
     render::{
+        arena::FrameArena,
         global::Context as GlobalContext,
+        label,
         object::{Context as ObjectContext, Instance as ObjectInstance, InstanceDesc},
-        Shaders, ShapeVertexDesc, COLOR_FORMAT, DEPTH_FORMAT,
+        Shaders, ShapeVertexDesc,
     },
 };
 
 use bytemuck::{Pod, Zeroable};
 use wgpu::util::DeviceExt as _;
 
-use std::{collections::HashMap, mem, num::NonZeroU64};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, mem,
+    num::NonZeroU64,
+    time::Duration,
+};
 
 const BLEND_FRONT: wgpu::BlendDescriptor = wgpu::BlendDescriptor::REPLACE;
 const BLEND_BEHIND: wgpu::BlendDescriptor = wgpu::BlendDescriptor {
@@ -35,6 +42,67 @@ pub struct Position {
 unsafe impl Pod for Position {}
 unsafe impl Zeroable for Position {}
 
+/// Replacements of the pure hues, keyed by the mask of the lit RGB channels.
+/// The colors are from the palette of Okabe and Ito.
+const DEUTERANOPIA_COLORS: [(u8, [u8; 3]); 6] = [
+    (0b001, [0xD5, 0x5E, 0x00]), // red -> vermillion
+    (0b010, [0x56, 0xB4, 0xE9]), // green -> sky blue
+    (0b100, [0x00, 0x72, 0xB2]), // blue -> blue
+    (0b011, [0xF0, 0xE4, 0x42]), // yellow -> yellow
+    (0b110, [0x00, 0x9E, 0x73]), // cyan -> bluish green
+    (0b101, [0xCC, 0x79, 0xA7]), // magenta -> reddish purple
+];
+/// Same as for the deuteranopia, but the red is brighter, since it's perceived darker.
+const PROTANOPIA_COLORS: [(u8, [u8; 3]); 6] = [
+    (0b001, [0xE6, 0x9F, 0x00]), // red -> orange
+    (0b010, [0x56, 0xB4, 0xE9]),
+    (0b100, [0x00, 0x72, 0xB2]),
+    (0b011, [0xF0, 0xE4, 0x42]),
+    (0b110, [0x00, 0x9E, 0x73]),
+    (0b101, [0xCC, 0x79, 0xA7]),
+];
+
+/// Map a debug color, given as RGBA bytes, into the palette.
+/// The pure hues are replaced, keeping their intensity and alpha,
+/// and the other colors are left as they are.
+pub fn remap_color(palette: settings::DebugPalette, color: u32) -> u32 {
+    let table = match palette {
+        settings::DebugPalette::Default => return color,
+        settings::DebugPalette::Deuteranopia => &DEUTERANOPIA_COLORS,
+        settings::DebugPalette::Protanopia => &PROTANOPIA_COLORS,
+    };
+    let channels = [color as u8, (color >> 8) as u8, (color >> 16) as u8];
+    let max = channels.iter().cloned().max().unwrap();
+    if max == 0 || channels.iter().any(|&c| c != 0 && c != max) {
+        return color;
+    }
+    let mask = channels
+        .iter()
+        .enumerate()
+        .fold(0, |mask, (i, &c)| if c != 0 { mask | 1 << i } else { mask });
+    match table.iter().find(|&&(key, _)| key == mask) {
+        Some(&(_, rgb)) => rgb
+            .iter()
+            .enumerate()
+            .fold(color & 0xFF000000, |result, (i, &c)| {
+                result | (c as u32 * max as u32 / 0xFF) << (i * 8)
+            }),
+        None => color,
+    }
+}
+
+fn remap_color_f32(palette: settings::DebugPalette, color: [f32; 4]) -> [f32; 4] {
+    let packed = color.iter().enumerate().fold(0, |packed, (i, &c)| {
+        packed | ((c * 255.0) as u32) << (i * 8)
+    });
+    let remapped = remap_color(palette, packed);
+    let mut result = color;
+    for (i, c) in result.iter_mut().enumerate().take(3) {
+        *c = ((remapped >> (i * 8)) & 0xFF) as f32 / 255.0;
+    }
+    result
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct Color {
@@ -79,6 +147,10 @@ impl LineBuffer {
         self.colors.clear();
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
     pub fn add(&mut self, from: [f32; 3], to: [f32; 3], color: u32) {
         self.vertices.push(Position {
             pos: [from[0], from[1], from[2], 1.0],
@@ -92,8 +164,156 @@ impl LineBuffer {
     }
 }
 
+/// Number of the frames kept in the statistics.
+const STATS_HISTORY: usize = 120;
+/// Frame time at the top of the graph, in seconds.
+const STATS_GRAPH_RANGE: f32 = 1.0 / 20.0;
+/// Offset of the graph from the bottom left corner, and its size,
+/// in the logical pixels, which are scaled by the UI scale.
+const STATS_GRAPH_MARGIN: [f32; 2] = [32.0, 20.0];
+const STATS_GRAPH_SIZE: [f32; 2] = [384.0, 120.0];
+
+fn seconds(duration: Duration) -> f32 {
+    duration.as_secs() as f32 + duration.subsec_nanos() as f32 * 1.0e-9
+}
+
+/// Timings of the recent frames, for diagnosing the performance.
+///
+/// The passes are timed by how long it takes to record them on CPU.
+/// The GPU time of the passes would need the timestamp queries,
+/// which are not exposed by the version of wgpu in use.
+pub struct Stats {
+    frame_times: VecDeque<f32>,
+    encode_times: VecDeque<f32>,
+    passes: Vec<(&'static str, f32)>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats {
+            frame_times: VecDeque::with_capacity(STATS_HISTORY),
+            encode_times: VecDeque::with_capacity(STATS_HISTORY),
+            passes: Vec::new(),
+        }
+    }
+
+    fn push(history: &mut VecDeque<f32>, value: f32) {
+        if history.len() == STATS_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(value);
+    }
+
+    /// Record the CPU time of a frame, in seconds.
+    pub fn record_frame(&mut self, delta: f32) {
+        Self::push(&mut self.frame_times, delta);
+    }
+
+    /// Record the time of building the command encoder of a frame.
+    pub fn record_encode(&mut self, duration: Duration) {
+        Self::push(&mut self.encode_times, seconds(duration));
+    }
+
+    /// Record the time of recording a pass of the current frame.
+    pub fn record_pass(&mut self, name: &'static str, duration: Duration) {
+        match self.passes.iter_mut().find(|&&mut (n, _)| n == name) {
+            Some(&mut (_, ref mut time)) => *time = seconds(duration),
+            None => self.passes.push((name, seconds(duration))),
+        }
+    }
+
+    /// Average and maximum frame time, in seconds.
+    pub fn frame_time(&self) -> (f32, f32) {
+        let sum = self.frame_times.iter().sum::<f32>();
+        let max = self.frame_times.iter().cloned().fold(0.0, f32::max);
+        (sum / self.frame_times.len().max(1) as f32, max)
+    }
+
+    /// Average time of building the command encoder, in seconds.
+    pub fn encode_time(&self) -> f32 {
+        let sum = self.encode_times.iter().sum::<f32>();
+        sum / self.encode_times.len().max(1) as f32
+    }
+
+    /// Recording time of the passes in the last frame, in seconds.
+    pub fn passes(&self) -> &[(&'static str, f32)] {
+        &self.passes
+    }
+
+    /// Build the bars of the frame time graph, as triangles in the normalized
+    /// device coordinates, with the bottom left corner at `origin`.
+    /// Each frame is a bar, colored by the frame rate it would sustain,
+    /// with the encoding time at the bottom of it.
+    pub fn graph(&self, origin: [f32; 2], size: [f32; 2]) -> (Vec<Position>, Vec<Color>) {
+        let bar_width = size[0] / STATS_HISTORY as f32;
+        let (mut vertices, mut colors) = (Vec::new(), Vec::new());
+        let mut add_quad = |x: f32, y0: f32, y1: f32, width: f32, color: u32| {
+            let (x0, x1) = (x, x + width);
+            for &(x, y) in &[(x0, y0), (x1, y0), (x0, y1), (x0, y1), (x1, y0), (x1, y1)] {
+                vertices.push(Position {
+                    pos: [x, y, 0.0, 1.0],
+                });
+                colors.push(Color { color });
+            }
+        };
+        let height = |time: f32| size[1] * (time / STATS_GRAPH_RANGE).min(1.0);
+        let [x0, y0] = origin;
+
+        for (i, &time) in self.frame_times.iter().enumerate() {
+            let color = if time <= 1.0 / 59.0 {
+                0xC000FF00
+            } else if time <= 1.0 / 29.0 {
+                0xC000FFFF
+            } else {
+                0xC00000FF
+            };
+            add_quad(
+                x0 + i as f32 * bar_width,
+                y0,
+                y0 + height(time),
+                bar_width,
+                color,
+            );
+        }
+        // the frames without the encoding time are the first ones
+        let skip = self
+            .encode_times
+            .len()
+            .saturating_sub(self.frame_times.len());
+        let offset = self.frame_times.len() + skip - self.encode_times.len();
+        for (i, &time) in self.encode_times.iter().skip(skip).enumerate() {
+            let x = x0 + (offset + i) as f32 * bar_width;
+            add_quad(x, y0, y0 + height(time), bar_width, 0xC0FF0000);
+        }
+        // the line of 60 frames per second
+        let y = y0 + height(1.0 / 60.0);
+        add_quad(x0, y, y + size[1] / 60.0, size[0], 0xFFFFFFFF);
+        (vertices, colors)
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (average, max) = self.frame_time();
+        write!(
+            f,
+            "frame {:.1}ms (max {:.1}) | encode {:.2}ms",
+            average * 1000.0,
+            max * 1000.0,
+            self.encode_time() * 1000.0,
+        )?;
+        for &(name, time) in self.passes.iter() {
+            write!(f, " | {} {:.2}", name, time * 1000.0)?;
+        }
+        Ok(())
+    }
+}
+
 pub struct Context {
     settings: settings::DebugRender,
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    sample_count: u32,
     pipeline_layout: wgpu::PipelineLayout,
     pipelines_line: HashMap<Selector, wgpu::RenderPipeline>,
     pipeline_face: Option<wgpu::RenderPipeline>,
@@ -102,9 +322,12 @@ pub struct Context {
     bind_group_line: wgpu::BindGroup,
     bind_group_face: wgpu::BindGroup,
     bind_group_edge: wgpu::BindGroup,
-    // hold the buffers alive
-    vertex_buf: Option<wgpu::Buffer>,
-    color_buf: Option<wgpu::Buffer>,
+    stats_pipeline_layout: wgpu::PipelineLayout,
+    pipeline_stats: Option<wgpu::RenderPipeline>,
+    ui_scale: f32,
+    // vertices and colors of the lines and the stats graph, reused between frames
+    line_arena: FrameArena,
+    stats_arena: FrameArena,
 }
 
 impl Context {
@@ -115,7 +338,7 @@ impl Context {
         object: &ObjectContext,
     ) -> Self {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Debug"),
+            label: Some(&label::resource("debug", "locals")),
             entries: &[
                 // locals
                 wgpu::BindGroupLayoutEntry {
@@ -130,7 +353,7 @@ impl Context {
             ],
         });
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("debug"),
+            label: Some(&label::pipeline("debug", "shape")),
             bind_group_layouts: &[
                 &global.bind_group_layout,
                 &bind_group_layout,
@@ -139,23 +362,32 @@ impl Context {
             push_constant_ranges: &[],
         });
 
+        let stats_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(&label::pipeline("debug", "stats")),
+                bind_group_layouts: &[],
+                push_constant_ranges: &[],
+            });
+
         let line_color_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("debug-line-color"),
-            contents: bytemuck::bytes_of(&Color { color: 0xFF000080 }), // line
+            label: Some(&label::resource("debug", "line color")),
+            contents: bytemuck::bytes_of(&Color {
+                color: remap_color(settings.palette, 0xFF000080),
+            }), // line
             usage: wgpu::BufferUsage::VERTEX,
         });
         let locals_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("debug-locals"),
+            label: Some(&label::resource("debug", "locals")),
             contents: bytemuck::cast_slice(&[
-                Locals::new([1.0; 4]),             // line
-                Locals::new([0.0, 1.0, 0.0, 0.2]), // face
-                Locals::new([1.0, 1.0, 0.0, 0.2]), // edge
+                Locals::new([1.0; 4]),                                                // line
+                Locals::new(remap_color_f32(settings.palette, [0.0, 1.0, 0.0, 0.2])), // face
+                Locals::new(remap_color_f32(settings.palette, [1.0, 1.0, 0.0, 0.2])), // edge
             ]),
             usage: wgpu::BufferUsage::UNIFORM,
         });
         let locals_size = mem::size_of::<Locals>() as wgpu::BufferAddress;
         let bind_group_line = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Debug line"),
+            label: Some(&label::resource("debug", "line")),
             layout: &bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
@@ -167,7 +399,7 @@ impl Context {
             }],
         });
         let bind_group_face = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Debug face"),
+            label: Some(&label::resource("debug", "face")),
             layout: &bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
@@ -179,7 +411,7 @@ impl Context {
             }],
         });
         let bind_group_edge = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Debug edge"),
+            label: Some(&label::resource("debug", "edge")),
             layout: &bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
@@ -193,6 +425,9 @@ impl Context {
 
         let mut result = Context {
             settings: settings.clone(),
+            color_format: global.color_format,
+            depth_format: global.depth_format,
+            sample_count: global.sample_count,
             pipeline_layout,
             pipelines_line: HashMap::new(),
             pipeline_face: None,
@@ -201,13 +436,48 @@ impl Context {
             bind_group_line,
             bind_group_face,
             bind_group_edge,
-            vertex_buf: None,
-            color_buf: None,
+            stats_pipeline_layout,
+            pipeline_stats: None,
+            ui_scale: 1.0,
+            line_arena: FrameArena::new(
+                label::resource("debug", "lines"),
+                wgpu::BufferUsage::VERTEX,
+            ),
+            stats_arena: FrameArena::new(
+                label::resource("debug", "stats"),
+                wgpu::BufferUsage::VERTEX,
+            ),
         };
         result.reload(device);
         result
     }
 
+    pub fn settings(&self) -> &settings::DebugRender {
+        &self.settings
+    }
+
+    /// Toggle a debug layer by name, returns `false` if there is no such layer.
+    pub fn toggle(&mut self, layer: &str, device: &wgpu::Device) -> bool {
+        if self.settings.toggle(layer) {
+            self.reload(device);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Scale of the overlays, in pixels per logical unit.
+    pub fn set_ui_scale(&mut self, scale: f32) {
+        self.ui_scale = scale;
+    }
+
+    /// Re-create the pipelines for the target formats of the global context.
+    pub fn set_target_formats(&mut self, global: &GlobalContext, device: &wgpu::Device) {
+        self.color_format = global.color_format;
+        self.depth_format = global.depth_format;
+        self.reload(device);
+    }
+
     pub fn reload(&mut self, device: &wgpu::Device) {
         let rasterization_state = wgpu::RasterizationStateDescriptor {
             front_face: wgpu::FrontFace::Ccw,
@@ -219,7 +489,7 @@ impl Context {
         if self.settings.collision_shapes {
             let shaders = Shaders::new("debug_shape", &[], device).unwrap();
             let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("debug-shape"),
+                label: Some(&label::pipeline("debug", "shape")),
                 layout: Some(&self.pipeline_layout),
                 vertex_stage: wgpu::ProgrammableStageDescriptor {
                     module: &shaders.vs,
@@ -232,7 +502,7 @@ impl Context {
                 rasterization_state: Some(rasterization_state.clone()),
                 primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
                 color_states: &[wgpu::ColorStateDescriptor {
-                    format: COLOR_FORMAT,
+                    format: self.color_format,
                     alpha_blend: wgpu::BlendDescriptor {
                         src_factor: wgpu::BlendFactor::One,
                         dst_factor: wgpu::BlendFactor::One,
@@ -246,7 +516,7 @@ impl Context {
                     write_mask: wgpu::ColorWrite::all(),
                 }],
                 depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                    format: DEPTH_FORMAT,
+                    format: self.depth_format,
                     depth_write_enabled: false,
                     depth_compare: wgpu::CompareFunction::LessEqual,
                     stencil: Default::default(),
@@ -258,7 +528,7 @@ impl Context {
                         InstanceDesc::new().buffer_desc(),
                     ],
                 },
-                sample_count: 1,
+                sample_count: self.sample_count,
                 alpha_to_coverage_enabled: false,
                 sample_mask: !0,
             });
@@ -266,8 +536,66 @@ impl Context {
             self.pipeline_edge = None; //TODO: line raster
         }
 
+        self.pipeline_stats = None;
+        if self.settings.stats {
+            let shaders = Shaders::new("debug_stats", &[], device).unwrap();
+            let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(&label::pipeline("debug", "stats")),
+                layout: Some(&self.stats_pipeline_layout),
+                vertex_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &shaders.vs,
+                    entry_point: "main",
+                },
+                fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                    module: &shaders.fs,
+                    entry_point: "main",
+                }),
+                rasterization_state: Some(rasterization_state.clone()),
+                primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+                color_states: &[wgpu::ColorStateDescriptor {
+                    format: self.color_format,
+                    alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                    color_blend: wgpu::BlendDescriptor {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    write_mask: wgpu::ColorWrite::all(),
+                }],
+                // drawn on top of the final image, after the resolve and post-processing
+                depth_stencil_state: None,
+                vertex_state: wgpu::VertexStateDescriptor {
+                    index_format: wgpu::IndexFormat::Uint16,
+                    vertex_buffers: &[
+                        wgpu::VertexBufferDescriptor {
+                            stride: mem::size_of::<Position>() as wgpu::BufferAddress,
+                            step_mode: wgpu::InputStepMode::Vertex,
+                            attributes: &[wgpu::VertexAttributeDescriptor {
+                                offset: 0,
+                                format: wgpu::VertexFormat::Float4,
+                                shader_location: 0,
+                            }],
+                        },
+                        wgpu::VertexBufferDescriptor {
+                            stride: mem::size_of::<Color>() as wgpu::BufferAddress,
+                            step_mode: wgpu::InputStepMode::Vertex,
+                            attributes: &[wgpu::VertexAttributeDescriptor {
+                                offset: 0,
+                                format: wgpu::VertexFormat::Uchar4Norm,
+                                shader_location: 1,
+                            }],
+                        },
+                    ],
+                },
+                sample_count: 1,
+                alpha_to_coverage_enabled: false,
+                sample_mask: !0,
+            });
+            self.pipeline_stats = Some(pipeline);
+        }
+
         self.pipelines_line.clear();
-        if self.settings.impulses {
+        if self.settings.draws_lines() {
             let shaders = Shaders::new("debug", &[], device).unwrap();
             for &visibility in &[Visibility::Front, Visibility::Behind] {
                 let (blend, depth_write_enabled, depth_compare) = match visibility {
@@ -288,15 +616,15 @@ impl Context {
                             entry_point: "main",
                         }),
                         rasterization_state: Some(rasterization_state.clone()),
-                        primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+                        primitive_topology: wgpu::PrimitiveTopology::LineList,
                         color_states: &[wgpu::ColorStateDescriptor {
-                            format: COLOR_FORMAT,
+                            format: self.color_format,
                             alpha_blend: blend.clone(),
                             color_blend: blend.clone(),
                             write_mask: wgpu::ColorWrite::all(),
                         }],
                         depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                            format: DEPTH_FORMAT,
+                            format: self.depth_format,
                             depth_write_enabled,
                             depth_compare,
                             stencil: Default::default(),
@@ -324,7 +652,7 @@ impl Context {
                                 },
                             ],
                         },
-                        sample_count: 1,
+                        sample_count: self.sample_count,
                         alpha_to_coverage_enabled: false,
                         sample_mask: !0,
                     });
@@ -338,14 +666,14 @@ impl Context {
     fn draw_liner<'a>(
         &'a self,
         pass: &mut wgpu::RenderPass<'a>,
-        vertex_buf: &'a wgpu::Buffer,
-        color_buf: &'a wgpu::Buffer,
+        vertices: wgpu::BufferSlice<'a>,
+        colors: wgpu::BufferSlice<'a>,
         color_rate: wgpu::InputStepMode,
         num_vert: usize,
     ) {
         pass.set_blend_color(wgpu::Color::WHITE);
-        pass.set_vertex_buffer(0, vertex_buf.slice(..));
-        pass.set_vertex_buffer(1, color_buf.slice(..));
+        pass.set_vertex_buffer(0, vertices);
+        pass.set_vertex_buffer(1, colors);
         for &vis in &[Visibility::Front, Visibility::Behind] {
             if let Some(ref pipeline) = self.pipelines_line.get(&(vis, color_rate)) {
                 pass.set_pipeline(pipeline);
@@ -396,8 +724,8 @@ impl Context {
             pass.set_bind_group(1, &self.bind_group_line, &[]);
             self.draw_liner(
                 pass,
-                sample_buf,
-                &self.line_color_buf,
+                sample_buf.slice(..),
+                self.line_color_buf.slice(..),
                 wgpu::InputStepMode::Instance,
                 num_vert,
             );
@@ -408,30 +736,74 @@ impl Context {
         &'a mut self,
         pass: &mut wgpu::RenderPass<'a>,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         linebuf: &LineBuffer,
     ) {
-        self.vertex_buf = Some(
-            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("debug-vertices"),
-                contents: bytemuck::cast_slice(&linebuf.vertices),
-                usage: wgpu::BufferUsage::VERTEX,
-            }),
-        );
-        self.color_buf = Some(
-            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("debug-colors"),
-                contents: bytemuck::cast_slice(&linebuf.colors),
-                usage: wgpu::BufferUsage::VERTEX,
-            }),
-        );
         assert_eq!(linebuf.vertices.len(), linebuf.colors.len());
+        self.line_arena.reset();
+        let vertices = self.line_arena.push(&linebuf.vertices);
+        let colors = match self.settings.palette {
+            settings::DebugPalette::Default => self.line_arena.push(&linebuf.colors),
+            palette => {
+                let remapped = linebuf
+                    .colors
+                    .iter()
+                    .map(|c| Color {
+                        color: remap_color(palette, c.color),
+                    })
+                    .collect::<Vec<_>>();
+                self.line_arena.push(&remapped)
+            }
+        };
+        self.line_arena.upload(device, queue);
 
+        pass.set_bind_group(1, &self.bind_group_line, &[]);
         self.draw_liner(
             pass,
-            self.vertex_buf.as_ref().unwrap(),
-            self.color_buf.as_ref().unwrap(),
+            self.line_arena.slice(vertices),
+            self.line_arena.slice(colors),
             wgpu::InputStepMode::Vertex,
             linebuf.vertices.len(),
         );
     }
+
+    /// Draw the frame statistics graph, if the layer is enabled.
+    pub fn draw_stats<'a>(
+        &'a mut self,
+        pass: &mut wgpu::RenderPass<'a>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        stats: &Stats,
+        extent: wgpu::Extent3d,
+    ) {
+        if self.pipeline_stats.is_none() {
+            return;
+        }
+        // size of a logical pixel in the normalized device coordinates
+        let pixel = [
+            2.0 * self.ui_scale / extent.width.max(1) as f32,
+            2.0 * self.ui_scale / extent.height.max(1) as f32,
+        ];
+        let origin = [
+            STATS_GRAPH_MARGIN[0] * pixel[0] - 1.0,
+            STATS_GRAPH_MARGIN[1] * pixel[1] - 1.0,
+        ];
+        let size = [
+            STATS_GRAPH_SIZE[0] * pixel[0],
+            STATS_GRAPH_SIZE[1] * pixel[1],
+        ];
+        let (vertices, mut colors) = stats.graph(origin, size);
+        for color in colors.iter_mut() {
+            color.color = remap_color(self.settings.palette, color.color);
+        }
+        self.stats_arena.reset();
+        let vertex_range = self.stats_arena.push(&vertices);
+        let color_range = self.stats_arena.push(&colors);
+        self.stats_arena.upload(device, queue);
+
+        pass.set_pipeline(self.pipeline_stats.as_ref().unwrap());
+        pass.set_vertex_buffer(0, self.stats_arena.slice(vertex_range));
+        pass.set_vertex_buffer(1, self.stats_arena.slice(color_range));
+        pass.draw(0..vertices.len() as u32, 0..1);
+    }
 }
@@ -1,4 +1,8 @@
-use crate::{config::settings, space::Camera};
+use crate::{
+    config::settings,
+    render::{label, DEPTH_FORMAT},
+    space::{Camera, Projection},
+};
 use bytemuck::{Pod, Zeroable};
 use std::mem;
 
@@ -11,6 +15,9 @@ pub struct Constants {
     m_light_vp: [[f32; 4]; 4],
     light_pos: [f32; 4],
     light_color: [f32; 4],
+    camera_dir: [f32; 4],
+    m_inv_view: [[f32; 4]; 4],
+    m_inv_proj: [[f32; 4]; 4],
 }
 unsafe impl Pod for Constants {}
 unsafe impl Zeroable for Constants {}
@@ -20,21 +27,34 @@ impl Constants {
         use cgmath::SquareMatrix;
 
         let m_light_vp = shadow_cam
-            .map_or_else(cgmath::Matrix4::identity, |sc| sc.get_view_proj())
+            .map_or_else(cgmath::Matrix4::identity, |sc| sc.view_proj())
             .into();
-        let mx_vp = cam.get_view_proj();
         Constants {
             camera_pos: cam.loc.extend(1.0).into(),
-            m_vp: mx_vp.into(),
-            m_inv_vp: mx_vp.invert().unwrap().into(),
+            m_vp: cam.view_proj().into(),
+            m_inv_vp: cam.inverse_view_proj().into(),
             m_light_vp,
             light_pos: light.pos,
             light_color: light.color,
+            camera_dir: cam
+                .dir()
+                .extend(match cam.proj {
+                    Projection::Ortho { .. } => 0.0,
+                    Projection::Perspective(_) => 1.0,
+                })
+                .into(),
+            m_inv_view: cam.view_matrix().invert().unwrap().into(),
+            m_inv_proj: cam.proj_matrix().invert().unwrap().into(),
         }
     }
 }
 
 pub struct Context {
+    pub color_format: wgpu::TextureFormat,
+    /// Format of the depth targets, `DEPTH_FORMAT` unless drawing into external targets.
+    pub depth_format: wgpu::TextureFormat,
+    /// Number of samples of the main color and depth targets.
+    pub sample_count: u32,
     pub bind_group_layout: wgpu::BindGroupLayout,
     pub uniform_buf: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
@@ -45,11 +65,13 @@ impl Context {
     pub fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
         store_buffer: wgpu::BindingResource,
         shadow_view: Option<&wgpu::TextureView>,
     ) -> Self {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Global"),
+            label: Some(&label::resource("global", "uniforms")),
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
@@ -99,7 +121,7 @@ impl Context {
             ],
         });
         let uniform_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Uniform"),
+            label: Some(&label::resource("global", "uniforms")),
             size: mem::size_of::<Constants>() as wgpu::BufferAddress,
             usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
             mapped_at_creation: false,
@@ -130,7 +152,7 @@ impl Context {
                 depth: 1,
             };
             let texture = device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("DummyShadow"),
+                label: Some(&label::resource("global", "dummy shadow")),
                 size,
                 mip_level_count: 1,
                 sample_count: 1,
@@ -157,7 +179,7 @@ impl Context {
         };
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Global"),
+            label: Some(&label::resource("global", "uniforms")),
             layout: &bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -185,7 +207,7 @@ impl Context {
             ],
         });
         let shadow_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("GlobalShadow"),
+            label: Some(&label::resource("global", "shadow uniforms")),
             layout: &bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -212,6 +234,9 @@ impl Context {
         });
 
         Context {
+            color_format,
+            depth_format: DEPTH_FORMAT,
+            sample_count,
             bind_group_layout,
             uniform_buf,
             bind_group,
@@ -0,0 +1,200 @@
+//! GPU initialization: enumerating the adapters, picking one, and creating the device.
+//!
+//! The capabilities of the picked adapter are reported back,
+//! so that the techniques it can't run are replaced before anything is created.
+
+use crate::config::settings;
+
+use std::fmt;
+
+/// Description of an adapter, and what it's capable of.
+#[derive(Clone, Debug)]
+pub struct AdapterReport {
+    pub info: wgpu::AdapterInfo,
+    pub features: wgpu::Features,
+    pub limits: wgpu::Limits,
+}
+
+impl AdapterReport {
+    pub fn new(adapter: &wgpu::Adapter) -> Self {
+        AdapterReport {
+            info: adapter.get_info(),
+            features: adapter.features(),
+            limits: adapter.limits(),
+        }
+    }
+
+    /// Check if the terrain technique can run on this adapter.
+    pub fn supports(&self, terrain: &settings::Terrain) -> bool {
+        match *terrain {
+            // the points are written by a compute shader and resolved in the fragment one
+            settings::Terrain::Scattered { .. } => {
+                self.limits.max_storage_buffers_per_shader_stage != 0
+            }
+            _ => true,
+        }
+    }
+
+    /// Return the terrain technique to use instead of the given one,
+    /// falling back to the ray tracing, which runs everywhere.
+    pub fn fallback_terrain(&self, terrain: &settings::Terrain) -> settings::Terrain {
+        if self.supports(terrain) {
+            terrain.clone()
+        } else {
+            warn!(
+                "Terrain technique {} is not supported by {}, falling back to {}",
+                terrain.name(),
+                self.info.name,
+                settings::Terrain::RayTraced.name(),
+            );
+            settings::Terrain::RayTraced
+        }
+    }
+}
+
+impl fmt::Display for AdapterReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:?}, {:?}), storage buffers per stage: {}",
+            self.info.name,
+            self.info.backend,
+            self.info.device_type,
+            self.limits.max_storage_buffers_per_shader_stage,
+        )
+    }
+}
+
+/// List all the adapters of the backends, e.g. for the user to pick one.
+pub fn enumerate(instance: &wgpu::Instance, backends: wgpu::BackendBit) -> Vec<AdapterReport> {
+    instance
+        .enumerate_adapters(backends)
+        .map(|adapter| AdapterReport::new(&adapter))
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum InitError {
+    /// No adapter has the name containing the requested one.
+    AdapterNotFound(String),
+    /// Adapters with the requested name can't present to the surface.
+    AdapterIncompatible(String),
+    /// No adapter is compatible with the surface.
+    NoAdapter,
+    Device(wgpu::RequestDeviceError),
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InitError::AdapterNotFound(ref name) => write!(f, "no adapter matches '{}'", name),
+            InitError::AdapterIncompatible(ref name) => {
+                write!(f, "adapter '{}' can't present to the window", name)
+            }
+            InitError::NoAdapter => write!(f, "no compatible adapter is found"),
+            InitError::Device(ref e) => write!(f, "unable to create the device: {}", e),
+        }
+    }
+}
+
+pub struct Gpu {
+    pub adapter: wgpu::Adapter,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub report: AdapterReport,
+}
+
+/// Check if the adapter can present to the surface.
+///
+/// There is no direct query for it, so the adapter is compared
+/// against the ones picked for the surface with either power preference.
+async fn is_compatible(
+    instance: &wgpu::Instance,
+    adapter: &wgpu::Adapter,
+    surface: &wgpu::Surface,
+) -> bool {
+    let info = adapter.get_info();
+    for &power_preference in &[
+        wgpu::PowerPreference::LowPower,
+        wgpu::PowerPreference::HighPerformance,
+    ] {
+        let options = wgpu::RequestAdapterOptions {
+            power_preference,
+            compatible_surface: Some(surface),
+        };
+        if let Some(other) = instance.request_adapter(&options).await {
+            let other_info = other.get_info();
+            if other_info.backend == info.backend
+                && other_info.vendor == info.vendor
+                && other_info.device == info.device
+            {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Pick the adapter and create the device on it.
+///
+/// The adapter is the one with the name containing `settings.adapter`,
+/// or the one compatible with the `surface` if the name is empty.
+/// The device gets the limits of the adapter, which `AdapterReport::supports`
+/// checks the terrain techniques against.
+pub async fn init(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface>,
+    settings: &settings::Settings,
+) -> Result<Gpu, InitError> {
+    let adapter = if settings.adapter.is_empty() {
+        instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::Default,
+                compatible_surface: surface,
+            })
+            .await
+            .ok_or(InitError::NoAdapter)?
+    } else {
+        let candidates = instance
+            .enumerate_adapters(settings.backend.to_wgpu())
+            .filter(|adapter| adapter.get_info().name.contains(&settings.adapter))
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return Err(InitError::AdapterNotFound(settings.adapter.clone()));
+        }
+        let mut picked = None;
+        for adapter in candidates {
+            let compatible = match surface {
+                Some(surface) => is_compatible(instance, &adapter, surface).await,
+                None => true,
+            };
+            if compatible {
+                picked = Some(adapter);
+                break;
+            }
+        }
+        picked.ok_or_else(|| InitError::AdapterIncompatible(settings.adapter.clone()))?
+    };
+    let report = AdapterReport::new(&adapter);
+    info!("Picked adapter {}", report);
+
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                features: wgpu::Features::empty(),
+                // the defaults are above what some adapters have, e.g. for the storage buffers
+                limits: report.limits.clone(),
+                shader_validation: settings.validation.shaders,
+            },
+            None,
+        )
+        .await
+        .map_err(InitError::Device)?;
+
+    Ok(Gpu {
+        adapter,
+        device,
+        queue,
+        report,
+    })
+}
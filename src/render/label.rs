@@ -0,0 +1,33 @@
+//! Naming convention of the GPU objects and the debug groups,
+//! which is what the graphics debuggers show in the captures:
+//!   - pipelines and their layouts: lowercase and dash-separated, `terrain-scatter`;
+//!   - resources and bind groups: capitalized words, `Terrain height`;
+//!   - buffers created for a single frame: prefixed with `temp-`;
+//!   - debug groups of the passes: lowercase words, `terrain scatter`.
+//!
+//! Every pass is wrapped into a debug group, named by the system recording it
+//! and the stage, and the draws of different systems within a pass get nested groups.
+
+/// Name of a pipeline or a pipeline layout.
+pub fn pipeline(system: &str, stage: &str) -> String {
+    format!("{}-{}", system, stage)
+}
+
+/// Name of a long-living resource or a bind group.
+pub fn resource(system: &str, purpose: &str) -> String {
+    let mut chars = system.chars();
+    match chars.next() {
+        Some(first) => format!("{}{} {}", first.to_uppercase(), chars.as_str(), purpose),
+        None => purpose.to_string(),
+    }
+}
+
+/// Name of a buffer that only lives for a frame.
+pub fn temp(purpose: &str) -> String {
+    format!("temp-{}", purpose)
+}
+
+/// Name of a debug group around a pass, or a part of it.
+pub fn group(system: &str, stage: &str) -> String {
+    format!("{} {}", system, stage)
+}
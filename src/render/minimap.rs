@@ -0,0 +1,146 @@
+//! Top-down map of the level, rendered into a texture for the HUD.
+//!
+//! The colors are evaluated by the terrain shaders, so the map follows
+//! the palette, the terrain table, and the color provider of the level.
+//! It's only re-rendered when the level data changes.
+
+use crate::render::{global::Context as GlobalContext, label, resource, terrain, Shaders};
+
+pub struct Minimap {
+    texture: wgpu::Texture,
+    /// View of the map texture, for composing into the HUD.
+    pub view: wgpu::TextureView,
+    pub size: u32,
+    format: wgpu::TextureFormat,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    // terrain revision the map was rendered at
+    drawn_revision: Option<usize>,
+}
+
+impl Minimap {
+    fn create_pipeline(
+        layout: &wgpu::PipelineLayout,
+        format: wgpu::TextureFormat,
+        device: &wgpu::Device,
+    ) -> wgpu::RenderPipeline {
+        let shaders = Shaders::new("minimap", &[], device).unwrap();
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&label::pipeline("minimap", "draw")),
+            layout: Some(layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &shaders.vs,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &shaders.fs,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                ..Default::default()
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[format.into()],
+            depth_stencil_state: None,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[],
+            },
+            sample_count: 1,
+            alpha_to_coverage_enabled: false,
+            sample_mask: !0,
+        })
+    }
+
+    pub fn new(
+        size: u32,
+        global: &GlobalContext,
+        terrain: &terrain::Context,
+        device: &wgpu::Device,
+    ) -> Self {
+        let format = global.color_format;
+        let texture = resource::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some(&label::resource("minimap", "texture")),
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+            },
+        );
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&label::pipeline("minimap", "draw")),
+            bind_group_layouts: &[&global.bind_group_layout, &terrain.bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(&pipeline_layout, format, device);
+
+        Minimap {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture,
+            size,
+            format,
+            pipeline_layout,
+            pipeline,
+            drawn_revision: None,
+        }
+    }
+
+    pub fn destroy(self) {
+        resource::destroy_texture(self.texture);
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(&self.pipeline_layout, self.format, device);
+        self.drawn_revision = None;
+    }
+
+    /// Force the map to be re-rendered, e.g. when the terrain context is replaced.
+    pub fn invalidate(&mut self) {
+        self.drawn_revision = None;
+    }
+
+    /// Check if the map is behind the level data.
+    pub fn is_outdated(&self, terrain: &terrain::Context) -> bool {
+        self.drawn_revision != Some(terrain.revision())
+    }
+
+    /// Re-render the map if the level data has changed since the last time.
+    pub fn update(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        global: &GlobalContext,
+        terrain: &terrain::Context,
+    ) {
+        if !self.is_outdated(terrain) {
+            return;
+        }
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: &self.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        pass.push_debug_group(&label::group("minimap", "draw"));
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &global.bind_group, &[]);
+        pass.set_bind_group(1, &terrain.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        pass.pop_debug_group();
+        self.drawn_revision = Some(terrain.revision());
+    }
+}
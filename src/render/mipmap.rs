@@ -1,7 +1,4 @@
-use crate::render::{
-    terrain::{Rect, HEIGHT_FORMAT},
-    Shaders,
-};
+use crate::render::{label, terrain::Rect, Shaders};
 use bytemuck::{Pod, Zeroable};
 use std::{mem, num::NonZeroU32};
 use wgpu::util::DeviceExt as _;
@@ -14,6 +11,11 @@ struct Vertex {
 unsafe impl Pod for Vertex {}
 unsafe impl Zeroable for Vertex {}
 
+/// Number of mip levels down to a single texel on the smaller side.
+pub fn full_mip_count(size: wgpu::Extent3d) -> u32 {
+    32 - size.width.min(size.height).leading_zeros()
+}
+
 struct Mip {
     view: wgpu::TextureView,
     bind_group: wgpu::BindGroup,
@@ -21,6 +23,7 @@ struct Mip {
 
 pub struct MaxMipper {
     size: wgpu::Extent3d,
+    format: wgpu::TextureFormat,
     pipeline_layout: wgpu::PipelineLayout,
     pipeline: wgpu::RenderPipeline,
     //data: terrain_mip::Data<R>,
@@ -30,11 +33,12 @@ pub struct MaxMipper {
 impl MaxMipper {
     fn create_pipeline(
         layout: &wgpu::PipelineLayout,
+        format: wgpu::TextureFormat,
         device: &wgpu::Device,
     ) -> wgpu::RenderPipeline {
         let shaders = Shaders::new("terrain/mip", &[], device).unwrap();
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("mipmap"),
+            label: Some(&label::pipeline("mipmap", "downsample")),
             layout: Some(layout),
             vertex_stage: wgpu::ProgrammableStageDescriptor {
                 module: &shaders.vs,
@@ -50,7 +54,7 @@ impl MaxMipper {
                 ..Default::default()
             }),
             primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[HEIGHT_FORMAT.into()],
+            color_states: &[format.into()],
             depth_stencil_state: None,
             vertex_state: wgpu::VertexStateDescriptor {
                 index_format: wgpu::IndexFormat::Uint16,
@@ -73,11 +77,12 @@ impl MaxMipper {
     pub fn new(
         texture: &wgpu::Texture,
         size: wgpu::Extent3d,
+        format: wgpu::TextureFormat,
         mip_count: u32,
         device: &wgpu::Device,
     ) -> Self {
         let bg_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("MaxMipper"),
+            label: Some(&label::resource("mipmap", "downsample")),
             entries: &[
                 // sampler
                 wgpu::BindGroupLayoutEntry {
@@ -100,7 +105,7 @@ impl MaxMipper {
             ],
         });
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("mipmap"),
+            label: Some(&label::pipeline("mipmap", "downsample")),
             bind_group_layouts: &[&bg_layout],
             push_constant_ranges: &[],
         });
@@ -117,7 +122,7 @@ impl MaxMipper {
         let mut mips = Vec::with_capacity(mip_count as usize);
         for level in 0..mip_count {
             let view = texture.create_view(&wgpu::TextureViewDescriptor {
-                label: None,
+                label: Some(&label::resource("mipmap", "level")),
                 format: None,
                 dimension: None,
                 aspect: wgpu::TextureAspect::All,
@@ -128,7 +133,7 @@ impl MaxMipper {
             });
 
             let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("MaxMipper"),
+                label: Some(&label::resource("mipmap", "downsample")),
                 layout: &bg_layout,
                 entries: &[
                     wgpu::BindGroupEntry {
@@ -145,10 +150,11 @@ impl MaxMipper {
             mips.push(Mip { view, bind_group });
         }
 
-        let pipeline = Self::create_pipeline(&pipeline_layout, device);
+        let pipeline = Self::create_pipeline(&pipeline_layout, format, device);
 
         MaxMipper {
             size,
+            format,
             pipeline_layout,
             pipeline,
             mips,
@@ -181,7 +187,7 @@ impl MaxMipper {
             }
         }
         let vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("mipmap-vertex"),
+            label: Some(&label::resource("mipmap", "vertices")),
             contents: bytemuck::cast_slice(&vertex_data),
             usage: wgpu::BufferUsage::VERTEX,
         });
@@ -198,14 +204,16 @@ impl MaxMipper {
                 }],
                 depth_stencil_attachment: None,
             });
+            pass.push_debug_group(&label::group("mipmap", "downsample"));
             pass.set_pipeline(&self.pipeline);
             pass.set_bind_group(0, &self.mips[mip].bind_group, &[]);
             pass.set_vertex_buffer(0, vertex_buf.slice(..));
             pass.draw(0..rects.len() as u32 * 6, 0..1);
+            pass.pop_debug_group();
         }
     }
 
     pub fn reload(&mut self, device: &wgpu::Device) {
-        self.pipeline = Self::create_pipeline(&self.pipeline_layout, device);
+        self.pipeline = Self::create_pipeline(&self.pipeline_layout, self.format, device);
     }
 }
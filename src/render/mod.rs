@@ -13,23 +13,61 @@ use std::{
     fs::File,
     io::{BufReader, Error as IoError, Read, Write},
     mem,
+    ops::Range,
     path::PathBuf,
     sync::Arc,
+    time::Instant,
 };
 
+pub mod arena;
 pub mod body;
+pub mod capture;
 pub mod collision;
+pub mod contact;
+pub mod custom;
 pub mod debug;
 pub mod global;
+pub mod init;
+pub mod label;
+pub mod loading;
+pub mod minimap;
 pub mod mipmap;
 pub mod object;
+pub mod post;
+pub mod probe;
+pub mod raycast;
+pub mod readback;
+pub mod resource;
 mod shadow;
+pub mod stats;
 pub mod terrain;
+pub mod upload;
+pub mod weather;
 
 pub use shadow::FORMAT as SHADOW_FORMAT;
-pub const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8Unorm;
+pub const DEFAULT_COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8Unorm;
 pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
+/// Resolve the color format of the swap chain, given the user preference
+/// and the format `preferred` by the surface.
+pub fn select_color_format(
+    preference: settings::ColorFormat,
+    preferred: wgpu::TextureFormat,
+) -> wgpu::TextureFormat {
+    use wgpu::TextureFormat as Tf;
+    match preference.to_wgpu() {
+        Some(format) => format,
+        // the shaders output gamma-encoded colors, so the sRGB formats are
+        // replaced by their plain versions, and the linear ones are not used
+        None => match preferred {
+            Tf::Bgra8UnormSrgb => Tf::Bgra8Unorm,
+            Tf::Rgba8UnormSrgb => Tf::Rgba8Unorm,
+            Tf::Bgra8Unorm | Tf::Rgba8Unorm | Tf::Rgb10a2Unorm => preferred,
+            _ => DEFAULT_COLOR_FORMAT,
+        },
+    }
+}
+
 pub struct GpuTransform {
     pub pos_scale: [f32; 4],
     pub orientation: [f32; 4],
@@ -44,6 +82,16 @@ impl GpuTransform {
     }
 }
 
+/// Formats of the color and depth targets the world is drawn into.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TargetFormats {
+    pub color: wgpu::TextureFormat,
+    pub depth: wgpu::TextureFormat,
+}
+
+/// Views of the targets to draw into, which formats have to match
+/// `Render::target_formats`.
+#[derive(Clone, Copy)]
 pub struct ScreenTargets<'a> {
     pub extent: wgpu::Extent3d,
     pub color: &'a wgpu::TextureView,
@@ -107,6 +155,17 @@ impl Shaders {
         name: &str,
         specialization: &[&str],
         device: &wgpu::Device,
+    ) -> Result<Self, IoError> {
+        Self::new_with_defines(name, specialization, &[], device)
+    }
+
+    /// Like `new`, but also defines the given values ahead of the code,
+    /// overriding the defaults that the shader guards with `#ifndef`.
+    pub fn new_with_defines(
+        name: &str,
+        specialization: &[&str],
+        defines: &[(String, String)],
+        device: &wgpu::Device,
     ) -> Result<Self, IoError> {
         let base_path = PathBuf::from("res").join("shader");
         let path = base_path.join(name).with_extension("glsl");
@@ -116,6 +175,10 @@ impl Shaders {
 
         let mut buf_vs = b"#version 450\n#define SHADER_VS\n".to_vec();
         let mut buf_fs = b"#version 450\n#define SHADER_FS\n".to_vec();
+        for &(ref define, ref value) in defines {
+            write!(buf_vs, "#define {} {}\n", define, value)?;
+            write!(buf_fs, "#define {} {}\n", define, value)?;
+        }
 
         let mut code = String::new();
         BufReader::new(File::open(&path)?).read_to_string(&mut code)?;
@@ -252,20 +315,59 @@ impl Shaders {
     }
 }
 
+/// Fraction of the full daylight at the given hour of the day,
+/// from 0 at midnight to 1 at noon.
+pub fn daylight(hours: f32) -> f32 {
+    let angle = hours / 24.0 * 2.0 * std::f32::consts::PI;
+    0.5 - 0.5 * angle.cos()
+}
+
+/// Mix the palettes, going from `night` at 0 to `day` at 1,
+/// e.g. with the `daylight` factor.
+pub fn blend_palettes(
+    night: &[[u8; 4]; 0x100],
+    day: &[[u8; 4]; 0x100],
+    factor: f32,
+) -> [[u8; 4]; 0x100] {
+    let t = factor.max(0.0).min(1.0);
+    let mut result = [[0; 4]; 0x100];
+    for (out, (a, b)) in result.iter_mut().zip(night.iter().zip(day.iter())) {
+        for ((o, &x), &y) in out.iter_mut().zip(a.iter()).zip(b.iter()) {
+            *o = (x as f32 * (1.0 - t) + y as f32 * t).round() as u8;
+        }
+    }
+    result
+}
+
+/// Colors at the night and at the day, blended by the time of the day.
+pub struct PaletteCycle<'a> {
+    pub night: &'a [[u8; 4]; 0x100],
+    pub day: &'a [[u8; 4]; 0x100],
+}
+
+impl PaletteCycle<'_> {
+    /// Colors at the given hour of the day.
+    pub fn at(&self, hours: f32) -> [[u8; 4]; 0x100] {
+        blend_palettes(self.night, self.day, daylight(hours))
+    }
+}
+
 pub struct Palette {
+    texture: wgpu::Texture,
     pub view: wgpu::TextureView,
 }
 
 impl Palette {
+    const EXTENT: wgpu::Extent3d = wgpu::Extent3d {
+        width: 0x100,
+        height: 1,
+        depth: 1,
+    };
+
     pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, data: &[[u8; 4]]) -> Self {
-        let extent = wgpu::Extent3d {
-            width: 0x100,
-            height: 1,
-            depth: 1,
-        };
         let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Palette"),
-            size: extent,
+            label: Some(&label::resource("palette", "texture")),
+            size: Self::EXTENT,
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D1,
@@ -285,25 +387,68 @@ impl Palette {
                 bytes_per_row: 0x100 * 4,
                 rows_per_image: 0,
             },
-            extent,
+            Self::EXTENT,
         );
 
         Palette {
             view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture,
         }
     }
+
+    /// Replace the colors in place, keeping the bind groups that use the palette valid.
+    pub fn update(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        data: &[[u8; 4]; 0x100],
+    ) {
+        let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&label::temp("palette")),
+            contents: bytemuck::cast_slice(data),
+            usage: wgpu::BufferUsage::COPY_SRC,
+        });
+        encoder.copy_buffer_to_texture(
+            wgpu::BufferCopyView {
+                buffer: &staging,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: 0x100 * 4,
+                    rows_per_image: 0,
+                },
+            },
+            wgpu::TextureCopyView {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            Self::EXTENT,
+        );
+    }
 }
 
 struct InstanceArray {
     data: Vec<object::Instance>,
-    // holding the mesh alive, while the key is just a raw pointer
+    // any of the meshes sharing this range of the buffers
     mesh: Arc<model::Mesh>,
-    // actual hardware buffer for this data
-    buffer: Option<wgpu::Buffer>,
+    // range of the pack instance buffer
+    range: Range<u32>,
+}
+
+/// Instances of the meshes sharing the same buffers.
+struct InstancePack {
+    // holding the buffers alive, while the key is just a raw pointer
+    buffers: Arc<model::MeshBuffers>,
+    // keyed by the first vertex and index, so that the identical meshes are drawn together
+    arrays: HashMap<(i32, u32), InstanceArray>,
+    // range of the instances of all the arrays in the arena
+    range: Range<usize>,
 }
 
 pub struct Batcher {
-    instances: HashMap<*const model::Mesh, InstanceArray>,
+    packs: HashMap<*const model::MeshBuffers, InstancePack>,
+    // instances of all the packs, uploaded into a buffer reused between frames
+    arena: arena::FrameArena,
     debug_shapes: Vec<Arc<model::Shape>>,
     debug_instances: Vec<object::Instance>,
 }
@@ -311,19 +456,30 @@ pub struct Batcher {
 impl Batcher {
     pub fn new() -> Self {
         Batcher {
-            instances: HashMap::new(),
+            packs: HashMap::new(),
+            arena: arena::FrameArena::new(
+                label::resource("batch", "instances"),
+                wgpu::BufferUsage::VERTEX,
+            ),
             debug_shapes: Vec::new(),
             debug_instances: Vec::new(),
         }
     }
 
     pub fn add_mesh(&mut self, mesh: &Arc<model::Mesh>, instance: object::Instance) {
-        self.instances
-            .entry(&**mesh)
+        self.packs
+            .entry(&*mesh.buffers)
+            .or_insert_with(|| InstancePack {
+                buffers: Arc::clone(&mesh.buffers),
+                arrays: HashMap::new(),
+                range: 0..0,
+            })
+            .arrays
+            .entry((mesh.base_vertex, mesh.first_index))
             .or_insert_with(|| InstanceArray {
                 data: Vec::new(),
                 mesh: Arc::clone(mesh),
-                buffer: None,
+                range: 0..0,
             })
             .data
             .push(instance);
@@ -337,8 +493,6 @@ impl Batcher {
         gpu_body: &body::GpuBody,
         color: object::BodyColor,
     ) {
-        use cgmath::{One as _, Rotation3 as _, Transform as _};
-
         // body
         self.add_mesh(
             &model.body,
@@ -354,77 +508,152 @@ impl Batcher {
             ));
         }
 
-        // wheels
-        for w in model.wheels.iter() {
-            if let Some(ref mesh) = w.mesh {
-                let transform = base_transform.concat(&Transform {
-                    disp: mesh.offset.into(),
-                    rot: cgmath::Quaternion::one(),
-                    scale: 1.0,
-                });
-                self.add_mesh(
-                    mesh,
-                    object::Instance::new(&transform, 0.0, gpu_body, color),
-                );
+        // wheels and slots, the debris are only shown when detached
+        for part in model::parts(model) {
+            if let model::Part::Wheel(_) | model::Part::Slot(_) = part {
+                self.add_part(model, part, base_transform, gpu_body, color);
             }
         }
+    }
 
-        // slots
-        for s in model.slots.iter() {
-            if let Some(ref mesh) = s.mesh {
-                let mut local = Transform {
-                    disp: cgmath::vec3(s.pos[0] as f32, s.pos[1] as f32, s.pos[2] as f32),
-                    rot: cgmath::Quaternion::from_angle_y(cgmath::Deg(s.angle as f32)),
-                    scale: s.scale / base_transform.scale,
-                };
-                local.disp -= local.transform_vector(cgmath::Vector3::from(mesh.offset));
-                let transform = base_transform.concat(&local);
-                self.add_mesh(
-                    mesh,
-                    object::Instance::new(&transform, 0.0, gpu_body, color),
-                );
-            }
+    /// Add a single part of the model, e.g. a detached debrie,
+    /// or a wheel with an overridden transform.
+    pub fn add_part(
+        &mut self,
+        model: &model::VisualModel,
+        part: model::Part,
+        base_transform: &Transform,
+        gpu_body: &body::GpuBody,
+        color: object::BodyColor,
+    ) {
+        let mesh = match model::part_mesh(model, part) {
+            Some(mesh) => mesh,
+            None => return,
+        };
+        if let Some(transform) = model::part_transform(model, part, base_transform) {
+            self.add_mesh(
+                mesh,
+                object::Instance::new(&transform, 0.0, gpu_body, color),
+            );
         }
     }
 
-    pub fn prepare(&mut self, device: &wgpu::Device) {
-        for array in self.instances.values_mut() {
-            if !array.data.is_empty() {
-                array.buffer = Some(
-                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: Some("instance"),
-                        contents: bytemuck::cast_slice(&array.data),
-                        usage: wgpu::BufferUsage::VERTEX,
-                    }),
-                );
+    /// Upload the instances of all the packs into a single buffer.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let stride = mem::size_of::<object::Instance>();
+        self.arena.reset();
+        for pack in self.packs.values_mut() {
+            let pack_start = self.arena.len();
+            for array in pack.arrays.values_mut() {
+                let bytes = self.arena.push(&array.data);
+                array.range = ((bytes.start - pack_start) / stride) as u32
+                    ..((bytes.end - pack_start) / stride) as u32;
             }
+            pack.range = pack_start..self.arena.len();
         }
+        self.arena.upload(device, queue);
     }
 
     pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
-        for array in self.instances.values() {
-            if array.data.is_empty() {
+        for pack in self.packs.values() {
+            if pack.range.start == pack.range.end {
                 continue;
             }
-            pass.set_vertex_buffer(0, array.mesh.vertex_buf.slice(..));
-            pass.set_vertex_buffer(1, array.buffer.as_ref().unwrap().slice(..));
-            pass.draw(
-                0..array.mesh.num_vertices as u32,
-                0..array.data.len() as u32,
-            );
+            pass.set_index_buffer(pack.buffers.index_buf.slice(..));
+            pass.set_vertex_buffer(0, pack.buffers.vertex_buf.slice(..));
+            pass.set_vertex_buffer(1, self.arena.slice(pack.range.clone()));
+            for array in pack.arrays.values() {
+                if array.data.is_empty() {
+                    continue;
+                }
+                let mesh = &array.mesh;
+                pass.draw_indexed(
+                    mesh.first_index..mesh.first_index + mesh.num_indices as u32,
+                    mesh.base_vertex,
+                    array.range.clone(),
+                );
+            }
         }
     }
 
     pub fn clear(&mut self) {
-        for array in self.instances.values_mut() {
-            array.data.clear();
-            array.buffer = None;
+        for pack in self.packs.values_mut() {
+            for array in pack.arrays.values_mut() {
+                array.data.clear();
+            }
+            pack.range = 0..0;
         }
         self.debug_shapes.clear();
         self.debug_instances.clear();
     }
 }
 
+/// Multisampled targets of the world passes, resolved into the screen color.
+struct Multisample {
+    color: (wgpu::Texture, wgpu::TextureView),
+    depth: (wgpu::Texture, wgpu::TextureView),
+}
+
+impl Multisample {
+    fn new(
+        extent: wgpu::Extent3d,
+        formats: TargetFormats,
+        sample_count: u32,
+        device: &wgpu::Device,
+    ) -> Self {
+        let create = |purpose, format| {
+            let texture = resource::create_texture(
+                device,
+                &wgpu::TextureDescriptor {
+                    label: Some(&label::resource("multisample", purpose)),
+                    size: extent,
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    // the depth is sampled by the contact shadows
+                    usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+                },
+            );
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            (texture, view)
+        };
+        Multisample {
+            color: create("color", formats.color),
+            depth: create("depth", formats.depth),
+        }
+    }
+
+    fn destroy(self) {
+        resource::destroy_texture(self.color.0);
+        resource::destroy_texture(self.depth.0);
+    }
+}
+
+/// Color and depth targets of the world passes, and the target
+/// to resolve the color into if they are multisampled.
+fn world_targets<'a>(
+    msaa: Option<&'a Multisample>,
+    post: Option<&'a post::Context>,
+    targets: ScreenTargets<'a>,
+) -> (ScreenTargets<'a>, Option<&'a wgpu::TextureView>) {
+    let color = match post {
+        Some(post) => post.input(),
+        None => targets.color,
+    };
+    match msaa {
+        Some(msaa) => (
+            ScreenTargets {
+                color: &msaa.color.1,
+                depth: &msaa.depth.1,
+                ..targets
+            },
+            Some(color),
+        ),
+        None => (ScreenTargets { color, ..targets }, None),
+    }
+}
+
 pub struct PipelineSet {
     main: wgpu::RenderPipeline,
     shadow: wgpu::RenderPipeline,
@@ -449,9 +678,16 @@ pub struct Render {
     pub object: object::Context,
     pub terrain: terrain::Context,
     pub debug: debug::Context,
+    pub stats: debug::Stats,
     pub shadow: Option<shadow::Shadow>,
+    pub minimap: Option<minimap::Minimap>,
+    post: Option<post::Context>,
+    contact_shadow: Option<contact::ContactShadow>,
+    msaa: Option<Multisample>,
     pub light_config: settings::Light,
     pub fog_config: settings::Fog,
+    /// Amount of the rain water on the terrain, from 0 to 1.
+    pub wetness: f32,
     screen_size: wgpu::Extent3d,
 }
 
@@ -463,6 +699,7 @@ impl Render {
         object_palette: &[[u8; 4]],
         settings: &settings::Render,
         screen_size: wgpu::Extent3d,
+        color_format: wgpu::TextureFormat,
         store_buffer: wgpu::BindingResource,
     ) -> Self {
         let shadow = if settings.light.shadow.size != 0 {
@@ -471,36 +708,181 @@ impl Render {
             None
         };
 
+        let sample_count = settings.msaa.max(1);
+        let msaa = if sample_count > 1 {
+            Some(Multisample::new(
+                screen_size,
+                TargetFormats {
+                    color: color_format,
+                    depth: DEPTH_FORMAT,
+                },
+                sample_count,
+                device,
+            ))
+        } else {
+            None
+        };
+
         let global = global::Context::new(
             device,
             queue,
+            color_format,
+            sample_count,
             store_buffer,
             shadow.as_ref().map(|shadow| &shadow.view),
         );
-        let object = object::Context::new(device, queue, object_palette, &global);
-        let terrain = terrain::Context::new(
-            device,
-            queue,
-            level,
-            &global,
-            &settings.terrain,
-            &settings.light.shadow.terrain,
+        let mut object = object::Context::new(device, queue, object_palette, &global);
+        if let Some(ref lp) = settings.light_probes {
+            object.set_light_probes(
+                Self::create_light_probes(device, queue, level, lp, &settings.fog),
+                device,
+            );
+        }
+        let terrain = Self::create_terrain(device, queue, level, &global, settings, screen_size);
+        let debug = debug::Context::new(device, &settings.debug, &global, &object);
+        let minimap = if settings.minimap_size != 0 {
+            Some(minimap::Minimap::new(
+                settings.minimap_size,
+                &global,
+                &terrain,
+                device,
+            ))
+        } else {
+            None
+        };
+        let contact_shadow = settings
+            .contact_shadow
+            .as_ref()
+            .map(|cs| contact::ContactShadow::new(cs, &global, device));
+        let post = post::Context::new(
+            &settings.post,
+            Palette::new(device, queue, &level.palette),
+            color_format,
             screen_size,
+            device,
         );
-        let debug = debug::Context::new(device, &settings.debug, &global, &object);
 
         Render {
             global,
             object,
             terrain,
             debug,
+            stats: debug::Stats::new(),
             shadow,
+            minimap,
+            post,
+            contact_shadow,
+            msaa,
             light_config: settings.light.clone(),
             fog_config: settings.fog.clone(),
+            wetness: 0.0,
             screen_size,
         }
     }
 
+    fn create_terrain(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        level: &level::Level,
+        global: &global::Context,
+        settings: &settings::Render,
+        screen_size: wgpu::Extent3d,
+    ) -> terrain::Context {
+        let mut upload =
+            upload::UploadQueue::new(queue, (settings.upload_chunk_megabytes as usize) << 20);
+        let mut terrain = terrain::Context::new(
+            device,
+            &mut upload,
+            level,
+            global,
+            &settings.terrain,
+            &settings.light.shadow.terrain,
+            settings.terrain_filter,
+            settings.terrain_height,
+            settings.world_seam,
+            &settings.terrain_color,
+            settings.terrain_detail.as_ref(),
+            &settings.terrain_defines,
+            screen_size,
+        );
+        terrain.set_debug_palette(settings.debug.palette);
+        if upload.submissions != 0 {
+            info!(
+                "Terrain is uploaded in {} submissions",
+                upload.submissions + 1
+            );
+        }
+        terrain
+    }
+
+    fn create_light_probes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        level: &level::Level,
+        settings: &settings::LightProbes,
+        fog: &settings::Fog,
+    ) -> probe::LightProbes {
+        let grid =
+            probe::ProbeGrid::bake(level, settings.cell_size, fog.color, settings.sky_weight);
+        probe::LightProbes::new(device, queue, &grid, settings)
+    }
+
+    /// Replace the terrain and the palette with the ones of another level.
+    pub fn set_level(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        level: &level::Level,
+        settings: &settings::Render,
+    ) {
+        let terrain = Self::create_terrain(
+            device,
+            queue,
+            level,
+            &self.global,
+            settings,
+            self.screen_size,
+        );
+        mem::replace(&mut self.terrain, terrain).destroy();
+        if let Some(ref lp) = settings.light_probes {
+            let light_probes = Self::create_light_probes(device, queue, level, lp, &settings.fog);
+            self.object.set_light_probes(light_probes, device);
+        }
+        if let Some(ref mut minimap) = self.minimap {
+            minimap.invalidate();
+        }
+        if let Some(ref mut post) = self.post {
+            let palette = Palette::new(device, queue, &level.palette);
+            post.set_palette(palette, self.screen_size, device);
+        }
+    }
+
+    /// Shift the colors of the terrain and the dithering by the `level` cycle,
+    /// and the ones of the objects by the `objects` cycle, to the given hour of the day.
+    /// The palettes are updated in place, so nothing else needs to be re-created.
+    pub fn set_time_of_day(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        hours: f32,
+        level: &PaletteCycle,
+        objects: &PaletteCycle,
+    ) {
+        let level_colors = level.at(hours);
+        self.terrain.palette.update(encoder, device, &level_colors);
+        if let Some(ref post) = self.post {
+            post.update_palette(encoder, device, &level_colors);
+        }
+        self.object
+            .palette
+            .update(encoder, device, &objects.at(hours));
+    }
+
+    /// Global uniforms and bind group layout, shared with the custom passes.
+    pub fn global(&self) -> &global::Context {
+        &self.global
+    }
+
     pub fn draw_world(
         &mut self,
         encoder: &mut wgpu::CommandEncoder,
@@ -508,8 +890,40 @@ impl Render {
         cam: &Camera,
         targets: ScreenTargets,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
     ) {
-        batcher.prepare(device);
+        let lines = debug::LineBuffer::new();
+        self.draw_world_with(
+            encoder,
+            batcher,
+            cam,
+            targets,
+            device,
+            queue,
+            &lines,
+            &mut [],
+        );
+    }
+
+    /// Draw the world together with the debug `lines`, which are tested
+    /// against its depth, recording the custom passes at their stages.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_world_with(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        batcher: &mut Batcher,
+        cam: &Camera,
+        targets: ScreenTargets,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lines: &debug::LineBuffer,
+        passes: &mut [&mut dyn custom::CustomPass],
+    ) {
+        batcher.prepare(device, queue);
+        if let Some(ref mut minimap) = self.minimap {
+            minimap.update(encoder, &self.global, &self.terrain);
+        }
+        let mut pass_start = Instant::now();
         //TODO: common routine for draw passes
         //TODO: use `write_buffer`
 
@@ -518,7 +932,7 @@ impl Render {
 
             let constants = global::Constants::new(&shadow.cam, &self.light_config, None);
             let global_staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("temp-global-shadow"),
+                label: Some(&label::temp("global-shadow")),
                 contents: bytemuck::bytes_of(&constants),
                 usage: wgpu::BufferUsage::COPY_SRC,
             });
@@ -535,6 +949,7 @@ impl Render {
                 device,
                 &self.global,
                 &self.fog_config,
+                self.wetness,
                 cam,
                 wgpu::Extent3d {
                     width: shadow.size,
@@ -555,6 +970,7 @@ impl Render {
                 }),
             });
 
+            pass.push_debug_group(&label::group("world", "shadow"));
             pass.set_bind_group(0, &self.global.shadow_bind_group, &[]);
             self.terrain.draw_shadow(&mut pass);
 
@@ -562,6 +978,10 @@ impl Render {
             pass.set_pipeline(&self.object.pipelines.shadow);
             pass.set_bind_group(1, &self.object.bind_group, &[]);
             batcher.draw(&mut pass);
+            pass.pop_debug_group();
+            drop(pass);
+            self.stats.record_pass("shadow", pass_start.elapsed());
+            pass_start = Instant::now();
         }
         // main pass
         {
@@ -571,7 +991,7 @@ impl Render {
                 self.shadow.as_ref().map(|shadow| &shadow.cam),
             );
             let global_staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("temp-global"),
+                label: Some(&label::temp("global")),
                 contents: bytemuck::bytes_of(&constants),
                 usage: wgpu::BufferUsage::COPY_SRC,
             });
@@ -588,14 +1008,17 @@ impl Render {
                 device,
                 &self.global,
                 &self.fog_config,
+                self.wetness,
                 cam,
                 self.screen_size,
             );
 
+            let (world, resolve_target) =
+                world_targets(self.msaa.as_ref(), self.post.as_ref(), targets);
             let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: targets.color,
-                    resolve_target: None,
+                    attachment: world.color,
+                    resolve_target,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear({
                             let c = self.fog_config.color;
@@ -610,7 +1033,7 @@ impl Render {
                     },
                 }],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
-                    attachment: targets.depth,
+                    attachment: world.depth,
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
                         store: true,
@@ -619,13 +1042,156 @@ impl Render {
                 }),
             });
 
+            pass.push_debug_group(&label::group("world", "terrain"));
             pass.set_bind_group(0, &self.global.bind_group, &[]);
             self.terrain.draw(&mut pass);
+            pass.pop_debug_group();
 
             // draw vehicle models
+            pass.push_debug_group(&label::group("world", "objects"));
             pass.set_pipeline(&self.object.pipelines.main);
             pass.set_bind_group(1, &self.object.bind_group, &[]);
             batcher.draw(&mut pass);
+            pass.pop_debug_group();
+
+            // the lines get resolved and post-processed together with the world
+            if !lines.is_empty() {
+                pass.push_debug_group(&label::group("debug", "lines"));
+                self.debug.draw_lines(&mut pass, device, queue, lines);
+                pass.pop_debug_group();
+            }
+        }
+        self.stats.record_pass("main", pass_start.elapsed());
+
+        if let Some(ref contact_shadow) = self.contact_shadow {
+            pass_start = Instant::now();
+            let (world, resolve_target) =
+                world_targets(self.msaa.as_ref(), self.post.as_ref(), targets);
+            contact_shadow.draw(encoder, device, &self.global, world, resolve_target);
+            self.stats.record_pass("contact", pass_start.elapsed());
+        }
+
+        let (world, resolve_target) =
+            world_targets(self.msaa.as_ref(), self.post.as_ref(), targets);
+        custom::record_stage(
+            passes,
+            custom::PassStage::World,
+            &mut custom::FrameContext {
+                device,
+                encoder,
+                global: &self.global,
+                camera: cam,
+                targets: world,
+                resolve_target,
+            },
+        );
+
+        if let Some(ref post) = self.post {
+            pass_start = Instant::now();
+            post.draw(encoder, targets.color);
+            self.stats.record_pass("post", pass_start.elapsed());
+        }
+
+        custom::record_stage(
+            passes,
+            custom::PassStage::Post,
+            &mut custom::FrameContext {
+                device,
+                encoder,
+                global: &self.global,
+                camera: cam,
+                targets,
+                resolve_target: None,
+            },
+        );
+    }
+
+    /// Destroy the GPU resources explicitly, without waiting for the backend
+    /// to reclaim them. In debug builds, checks that nothing is leaked.
+    pub fn destroy(self) {
+        self.terrain.destroy();
+        if let Some(shadow) = self.shadow {
+            shadow.destroy();
+        }
+        if let Some(minimap) = self.minimap {
+            minimap.destroy();
+        }
+        if let Some(post) = self.post {
+            post.destroy();
+        }
+        if let Some(msaa) = self.msaa {
+            msaa.destroy();
+        }
+        debug_assert_eq!(
+            resource::live_count(),
+            resource::LiveCount::default(),
+            "Leaked render resources"
+        );
+    }
+
+    /// Draw the frame statistics graph on top of the final image,
+    /// if the `stats` debug layer is enabled.
+    pub fn draw_stats(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        targets: ScreenTargets,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) {
+        if !self.debug.settings().stats {
+            return;
+        }
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: targets.color,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        pass.push_debug_group(&label::group("debug", "stats"));
+        self.debug
+            .draw_stats(&mut pass, device, queue, &self.stats, targets.extent);
+        pass.pop_debug_group();
+    }
+
+    pub fn target_formats(&self) -> TargetFormats {
+        TargetFormats {
+            color: self.global.color_format,
+            depth: self.global.depth_format,
+        }
+    }
+
+    /// Switch to drawing into the targets of other formats, e.g. the ones
+    /// of another engine, re-creating the pipelines and the intermediate targets.
+    /// The custom passes need to be reloaded after that.
+    pub fn set_target_formats(&mut self, formats: TargetFormats, device: &wgpu::Device) {
+        if formats == self.target_formats() {
+            return;
+        }
+        info!("Switching the target formats to {:?}", formats);
+        self.global.color_format = formats.color;
+        self.global.depth_format = formats.depth;
+        self.object.set_target_formats(&self.global, device);
+        self.terrain.set_target_formats(&self.global, device);
+        self.debug.set_target_formats(&self.global, device);
+        if let Some(ref mut post) = self.post {
+            post.set_color_format(formats.color, self.screen_size, device);
+        }
+        if let Some(ref mut contact_shadow) = self.contact_shadow {
+            contact_shadow.set_target_formats(&self.global, device);
+        }
+        if let Some(msaa) = self.msaa.take() {
+            msaa.destroy();
+            self.msaa = Some(Multisample::new(
+                self.screen_size,
+                formats,
+                self.global.sample_count,
+                device,
+            ));
         }
     }
 
@@ -633,10 +1199,37 @@ impl Render {
         info!("Reloading shaders");
         self.object.reload(device);
         self.terrain.reload(device);
+        if let Some(ref mut minimap) = self.minimap {
+            minimap.reload(device);
+        }
+        if let Some(ref mut post) = self.post {
+            post.reload(device);
+        }
+        if let Some(ref mut contact_shadow) = self.contact_shadow {
+            contact_shadow.reload(device);
+        }
+    }
+
+    /// Scale of the overlays drawn on top of the final image, e.g. the stats graph,
+    /// in pixels per logical unit.
+    pub fn set_ui_scale(&mut self, scale: f32) {
+        self.debug.set_ui_scale(scale);
     }
 
     pub fn resize(&mut self, extent: wgpu::Extent3d, device: &wgpu::Device) {
         self.terrain.resize(extent, device);
+        if let Some(msaa) = self.msaa.take() {
+            msaa.destroy();
+            self.msaa = Some(Multisample::new(
+                extent,
+                self.target_formats(),
+                self.global.sample_count,
+                device,
+            ));
+        }
+        if let Some(ref mut post) = self.post {
+            post.resize(extent, device);
+        }
         self.screen_size = extent;
     }
 
@@ -1,7 +1,7 @@
 use crate::{
     render::{
-        body::GpuBody, global::Context as GlobalContext, GpuTransform, Palette, PipelineSet,
-        Shaders, COLOR_FORMAT, DEPTH_FORMAT, SHADOW_FORMAT,
+        body::GpuBody, global::Context as GlobalContext, label, probe::LightProbes, GpuTransform,
+        Palette, PipelineSet, Shaders, SHADOW_FORMAT,
     },
     space::Transform,
 };
@@ -104,13 +104,26 @@ impl InstanceDesc {
 
 pub struct Context {
     pub bind_group: wgpu::BindGroup,
+    bind_group_layout: wgpu::BindGroupLayout,
+    color_table: (wgpu::TextureView, wgpu::Sampler),
+    /// Palette of the objects, bound to the object shaders.
+    pub palette: Palette,
     pub shape_bind_group_layout: wgpu::BindGroupLayout,
     pub pipeline_layout: wgpu::PipelineLayout,
     pub pipelines: PipelineSet,
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    sample_count: u32,
 }
 
 impl Context {
-    fn create_pipelines(layout: &wgpu::PipelineLayout, device: &wgpu::Device) -> PipelineSet {
+    fn create_pipelines(
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+        device: &wgpu::Device,
+    ) -> PipelineSet {
         let vertex_descriptor = wgpu::VertexBufferDescriptor {
             stride: mem::size_of::<Vertex>() as wgpu::BufferAddress,
             step_mode: wgpu::InputStepMode::Vertex,
@@ -120,7 +133,7 @@ impl Context {
 
         let main_shaders = Shaders::new("object", &["COLOR"], device).unwrap();
         let main = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("object"),
+            label: Some(&label::pipeline("object", "color")),
             layout: Some(layout),
             vertex_stage: wgpu::ProgrammableStageDescriptor {
                 module: &main_shaders.vs,
@@ -137,25 +150,25 @@ impl Context {
                 ..Default::default()
             }),
             primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[COLOR_FORMAT.into()],
+            color_states: &[color_format.into()],
             depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: DEPTH_FORMAT,
+                format: depth_format,
                 depth_write_enabled: true,
                 depth_compare: wgpu::CompareFunction::LessEqual,
                 stencil: wgpu::StencilStateDescriptor::default(),
             }),
             vertex_state: wgpu::VertexStateDescriptor {
-                index_format: wgpu::IndexFormat::Uint16,
+                index_format: wgpu::IndexFormat::Uint32,
                 vertex_buffers: &[vertex_descriptor.clone(), instance_desc.buffer_desc()],
             },
-            sample_count: 1,
+            sample_count,
             alpha_to_coverage_enabled: false,
             sample_mask: !0,
         });
 
         let shadow_shaders = Shaders::new("object", &[], device).unwrap();
         let shadow = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("object-shadow"),
+            label: Some(&label::pipeline("object", "shadow")),
             layout: Some(layout),
             vertex_stage: wgpu::ProgrammableStageDescriptor {
                 module: &shadow_shaders.vs,
@@ -182,7 +195,7 @@ impl Context {
                 stencil: wgpu::StencilStateDescriptor::default(),
             }),
             vertex_state: wgpu::VertexStateDescriptor {
-                index_format: wgpu::IndexFormat::Uint16,
+                index_format: wgpu::IndexFormat::Uint32,
                 vertex_buffers: &[vertex_descriptor, instance_desc.buffer_desc()],
             },
             sample_count: 1,
@@ -203,7 +216,7 @@ impl Context {
             depth: 1,
         };
         let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Color table"),
+            label: Some(&label::resource("object", "color table")),
             size: extent,
             mip_level_count: 1,
             sample_count: 1,
@@ -242,6 +255,45 @@ impl Context {
         )
     }
 
+    fn create_bind_group(
+        layout: &wgpu::BindGroupLayout,
+        color_table: &(wgpu::TextureView, wgpu::Sampler),
+        palette: &Palette,
+        light_probes: &LightProbes,
+        device: &wgpu::Device,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&label::resource("object", "locals")),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&color_table.0),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&palette.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&color_table.1),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&light_probes.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&light_probes.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: light_probes.uniform_buf.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
     pub fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -249,7 +301,7 @@ impl Context {
         global: &GlobalContext,
     ) -> Self {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Object"),
+            label: Some(&label::resource("object", "locals")),
             entries: &[
                 // color map
                 wgpu::BindGroupLayoutEntry {
@@ -280,11 +332,39 @@ impl Context {
                     ty: wgpu::BindingType::Sampler { comparison: false },
                     count: None,
                 },
+                // light probes
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // light probe sampler
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+                // light probe params
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
         let shape_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Shape"),
+                label: Some(&label::resource("object", "shape locals")),
                 entries: &[
                     // shape locals
                     wgpu::BindGroupLayoutEntry {
@@ -301,41 +381,67 @@ impl Context {
             });
 
         let palette = Palette::new(device, queue, palette_data);
-        let (color_table_view, color_table_sampler) = Self::create_color_table(device, queue);
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Object"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&color_table_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&palette.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Sampler(&color_table_sampler),
-                },
-            ],
-        });
+        let color_table = Self::create_color_table(device, queue);
+        let light_probes = LightProbes::neutral(device, queue);
+        let bind_group = Self::create_bind_group(
+            &bind_group_layout,
+            &color_table,
+            &palette,
+            &light_probes,
+            device,
+        );
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("object"),
+            label: Some(&label::pipeline("object", "color")),
             bind_group_layouts: &[&global.bind_group_layout, &bind_group_layout],
             push_constant_ranges: &[],
         });
-        let pipelines = Self::create_pipelines(&pipeline_layout, device);
+        let pipelines = Self::create_pipelines(
+            &pipeline_layout,
+            global.color_format,
+            global.depth_format,
+            global.sample_count,
+            device,
+        );
 
         Context {
             bind_group,
+            bind_group_layout,
+            color_table,
+            palette,
             shape_bind_group_layout,
             pipeline_layout,
             pipelines,
+            color_format: global.color_format,
+            depth_format: global.depth_format,
+            sample_count: global.sample_count,
         }
     }
 
     pub fn reload(&mut self, device: &wgpu::Device) {
-        self.pipelines = Self::create_pipelines(&self.pipeline_layout, device);
+        self.pipelines = Self::create_pipelines(
+            &self.pipeline_layout,
+            self.color_format,
+            self.depth_format,
+            self.sample_count,
+            device,
+        );
+    }
+
+    /// Replace the ambient light probes, e.g. when the level changes.
+    pub fn set_light_probes(&mut self, light_probes: LightProbes, device: &wgpu::Device) {
+        self.bind_group = Self::create_bind_group(
+            &self.bind_group_layout,
+            &self.color_table,
+            &self.palette,
+            &light_probes,
+            device,
+        );
+    }
+
+    /// Re-create the pipelines for the target formats of the global context.
+    pub fn set_target_formats(&mut self, global: &GlobalContext, device: &wgpu::Device) {
+        self.color_format = global.color_format;
+        self.depth_format = global.depth_format;
+        self.reload(device);
     }
 }
@@ -0,0 +1,312 @@
+use crate::{
+    config::settings,
+    render::{label, resource, Palette, Shaders},
+};
+
+use wgpu::util::DeviceExt as _;
+
+/// A full-screen pass, reading the output of the previous one.
+struct Stage {
+    /// Name of the stage, also picking the `post/<name>` shader.
+    name: &'static str,
+    // stage-specific parameters
+    uniform_buf: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Stage {
+    fn new(
+        name: &'static str,
+        params: [f32; 4],
+        color_format: wgpu::TextureFormat,
+        device: &wgpu::Device,
+    ) -> Self {
+        let uniform_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&label::resource("post", name)),
+            contents: bytemuck::cast_slice(&params),
+            usage: wgpu::BufferUsage::UNIFORM,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&label::resource("post", name)),
+            entries: &[
+                // source image
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // source sampler
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+                // palette
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        dimension: wgpu::TextureViewDimension::D1,
+                        component_type: wgpu::TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // parameters
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&label::pipeline("post", name)),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(name, &pipeline_layout, color_format, device);
+        Stage {
+            name,
+            uniform_buf,
+            bind_group_layout,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        name: &str,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        device: &wgpu::Device,
+    ) -> wgpu::RenderPipeline {
+        let shaders = Shaders::new(&format!("post/{}", name), &[], device).unwrap();
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&label::pipeline("post", name)),
+            layout: Some(layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &shaders.vs,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &shaders.fs,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                ..Default::default()
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[color_format.into()],
+            depth_stencil_state: None,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[],
+            },
+            sample_count: 1,
+            alpha_to_coverage_enabled: false,
+            sample_mask: !0,
+        })
+    }
+}
+
+/// Post-processing chain, applied to the rendered image before presenting.
+pub struct Context {
+    color_format: wgpu::TextureFormat,
+    sampler: wgpu::Sampler,
+    palette: Palette,
+    stages: Vec<Stage>,
+    // one intermediate target and bind group per stage
+    targets: Vec<(wgpu::TextureView, wgpu::BindGroup)>,
+    textures: Vec<wgpu::Texture>,
+}
+
+impl Context {
+    /// Create the post-processing chain, or return `None` if it's empty.
+    pub fn new(
+        config: &settings::Post,
+        palette: Palette,
+        color_format: wgpu::TextureFormat,
+        extent: wgpu::Extent3d,
+        device: &wgpu::Device,
+    ) -> Option<Self> {
+        let mut stages = Vec::new();
+        if config.palette_dither {
+            stages.push(Stage::new("dither", [0.0; 4], color_format, device));
+        }
+        // CRT goes last, after all the other stages
+        if let Some(ref crt) = config.crt {
+            let params = [crt.curvature, crt.scanlines, crt.vignette, 0.0];
+            stages.push(Stage::new("crt", params, color_format, device));
+        }
+        if stages.is_empty() {
+            return None;
+        }
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(&label::resource("post", "source")),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let mut context = Context {
+            color_format,
+            sampler,
+            palette,
+            stages,
+            targets: Vec::new(),
+            textures: Vec::new(),
+        };
+        context.resize(extent, device);
+        Some(context)
+    }
+
+    /// The view that the scene has to be rendered into.
+    pub fn input(&self) -> &wgpu::TextureView {
+        &self.targets[0].0
+    }
+
+    pub fn resize(&mut self, extent: wgpu::Extent3d, device: &wgpu::Device) {
+        for texture in self.textures.drain(..) {
+            resource::destroy_texture(texture);
+        }
+        let mut textures = Vec::with_capacity(self.stages.len());
+        let targets = self
+            .stages
+            .iter()
+            .map(|stage| {
+                let texture = resource::create_texture(
+                    device,
+                    &wgpu::TextureDescriptor {
+                        label: Some(&label::resource("post", stage.name)),
+                        size: extent,
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format: self.color_format,
+                        usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+                    },
+                );
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some(&label::resource("post", stage.name)),
+                    layout: &stage.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&self.palette.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: stage.uniform_buf.as_entire_binding(),
+                        },
+                    ],
+                });
+                textures.push(texture);
+                (view, bind_group)
+            })
+            .collect();
+        self.targets = targets;
+        self.textures = textures;
+    }
+
+    /// Use another palette for the dithering, e.g. after switching the level.
+    pub fn set_palette(&mut self, palette: Palette, extent: wgpu::Extent3d, device: &wgpu::Device) {
+        self.palette = palette;
+        self.resize(extent, device);
+    }
+
+    /// Replace the colors of the dithering palette in place, e.g. for the time of the day.
+    pub fn update_palette(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        data: &[[u8; 4]; 0x100],
+    ) {
+        self.palette.update(encoder, device, data);
+    }
+
+    /// Re-create the pipelines and the intermediate targets for another color format.
+    pub fn set_color_format(
+        &mut self,
+        color_format: wgpu::TextureFormat,
+        extent: wgpu::Extent3d,
+        device: &wgpu::Device,
+    ) {
+        self.color_format = color_format;
+        self.reload(device);
+        self.resize(extent, device);
+    }
+
+    pub fn destroy(self) {
+        for texture in self.textures {
+            resource::destroy_texture(texture);
+        }
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device) {
+        for stage in self.stages.iter_mut() {
+            stage.pipeline = Stage::create_pipeline(
+                stage.name,
+                &stage.pipeline_layout,
+                self.color_format,
+                device,
+            );
+        }
+    }
+
+    /// Run all the stages, writing the result into `output`.
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        for (i, (stage, &(_, ref bind_group))) in
+            self.stages.iter().zip(self.targets.iter()).enumerate()
+        {
+            let attachment = match self.targets.get(i + 1) {
+                Some(&(ref view, _)) => view,
+                None => output,
+            };
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            pass.push_debug_group(&label::group("post", stage.name));
+            pass.set_pipeline(&stage.pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.draw(0..3, 0..1);
+            pass.pop_debug_group();
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Ambient light probes for the objects.
+//!
+//! The level is split into a sparse grid of cells, and each cell gets the average
+//! color of its terrain, blended with the sky. The object shader interpolates
+//! the probes at the fragment position, so that the vehicles pick up the hue
+//! of their surroundings in the unlit parts.
+
+use crate::{
+    config::settings,
+    level::{Level, Texel},
+    render::label,
+};
+
+use wgpu::util::DeviceExt as _;
+
+/// Number of the texels sampled along each side of a cell.
+const SAMPLES_PER_CELL: u32 = 8;
+
+/// Colors of the probes, baked on CPU.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbeGrid {
+    /// Number of the probes in each dimension.
+    pub size: (u32, u32),
+    /// Side of a cell covered by a probe, in texels.
+    pub cell_size: u32,
+    /// Row-major RGBA colors of the probes.
+    pub colors: Vec<[u8; 4]>,
+}
+
+impl ProbeGrid {
+    /// Average the terrain colors of every cell and blend them with the sky.
+    /// The terrain color is the middle of the palette range of its type,
+    /// which is what the unlit slopes around an object mostly reflect.
+    pub fn bake(level: &Level, cell_size: u32, sky: [f32; 4], sky_weight: f32) -> Self {
+        let size = (
+            (level.size.0 as u32 + cell_size - 1) / cell_size,
+            (level.size.1 as u32 + cell_size - 1) / cell_size,
+        );
+        let step = (cell_size / SAMPLES_PER_CELL).max(1);
+        let terrain_colors = level
+            .terrains
+            .iter()
+            .map(|terr| {
+                let index = (terr.colors.start as usize + terr.colors.end as usize) / 2;
+                let c = level.palette[index.min(0xFF)];
+                [c[0] as f32, c[1] as f32, c[2] as f32]
+            })
+            .collect::<Vec<_>>();
+
+        let mut colors = Vec::with_capacity((size.0 * size.1) as usize);
+        for cy in 0..size.1 {
+            for cx in 0..size.0 {
+                let mut sum = [0.0f32; 3];
+                let mut count = 0;
+                for y in (cy * cell_size..((cy + 1) * cell_size).min(level.size.1 as u32))
+                    .step_by(step as usize)
+                {
+                    for x in (cx * cell_size..((cx + 1) * cell_size).min(level.size.0 as u32))
+                        .step_by(step as usize)
+                    {
+                        let terrain = match level.get((x as i32, y as i32)) {
+                            Texel::Single(point) => point.1,
+                            Texel::Dual { high, .. } => high.1,
+                        };
+                        let color = terrain_colors[terrain as usize];
+                        for (s, c) in sum.iter_mut().zip(color.iter()) {
+                            *s += c;
+                        }
+                        count += 1;
+                    }
+                }
+                let mut probe = [0xFF; 4];
+                for i in 0..3 {
+                    let ground = sum[i] / count as f32;
+                    let value = ground * (1.0 - sky_weight) + sky[i] * 255.0 * sky_weight;
+                    probe[i] = value.max(0.0).min(255.0) as u8;
+                }
+                colors.push(probe);
+            }
+        }
+
+        ProbeGrid {
+            size,
+            cell_size,
+            colors,
+        }
+    }
+}
+
+/// Probes on GPU, bound to the object shaders.
+pub struct LightProbes {
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    /// Uniforms: world size covered by the probes in XY, strength in Z.
+    pub uniform_buf: wgpu::Buffer,
+}
+
+impl LightProbes {
+    fn upload(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: (u32, u32),
+        colors: &[[u8; 4]],
+        params: [f32; 4],
+    ) -> Self {
+        let extent = wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&label::resource("light", "probes")),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(colors),
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: size.0 * 4,
+                rows_per_image: 0,
+            },
+            extent,
+        );
+
+        // the probes repeat together with the world
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(&label::resource("light", "probes")),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let uniform_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&label::resource("light", "probes")),
+            contents: bytemuck::cast_slice(&params),
+            usage: wgpu::BufferUsage::UNIFORM,
+        });
+
+        LightProbes {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            sampler,
+            uniform_buf,
+        }
+    }
+
+    /// Single white probe with no effect, for the scenes without a level.
+    pub fn neutral(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self::upload(device, queue, (1, 1), &[[0xFF; 4]], [1.0, 1.0, 0.0, 0.0])
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        grid: &ProbeGrid,
+        settings: &settings::LightProbes,
+    ) -> Self {
+        let world_size = [
+            (grid.size.0 * grid.cell_size) as f32,
+            (grid.size.1 * grid.cell_size) as f32,
+        ];
+        Self::upload(
+            device,
+            queue,
+            grid.size,
+            &grid.colors,
+            [world_size[0], world_size[1], settings.strength, 0.0],
+        )
+    }
+}
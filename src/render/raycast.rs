@@ -0,0 +1,329 @@
+use crate::render::{label, readback::Readback, terrain::Context as TerrainContext, Shaders};
+
+use bytemuck::{Pod, Zeroable};
+use futures::executor::LocalSpawner;
+use wgpu::util::DeviceExt as _;
+
+use std::{
+    mem, slice,
+    sync::{Arc, Mutex},
+};
+
+const WORK_GROUP_WIDTH: u32 = 64;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: [f32; 4],
+    /// Direction of the ray, W is the maximum distance.
+    pub dir: [f32; 4],
+}
+unsafe impl Pod for Ray {}
+unsafe impl Zeroable for Ray {}
+
+impl Ray {
+    pub fn new(origin: cgmath::Vector3<f32>, dir: cgmath::Vector3<f32>, max_distance: f32) -> Self {
+        Ray {
+            origin: origin.extend(1.0).into(),
+            dir: dir.extend(max_distance).into(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    /// Position of the hit, W is the distance along the ray,
+    /// which is negative if nothing is hit.
+    pub pos: [f32; 4],
+    /// X is the terrain type, Y is 1 if the upper layer is hit.
+    pub info: [u32; 4],
+}
+unsafe impl Pod for RayHit {}
+unsafe impl Zeroable for RayHit {}
+
+impl RayHit {
+    pub fn is_hit(&self) -> bool {
+        self.pos[3] >= 0.0
+    }
+
+    pub fn position(&self) -> cgmath::Point3<f32> {
+        cgmath::Point3::new(self.pos[0], self.pos[1], self.pos[2])
+    }
+
+    pub fn is_upper_layer(&self) -> bool {
+        self.info[1] != 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, PartialOrd)]
+pub struct GpuBatch(usize);
+
+/// Hits of a batch that has been read back,
+/// in the same order as the rays were added.
+#[derive(Debug, Default)]
+pub struct GpuCastResult {
+    pub hits: Vec<RayHit>,
+    pub batch: GpuBatch,
+}
+
+struct PendingResult {
+    readback: Readback,
+    count: usize,
+    batch: GpuBatch,
+}
+
+/// Casts batches of rays against both layers of the terrain on GPU.
+pub struct GpuRayCaster {
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::ComputePipeline,
+    uniform_buf: wgpu::Buffer,
+    ray_buf: wgpu::Buffer,
+    hit_buf: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    capacity: usize,
+    rays: Vec<Ray>,
+    batch: GpuBatch,
+    pending_result: Option<PendingResult>,
+    finished: Arc<Mutex<Vec<GpuCastResult>>>,
+}
+
+impl GpuRayCaster {
+    fn create_pipeline(
+        layout: &wgpu::PipelineLayout,
+        device: &wgpu::Device,
+    ) -> wgpu::ComputePipeline {
+        let shader = Shaders::new_compute(
+            "physics/terrain_cast",
+            [WORK_GROUP_WIDTH, 1, 1],
+            &[],
+            device,
+        )
+        .unwrap();
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&label::pipeline("terrain", "cast")),
+            layout: Some(layout),
+            compute_stage: wgpu::ProgrammableStageDescriptor {
+                module: &shader,
+                entry_point: "main",
+            },
+        })
+    }
+
+    pub fn new(device: &wgpu::Device, terrain: &TerrainContext, max_rays: usize) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&label::resource("terrain", "cast")),
+            entries: &[
+                // locals
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // rays
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        readonly: true,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // hits
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        readonly: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&label::pipeline("terrain", "cast")),
+            bind_group_layouts: &[&bind_group_layout, &terrain.bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(&pipeline_layout, device);
+
+        // ensure the total size fits complete number of workgroups
+        let group = WORK_GROUP_WIDTH as usize;
+        let capacity = ((max_rays.max(1) + group - 1) / group) * group;
+        let uniform_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&label::resource("terrain", "cast locals")),
+            size: mem::size_of::<[u32; 4]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let ray_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&label::resource("terrain", "cast rays")),
+            size: (capacity * mem::size_of::<Ray>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let hit_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&label::resource("terrain", "cast hits")),
+            size: (capacity * mem::size_of::<RayHit>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&label::resource("terrain", "cast")),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: ray_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: hit_buf.as_entire_binding(),
+                },
+            ],
+        });
+
+        GpuRayCaster {
+            pipeline_layout,
+            pipeline,
+            uniform_buf,
+            ray_buf,
+            hit_buf,
+            bind_group,
+            capacity,
+            rays: Vec::new(),
+            batch: GpuBatch::default(),
+            pending_result: None,
+            finished: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(&self.pipeline_layout, device);
+    }
+
+    /// Add a ray to the current batch, returning its index in the results,
+    /// or `None` if the batch is full.
+    pub fn add(&mut self, ray: Ray) -> Option<usize> {
+        if self.rays.len() == self.capacity {
+            return None;
+        }
+        self.rays.push(ray);
+        Some(self.rays.len() - 1)
+    }
+
+    /// Cast all the rays of the current batch, and schedule a copy of the hits
+    /// for the read back. Returns the batch that the hits will be tagged with.
+    pub fn cast(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        terrain: &TerrainContext,
+    ) -> GpuBatch {
+        let batch = self.batch;
+        self.batch.0 += 1;
+        let count = self.rays.len();
+        if count == 0 {
+            return batch;
+        }
+
+        let temp_rays = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&label::temp("rays")),
+            contents: bytemuck::cast_slice(&self.rays),
+            usage: wgpu::BufferUsage::COPY_SRC,
+        });
+        encoder.copy_buffer_to_buffer(
+            &temp_rays,
+            0,
+            &self.ray_buf,
+            0,
+            (count * mem::size_of::<Ray>()) as wgpu::BufferAddress,
+        );
+        let temp_locals = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&label::temp("locals")),
+            contents: bytemuck::cast_slice(&[count as u32, 0, 0, 0]),
+            usage: wgpu::BufferUsage::COPY_SRC,
+        });
+        encoder.copy_buffer_to_buffer(
+            &temp_locals,
+            0,
+            &self.uniform_buf,
+            0,
+            mem::size_of::<[u32; 4]>() as wgpu::BufferAddress,
+        );
+        self.rays.clear();
+
+        {
+            let mut pass = encoder.begin_compute_pass();
+            pass.push_debug_group(&label::group("terrain", "cast"));
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.set_bind_group(1, &terrain.bind_group, &[]);
+            let num_groups = (count as u32 - 1) / WORK_GROUP_WIDTH + 1;
+            pass.dispatch(num_groups, 1, 1);
+            pass.pop_debug_group();
+        }
+
+        let readback = Readback::from_buffer(
+            device,
+            encoder,
+            &label::temp("terrain-cast"),
+            &self.hit_buf,
+            0,
+            (count * mem::size_of::<RayHit>()) as wgpu::BufferAddress,
+        );
+        self.pending_result = Some(PendingResult {
+            readback,
+            count,
+            batch,
+        });
+        batch
+    }
+
+    /// Start reading back the hits of the last cast batch.
+    /// Has to be called after the cast commands are submitted.
+    pub fn consume_results(&mut self, spawner: &LocalSpawner) {
+        let PendingResult {
+            readback,
+            count,
+            batch,
+        } = match self.pending_result.take() {
+            Some(pr) => pr,
+            None => return,
+        };
+
+        let finished = Arc::clone(&self.finished);
+        readback.consume(spawner, move |result| {
+            let mapping = match result {
+                Ok(mapping) => mapping,
+                Err(e) => {
+                    error!("Unable to map the terrain cast results: {:?}", e);
+                    return;
+                }
+            };
+            let hits = unsafe { slice::from_raw_parts(mapping.as_ptr() as *const RayHit, count) };
+
+            finished.lock().unwrap().push(GpuCastResult {
+                hits: hits.to_vec(),
+                batch,
+            });
+        });
+    }
+
+    /// Take the batches that have been read back since the last call, oldest first.
+    pub fn take_results(&self) -> Vec<GpuCastResult> {
+        mem::replace(&mut *self.finished.lock().unwrap(), Vec::new())
+    }
+}
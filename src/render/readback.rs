@@ -0,0 +1,176 @@
+//! Reading the GPU data back on CPU.
+//!
+//! A readback is produced by copying the data into a staging buffer while
+//! recording the commands, and consumed after the commands are submitted,
+//! by mapping the staging buffer asynchronously.
+
+use futures::{executor::LocalSpawner, task::LocalSpawn as _, FutureExt};
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Row pitch of a texture copy, aligned as required for the copies into buffers.
+pub fn padded_bytes_per_row(bytes_per_row: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (bytes_per_row + align - 1) / align * align
+}
+
+#[derive(Clone, Copy, Debug)]
+struct RowLayout {
+    packed: usize,
+    padded: usize,
+    count: usize,
+}
+
+/// Data copied into a staging buffer, waiting for the commands to be submitted.
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    size: wgpu::BufferAddress,
+    rows: Option<RowLayout>,
+}
+
+impl Readback {
+    /// Create an empty staging buffer, to be filled by custom copies.
+    pub fn new(device: &wgpu::Device, label: &str, size: wgpu::BufferAddress) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Readback {
+            buffer,
+            size,
+            rows: None,
+        }
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Schedule a copy of a range of the source buffer.
+    pub fn from_buffer(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        source: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        size: wgpu::BufferAddress,
+    ) -> Self {
+        let readback = Self::new(device, label, size);
+        encoder.copy_buffer_to_buffer(source, offset, &readback.buffer, 0, size);
+        readback
+    }
+
+    /// Schedule a copy of the first mip level of a 2D texture.
+    /// The rows are packed tightly when the data is read back.
+    pub fn from_texture(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        texture: &wgpu::Texture,
+        extent: wgpu::Extent3d,
+        bytes_per_texel: u32,
+    ) -> Self {
+        let packed = extent.width * bytes_per_texel;
+        let padded = padded_bytes_per_row(packed);
+        let mut readback = Self::new(
+            device,
+            label,
+            (padded * extent.height) as wgpu::BufferAddress,
+        );
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &readback.buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: padded,
+                    rows_per_image: 0,
+                },
+            },
+            extent,
+        );
+        readback.rows = Some(RowLayout {
+            packed: packed as usize,
+            padded: padded as usize,
+            count: extent.height as usize,
+        });
+        readback
+    }
+
+    /// Start mapping the data. Has to be called after the copy commands are submitted.
+    /// The callback receives the data once it's available.
+    pub fn consume(
+        self,
+        spawner: &LocalSpawner,
+        callback: impl FnOnce(Result<&[u8], wgpu::BufferAsyncError>) + 'static,
+    ) {
+        let Readback { buffer, size, rows } = self;
+        let future = buffer
+            .slice(..size)
+            .map_async(wgpu::MapMode::Read)
+            .map(move |result| {
+                if let Err(e) = result {
+                    callback(Err(e));
+                    return;
+                }
+                let mapping = buffer.slice(..size).get_mapped_range();
+                match rows {
+                    Some(layout) if layout.packed != layout.padded => {
+                        let mut data = Vec::with_capacity(layout.packed * layout.count);
+                        for row in mapping.chunks(layout.padded).take(layout.count) {
+                            data.extend_from_slice(&row[..layout.packed]);
+                        }
+                        callback(Ok(&data));
+                    }
+                    _ => callback(Ok(&mapping)),
+                }
+            });
+        spawner.spawn_local_obj(Box::new(future).into()).unwrap();
+    }
+}
+
+/// Limits the number of readbacks in flight, so that producing one every frame
+/// doesn't pile up staging buffers while the GPU is a few frames behind.
+pub struct Throttle {
+    in_flight: Arc<AtomicUsize>,
+    max_in_flight: usize,
+}
+
+impl Throttle {
+    pub fn new(max_in_flight: usize) -> Self {
+        Throttle {
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_in_flight,
+        }
+    }
+
+    /// Returns `true` if another readback can be produced.
+    pub fn is_ready(&self) -> bool {
+        self.in_flight.load(Ordering::Acquire) < self.max_in_flight
+    }
+
+    /// Same as `Readback::consume`, but counting the readback as in flight
+    /// until the callback is done.
+    pub fn consume(
+        &self,
+        readback: Readback,
+        spawner: &LocalSpawner,
+        callback: impl FnOnce(Result<&[u8], wgpu::BufferAsyncError>) + 'static,
+    ) {
+        let in_flight = Arc::clone(&self.in_flight);
+        in_flight.fetch_add(1, Ordering::AcqRel);
+        readback.consume(spawner, move |result| {
+            callback(result);
+            in_flight.fetch_sub(1, Ordering::AcqRel);
+        });
+    }
+}
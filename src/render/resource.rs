@@ -0,0 +1,46 @@
+//! Explicit lifetime of the GPU resources owned by the render contexts.
+//!
+//! Some backends don't reclaim the memory of a resource until it's destroyed,
+//! even if all the handles are dropped. Resources created here are counted,
+//! so that a context forgetting to destroy them is caught in debug builds.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static LIVE_TEXTURES: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BUFFERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Numbers of the live textures and buffers.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LiveCount {
+    pub textures: usize,
+    pub buffers: usize,
+}
+
+pub fn live_count() -> LiveCount {
+    LiveCount {
+        textures: LIVE_TEXTURES.load(Ordering::Relaxed),
+        buffers: LIVE_BUFFERS.load(Ordering::Relaxed),
+    }
+}
+
+pub fn create_texture(device: &wgpu::Device, desc: &wgpu::TextureDescriptor) -> wgpu::Texture {
+    LIVE_TEXTURES.fetch_add(1, Ordering::Relaxed);
+    device.create_texture(desc)
+}
+
+pub fn destroy_texture(texture: wgpu::Texture) {
+    texture.destroy();
+    let old = LIVE_TEXTURES.fetch_sub(1, Ordering::Relaxed);
+    debug_assert_ne!(old, 0, "Destroyed more textures than created");
+}
+
+pub fn create_buffer(device: &wgpu::Device, desc: &wgpu::BufferDescriptor) -> wgpu::Buffer {
+    LIVE_BUFFERS.fetch_add(1, Ordering::Relaxed);
+    device.create_buffer(desc)
+}
+
+pub fn destroy_buffer(buffer: wgpu::Buffer) {
+    buffer.destroy();
+    let old = LIVE_BUFFERS.fetch_sub(1, Ordering::Relaxed);
+    debug_assert_ne!(old, 0, "Destroyed more buffers than created");
+}
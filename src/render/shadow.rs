@@ -1,6 +1,7 @@
 use crate::{
     config::settings,
     level::HEIGHT_SCALE,
+    render::{label, resource},
     space::{Camera, Projection},
 };
 
@@ -9,6 +10,7 @@ use cgmath::{EuclideanSpace as _, InnerSpace as _, Rotation as _};
 pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24Plus;
 
 pub struct Shadow {
+    texture: wgpu::Texture,
     pub(super) view: wgpu::TextureView,
     pub(super) cam: Camera,
     pub(super) size: u32,
@@ -18,19 +20,22 @@ pub struct Shadow {
 impl Shadow {
     pub(super) fn new(light: &settings::Light, device: &wgpu::Device) -> Self {
         let size = light.shadow.size;
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Shadow"),
-            size: wgpu::Extent3d {
-                width: size,
-                height: size,
-                depth: 1,
+        let texture = resource::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some(&label::resource("shadow", "map")),
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: FORMAT,
+                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::OUTPUT_ATTACHMENT,
             },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: FORMAT,
-            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-        });
+        );
 
         let dir = cgmath::Vector4::from(light.pos).truncate();
         let up = if dir.x == 0.0 && dir.y == 0.0 {
@@ -41,16 +46,21 @@ impl Shadow {
 
         Shadow {
             view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
-            cam: Camera {
-                loc: cgmath::Zero::zero(),
-                rot: cgmath::Quaternion::look_at(dir, up),
-                proj: Projection::ortho(1, 1, 0.0..1.0),
-            },
+            texture,
+            cam: Camera::new(
+                cgmath::Zero::zero(),
+                cgmath::Quaternion::look_at(dir, up),
+                Projection::ortho(1, 1, 0.0..1.0),
+            ),
             size,
             dir,
         }
     }
 
+    pub(super) fn destroy(self) {
+        resource::destroy_texture(self.texture);
+    }
+
     fn get_local_point(&self, world_pt: cgmath::Point3<f32>) -> cgmath::Point3<f32> {
         let diff = world_pt.to_vec() - self.cam.loc;
         let right = self.cam.rot * cgmath::Vector3::unit_x();
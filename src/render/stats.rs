@@ -0,0 +1,200 @@
+//! Per-tile statistics of the level height map.
+//!
+//! The height map is reduced on GPU into a texture with a texel per tile,
+//! holding the minimum, maximum, mean, and variance of the altitudes.
+//! It's a coarse view of the level for the techniques that need to adapt
+//! to the terrain: level of detail, occlusion culling, the altitude range
+//! of the slices, and the density of the scattered points.
+
+use crate::render::{label, readback::Readback, resource, Shaders};
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt as _;
+
+use std::mem;
+
+/// Size of a square tile, in texels of the height map.
+pub const TILE_SIZE: u32 = 16;
+pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+const WORK_GROUP_WIDTH: u32 = 8;
+
+/// Statistics of a tile, in the same layout as the texels.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TileStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub variance: f32,
+}
+unsafe impl Pod for TileStats {}
+unsafe impl Zeroable for TileStats {}
+
+pub struct HeightStats {
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    extent: wgpu::Extent3d,
+}
+
+impl HeightStats {
+    fn create_pipeline(
+        layout: &wgpu::PipelineLayout,
+        device: &wgpu::Device,
+    ) -> wgpu::ComputePipeline {
+        let shader = Shaders::new_compute(
+            "terrain/stats",
+            [WORK_GROUP_WIDTH, WORK_GROUP_WIDTH, 1],
+            &[],
+            device,
+        )
+        .unwrap();
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&label::pipeline("height", "stats")),
+            layout: Some(layout),
+            compute_stage: wgpu::ProgrammableStageDescriptor {
+                module: &shader,
+                entry_point: "main",
+            },
+        })
+    }
+
+    /// Create the statistics for a level of the given size.
+    /// The terrain layout provides the height map to the reduction.
+    pub fn new(
+        device: &wgpu::Device,
+        terrain_layout: &wgpu::BindGroupLayout,
+        level_size: (u32, u32),
+    ) -> Self {
+        let extent = wgpu::Extent3d {
+            width: (level_size.0 + TILE_SIZE - 1) / TILE_SIZE,
+            height: (level_size.1 + TILE_SIZE - 1) / TILE_SIZE,
+            depth: 1,
+        };
+        let texture = resource::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some(&label::resource("height", "stats")),
+                size: extent,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: FORMAT,
+                usage: wgpu::TextureUsage::STORAGE
+                    | wgpu::TextureUsage::SAMPLED
+                    | wgpu::TextureUsage::COPY_SRC,
+            },
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&label::resource("height", "stats")),
+            entries: &[
+                // locals
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // stats
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        dimension: wgpu::TextureViewDimension::D2,
+                        format: FORMAT,
+                        readonly: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&label::pipeline("height", "stats")),
+            bind_group_layouts: &[&bind_group_layout, terrain_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(&pipeline_layout, device);
+
+        let uniform_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&label::resource("height", "stats locals")),
+            contents: bytemuck::cast_slice(&[extent.width, extent.height, TILE_SIZE, 0]),
+            usage: wgpu::BufferUsage::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&label::resource("height", "stats")),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+            ],
+        });
+
+        HeightStats {
+            pipeline_layout,
+            pipeline,
+            bind_group,
+            texture,
+            view,
+            extent,
+        }
+    }
+
+    pub fn destroy(self) {
+        resource::destroy_texture(self.texture);
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(&self.pipeline_layout, device);
+    }
+
+    /// Texture view of the statistics, with a texel per tile.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    /// Number of tiles in each dimension.
+    pub fn extent(&self) -> wgpu::Extent3d {
+        self.extent
+    }
+
+    /// Reduce the whole height map again, after it has been modified.
+    pub fn update(&self, encoder: &mut wgpu::CommandEncoder, terrain_bind_group: &wgpu::BindGroup) {
+        let mut pass = encoder.begin_compute_pass();
+        pass.push_debug_group(&label::group("height", "stats"));
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_bind_group(1, terrain_bind_group, &[]);
+        pass.dispatch(
+            (self.extent.width - 1) / WORK_GROUP_WIDTH + 1,
+            (self.extent.height - 1) / WORK_GROUP_WIDTH + 1,
+            1,
+        );
+        pass.pop_debug_group();
+    }
+
+    /// Schedule a copy of the statistics for reading them on CPU.
+    /// The data is a row-major array of `TileStats`.
+    pub fn read(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) -> Readback {
+        Readback::from_texture(
+            device,
+            encoder,
+            &label::temp("height-stats"),
+            &self.texture,
+            self.extent,
+            mem::size_of::<TileStats>() as u32,
+        )
+    }
+}
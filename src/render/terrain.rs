@@ -2,18 +2,31 @@ use crate::{
     config::settings,
     level,
     render::{
-        global::Context as GlobalContext, mipmap::MaxMipper, Palette, PipelineKind, Shaders,
-        COLOR_FORMAT, DEPTH_FORMAT, SHADOW_FORMAT,
+        global::Context as GlobalContext,
+        label,
+        mipmap::{self, MaxMipper},
+        readback::{padded_bytes_per_row, Readback},
+        resource,
+        stats::HeightStats,
+        upload::UploadQueue,
+        Palette, PipelineKind, Shaders, SHADOW_FORMAT,
     },
     space::Camera,
 };
 
 use bytemuck::{Pod, Zeroable};
+use futures::executor::LocalSpawner;
 use wgpu::util::DeviceExt as _;
 
-use std::{mem, ops::Range};
+use std::{
+    fs::File,
+    io::{BufWriter, Write as _},
+    mem,
+    ops::Range,
+    path::{Path, PathBuf},
+    slice,
+};
 
-pub const HEIGHT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
 const SCATTER_GROUP_SIZE: [u32; 3] = [16, 16, 1];
 
 #[repr(C)]
@@ -32,6 +45,17 @@ struct SurfaceConstants {
 unsafe impl Pod for SurfaceConstants {}
 unsafe impl Zeroable for SurfaceConstants {}
 
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ColorConstants {
+    provider: [u32; 4],
+    detail_camera: [f32; 4],
+    detail_params: [[f32; 4]; 8],
+    weather: [f32; 4],
+}
+unsafe impl Pod for ColorConstants {}
+unsafe impl Zeroable for ColorConstants {}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 struct Constants {
@@ -77,7 +101,7 @@ fn compute_scatter_constants(cam: &Camera) -> ScatterConstants {
         Point2::new(end.x, end.y)
     }
 
-    let mx_invp = cam.get_view_proj().invert().unwrap();
+    let mx_invp = cam.inverse_view_proj();
     let y_center = {
         let center = mx_invp.transform_point(Point3::new(0.0, 0.0, 0.0));
         let center_base = intersect(&cam.loc, center, 0);
@@ -118,6 +142,26 @@ fn compute_scatter_constants(cam: &Camera) -> ScatterConstants {
     }
 }
 
+/// Number of the scatter groups covering the sampled area of the terrain
+/// with `texel_density` points per texel, bounded by `max_groups`.
+fn compute_scatter_groups(
+    sc: &ScatterConstants,
+    texel_density: f32,
+    max_groups: [u32; 2],
+) -> [u32; 2] {
+    // the sampled area is a trapezoid, with the half-widths at the near and far ends
+    let width = sc.sample_x.start + sc.sample_x.end;
+    let depth = sc.sample_y.end - sc.sample_y.start;
+    let groups = |length: f32, group_size: u32, max: u32| {
+        let count = (length.max(0.0) * texel_density / group_size as f32).ceil() as u32;
+        count.max(1).min(max)
+    };
+    [
+        groups(width, SCATTER_GROUP_SIZE[0], max_groups[0]),
+        groups(depth, SCATTER_GROUP_SIZE[1], max_groups[1]),
+    ]
+}
+
 struct Geometry {
     vertex_buf: wgpu::Buffer,
     index_buf: wgpu::Buffer,
@@ -128,12 +172,12 @@ impl Geometry {
     fn new(vertices: &[Vertex], indices: &[u16], device: &wgpu::Device) -> Self {
         Geometry {
             vertex_buf: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("terrain-vertex"),
+                label: Some(&label::resource("terrain", "vertices")),
                 contents: bytemuck::cast_slice(&vertices),
                 usage: wgpu::BufferUsage::VERTEX,
             }),
             index_buf: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("terrain-index"),
+                label: Some(&label::resource("terrain", "indices")),
                 contents: bytemuck::cast_slice(&indices),
                 usage: wgpu::BufferUsage::INDEX,
             }),
@@ -159,6 +203,10 @@ enum Kind {
     Slice {
         pipeline: wgpu::RenderPipeline,
         geo: Geometry,
+        quality: settings::SliceQuality,
+        seam: settings::WorldSeam,
+        /// Slices crossing the camera frustum.
+        instances: Range<u32>,
     },
     Paint {
         pipeline: wgpu::RenderPipeline,
@@ -172,11 +220,21 @@ enum Kind {
         clear_pipeline: wgpu::ComputePipeline,
         copy_pipeline: wgpu::RenderPipeline,
         bind_group: wgpu::BindGroup,
+        storage_buffer: wgpu::Buffer,
+        extent: wgpu::Extent3d,
         compute_groups: [u32; 3],
         density: [u32; 3],
+        texel_density: Option<f32>,
     },
 }
 
+/// A copy of the scatter storage, waiting to be read back.
+struct ScatterDump {
+    readback: Readback,
+    extent: wgpu::Extent3d,
+    inverse_view_proj: cgmath::Matrix4<f32>,
+}
+
 pub struct Rect {
     pub x: u16,
     pub y: u16,
@@ -187,18 +245,41 @@ pub struct Rect {
 pub struct Context {
     pub surface_uni_buf: wgpu::Buffer,
     pub uniform_buf: wgpu::Buffer,
+    color_buf: wgpu::Buffer,
+    color_provider: u32,
+    debug_palette: u32,
+    color_filter: settings::TerrainFilter,
+    detail_params: [[f32; 4]; 8],
+    detail_distance: f32,
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
+    /// Level palette, bound to the terrain shaders.
+    pub palette: Palette,
     pipeline_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    sample_count: u32,
+    defines: Vec<(String, String)>,
     raytrace_geo: Geometry,
     kind: Kind,
     shadow_kind: Kind,
     dirty_rects: Vec<Rect>,
+    // bumped on every upload of the level data
+    revision: usize,
+    scatter_dump: Option<ScatterDump>,
+    height_stats: HeightStats,
+    height_precision: settings::TerrainHeight,
+    // height, meta, flood, and table
+    level_textures: Vec<wgpu::Texture>,
 }
 
 impl Context {
     fn create_ray_pipeline(
         layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+        defines: &[(String, String)],
         device: &wgpu::Device,
         name: &str,
         kind: PipelineKind,
@@ -217,19 +298,19 @@ impl Context {
         };
 
         let color_descs = [wgpu::ColorStateDescriptor {
-            format: COLOR_FORMAT,
+            format: color_format,
             alpha_blend: wgpu::BlendDescriptor::REPLACE,
             color_blend: wgpu::BlendDescriptor::REPLACE,
             write_mask: wgpu::ColorWrite::all(),
         }];
-        let (features, color_states, depth_format) = match kind {
-            PipelineKind::Main => (&["COLOR"][..], &color_descs[..], DEPTH_FORMAT),
-            PipelineKind::Shadow => (&[][..], &[][..], SHADOW_FORMAT),
+        let (features, color_states, depth_format, sample_count) = match kind {
+            PipelineKind::Main => (&["COLOR"][..], &color_descs[..], depth_format, sample_count),
+            PipelineKind::Shadow => (&[][..], &[][..], SHADOW_FORMAT, 1),
         };
 
-        let shaders = Shaders::new(name, features, device).unwrap();
+        let shaders = Shaders::new_with_defines(name, features, defines, device).unwrap();
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("terrain-ray"),
+            label: Some(&label::pipeline("terrain", "ray")),
             layout: Some(layout),
             vertex_stage: wgpu::ProgrammableStageDescriptor {
                 module: &shaders.vs,
@@ -253,7 +334,7 @@ impl Context {
                 stencil: Default::default(),
             }),
             vertex_state: vertex_state.clone(),
-            sample_count: 1,
+            sample_count,
             alpha_to_coverage_enabled: false,
             sample_mask: !0,
         })
@@ -261,11 +342,23 @@ impl Context {
 
     fn create_slice_pipeline(
         layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
         device: &wgpu::Device,
+        quality: settings::SliceQuality,
+        seam: settings::WorldSeam,
     ) -> wgpu::RenderPipeline {
-        let shaders = Shaders::new("terrain/slice", &[], device).unwrap();
+        let mut features = Vec::new();
+        if quality == settings::SliceQuality::High {
+            features.push("SMOOTH_HEIGHT");
+        }
+        if seam == settings::WorldSeam::Wrap {
+            features.push("WRAP_SEAM");
+        }
+        let shaders = Shaders::new("terrain/slice", &features, device).unwrap();
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("terrain-slice"),
+            label: Some(&label::pipeline("terrain", "slice")),
             layout: Some(layout),
             vertex_stage: wgpu::ProgrammableStageDescriptor {
                 module: &shaders.vs,
@@ -282,13 +375,13 @@ impl Context {
             }),
             primitive_topology: wgpu::PrimitiveTopology::TriangleList,
             color_states: &[wgpu::ColorStateDescriptor {
-                format: COLOR_FORMAT,
+                format: color_format,
                 alpha_blend: wgpu::BlendDescriptor::REPLACE,
                 color_blend: wgpu::BlendDescriptor::REPLACE,
                 write_mask: wgpu::ColorWrite::all(),
             }],
             depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: DEPTH_FORMAT,
+                format: depth_format,
                 depth_write_enabled: true,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: Default::default(),
@@ -305,7 +398,7 @@ impl Context {
                     }],
                 }],
             },
-            sample_count: 1,
+            sample_count,
             alpha_to_coverage_enabled: false,
             sample_mask: !0,
         })
@@ -313,11 +406,14 @@ impl Context {
 
     fn create_paint_pipeline(
         layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
         device: &wgpu::Device,
     ) -> wgpu::RenderPipeline {
         let shaders = Shaders::new("terrain/paint", &[], device).unwrap();
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("terrain-paint"),
+            label: Some(&label::pipeline("terrain", "paint")),
             layout: Some(layout),
             vertex_stage: wgpu::ProgrammableStageDescriptor {
                 module: &shaders.vs,
@@ -333,9 +429,9 @@ impl Context {
                 ..Default::default()
             }),
             primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[COLOR_FORMAT.into()],
+            color_states: &[color_format.into()],
             depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: DEPTH_FORMAT,
+                format: depth_format,
                 depth_write_enabled: true,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: Default::default(),
@@ -344,7 +440,7 @@ impl Context {
                 index_format: wgpu::IndexFormat::Uint16,
                 vertex_buffers: &[],
             },
-            sample_count: 1,
+            sample_count,
             alpha_to_coverage_enabled: false,
             sample_mask: !0,
         })
@@ -352,6 +448,9 @@ impl Context {
 
     fn create_scatter_pipelines(
         layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
         device: &wgpu::Device,
     ) -> (
         wgpu::ComputePipeline,
@@ -361,7 +460,7 @@ impl Context {
         let scatter_shader =
             Shaders::new_compute("terrain/scatter", SCATTER_GROUP_SIZE, &[], device).unwrap();
         let scatter_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("terrain-scatter"),
+            label: Some(&label::pipeline("terrain", "scatter")),
             layout: Some(layout),
             compute_stage: wgpu::ProgrammableStageDescriptor {
                 module: &scatter_shader,
@@ -371,7 +470,7 @@ impl Context {
         let clear_shader =
             Shaders::new_compute("terrain/scatter_clear", SCATTER_GROUP_SIZE, &[], device).unwrap();
         let clear_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("terrain-scatter-clear"),
+            label: Some(&label::pipeline("terrain", "scatter-clear")),
             layout: Some(layout),
             compute_stage: wgpu::ProgrammableStageDescriptor {
                 module: &clear_shader,
@@ -381,7 +480,7 @@ impl Context {
 
         let copy_shaders = Shaders::new("terrain/scatter_copy", &[], device).unwrap();
         let copy_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("terrain-scatter-copy"),
+            label: Some(&label::pipeline("terrain", "scatter-copy")),
             layout: Some(layout),
             vertex_stage: wgpu::ProgrammableStageDescriptor {
                 module: &copy_shaders.vs,
@@ -397,9 +496,9 @@ impl Context {
                 ..Default::default()
             }),
             primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
-            color_states: &[COLOR_FORMAT.into()],
+            color_states: &[color_format.into()],
             depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: DEPTH_FORMAT,
+                format: depth_format,
                 depth_write_enabled: true,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: Default::default(),
@@ -408,7 +507,7 @@ impl Context {
                 index_format: wgpu::IndexFormat::Uint16,
                 vertex_buffers: &[],
             },
-            sample_count: 1,
+            sample_count,
             alpha_to_coverage_enabled: false,
             sample_mask: !0,
         });
@@ -420,16 +519,19 @@ impl Context {
         extent: wgpu::Extent3d,
         layout: &wgpu::BindGroupLayout,
         device: &wgpu::Device,
-    ) -> (wgpu::BindGroup, [u32; 3]) {
-        let storage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Scatter"),
-            size: 4 * (extent.width * extent.height) as wgpu::BufferAddress,
-            usage: wgpu::BufferUsage::STORAGE,
-            mapped_at_creation: false,
-        });
+    ) -> (wgpu::BindGroup, wgpu::Buffer, [u32; 3]) {
+        let storage_buffer = resource::create_buffer(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some(&label::resource("terrain", "scatter")),
+                size: 4 * (extent.width * extent.height) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC,
+                mapped_at_creation: false,
+            },
+        );
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Scatter"),
+            label: Some(&label::resource("terrain", "scatter")),
             layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
@@ -443,16 +545,22 @@ impl Context {
                 + (extent.height % SCATTER_GROUP_SIZE[1]).min(1),
             1,
         ];
-        (bind_group, group_count)
+        (bind_group, storage_buffer, group_count)
     }
 
     pub fn new(
         device: &wgpu::Device,
-        queue: &wgpu::Queue,
+        upload: &mut UploadQueue,
         level: &level::Level,
         global: &GlobalContext,
         config: &settings::Terrain,
         shadow_config: &settings::ShadowTerrain,
+        color_filter: settings::TerrainFilter,
+        height_precision: settings::TerrainHeight,
+        seam: settings::WorldSeam,
+        color: &settings::TerrainColor,
+        detail: Option<&settings::TerrainDetail>,
+        defines: &[(String, String)],
         screen_extent: wgpu::Extent3d,
     ) -> Self {
         let extent = wgpu::Extent3d {
@@ -461,7 +569,7 @@ impl Context {
             depth: 1,
         };
         let flood_extent = wgpu::Extent3d {
-            width: level.size.1 as u32 >> level.flood_section_power,
+            width: level.flood_section_power.count_in(level.size.1) as u32,
             height: 1,
             depth: 1,
         };
@@ -472,7 +580,13 @@ impl Context {
         };
         let (terrain_mip_count, terrain_extra_usage) = match *config {
             settings::Terrain::RayMipTraced { mip_count, .. } => {
-                (mip_count, wgpu::TextureUsage::OUTPUT_ATTACHMENT)
+                let full_count = mipmap::full_mip_count(extent);
+                let count = if mip_count == 0 {
+                    full_count
+                } else {
+                    mip_count.min(full_count)
+                };
+                (count, wgpu::TextureUsage::OUTPUT_ATTACHMENT)
             }
             _ => (1, wgpu::TextureUsage::empty()),
         };
@@ -490,101 +604,116 @@ impl Context {
             })
             .collect::<Vec<_>>();
 
-        let height_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Terrain height"),
-            size: extent,
-            mip_level_count: terrain_mip_count,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: HEIGHT_FORMAT,
-            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST | terrain_extra_usage,
-        });
-        let meta_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Terrain meta"),
-            size: extent,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::R8Uint,
-            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-        });
-        let flood_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Terrain flood"),
-            size: flood_extent,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D1,
-            format: wgpu::TextureFormat::R8Unorm,
-            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-        });
-        let table_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Terrain table"),
-            size: table_extent,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D1,
-            format: wgpu::TextureFormat::Rgba8Uint,
-            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-        });
-
-        queue.write_texture(
-            wgpu::TextureCopyView {
-                texture: &height_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
+        let height_texture = resource::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some(&label::resource("terrain", "height")),
+                size: extent,
+                mip_level_count: terrain_mip_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: height_precision.to_wgpu(),
+                usage: wgpu::TextureUsage::SAMPLED
+                    | wgpu::TextureUsage::COPY_DST
+                    | terrain_extra_usage,
             },
-            bytemuck::cast_slice(&level.height),
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: level.size.0 as u32,
-                rows_per_image: 0,
+        );
+        let meta_texture = resource::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some(&label::resource("terrain", "meta")),
+                size: extent,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::R8Uint,
+                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
             },
-            extent,
         );
-        queue.write_texture(
-            wgpu::TextureCopyView {
-                texture: &meta_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
+        let flood_texture = resource::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some(&label::resource("terrain", "flood")),
+                size: flood_extent,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D1,
+                format: wgpu::TextureFormat::R8Unorm,
+                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
             },
-            bytemuck::cast_slice(&level.meta),
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: level.size.0 as u32,
-                rows_per_image: 0,
+        );
+        let table_texture = resource::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some(&label::resource("terrain", "table")),
+                size: table_extent,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D1,
+                format: wgpu::TextureFormat::Rgba8Uint,
+                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
             },
+        );
+
+        let full_rect = Rect {
+            x: 0,
+            y: 0,
+            w: level.size.0 as u16,
+            h: level.size.1 as u16,
+        };
+        let (height_data, height_texel_size) = height_texels(level, height_precision, &full_rect);
+        upload.write_texture(
+            &height_texture,
+            &height_data,
+            level.size.0 as u32 * height_texel_size as u32,
             extent,
         );
-        queue.write_texture(
-            wgpu::TextureCopyView {
-                texture: &flood_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            bytemuck::cast_slice(&level.flood_map),
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: flood_extent.width,
-                rows_per_image: 0,
-            },
+        upload.write_texture(&meta_texture, &level.meta, level.size.0 as u32, extent);
+        upload.write_texture(
+            &flood_texture,
+            &level.flood_map,
+            flood_extent.width,
             flood_extent,
         );
-        queue.write_texture(
-            wgpu::TextureCopyView {
-                texture: &table_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
+        upload.write_texture(
+            &table_texture,
             bytemuck::cast_slice(&terrrain_table),
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: table_extent.width * 4,
-                rows_per_image: 0,
-            },
+            table_extent.width * 4,
             table_extent,
         );
 
-        let palette = Palette::new(device, queue, &level.palette);
+        let (lut_extent, lut_data) = match *color {
+            settings::TerrainColor::Lut { ref path } => load_color_lut(path),
+            _ => (
+                wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth: 1,
+                },
+                vec![0xFF; 4],
+            ),
+        };
+        let lut_texture = resource::create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some(&label::resource("terrain", "color LUT")),
+                size: lut_extent,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+            },
+        );
+        upload.write_texture(&lut_texture, &lut_data, lut_extent.width * 4, lut_extent);
+        let color_provider = match *color {
+            settings::TerrainColor::Palette => 0,
+            settings::TerrainColor::Hypsometric => 1,
+            settings::TerrainColor::Meta => 2,
+            settings::TerrainColor::Lut { .. } => 3,
+        };
+
+        let palette = Palette::new(device, upload.queue(), &level.palette);
 
         let repeat_nearest_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::Repeat,
@@ -613,9 +742,18 @@ impl Context {
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         });
+        let color_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: color_filter.to_wgpu(),
+            min_filter: color_filter.to_wgpu(),
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Terrain"),
+            label: Some(&label::resource("terrain", "data")),
             entries: &[
                 // surface uniforms
                 wgpu::BindGroupLayoutEntry {
@@ -713,11 +851,39 @@ impl Context {
                     ty: wgpu::BindingType::Sampler { comparison: false },
                     count: None,
                 },
+                // color sampler
+                wgpu::BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+                // color uniforms
+                wgpu::BindGroupLayoutEntry {
+                    binding: 11,
+                    visibility: wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // color LUT
+                wgpu::BindGroupLayoutEntry {
+                    binding: 12,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Float,
+                    },
+                    count: None,
+                },
             ],
         });
 
         let surface_uni_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("surface-uniforms"),
+            label: Some(&label::resource("terrain", "surface uniforms")),
             contents: bytemuck::bytes_of(&SurfaceConstants {
                 _tex_scale: [
                     level.size.0 as f32,
@@ -729,14 +895,29 @@ impl Context {
             usage: wgpu::BufferUsage::UNIFORM,
         });
         let uniform_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Terrain uniforms"),
+            label: Some(&label::resource("terrain", "uniforms")),
             size: mem::size_of::<Constants>() as wgpu::BufferAddress,
             usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
             mapped_at_creation: false,
         });
 
+        let mut detail_params = [[0.0; 4]; 8];
+        let mut detail_distance = 1.0;
+        if let Some(detail) = detail {
+            for (i, params) in detail_params.iter_mut().enumerate() {
+                *params = [detail.scale[i], detail.intensity[i], 0.0, 0.0];
+            }
+            detail_distance = detail.fade_distance;
+        }
+        let color_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&label::resource("terrain", "color")),
+            size: mem::size_of::<ColorConstants>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Terrain"),
+            label: Some(&label::resource("terrain", "data")),
             layout: &bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -787,14 +968,33 @@ impl Context {
                     binding: 9,
                     resource: wgpu::BindingResource::Sampler(&table_sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::Sampler(&color_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: color_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: wgpu::BindingResource::TextureView(
+                        &lut_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
             ],
         });
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("terrain"),
+            label: Some(&label::pipeline("terrain", "draw")),
             bind_group_layouts: &[&global.bind_group_layout, &bind_group_layout],
             push_constant_ranges: &[],
         });
+        let height_stats = HeightStats::new(
+            device,
+            &bind_group_layout,
+            (level.size.0 as u32, level.size.1 as u32),
+        );
 
         let raytrace_geo = Geometry::new(
             &[
@@ -816,6 +1016,10 @@ impl Context {
             settings::Terrain::RayTraced => {
                 let pipeline = Self::create_ray_pipeline(
                     &pipeline_layout,
+                    global.color_format,
+                    global.depth_format,
+                    global.sample_count,
+                    defines,
                     device,
                     "terrain/ray",
                     PipelineKind::Main,
@@ -823,24 +1027,34 @@ impl Context {
                 Kind::Ray { pipeline }
             }
             settings::Terrain::RayMipTraced {
-                mip_count,
+                mip_count: _,
                 max_jumps,
                 max_steps,
                 debug,
             } => {
                 let pipeline = Self::create_ray_pipeline(
                     &pipeline_layout,
+                    global.color_format,
+                    global.depth_format,
+                    global.sample_count,
+                    defines,
                     device,
                     "terrain/ray_mip",
                     PipelineKind::Main,
                 );
-                let mipper = MaxMipper::new(&height_texture, extent, mip_count, device);
+                let mipper = MaxMipper::new(
+                    &height_texture,
+                    extent,
+                    height_precision.to_wgpu(),
+                    terrain_mip_count,
+                    device,
+                );
 
                 Kind::RayMip {
                     pipeline,
                     mipper,
                     params: [
-                        mip_count - 1,
+                        terrain_mip_count - 1,
                         max_jumps,
                         max_steps,
                         if debug { 1 } else { 0 },
@@ -848,7 +1062,7 @@ impl Context {
                 }
             }
             settings::Terrain::Tessellated { .. } => unimplemented!(),
-            settings::Terrain::Sliced => {
+            settings::Terrain::Sliced { quality } => {
                 let geo = Geometry::new(
                     &[
                         Vertex {
@@ -866,9 +1080,23 @@ impl Context {
                     device,
                 );
 
-                let pipeline = Self::create_slice_pipeline(&pipeline_layout, device);
+                let pipeline = Self::create_slice_pipeline(
+                    &pipeline_layout,
+                    global.color_format,
+                    global.depth_format,
+                    global.sample_count,
+                    device,
+                    quality,
+                    seam,
+                );
 
-                Kind::Slice { pipeline, geo }
+                Kind::Slice {
+                    pipeline,
+                    geo,
+                    quality,
+                    seam,
+                    instances: 0..level::HEIGHT_SCALE,
+                }
             }
             settings::Terrain::Painted => {
                 let geo = Geometry::new(
@@ -883,7 +1111,13 @@ impl Context {
                     device,
                 );
 
-                let pipeline = Self::create_paint_pipeline(&pipeline_layout, device);
+                let pipeline = Self::create_paint_pipeline(
+                    &pipeline_layout,
+                    global.color_format,
+                    global.depth_format,
+                    global.sample_count,
+                    device,
+                );
 
                 Kind::Paint {
                     pipeline,
@@ -891,10 +1125,13 @@ impl Context {
                     bar_count: 0,
                 }
             }
-            settings::Terrain::Scattered { density } => {
+            settings::Terrain::Scattered {
+                density,
+                texel_density,
+            } => {
                 let local_bg_layout =
                     device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                        label: Some("Terrain locals"),
+                        label: Some(&label::resource("terrain", "locals")),
                         entries: &[
                             // output map
                             wgpu::BindGroupLayoutEntry {
@@ -912,7 +1149,7 @@ impl Context {
                     });
                 let local_pipeline_layout =
                     device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: Some("scatter"),
+                        label: Some(&label::pipeline("terrain", "scatter-locals")),
                         bind_group_layouts: &[
                             &global.bind_group_layout,
                             &bind_group_layout,
@@ -922,8 +1159,14 @@ impl Context {
                     });
 
                 let (scatter_pipeline, clear_pipeline, copy_pipeline) =
-                    Self::create_scatter_pipelines(&local_pipeline_layout, device);
-                let (local_bg, compute_groups) =
+                    Self::create_scatter_pipelines(
+                        &local_pipeline_layout,
+                        global.color_format,
+                        global.depth_format,
+                        global.sample_count,
+                        device,
+                    );
+                let (local_bg, storage_buffer, compute_groups) =
                     Self::create_scatter_resources(screen_extent, &local_bg_layout, device);
                 Kind::Scatter {
                     pipeline_layout: local_pipeline_layout,
@@ -932,8 +1175,11 @@ impl Context {
                     clear_pipeline,
                     copy_pipeline,
                     bind_group: local_bg,
+                    storage_buffer,
+                    extent: screen_extent,
                     compute_groups,
                     density,
+                    texel_density,
                 }
             }
         };
@@ -942,6 +1188,10 @@ impl Context {
             settings::ShadowTerrain::RayTraced => {
                 let pipeline = Self::create_ray_pipeline(
                     &pipeline_layout,
+                    global.color_format,
+                    global.depth_format,
+                    global.sample_count,
+                    defines,
                     device,
                     "terrain/ray",
                     PipelineKind::Shadow,
@@ -953,9 +1203,20 @@ impl Context {
         Context {
             surface_uni_buf,
             uniform_buf,
+            color_buf,
+            color_provider,
+            debug_palette: 0,
+            color_filter,
+            detail_params,
+            detail_distance,
             bind_group,
             bind_group_layout,
+            palette,
             pipeline_layout,
+            color_format: global.color_format,
+            depth_format: global.depth_format,
+            sample_count: global.sample_count,
+            defines: defines.to_vec(),
             raytrace_geo,
             kind,
             shadow_kind,
@@ -965,16 +1226,234 @@ impl Context {
                 w: level.size.0 as u16,
                 h: level.size.1 as u16,
             }],
+            revision: 1,
+            scatter_dump: None,
+            height_stats,
+            height_precision,
+            level_textures: vec![
+                height_texture,
+                meta_texture,
+                flood_texture,
+                table_texture,
+                lut_texture,
+            ],
+        }
+    }
+
+    /// Destroy the level textures and the storage of the current technique.
+    pub fn destroy(self) {
+        self.height_stats.destroy();
+        for texture in self.level_textures {
+            resource::destroy_texture(texture);
+        }
+        for kind in vec![self.kind, self.shadow_kind] {
+            if let Kind::Scatter { storage_buffer, .. } = kind {
+                resource::destroy_buffer(storage_buffer);
+            }
+        }
+    }
+
+    fn upload_rect(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        level: &level::Level,
+        rect: Rect,
+    ) {
+        match self.height_precision {
+            settings::TerrainHeight::Low => self.upload_texels(device, encoder, level, rect),
+            // the smoothing spreads the changes to the neighbors,
+            // which wrap around the edges of the level
+            settings::TerrainHeight::Smooth => {
+                let xs = wrap_span(rect.x as i32 - 1, rect.w as i32 + 2, level.size.0);
+                let ys = wrap_span(rect.y as i32 - 1, rect.h as i32 + 2, level.size.1);
+                for &(y, h) in ys.iter() {
+                    for &(x, w) in xs.iter() {
+                        self.upload_texels(device, encoder, level, Rect { x, y, w, h });
+                    }
+                }
+            }
+        }
+    }
+
+    fn upload_texels(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        level: &level::Level,
+        rect: Rect,
+    ) {
+        let (x, y, w, h) = (
+            rect.x as usize,
+            rect.y as usize,
+            rect.w as usize,
+            rect.h as usize,
+        );
+        // height and meta textures
+        let sources = [
+            height_texels(level, self.height_precision, &rect),
+            (rect_texels(&level.meta, level.size.0 as usize, &rect), 1),
+        ];
+        for (texture, &(ref source, texel_size)) in self.level_textures[..2].iter().zip(&sources) {
+            let row_size = w * texel_size;
+            let pitch = padded_bytes_per_row(row_size as u32) as usize;
+            let mut data = vec![0u8; pitch * h];
+            for (dst, src) in data.chunks_mut(pitch).zip(source.chunks(row_size)) {
+                dst[..row_size].copy_from_slice(src);
+            }
+            let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&label::temp("terrain-rect")),
+                contents: &data,
+                usage: wgpu::BufferUsage::COPY_SRC,
+            });
+            encoder.copy_buffer_to_texture(
+                wgpu::BufferCopyView {
+                    buffer: &staging,
+                    layout: wgpu::TextureDataLayout {
+                        offset: 0,
+                        bytes_per_row: pitch as u32,
+                        rows_per_image: 0,
+                    },
+                },
+                wgpu::TextureCopyView {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: x as u32,
+                        y: y as u32,
+                        z: 0,
+                    },
+                },
+                wgpu::Extent3d {
+                    width: w as u32,
+                    height: h as u32,
+                    depth: 1,
+                },
+            );
         }
+        self.dirty_rects.push(rect);
+        self.revision += 1;
+    }
+
+    /// Upload the given rows of the level data, e.g. as they are streamed in.
+    pub fn update_rows(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        level: &level::Level,
+        rows: Range<i32>,
+    ) {
+        let rect = Rect {
+            x: 0,
+            y: rows.start as u16,
+            w: level.size.0 as u16,
+            h: (rows.end - rows.start) as u16,
+        };
+        self.upload_rect(device, encoder, level, rect);
+    }
+
+    /// Upload the areas of the level modified since the last call,
+    /// see `Level::set_height` and `Level::set_meta`.
+    pub fn update_regions(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        level: &mut level::Level,
+    ) {
+        for r in level.dirty.take() {
+            let rect = Rect {
+                x: r.x as u16,
+                y: r.y as u16,
+                w: r.w as u16,
+                h: r.h as u16,
+            };
+            self.upload_rect(device, encoder, level, rect);
+        }
+    }
+
+    /// Upload the flood map of the level, e.g. after it's reloaded from disk.
+    pub fn update_flood(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        level: &level::Level,
+    ) {
+        let width = level.flood_map.len();
+        let pitch = padded_bytes_per_row(width as u32) as usize;
+        let mut data = vec![0u8; pitch];
+        data[..width].copy_from_slice(&level.flood_map);
+        let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&label::temp("flood")),
+            contents: &data,
+            usage: wgpu::BufferUsage::COPY_SRC,
+        });
+        encoder.copy_buffer_to_texture(
+            wgpu::BufferCopyView {
+                buffer: &staging,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: pitch as u32,
+                    rows_per_image: 0,
+                },
+            },
+            wgpu::TextureCopyView {
+                texture: &self.level_textures[2],
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::Extent3d {
+                width: width as u32,
+                height: 1,
+                depth: 1,
+            },
+        );
+        self.revision += 1;
+    }
+
+    /// Select the palette of the debug colors, used by the `Meta` terrain color.
+    pub fn set_debug_palette(&mut self, palette: settings::DebugPalette) {
+        self.debug_palette = match palette {
+            settings::DebugPalette::Default => 0,
+            settings::DebugPalette::Deuteranopia => 1,
+            settings::DebugPalette::Protanopia => 2,
+        };
+    }
+
+    /// Counter of the level data changes, for the views derived from it.
+    pub fn revision(&self) -> usize {
+        self.revision
+    }
+
+    /// Per-tile statistics of the height map, kept up to date with the level.
+    pub fn height_stats(&self) -> &HeightStats {
+        &self.height_stats
+    }
+
+    /// Replace the shader defines, and rebuild the pipelines with them.
+    pub fn set_defines(&mut self, defines: &[(String, String)], device: &wgpu::Device) {
+        self.defines = defines.to_vec();
+        self.reload(device);
+    }
+
+    /// Re-create the pipelines for the target formats of the global context.
+    pub fn set_target_formats(&mut self, global: &GlobalContext, device: &wgpu::Device) {
+        self.color_format = global.color_format;
+        self.depth_format = global.depth_format;
+        self.reload(device);
     }
 
     pub fn reload(&mut self, device: &wgpu::Device) {
+        self.height_stats.reload(device);
         match self.kind {
             Kind::Ray {
                 ref mut pipeline, ..
             } => {
                 *pipeline = Self::create_ray_pipeline(
                     &self.pipeline_layout,
+                    self.color_format,
+                    self.depth_format,
+                    self.sample_count,
+                    &self.defines,
                     device,
                     "terrain/ray",
                     PipelineKind::Main,
@@ -987,6 +1466,10 @@ impl Context {
             } => {
                 *pipeline = Self::create_ray_pipeline(
                     &self.pipeline_layout,
+                    self.color_format,
+                    self.depth_format,
+                    self.sample_count,
+                    &self.defines,
                     device,
                     "terrain/ray_mip",
                     PipelineKind::Main,
@@ -1000,14 +1483,31 @@ impl Context {
                 *high = hi;
             }*/
             Kind::Slice {
-                ref mut pipeline, ..
+                ref mut pipeline,
+                quality,
+                seam,
+                ..
             } => {
-                *pipeline = Self::create_slice_pipeline(&self.pipeline_layout, device);
+                *pipeline = Self::create_slice_pipeline(
+                    &self.pipeline_layout,
+                    self.color_format,
+                    self.depth_format,
+                    self.sample_count,
+                    device,
+                    quality,
+                    seam,
+                );
             }
             Kind::Paint {
                 ref mut pipeline, ..
             } => {
-                *pipeline = Self::create_paint_pipeline(&self.pipeline_layout, device);
+                *pipeline = Self::create_paint_pipeline(
+                    &self.pipeline_layout,
+                    self.color_format,
+                    self.depth_format,
+                    self.sample_count,
+                    device,
+                );
             }
             Kind::Scatter {
                 ref pipeline_layout,
@@ -1016,8 +1516,13 @@ impl Context {
                 ref mut copy_pipeline,
                 ..
             } => {
-                let (scatter, clear, copy) =
-                    Self::create_scatter_pipelines(pipeline_layout, device);
+                let (scatter, clear, copy) = Self::create_scatter_pipelines(
+                    pipeline_layout,
+                    self.color_format,
+                    self.depth_format,
+                    self.sample_count,
+                    device,
+                );
                 *scatter_pipeline = scatter;
                 *clear_pipeline = clear;
                 *copy_pipeline = copy;
@@ -1030,6 +1535,10 @@ impl Context {
             } => {
                 *pipeline = Self::create_ray_pipeline(
                     &self.pipeline_layout,
+                    self.color_format,
+                    self.depth_format,
+                    self.sample_count,
+                    &self.defines,
                     device,
                     "terrain/ray",
                     PipelineKind::Shadow,
@@ -1044,11 +1553,15 @@ impl Context {
             Kind::Scatter {
                 ref bg_layout,
                 ref mut bind_group,
+                ref mut storage_buffer,
+                extent: ref mut scatter_extent,
                 ref mut compute_groups,
                 ..
             } => {
-                let (bg, gs) = Self::create_scatter_resources(extent, bg_layout, device);
+                let (bg, buf, gs) = Self::create_scatter_resources(extent, bg_layout, device);
                 *bind_group = bg;
+                resource::destroy_buffer(mem::replace(storage_buffer, buf));
+                *scatter_extent = extent;
                 *compute_groups = gs;
             }
             _ => {}
@@ -1061,6 +1574,7 @@ impl Context {
         device: &wgpu::Device,
         global: &GlobalContext,
         fog: &settings::Fog,
+        wetness: f32,
         cam: &Camera,
         screen_size: wgpu::Extent3d,
     ) {
@@ -1068,6 +1582,7 @@ impl Context {
             if let Kind::RayMip { ref mipper, .. } = self.kind {
                 mipper.update(&self.dirty_rects, encoder, device);
             }
+            self.height_stats.update(encoder, &self.bind_group);
             self.dirty_rects.clear();
         }
 
@@ -1093,7 +1608,7 @@ impl Context {
             // constants update
             let depth_range = cam.depth_range();
             let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("temp-constants"),
+                label: Some(&label::temp("constants")),
                 contents: bytemuck::bytes_of(&Constants {
                     screen_size: [screen_size.width, screen_size.height, 0, 0],
                     params,
@@ -1116,9 +1631,42 @@ impl Context {
                 0,
                 mem::size_of::<Constants>() as wgpu::BufferAddress,
             );
+
+            let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&label::temp("color")),
+                contents: bytemuck::bytes_of(&ColorConstants {
+                    provider: [
+                        self.color_provider,
+                        self.debug_palette,
+                        self.color_filter as u32,
+                        0,
+                    ],
+                    detail_camera: cam.loc.extend(self.detail_distance).into(),
+                    detail_params: self.detail_params,
+                    weather: [wetness, 0.0, 0.0, 0.0],
+                }),
+                usage: wgpu::BufferUsage::COPY_SRC,
+            });
+            encoder.copy_buffer_to_buffer(
+                &staging,
+                0,
+                &self.color_buf,
+                0,
+                mem::size_of::<ColorConstants>() as wgpu::BufferAddress,
+            );
         }
 
         match self.kind {
+            Kind::Slice {
+                ref mut instances, ..
+            } => {
+                // slice N is drawn at the height of `HEIGHT_SCALE - N - 1`
+                let heights = cam.height_range();
+                let top = level::HEIGHT_SCALE as f32 - 1.0;
+                let start = (top - heights.end).floor().max(0.0) as u32;
+                let end = (top - heights.start + 1.0).ceil().max(0.0) as u32;
+                *instances = start.min(level::HEIGHT_SCALE)..end.min(level::HEIGHT_SCALE);
+            }
             Kind::Paint {
                 ref mut bar_count, ..
             } => {
@@ -1139,20 +1687,27 @@ impl Context {
                 ref bind_group,
                 compute_groups,
                 density,
+                texel_density,
                 ..
             } => {
+                let max_groups = [
+                    compute_groups[0] * density[0],
+                    compute_groups[1] * density[1],
+                ];
+                let scatter_groups = match texel_density {
+                    Some(texel_density) => compute_scatter_groups(&sc, texel_density, max_groups),
+                    None => max_groups,
+                };
                 let mut pass = encoder.begin_compute_pass();
+                pass.push_debug_group(&label::group("terrain", "scatter"));
                 pass.set_bind_group(0, &global.bind_group, &[]);
                 pass.set_bind_group(1, &self.bind_group, &[]);
                 pass.set_bind_group(2, bind_group, &[]);
                 pass.set_pipeline(clear_pipeline);
                 pass.dispatch(compute_groups[0], compute_groups[1], compute_groups[2]);
                 pass.set_pipeline(scatter_pipeline);
-                pass.dispatch(
-                    compute_groups[0] * density[0],
-                    compute_groups[1] * density[1],
-                    density[2],
-                );
+                pass.dispatch(scatter_groups[0], scatter_groups[1], density[2]);
+                pass.pop_debug_group();
             }
             _ => {}
         }
@@ -1182,7 +1737,7 @@ impl Context {
         {
             // constants update
             let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("temp-constants"),
+                label: Some(&label::temp("constants")),
                 contents: bytemuck::bytes_of(&Constants {
                     screen_size: [screen_size.width, screen_size.height, 0, 0],
                     params,
@@ -1227,11 +1782,13 @@ impl Context {
             Kind::Slice {
                 ref pipeline,
                 ref geo,
+                ref instances,
+                ..
             } => {
                 pass.set_pipeline(pipeline);
                 pass.set_index_buffer(geo.index_buf.slice(..));
                 pass.set_vertex_buffer(0, geo.vertex_buf.slice(..));
-                pass.draw_indexed(0..geo.num_indices, 0, 0..level::HEIGHT_SCALE);
+                pass.draw_indexed(0..geo.num_indices, 0, instances.clone());
             }
             Kind::Paint {
                 ref pipeline,
@@ -1254,6 +1811,101 @@ impl Context {
         }
     }
 
+    /// Schedule a copy of the scattered terrain points for the read back.
+    /// Has to be called after the terrain is drawn for this frame.
+    pub fn produce_scatter_dump(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        cam: &Camera,
+    ) {
+        let (storage_buffer, extent) = match self.kind {
+            Kind::Scatter {
+                ref storage_buffer,
+                extent,
+                ..
+            } => (storage_buffer, extent),
+            _ => {
+                warn!("Scatter dump is only available for the scattered terrain");
+                return;
+            }
+        };
+
+        let size = 4 * (extent.width * extent.height) as wgpu::BufferAddress;
+        let readback = Readback::from_buffer(
+            device,
+            encoder,
+            &label::temp("scatter-dump"),
+            storage_buffer,
+            0,
+            size,
+        );
+
+        self.scatter_dump = Some(ScatterDump {
+            readback,
+            extent,
+            inverse_view_proj: cam.inverse_view_proj(),
+        });
+    }
+
+    /// Read back the scattered points and write them out as a PLY point cloud.
+    pub fn consume_scatter_dump(
+        &mut self,
+        path: PathBuf,
+        palette: &[[u8; 4]; 0x100],
+        spawner: &LocalSpawner,
+    ) {
+        let ScatterDump {
+            readback,
+            extent,
+            inverse_view_proj,
+        } = match self.scatter_dump.take() {
+            Some(dump) => dump,
+            None => return,
+        };
+
+        let palette = *palette;
+        readback.consume(spawner, move |result| {
+            let mapping = match result {
+                Ok(mapping) => mapping,
+                Err(e) => {
+                    error!("Unable to map the scatter dump: {:?}", e);
+                    return;
+                }
+            };
+            let data = unsafe {
+                slice::from_raw_parts(
+                    mapping.as_ptr() as *const u32,
+                    (extent.width * extent.height) as usize,
+                )
+            };
+
+            let mut points = Vec::new();
+            for (i, &value) in data.iter().enumerate() {
+                let depth = value >> 8;
+                // cleared entries have the maximum depth
+                if depth >= 0xFFFFFF {
+                    continue;
+                }
+                let x = (i as u32 % extent.width) as f32 / extent.width as f32;
+                let y = (i as u32 / extent.width) as f32 / extent.height as f32;
+                let ndc = cgmath::vec4(
+                    x * 2.0 - 1.0,
+                    1.0 - y * 2.0,
+                    depth as f32 / 0xFFFFFF as f32,
+                    1.0,
+                );
+                let pos = inverse_view_proj * ndc;
+                points.push((pos.truncate() / pos.w, palette[(value & 0xFF) as usize]));
+            }
+
+            match write_ply(&path, &points) {
+                Ok(()) => info!("Exported {} scattered points to {:?}", points.len(), path),
+                Err(e) => error!("Unable to write {:?}: {}", path, e),
+            }
+        });
+    }
+
     pub fn draw_shadow<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
         pass.set_bind_group(1, &self.bind_group, &[]);
         // draw terrain
@@ -1269,3 +1921,146 @@ impl Context {
         }
     }
 }
+
+/// Copy the rectangle out of the level data with the given row width.
+fn rect_texels(source: &[u8], width: usize, rect: &Rect) -> Vec<u8> {
+    let (x, w) = (rect.x as usize, rect.w as usize);
+    let mut data = Vec::with_capacity(w * rect.h as usize);
+    for y in rect.y as usize..(rect.y + rect.h) as usize {
+        let offset = y * width + x;
+        data.extend_from_slice(&source[offset..offset + w]);
+    }
+    data
+}
+
+/// Convert a non-negative value to the bits of a half float, flushing the denormals to zero.
+fn f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+    if exponent <= 0 {
+        0
+    } else if exponent >= 0x1F {
+        0x7C00
+    } else {
+        let mantissa = bits & 0x7F_FFFF;
+        // rounding may carry into the exponent, which is still correct
+        (((exponent as u32) << 10 | mantissa >> 13) + ((mantissa >> 12) & 1)) as u16
+    }
+}
+
+/// Cubic B-spline weights at the texel centers.
+const SMOOTH_WEIGHTS: [f32; 3] = [1.0 / 6.0, 4.0 / 6.0, 1.0 / 6.0];
+
+/// Height at the texel smoothed with the bicubic B-spline, normalized.
+///
+/// The texels of the double level keep the original heights, since the pairs hold
+/// both the lower and the upper level. The result stays within one step of the original,
+/// so only the quantization terraces are removed, and the cliffs remain sharp.
+fn smooth_height(level: &level::Level, x: i32, y: i32) -> f32 {
+    let (width, height) = level.size;
+    let original = level.height[(y * width + x) as usize] as f32;
+    let mut sum = 0.0;
+    for (dy, wy) in SMOOTH_WEIGHTS.iter().enumerate() {
+        for (dx, wx) in SMOOTH_WEIGHTS.iter().enumerate() {
+            let sx = (x + dx as i32 - 1).rem_euclid(width);
+            let sy = (y + dy as i32 - 1).rem_euclid(height);
+            let index = (sy * width + sx) as usize;
+            if level.meta[index] & level::DOUBLE_LEVEL != 0 {
+                return original / 255.0;
+            }
+            sum += wx * wy * level.height[index] as f32;
+        }
+    }
+    sum.max(original - 1.0).min(original + 1.0) / 255.0
+}
+
+/// Height texels of the rectangle in the given precision, and the size of a texel in bytes.
+fn height_texels(
+    level: &level::Level,
+    precision: settings::TerrainHeight,
+    rect: &Rect,
+) -> (Vec<u8>, usize) {
+    match precision {
+        settings::TerrainHeight::Low => {
+            (rect_texels(&level.height, level.size.0 as usize, rect), 1)
+        }
+        settings::TerrainHeight::Smooth => {
+            let mut data = Vec::with_capacity(rect.w as usize * rect.h as usize * 2);
+            for y in rect.y as i32..(rect.y + rect.h) as i32 {
+                for x in rect.x as i32..(rect.x + rect.w) as i32 {
+                    let bits = f16_bits(smooth_height(level, x, y));
+                    data.extend_from_slice(&bits.to_le_bytes());
+                }
+            }
+            (data, 2)
+        }
+    }
+}
+
+/// Split the span of texels into the parts within the level size,
+/// wrapping around the edges, as pairs of the start and the length.
+fn wrap_span(start: i32, length: i32, size: i32) -> Vec<(u16, u16)> {
+    if length >= size {
+        return vec![(0, size as u16)];
+    }
+    let start = start.rem_euclid(size);
+    if start + length <= size {
+        vec![(start as u16, length as u16)]
+    } else {
+        vec![
+            (start as u16, (size - start) as u16),
+            (0, (start + length - size) as u16),
+        ]
+    }
+}
+
+/// Load the color table, converting it to RGBA.
+fn load_color_lut(path: &Path) -> (wgpu::Extent3d, Vec<u8>) {
+    let file = File::open(path).unwrap_or_else(|e| panic!("Unable to open {:?}: {}", path, e));
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info().unwrap();
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data).unwrap();
+    let data = match info.color_type {
+        png::ColorType::RGBA => data,
+        png::ColorType::RGB => data
+            .chunks(3)
+            .flat_map(|c| vec![c[0], c[1], c[2], 0xFF])
+            .collect(),
+        png::ColorType::Grayscale => data.iter().flat_map(|&g| vec![g, g, g, 0xFF]).collect(),
+        png::ColorType::GrayscaleAlpha => data
+            .chunks(2)
+            .flat_map(|c| vec![c[0], c[0], c[0], c[1]])
+            .collect(),
+        png::ColorType::Indexed => unreachable!("Palette is expanded"),
+    };
+    let extent = wgpu::Extent3d {
+        width: info.width,
+        height: info.height,
+        depth: 1,
+    };
+    (extent, data)
+}
+
+fn write_ply(path: &Path, points: &[(cgmath::Vector3<f32>, [u8; 4])]) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "ply")?;
+    writeln!(out, "format ascii 1.0")?;
+    writeln!(out, "element vertex {}", points.len())?;
+    for name in &["x", "y", "z"] {
+        writeln!(out, "property float {}", name)?;
+    }
+    for name in &["red", "green", "blue"] {
+        writeln!(out, "property uchar {}", name)?;
+    }
+    writeln!(out, "end_header")?;
+    for &(pos, color) in points {
+        writeln!(
+            out,
+            "{} {} {} {} {} {}",
+            pos.x, pos.y, pos.z, color[0], color[1], color[2]
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+//! Uploads of the large textures, split across several submissions.
+//!
+//! The height map of a big level is tens of megabytes, and copying it at once
+//! stalls the first frame, or even loses the device on the low-end GPUs.
+
+/// Writes the textures by chunks of rows, submitting the pending copies
+/// whenever the chunk size is reached.
+pub struct UploadQueue<'a> {
+    queue: &'a wgpu::Queue,
+    /// Bytes per submission, 0 for no limit.
+    chunk_size: usize,
+    pending: usize,
+    /// Number of the submissions made so far.
+    pub submissions: usize,
+}
+
+impl<'a> UploadQueue<'a> {
+    pub fn new(queue: &'a wgpu::Queue, chunk_size: usize) -> Self {
+        UploadQueue {
+            queue,
+            chunk_size,
+            pending: 0,
+            submissions: 0,
+        }
+    }
+
+    /// Queue for the small writes, which don't need to be split.
+    pub fn queue(&self) -> &'a wgpu::Queue {
+        self.queue
+    }
+
+    /// Submit the copies written so far.
+    pub fn flush(&mut self) {
+        if self.pending != 0 {
+            self.queue.submit(None);
+            self.pending = 0;
+            self.submissions += 1;
+        }
+    }
+
+    /// Write the mip 0 of a texture. The rows are tightly packed in the `data`.
+    pub fn write_texture(
+        &mut self,
+        texture: &wgpu::Texture,
+        data: &[u8],
+        bytes_per_row: u32,
+        extent: wgpu::Extent3d,
+    ) {
+        let rows_per_chunk = if self.chunk_size == 0 {
+            extent.height
+        } else {
+            (self.chunk_size / bytes_per_row as usize).max(1) as u32
+        };
+        let mut y = 0;
+        while y < extent.height {
+            let rows = rows_per_chunk.min(extent.height - y);
+            let size = (rows * bytes_per_row) as usize;
+            if self.chunk_size != 0 && self.pending + size > self.chunk_size {
+                self.flush();
+            }
+            let offset = (y * bytes_per_row) as usize;
+            self.queue.write_texture(
+                wgpu::TextureCopyView {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y, z: 0 },
+                },
+                &data[offset..offset + size],
+                wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row,
+                    rows_per_image: 0,
+                },
+                wgpu::Extent3d {
+                    width: extent.width,
+                    height: rows,
+                    depth: 1,
+                },
+            );
+            self.pending += size;
+            y += rows;
+        }
+    }
+}
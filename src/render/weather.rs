@@ -0,0 +1,316 @@
+//! Precipitation particles around the camera, colliding with the terrain.
+//!
+//! The particles are simulated on the CPU and drawn as streaks by a custom pass.
+//! Rain also makes the terrain wet over time, which darkens it.
+
+use crate::{
+    config::settings::{Precipitation, Weather as Config},
+    level,
+    render::{
+        custom::{CustomPass, FrameContext, PassStage},
+        global::Context as GlobalContext,
+        label, Shaders,
+    },
+};
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::prelude::*;
+use rand::Rng as _;
+use wgpu::util::DeviceExt as _;
+
+use std::mem;
+
+/// Time of the motion shown by a streak, in seconds.
+const STREAK_TIME: f32 = 0.02;
+/// Time a splash or a dust puff stays visible.
+const SPLASH_LIFETIME: f32 = 0.3;
+/// Number of particles in a splash.
+const SPLASH_PARTICLES: usize = 3;
+/// Time to get the terrain fully wet by the rain, and to dry it.
+const WETTING_TIME: f32 = 30.0;
+const DRYING_TIME: f32 = 90.0;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Vertex {
+    pos: [f32; 4],
+    color: [f32; 4],
+}
+unsafe impl Pod for Vertex {}
+unsafe impl Zeroable for Vertex {}
+
+struct Particle {
+    pos: cgmath::Vector3<f32>,
+    vel: cgmath::Vector3<f32>,
+}
+
+struct Splash {
+    particle: Particle,
+    age: f32,
+}
+
+pub struct Weather {
+    config: Config,
+    particles: Vec<Particle>,
+    splashes: Vec<Splash>,
+    wetness: f32,
+    vertices: Vec<Vertex>,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Weather {
+    fn create_pipeline(
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+        device: &wgpu::Device,
+    ) -> wgpu::RenderPipeline {
+        let shaders = Shaders::new("weather", &[], device).unwrap();
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&label::pipeline("weather", "precipitation")),
+            layout: Some(layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &shaders.vs,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &shaders.fs,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                ..Default::default()
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::LineList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: color_format,
+                alpha_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                write_mask: wgpu::ColorWrite::all(),
+            }],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: Default::default(),
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: mem::size_of::<Vertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float4, 1 => Float4],
+                }],
+            },
+            sample_count,
+            alpha_to_coverage_enabled: false,
+            sample_mask: !0,
+        })
+    }
+
+    pub fn new(config: &Config, global: &GlobalContext, device: &wgpu::Device) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&label::pipeline("weather", "precipitation")),
+            bind_group_layouts: &[&global.bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(
+            &pipeline_layout,
+            global.color_format,
+            global.depth_format,
+            global.sample_count,
+            device,
+        );
+
+        Weather {
+            config: config.clone(),
+            particles: Vec::with_capacity(config.count),
+            splashes: Vec::new(),
+            wetness: 0.0,
+            vertices: Vec::new(),
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    pub fn reload(&mut self, global: &GlobalContext, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(
+            &self.pipeline_layout,
+            global.color_format,
+            global.depth_format,
+            global.sample_count,
+            device,
+        );
+    }
+
+    /// Amount of the rain water on the terrain, from 0 to 1.
+    pub fn wetness(&self) -> f32 {
+        self.wetness
+    }
+
+    fn spawn(&self, center: cgmath::Vector3<f32>, height: f32) -> Particle {
+        let mut rng = rand::thread_rng();
+        let r = self.config.radius;
+        let speed = self.config.speed * rng.gen_range(0.8, 1.2);
+        Particle {
+            pos: center
+                + cgmath::vec3(
+                    rng.gen_range(-r, r),
+                    rng.gen_range(-r, r),
+                    rng.gen_range(0.0, height),
+                ),
+            vel: cgmath::vec3(self.config.wind.0, self.config.wind.1, -speed),
+        }
+    }
+
+    /// Advance the particles around the `center` point, usually the camera focus.
+    pub fn update(&mut self, dt: f32, center: cgmath::Vector3<f32>, level: &level::Level) {
+        let mut rng = rand::thread_rng();
+        let top = center.z + self.config.height;
+        // fill the whole volume at start, spawn at the top later on
+        while self.particles.len() < self.config.count {
+            let p = self.spawn(center, self.config.height);
+            self.particles.push(p);
+        }
+
+        let (splash_color, splash_speed) = match self.config.kind {
+            Precipitation::Rain => ([0.7, 0.8, 1.0, 0.6], 10.0),
+            Precipitation::Sand => ([0.8, 0.7, 0.5, 0.4], 4.0),
+        };
+        let r = self.config.radius;
+        for i in 0..self.particles.len() {
+            let p = &mut self.particles[i];
+            p.pos += p.vel * dt;
+            // keep the particles in the box around the center
+            let offset = p.pos.truncate() - center.truncate();
+            if offset.x.abs() > r {
+                p.pos.x -= 2.0 * r * offset.x.signum();
+            }
+            if offset.y.abs() > r {
+                p.pos.y -= 2.0 * r * offset.y.signum();
+            }
+            // the drops keep falling through the gap under the upper layer
+            let ground = match level.collision_surface(p.pos) {
+                Some((height, _)) if p.pos.z <= height => height,
+                _ => continue,
+            };
+            let hit = cgmath::vec3(p.pos.x, p.pos.y, ground);
+            for _ in 0..SPLASH_PARTICLES {
+                let dir = cgmath::vec3(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0), 1.5);
+                self.splashes.push(Splash {
+                    particle: Particle {
+                        pos: hit,
+                        vel: dir * splash_speed,
+                    },
+                    age: 0.0,
+                });
+            }
+            let mut fresh = self.spawn(center, 0.0);
+            fresh.pos.z = top;
+            self.particles[i] = fresh;
+        }
+
+        for splash in self.splashes.iter_mut() {
+            splash.age += dt;
+            splash.particle.vel.z -= 2.0 * splash_speed * dt / SPLASH_LIFETIME;
+            splash.particle.pos += splash.particle.vel * dt;
+        }
+        self.splashes.retain(|s| s.age < SPLASH_LIFETIME);
+
+        self.wetness = match self.config.kind {
+            Precipitation::Rain => (self.wetness + dt / WETTING_TIME).min(1.0),
+            Precipitation::Sand => (self.wetness - dt / DRYING_TIME).max(0.0),
+        };
+
+        let particle_color = match self.config.kind {
+            Precipitation::Rain => [0.6, 0.7, 0.9, 0.5],
+            Precipitation::Sand => [0.7, 0.6, 0.4, 0.7],
+        };
+        self.vertices.clear();
+        for p in self.particles.iter() {
+            let tail = p.pos - p.vel * STREAK_TIME;
+            self.vertices.push(Vertex {
+                pos: tail.extend(1.0).into(),
+                color: particle_color,
+            });
+            self.vertices.push(Vertex {
+                pos: p.pos.extend(1.0).into(),
+                color: particle_color,
+            });
+        }
+        for s in self.splashes.iter() {
+            let fade = 1.0 - s.age / SPLASH_LIFETIME;
+            let color = [
+                splash_color[0],
+                splash_color[1],
+                splash_color[2],
+                splash_color[3] * fade,
+            ];
+            let tail = s.particle.pos - s.particle.vel * STREAK_TIME;
+            self.vertices.push(Vertex {
+                pos: tail.extend(1.0).into(),
+                color,
+            });
+            self.vertices.push(Vertex {
+                pos: s.particle.pos.extend(1.0).into(),
+                color,
+            });
+        }
+    }
+}
+
+impl CustomPass for Weather {
+    fn stage(&self) -> PassStage {
+        PassStage::World
+    }
+
+    fn record(&mut self, frame: &mut FrameContext) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        let vertex_buf = frame
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&label::temp("weather-vertex")),
+                contents: bytemuck::cast_slice(&self.vertices),
+                usage: wgpu::BufferUsage::VERTEX,
+            });
+        let mut pass = frame
+            .encoder
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: frame.targets.color,
+                    resolve_target: frame.resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: frame.targets.depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+        pass.push_debug_group(&label::group("weather", "precipitation"));
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &frame.global.bind_group, &[]);
+        pass.set_vertex_buffer(0, vertex_buf.slice(..));
+        pass.draw(0..self.vertices.len() as u32, 0..1);
+        pass.pop_debug_group();
+    }
+}
@@ -0,0 +1,95 @@
+//! Recorded driver input, played back with a fixed time step.
+//!
+//! The final state of the vehicle is hashed over the bits of its transform
+//! and velocities, and compared against the golden hash stored next to the replay,
+//! so that any change of the physics results is caught.
+
+use crate::{hash::Fnv, space};
+
+use std::{
+    fs::{self, File},
+    hash::Hasher as _,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Player input recorded for a single step.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub motor: f32,
+    pub rudder: f32,
+    pub roll: f32,
+    pub turbo: bool,
+    pub jump: Option<f32>,
+}
+
+/// Input of the steps, which are all simulated with the same time step.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Replay {
+    /// Duration of a step, in seconds.
+    pub time_step: f32,
+    pub frames: Vec<Frame>,
+}
+
+impl Replay {
+    pub fn new(time_step: f32) -> Self {
+        Replay {
+            time_step,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        ron::de::from_reader(file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let string = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, string)
+    }
+}
+
+/// Hash the physical state of a vehicle, to be compared against the goldens.
+pub fn hash_state(
+    transform: &space::Transform,
+    linear_velocity: cgmath::Vector3<f32>,
+    angular_velocity: cgmath::Vector3<f32>,
+) -> u64 {
+    let values = [
+        transform.disp.x,
+        transform.disp.y,
+        transform.disp.z,
+        transform.rot.s,
+        transform.rot.v.x,
+        transform.rot.v.y,
+        transform.rot.v.z,
+        linear_velocity.x,
+        linear_velocity.y,
+        linear_velocity.z,
+        angular_velocity.x,
+        angular_velocity.y,
+        angular_velocity.z,
+    ];
+    let mut hasher = Fnv::default();
+    for value in values.iter() {
+        hasher.write(&value.to_bits().to_le_bytes());
+    }
+    hasher.finish()
+}
+
+/// Path of the golden hash of the replay at `path`.
+pub fn golden_path(path: &Path) -> PathBuf {
+    path.with_extension("golden")
+}
+
+pub fn read_golden(path: &Path) -> io::Result<u64> {
+    let string = fs::read_to_string(golden_path(path))?;
+    u64::from_str_radix(string.trim(), 16)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_golden(path: &Path, state_hash: u64) -> io::Result<()> {
+    fs::write(golden_path(path), format!("{:016x}\n", state_hash))
+}
@@ -1,8 +1,9 @@
 use cgmath::{EuclideanSpace as _, InnerSpace as _, Rotation as _, Rotation3 as _, Transform as _};
-use std::ops::Range;
+use std::{cell::Cell, ops::Range};
 
 pub type Transform = cgmath::Decomposed<cgmath::Vector3<f32>, cgmath::Quaternion<f32>>;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Projection {
     Ortho {
         p: cgmath::Ortho<f32>,
@@ -56,10 +57,21 @@ impl Projection {
     }
 }
 
+/// Matrices derived from the camera state, together with the state they were computed for.
+#[derive(Clone, Copy)]
+struct CachedMatrices {
+    loc: cgmath::Vector3<f32>,
+    rot: cgmath::Quaternion<f32>,
+    proj: Projection,
+    view_proj: cgmath::Matrix4<f32>,
+    inverse_view_proj: cgmath::Matrix4<f32>,
+}
+
 pub struct Camera {
     pub loc: cgmath::Vector3<f32>,
     pub rot: cgmath::Quaternion<f32>,
     pub proj: Projection,
+    cache: Cell<Option<CachedMatrices>>,
 }
 
 #[derive(Debug)]
@@ -75,6 +87,15 @@ pub struct Direction {
 }
 
 impl Camera {
+    pub fn new(loc: cgmath::Vector3<f32>, rot: cgmath::Quaternion<f32>, proj: Projection) -> Self {
+        Camera {
+            loc,
+            rot,
+            proj,
+            cache: Cell::new(None),
+        }
+    }
+
     pub fn dir(&self) -> cgmath::Vector3<f32> {
         self.rot * -cgmath::Vector3::unit_z()
     }
@@ -86,7 +107,7 @@ impl Camera {
         }
     }
 
-    fn get_proj_matrix(&self) -> cgmath::Matrix4<f32> {
+    pub fn proj_matrix(&self) -> cgmath::Matrix4<f32> {
         let mut proj = self.proj.to_matrix();
         // convert from GL's depth of [-1,1] to wgpu/gfx-rs [0,1]
         proj.x.z = 0.5 * (proj.x.z + proj.x.w);
@@ -104,10 +125,75 @@ impl Camera {
         }
     }
 
-    pub fn get_view_proj(&self) -> cgmath::Matrix4<f32> {
+    pub fn view_matrix(&self) -> cgmath::Matrix4<f32> {
         let view = self.view_transform();
-        let view_mx = cgmath::Matrix4::from(view.inverse_transform().unwrap());
-        self.get_proj_matrix() * view_mx
+        cgmath::Matrix4::from(view.inverse_transform().unwrap())
+    }
+
+    fn matrices(&self) -> CachedMatrices {
+        use cgmath::SquareMatrix as _;
+
+        if let Some(cached) = self.cache.get() {
+            if cached.loc == self.loc && cached.rot == self.rot && cached.proj == self.proj {
+                return cached;
+            }
+        }
+        let view_proj = self.proj_matrix() * self.view_matrix();
+        let cached = CachedMatrices {
+            loc: self.loc,
+            rot: self.rot,
+            proj: self.proj,
+            view_proj,
+            inverse_view_proj: view_proj.invert().unwrap(),
+        };
+        self.cache.set(Some(cached));
+        cached
+    }
+
+    pub fn view_proj(&self) -> cgmath::Matrix4<f32> {
+        self.matrices().view_proj
+    }
+
+    pub fn inverse_view_proj(&self) -> cgmath::Matrix4<f32> {
+        self.matrices().inverse_view_proj
+    }
+
+    /// Returns the left, right, bottom, top, near, and far planes of the view frustum.
+    /// Each plane is `(normal, distance)` in world space, with the normal pointing inside.
+    pub fn frustum_planes(&self) -> [cgmath::Vector4<f32>; 6] {
+        use cgmath::Matrix as _;
+
+        let mx = self.view_proj().transpose();
+        let planes = [
+            mx.w + mx.x,
+            mx.w - mx.x,
+            mx.w + mx.y,
+            mx.w - mx.y,
+            mx.z, // depth range is [0, 1]
+            mx.w - mx.z,
+        ];
+        let mut result = [cgmath::Vector4::new(0.0, 0.0, 0.0, 0.0); 6];
+        for (out, plane) in result.iter_mut().zip(planes.iter()) {
+            *out = plane / plane.truncate().magnitude();
+        }
+        result
+    }
+
+    /// Range of the heights crossed by the view frustum.
+    pub fn height_range(&self) -> Range<f32> {
+        let mx = self.inverse_view_proj();
+        let mut range = std::f32::MAX..std::f32::MIN;
+        for &x in &[-1.0, 1.0] {
+            for &y in &[-1.0, 1.0] {
+                for &z in &[0.0, 1.0] {
+                    let wp = mx * cgmath::vec4(x, y, z, 1.0);
+                    let height = wp.z / wp.w;
+                    range.start = range.start.min(height);
+                    range.end = range.end.max(height);
+                }
+            }
+        }
+        range
     }
 
     fn intersect_ray_height(&self, dir: cgmath::Vector3<f32>, height: f32) -> cgmath::Point3<f32> {
@@ -126,7 +212,7 @@ impl Camera {
         let center = self.intersect_height(height).to_vec().truncate();
         let mut bounds = center..center;
 
-        let proj = self.get_proj_matrix();
+        let proj = self.proj_matrix();
         let view = self.view_transform();
         let mx = cgmath::Matrix4::from(view) * proj.inverse_transform().unwrap();
         // Scale vectors in a way that makes their Z footprint to be -1 in local space.
@@ -0,0 +1,79 @@
+//! Opt-in hooks for the session statistics.
+//!
+//! Nothing is collected by the binaries of this crate, which discard the events.
+//! An application built on the library can implement its own `Telemetry`,
+//! e.g. for aggregating the performance reports of its players.
+//! The events are structured, so they don't need to be parsed out of the log.
+
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// GPU that the session is running on.
+    Adapter {
+        name: &'a str,
+        backend: wgpu::Backend,
+    },
+    /// Level is loaded and ready to be shown.
+    LevelLoaded { name: &'a str, duration: Duration },
+    /// Average frame rate since the previous report, and the terrain technique drawn.
+    FrameRate {
+        technique: &'a str,
+        average_fps: f32,
+        frames: u32,
+    },
+}
+
+/// Receiver of the events, which may be called from any thread.
+pub trait Telemetry: Send + Sync {
+    fn event(&self, event: &Event);
+}
+
+/// Default telemetry, discarding all the events.
+pub struct NoTelemetry;
+
+impl Telemetry for NoTelemetry {
+    fn event(&self, _event: &Event) {}
+}
+
+/// Accumulates the frame times into the periodic frame rate reports.
+pub struct FrameRateCounter {
+    technique: &'static str,
+    period: f32,
+    frames: u32,
+    time: f32,
+}
+
+impl FrameRateCounter {
+    /// Count the frames drawn with the `technique`, reporting every `period` seconds.
+    pub fn new(technique: &'static str, period: f32) -> Self {
+        FrameRateCounter {
+            technique,
+            period,
+            frames: 0,
+            time: 0.0,
+        }
+    }
+
+    /// Count a frame, reporting the frame rate once the period is over.
+    pub fn record(&mut self, delta: f32, telemetry: &dyn Telemetry) {
+        self.frames += 1;
+        self.time += delta;
+        if self.time >= self.period {
+            self.flush(telemetry);
+        }
+    }
+
+    /// Report the frames counted since the previous report, e.g. at exit.
+    pub fn flush(&mut self, telemetry: &dyn Telemetry) {
+        if self.frames != 0 && self.time > 0.0 {
+            telemetry.event(&Event::FrameRate {
+                technique: self.technique,
+                average_fps: self.frames as f32 / self.time,
+                frames: self.frames,
+            });
+        }
+        self.frames = 0;
+        self.time = 0.0;
+    }
+}
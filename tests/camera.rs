@@ -0,0 +1,54 @@
+use cgmath::{EuclideanSpace as _, InnerSpace as _, SquareMatrix as _};
+use vangers::space::{Camera, Projection};
+
+fn make_camera() -> Camera {
+    Camera::new(
+        cgmath::vec3(0.0, 0.0, 100.0),
+        cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        Projection::Perspective(cgmath::PerspectiveFov {
+            fovy: cgmath::Deg(45.0).into(),
+            aspect: 1.5,
+            near: 1.0,
+            far: 1000.0,
+        }),
+    )
+}
+
+#[test]
+fn view_proj_matches_components() {
+    let cam = make_camera();
+    let expected = cam.proj_matrix() * cam.view_matrix();
+    assert_eq!(cam.view_proj(), expected);
+    let diff = cam.view_proj() * cam.inverse_view_proj() - cgmath::Matrix4::identity();
+    let values: &[f32; 16] = diff.as_ref();
+    assert!(values.iter().all(|v| v.abs() < 1.0e-4));
+}
+
+#[test]
+fn cache_follows_changes() {
+    let mut cam = make_camera();
+    let before = cam.view_proj();
+    cam.loc.z += 50.0;
+    assert_ne!(cam.view_proj(), before);
+    cam.proj.update(100, 100);
+    assert_eq!(cam.view_proj(), cam.proj_matrix() * cam.view_matrix());
+}
+
+#[test]
+fn frustum_contains_view_direction() {
+    let cam = make_camera();
+    let planes = cam.frustum_planes();
+    let inside = cgmath::Point3::from_vec(cam.loc + cam.dir() * 10.0);
+    let behind = cgmath::Point3::from_vec(cam.loc - cam.dir() * 10.0);
+    assert!(planes.iter().all(|p| p.dot(inside.to_homogeneous()) >= 0.0));
+    assert!(planes.iter().any(|p| p.dot(behind.to_homogeneous()) < 0.0));
+}
+
+#[test]
+fn height_range_covers_view() {
+    let cam = make_camera();
+    let range = cam.height_range();
+    let near = cam.loc + cam.dir() * 2.0;
+    assert!(range.start <= near.z && near.z <= range.end);
+    assert!(range.start < range.end);
+}
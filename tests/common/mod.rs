@@ -0,0 +1,73 @@
+//! Fixtures shared by the tests.
+
+use m3d::{CollisionQuad, DrawTriangle, Geometry, Vertex};
+
+fn vertex(pos: u16, normal: u16) -> Vertex {
+    Vertex { pos, normal }
+}
+
+/// A quad made of two triangles, sharing two of the corners.
+pub fn quad() -> Geometry<DrawTriangle> {
+    let triangle = |vertices| DrawTriangle {
+        vertices,
+        flat_normal: [0, 0, 127],
+        material: [3, 0],
+    };
+    Geometry {
+        positions: vec![[0, 0, 0], [10, 0, 0], [10, 10, 0], [0, 10, 0]],
+        normals: vec![[0, 0, 127]],
+        polygons: vec![
+            triangle([vertex(2, 0), vertex(0, 0), vertex(1, 0)]),
+            triangle([vertex(0, 0), vertex(2, 0), vertex(3, 0)]),
+        ],
+    }
+}
+
+fn mesh<G>(geometry: G) -> m3d::Mesh<G> {
+    m3d::Mesh {
+        geometry,
+        bounds: m3d::Bounds {
+            coord_min: [0, 0, 0],
+            coord_max: [10, 10, 0],
+        },
+        parent_off: [0; 3],
+        parent_rot: [0; 3],
+        max_radius: 8,
+        physics: m3d::Physics {
+            volume: 1.0,
+            rcm: [0.0; 3],
+            jacobi: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        },
+    }
+}
+
+/// Model with the `quad` as the body and the collision shape,
+/// and one of the slots placed.
+pub fn quad_model() -> m3d::FullModel {
+    let mut slots = [m3d::Slot::EMPTY, m3d::Slot::EMPTY, m3d::Slot::EMPTY];
+    slots[1].pos = [1, 2, 3];
+    slots[1].angle = 90;
+    m3d::FullModel {
+        body: mesh(quad()),
+        shape: mesh(Geometry {
+            positions: vec![[0, 0, 0], [10, 0, 0], [10, 10, 0], [0, 10, 0]],
+            normals: vec![[0, 0, 127]],
+            polygons: vec![CollisionQuad {
+                vertices: [0, 1, 2, 3],
+                middle: [5, 5, 0],
+                flat_normal: [0, 0, 127],
+            }],
+        }),
+        bound: m3d::UpperBound {
+            dimensions: [10, 10, 1],
+            radius: 8,
+        },
+        color: m3d::BodyColor {
+            offset: 0,
+            shift: 0,
+        },
+        wheels: Vec::new(),
+        debris: Vec::new(),
+        slots,
+    }
+}
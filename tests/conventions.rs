@@ -0,0 +1,176 @@
+//! Coordinate conventions that the rendering, the physics, and the assets rely on:
+//! Z is up, X is east (to the right on the screen), Y is north (up on the screen),
+//! the space is right-handed, and the front faces are counter-clockwise.
+
+mod common;
+
+use cgmath::{EuclideanSpace as _, InnerSpace as _, Rotation3 as _, Transform as _};
+use vangers::{
+    level::{self, Level},
+    model,
+    space::{Camera, Projection, Transform},
+};
+
+use std::io::Cursor;
+
+fn top_down_camera() -> Camera {
+    Camera::new(
+        cgmath::vec3(0.0, 0.0, 100.0),
+        cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        Projection::Perspective(cgmath::PerspectiveFov {
+            fovy: cgmath::Deg(45.0).into(),
+            aspect: 1.0,
+            near: 1.0,
+            far: 1000.0,
+        }),
+    )
+}
+
+fn project(cam: &Camera, x: f32, y: f32, z: f32) -> cgmath::Point3<f32> {
+    cam.view_proj()
+        .transform_point(cgmath::Point3::new(x, y, z))
+}
+
+fn at_origin(yaw: cgmath::Deg<f32>) -> Transform {
+    cgmath::Decomposed {
+        scale: 1.0,
+        disp: cgmath::vec3(0.0, 0.0, 0.0),
+        rot: cgmath::Quaternion::from_angle_z(yaw),
+    }
+}
+
+#[test]
+fn space_is_right_handed() {
+    // turning left, from east to north, is a positive rotation around Z
+    let turned = at_origin(cgmath::Deg(90.0));
+    let forward = turned.transform_vector(cgmath::Vector3::unit_x());
+    assert!((forward - cgmath::Vector3::unit_y()).magnitude() < 1.0e-5);
+
+    // the focused camera starts south of the target,
+    // and orbits counter-clockwise when seen from above
+    let target = at_origin(cgmath::Deg(0.0));
+    let mut cam = top_down_camera();
+    cam.focus_on(&target);
+    assert!(cam.loc.y < 0.0 && cam.loc.x.abs() < 1.0e-5);
+    cam.rotate_focus(&target, cgmath::Deg(90.0).into(), cgmath::Rad(0.0));
+    assert!(cam.loc.x > 0.0 && cam.loc.y.abs() < 1.0e-3, "{:?}", cam.loc);
+
+    // the view doesn't mirror the space
+    let view = cam.view_matrix();
+    let axes = cgmath::Matrix3::from_cols(view.x.truncate(), view.y.truncate(), view.z.truncate());
+    assert!(cgmath::SquareMatrix::determinant(&axes) > 0.0);
+}
+
+#[test]
+fn camera_maps_east_right_and_north_up() {
+    let cam = top_down_camera();
+    // the default camera looks down
+    assert_eq!(cam.dir(), -cgmath::Vector3::unit_z());
+    let center = project(&cam, 0.0, 0.0, 0.0);
+    let east = project(&cam, 10.0, 0.0, 0.0);
+    let north = project(&cam, 0.0, 10.0, 0.0);
+    assert!(east.x > center.x && (east.y - center.y).abs() < 1.0e-5);
+    assert!(north.y > center.y && (north.x - center.x).abs() < 1.0e-5);
+    // the depth is in [0, 1], growing away from the camera
+    let higher = project(&cam, 0.0, 0.0, 50.0);
+    assert!(0.0 <= higher.z && higher.z < center.z && center.z <= 1.0);
+}
+
+#[test]
+fn terrain_rows_go_along_y_with_heights_up() {
+    let mut level = Level::new_test();
+    level.set_height(1, 0, 200);
+    // texels are stored row by row, X being the column
+    assert_eq!(level.height, vec![0, 200]);
+    assert_eq!(level.get((1, 0)).top(), 200);
+
+    // the ray cast from the sky stops at the surface, above the ground
+    let hit = level::cast_ray(
+        &level,
+        cgmath::vec3(1.5, 0.5, 200.0),
+        -cgmath::Vector3::unit_z(),
+    );
+    assert!(
+        (hit.pos.z - level::get_height(200)).abs() < 2.0,
+        "{:?}",
+        hit.pos
+    );
+    let hit = level::cast_ray(
+        &level,
+        cgmath::vec3(0.5, 0.5, 200.0),
+        -cgmath::Vector3::unit_z(),
+    );
+    assert!(hit.pos.z < 2.0, "{:?}", hit.pos);
+}
+
+/// Model with the body normals pointing up, written in the M3D format of the game.
+fn quad_m3d() -> Vec<u8> {
+    let mut data = Vec::new();
+    model::save_m3d(&common::quad_model(), &mut data).unwrap();
+    data
+}
+
+#[test]
+fn model_faces_are_counter_clockwise() {
+    let raw = m3d::FullModel::load(Cursor::new(quad_m3d()));
+    let (vertices, indices) = model::build_vertices(&raw.body.geometry, true);
+    let corners = indices
+        .iter()
+        .map(|&i| {
+            let p = vertices[i as usize].pos;
+            cgmath::vec3(p[0] as f32, p[1] as f32, p[2] as f32)
+        })
+        .collect::<Vec<_>>();
+
+    // the winding agrees with the normal of the vertices
+    let winding = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+    let n = vertices[indices[0] as usize].normal;
+    let normal = cgmath::vec3(n[0] as f32, n[1] as f32, n[2] as f32);
+    assert!(winding.normalize().dot(normal.normalize()) > 0.99);
+
+    // seen from above, the winding stays counter-clockwise on the screen
+    let cam = top_down_camera();
+    let screen = corners
+        .iter()
+        .map(|c| project(&cam, c.x, c.y, c.z).to_vec())
+        .collect::<Vec<_>>();
+    let area = (screen[1] - screen[0]).cross(screen[2] - screen[0]).z;
+    assert!(area > 0.0);
+}
+
+/// Value of an unsigned constant in the shader, written as `1U<<6` or `3U`.
+fn shader_constant(source: &str, name: &str) -> u32 {
+    let start = source.find(&format!("{} = ", name)).unwrap() + name.len() + 3;
+    let end = start + source[start..].find(|c| c == ',' || c == ';').unwrap();
+    let value = |s: &str| s.trim().trim_end_matches('U').parse::<u32>().unwrap();
+    match source[start..end].find("<<") {
+        Some(shift) => {
+            value(&source[start..start + shift]) << value(&source[start + shift + 2..end])
+        }
+        None => value(&source[start..end]),
+    }
+}
+
+#[test]
+fn terrain_shader_decodes_the_level_layout() {
+    let source = std::fs::read_to_string("res/shader/surface.inc.glsl").unwrap();
+    assert_eq!(
+        shader_constant(&source, "c_DoubleLevelMask"),
+        level::DOUBLE_LEVEL as u32
+    );
+    let bits = level::TerrainBits::new(8);
+    assert_eq!(
+        shader_constant(&source, "c_TerrainShift"),
+        bits.shift as u32
+    );
+    assert_eq!(
+        (1 << shader_constant(&source, "c_TerrainBits")) - 1,
+        bits.mask as u32
+    );
+    assert_eq!(
+        (1 << shader_constant(&source, "c_DeltaBits")) - 1,
+        level::DELTA_MASK as u32
+    );
+    // the texels are fetched by the world X and Y, wrapped into the level size
+    assert!(source.contains("ivec2 tci = ivec2(mod(pos, u_TextureScale.xy));"));
+}
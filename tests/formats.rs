@@ -0,0 +1,45 @@
+use vangers::formats::{detect_format, Format};
+
+use std::io::Cursor;
+
+fn mesh_header(version: u32) -> Vec<u8> {
+    // version, positions, normals, polygons, total vertices
+    [version, 8, 6, 12, 36]
+        .iter()
+        .flat_map(|v| v.to_le_bytes().to_vec())
+        .collect()
+}
+
+#[test]
+fn detect_mesh_version() {
+    let mut data = Cursor::new(mesh_header(8));
+    let format = detect_format(&mut data).unwrap();
+    assert_eq!(format, Some(Format::M3d { version: 8 }));
+    assert!(format.unwrap().check().is_ok());
+    assert_eq!(data.position(), 0);
+
+    let old = detect_format(&mut Cursor::new(mesh_header(7)))
+        .unwrap()
+        .unwrap();
+    let message = old.check().unwrap_err().to_string();
+    assert!(message.contains("version 7"), "{}", message);
+}
+
+#[test]
+fn detect_vmc_rows() {
+    let mut data = Vec::new();
+    let rows = 16u32;
+    let base = rows * 6 + 4096;
+    for i in 0..rows {
+        data.extend_from_slice(&(base + i * 64).to_le_bytes());
+        data.extend_from_slice(&64u16.to_le_bytes());
+    }
+    let format = detect_format(&mut Cursor::new(data)).unwrap();
+    assert_eq!(format, Some(Format::Vmc { rows }));
+}
+
+#[test]
+fn reject_garbage() {
+    let format = detect_format(&mut Cursor::new(vec![0xFFu8; 64])).unwrap();
+    assert_eq!(format, None);
+}
@@ -0,0 +1,99 @@
+use vangers::render::global::Constants;
+
+use std::mem;
+
+#[test]
+fn constants_layout() {
+    // must match the std140 layout of `Globals` in `globals.inc.glsl`
+    const VEC4: usize = 16;
+    const MAT4: usize = 4 * VEC4;
+    assert_eq!(mem::size_of::<Constants>(), 4 * VEC4 + 5 * MAT4);
+    assert_eq!(mem::size_of::<Constants>() % VEC4, 0);
+    assert!(mem::align_of::<Constants>() <= VEC4);
+}
+
+#[test]
+fn day_cycle_blends_palettes() {
+    use vangers::render::{blend_palettes, daylight, PaletteCycle};
+
+    assert!(daylight(0.0) < 1.0e-6);
+    assert!((daylight(12.0) - 1.0).abs() < 1.0e-6);
+    assert!((daylight(6.0) - 0.5).abs() < 1.0e-6);
+    assert!((daylight(18.0) - daylight(6.0)).abs() < 1.0e-6);
+
+    let night = [[0, 10, 20, 255]; 0x100];
+    let day = [[200, 110, 20, 255]; 0x100];
+    assert_eq!(blend_palettes(&night, &day, 0.0)[7], night[7]);
+    assert_eq!(blend_palettes(&night, &day, 1.0)[7], day[7]);
+    assert_eq!(blend_palettes(&night, &day, 0.5)[7], [100, 60, 20, 255]);
+    assert_eq!(blend_palettes(&night, &day, 2.0)[7], day[7]);
+
+    let cycle = PaletteCycle {
+        night: &night,
+        day: &day,
+    };
+    assert_eq!(cycle.at(0.0)[7], night[7]);
+    assert_eq!(cycle.at(12.0)[7], day[7]);
+    assert_eq!(cycle.at(6.0)[7], cycle.at(18.0)[7]);
+}
+
+#[test]
+fn debug_palette_replaces_pure_hues() {
+    use vangers::{config::settings::DebugPalette, render::debug::remap_color};
+
+    assert_eq!(remap_color(DebugPalette::Default, 0xFF0000FF), 0xFF0000FF);
+    // red becomes vermillion, keeping the alpha
+    assert_eq!(
+        remap_color(DebugPalette::Deuteranopia, 0x800000FF),
+        0x80005ED5
+    );
+    // half-intensity red becomes half-intensity orange
+    assert_eq!(
+        remap_color(DebugPalette::Protanopia, 0xFF000080),
+        0xFF004F73
+    );
+    // mixed colors are left alone
+    assert_eq!(
+        remap_color(DebugPalette::Protanopia, 0xFF204080),
+        0xFF204080
+    );
+}
+
+#[test]
+fn frame_stats_summarize_the_history() {
+    use std::time::Duration;
+    use vangers::render::debug::Stats;
+
+    let mut stats = Stats::new();
+    for &delta in &[0.010, 0.020, 0.030] {
+        stats.record_frame(delta);
+        stats.record_encode(Duration::from_millis(2));
+    }
+    stats.record_pass("main", Duration::from_millis(1));
+    stats.record_pass("main", Duration::from_millis(3));
+    let (average, max) = stats.frame_time();
+    assert!((average - 0.020).abs() < 1.0e-6);
+    assert_eq!(max, 0.030);
+    assert!((stats.encode_time() - 0.002).abs() < 1.0e-6);
+    // a pass is only listed once, with the latest time
+    assert_eq!(stats.passes().len(), 1);
+    assert!((stats.passes()[0].1 - 0.003).abs() < 1.0e-6);
+
+    // a frame bar and an encoding bar per frame, plus the 60 FPS line
+    let (vertices, colors) = stats.graph([-0.95, -0.95], [0.6, 0.3]);
+    assert_eq!(vertices.len(), (2 * 3 + 1) * 6);
+    assert_eq!(colors.len(), vertices.len());
+    for v in vertices.iter() {
+        assert!(v.pos[0] >= -1.0 && v.pos[0] <= 1.0, "{:?}", v);
+        assert!(v.pos[1] >= -1.0 && v.pos[1] <= 1.0, "{:?}", v);
+    }
+}
+
+#[test]
+fn debug_labels_follow_the_convention() {
+    use vangers::render::label;
+    assert_eq!(label::pipeline("terrain", "scatter"), "terrain-scatter");
+    assert_eq!(label::resource("terrain", "height"), "Terrain height");
+    assert_eq!(label::temp("instances"), "temp-instances");
+    assert_eq!(label::group("world", "shadow"), "world shadow");
+}
@@ -0,0 +1,27 @@
+use vangers::{config::settings, render::init::AdapterReport};
+
+#[test]
+fn terrain_falls_back_without_storage() {
+    let report = AdapterReport {
+        info: wgpu::AdapterInfo {
+            name: "Test".to_string(),
+            vendor: 0,
+            device: 0,
+            device_type: wgpu::DeviceType::Other,
+            backend: wgpu::Backend::Empty,
+        },
+        features: wgpu::Features::empty(),
+        limits: wgpu::Limits {
+            max_storage_buffers_per_shader_stage: 0,
+            ..wgpu::Limits::default()
+        },
+    };
+    let scattered = settings::Terrain::Scattered {
+        density: [1, 1, 1],
+        texel_density: None,
+    };
+    assert!(!report.supports(&scattered));
+    assert_eq!(report.fallback_terrain(&scattered).name(), "RayTraced");
+    let painted = settings::Terrain::Painted;
+    assert_eq!(report.fallback_terrain(&painted).name(), "Painted");
+}
@@ -0,0 +1,169 @@
+mod common;
+
+use common::{quad, quad_model};
+use vangers::model;
+
+use std::io::Cursor;
+
+#[test]
+fn dedup_merges_shared_corners() {
+    let (vertices, indices) = model::build_vertices(&quad(), true);
+    assert_eq!(vertices.len(), 4);
+    assert_eq!(indices.len(), 6);
+    assert_eq!(indices[0], indices[4]);
+    assert_eq!(indices[1], indices[3]);
+}
+
+#[test]
+fn raw_vertices_are_kept() {
+    let (vertices, indices) = model::build_vertices(&quad(), false);
+    assert_eq!(vertices.len(), 6);
+    assert_eq!(indices, (0..6).collect::<Vec<_>>());
+}
+
+#[test]
+fn buffers_are_identical_across_loads() {
+    for &dedup in &[false, true] {
+        let (v0, i0) = model::build_vertices(&quad(), dedup);
+        let (v1, i1) = model::build_vertices(&quad(), dedup);
+        assert_eq!(
+            bytemuck::cast_slice::<_, u8>(&v0),
+            bytemuck::cast_slice::<_, u8>(&v1)
+        );
+        assert_eq!(i0, i1);
+    }
+}
+
+#[test]
+fn dedup_order_depends_on_contents_only() {
+    let mut swapped = quad();
+    swapped.polygons.swap(0, 1);
+    let (v0, _) = model::build_vertices(&quad(), true);
+    let (v1, _) = model::build_vertices(&swapped, true);
+    assert_eq!(
+        bytemuck::cast_slice::<_, u8>(&v0),
+        bytemuck::cast_slice::<_, u8>(&v1)
+    );
+}
+
+#[test]
+fn m3d_round_trip() {
+    let raw = quad_model();
+    let mut first = Vec::new();
+    model::save_m3d(&raw, &mut first).unwrap();
+    let loaded = m3d::FullModel::load(Cursor::new(&first));
+    assert_eq!(loaded.body.geometry.polygons.len(), 2);
+    assert_eq!(loaded.slots[1].pos, [1, 2, 3]);
+    assert_eq!(loaded.slots[1].angle, 90);
+    let mut second = Vec::new();
+    model::save_m3d(&loaded, &mut second).unwrap();
+    assert_eq!(first, second);
+}
+
+#[test]
+fn m3d_rejects_steering_wheels_without_mesh() {
+    let mut raw = quad_model();
+    raw.wheels.push(m3d::Wheel {
+        mesh: None,
+        steer: 1,
+        pos: [0.0; 3],
+        width: 4,
+        radius: 4,
+        bound_index: 0,
+    });
+    let mut data = Vec::new();
+    assert_eq!(
+        model::save_m3d(&raw, &mut data),
+        Err(m3d::InvalidModel::WheelMesh(0))
+    );
+    assert!(data.is_empty());
+}
+
+#[test]
+fn obj_export_groups_colors() {
+    let path = std::env::temp_dir().join("vangers-quad.obj");
+    model::export_obj(&quad_model(), &path).unwrap();
+    let text = std::fs::read_to_string(&path).unwrap();
+    let count = |prefix: &str| text.lines().filter(|l| l.starts_with(prefix)).count();
+    assert_eq!(count("o "), 1);
+    assert_eq!(count("v "), 4);
+    assert_eq!(count("vn "), 1);
+    assert_eq!(count("usemtl "), 1);
+    assert!(text.contains("usemtl color3"));
+    assert!(text.contains("f 3//1 1//1 2//1"));
+}
+
+/// Wavefront OBJ text of a cube with the given half side.
+fn cube_obj(half: i32) -> String {
+    let mut text = String::new();
+    for &(x, y, z) in &[
+        (-1, -1, -1),
+        (1, -1, -1),
+        (1, 1, -1),
+        (-1, 1, -1),
+        (-1, -1, 1),
+        (1, -1, 1),
+        (1, 1, 1),
+        (-1, 1, 1),
+    ] {
+        text += &format!("v {} {} {}\n", x * half, y * half, z * half);
+    }
+    text += "o body\nusemtl color1\n";
+    text += "f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 2 3 7 6\nf 3 4 8 7\nf 4 1 5 8\n";
+    text
+}
+
+#[test]
+fn obj_import_estimates_physics() {
+    let path = std::env::temp_dir().join("vangers-cube.obj");
+    std::fs::write(&path, cube_obj(10)).unwrap();
+
+    let raw = model::import_obj(&path).unwrap();
+    assert_eq!(raw.body.geometry.polygons.len(), 12);
+    assert_eq!(raw.shape.geometry.polygons.len(), 12);
+    let physics = &raw.body.physics;
+    assert!((physics.volume - 8000.0).abs() < 1.0);
+    assert!(physics.rcm.iter().all(|c| c.abs() < 0.01));
+    // solid cube: side² * volume / 6 around each axis
+    let expected = 400.0 * 8000.0 / 6.0;
+    for axis in 0..3 {
+        assert!((physics.jacobi[axis][axis] - expected).abs() < 1.0);
+    }
+}
+
+#[test]
+fn obj_import_scales_large_models() {
+    let path = std::env::temp_dir().join("vangers-large-cube.obj");
+    std::fs::write(&path, cube_obj(1000)).unwrap();
+    let raw = model::import_obj(&path).unwrap();
+    let extent = raw
+        .body
+        .geometry
+        .positions
+        .iter()
+        .flat_map(|p| p.iter())
+        .map(|&v| (v as i32).abs())
+        .max();
+    assert_eq!(extent, Some(127));
+    assert_eq!(raw.body.bounds.coord_max, [127; 3]);
+}
+
+#[test]
+fn obj_import_rejects_invalid_meshes() {
+    // an open mesh has no inertia to simulate
+    let path = std::env::temp_dir().join("vangers-open.obj");
+    std::fs::write(&path, "v 0 0 0\nv 10 0 0\nv 0 10 0\nf 1 2 3\n").unwrap();
+    let err = model::import_obj(&path).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    // separate triangles with more vertices than the 16-bit indices can address
+    let path = std::env::temp_dir().join("vangers-huge.obj");
+    let mut text = String::new();
+    for i in 0..0x5556 {
+        text += "v 0 0 0\nv 10 0 0\nv 0 10 0\n";
+        text += &format!("f {} {} {}\n", 3 * i + 1, 3 * i + 2, 3 * i + 3);
+    }
+    std::fs::write(&path, text).unwrap();
+    let err = model::import_obj(&path).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
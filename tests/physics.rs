@@ -0,0 +1,85 @@
+use cgmath::InnerSpace as _;
+use vangers::{
+    level::{self, Level},
+    model, physics,
+};
+
+const SIZE: (i32, i32) = (64, 64);
+const GROUND: u8 = 64;
+/// Half of the box sides, in model units.
+const HALF_BOX: [i8; 3] = [10, 10, 5];
+
+fn make_level(altitude: impl Fn(i32, i32) -> u8) -> Level {
+    let mut level = Level::new_flat(SIZE, 0, 1);
+    for y in 0..SIZE.1 {
+        for x in 0..SIZE.0 {
+            level.height[(y * SIZE.0 + x) as usize] = altitude(x, y);
+        }
+    }
+    level
+}
+
+/// Corners of a box around the center of mass.
+fn box_samples() -> Vec<model::RawVertex> {
+    let [x, y, z] = HALF_BOX;
+    let mut samples = Vec::new();
+    for &sx in &[-x, x] {
+        for &sy in &[-y, y] {
+            for &sz in &[-z, z] {
+                samples.push([sx, sy, sz]);
+            }
+        }
+    }
+    samples
+}
+
+fn box_body(height: f32) -> physics::Body {
+    // inertia of a solid box per unit mass, roughly
+    let inertia = 60.0;
+    let mass_props = m3d::Physics {
+        volume: 1.0,
+        rcm: [0.0; 3],
+        jacobi: [
+            [inertia, 0.0, 0.0],
+            [0.0, inertia, 0.0],
+            [0.0, 0.0, inertia],
+        ],
+    };
+    let transform = cgmath::Decomposed {
+        scale: 1.0,
+        disp: cgmath::vec3(SIZE.0 as f32 * 0.5, SIZE.1 as f32 * 0.5, height),
+        rot: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+    };
+    physics::Body::from_physics(&mass_props, box_samples(), transform).unwrap()
+}
+
+#[test]
+fn dropped_body_comes_to_rest_on_flat_terrain() {
+    let level = make_level(|_, _| GROUND);
+    let surface = level::get_height(GROUND);
+    let mut body = box_body(surface + 20.0);
+    for _ in 0..600 {
+        body.step(1.0 / 60.0, &level);
+    }
+
+    let bottom = body.transform.disp.z - HALF_BOX[2] as f32;
+    assert!((bottom - surface).abs() < 1.5, "bottom {}", bottom);
+    assert!(body.vel.magnitude() < 0.1, "velocity {:?}", body.vel);
+    assert!(body.wel.magnitude() < 0.01, "angular {:?}", body.wel);
+    let up = body.transform.rot * cgmath::Vector3::unit_z();
+    assert!(up.z > 0.99, "up {:?}", up);
+}
+
+#[test]
+fn contacts_are_pushed_along_the_slope() {
+    // the ground is rising along X
+    let level = make_level(|x, _| GROUND + x as u8);
+    let body = box_body(0.0);
+    let contacts = model::project_samples(&box_samples(), &level, &body.transform);
+    assert!(!contacts.is_empty());
+    for contact in contacts {
+        assert!(contact.normal.x < -0.1, "{:?}", contact);
+        assert!(contact.normal.y.abs() < 1.0e-5, "{:?}", contact);
+        assert!((contact.normal.magnitude() - 1.0).abs() < 1.0e-5);
+    }
+}
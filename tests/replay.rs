@@ -0,0 +1,16 @@
+use vangers::replay;
+
+use std::path::Path;
+
+#[test]
+fn replay_survives_saving() {
+    let replay = replay::Replay::load(Path::new("tests/replays/drive.ron")).unwrap();
+    let dir = std::env::temp_dir().join(format!("vangers-replay-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("copy.ron");
+    replay.save(&path).unwrap();
+    let copy = replay::Replay::load(&path).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(copy.time_step, replay.time_step);
+    assert_eq!(copy.frames, replay.frames);
+}
@@ -3,3 +3,22 @@ fn load_settings() {
     let file = std::fs::File::open("config/settings.template.ron").unwrap();
     ron::de::from_reader::<_, vangers::config::settings::Settings>(file).unwrap();
 }
+
+#[test]
+fn save_car_id() {
+    use vangers::config::settings::{Car, Settings};
+
+    let template = std::fs::read_to_string("config/settings.template.ron").unwrap();
+    let path = std::env::temp_dir().join("vangers-settings.ron");
+    std::fs::write(&path, &template).unwrap();
+    Car::save_id(&path, "Pilgrim").unwrap();
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    let settings = ron::de::from_str::<Settings>(&text).unwrap();
+    assert_eq!(settings.car.id, "Pilgrim");
+    // only the id is changed, the comments are kept
+    assert_eq!(
+        text.replace("\"Pilgrim\",\n", ""),
+        template.replace("\"OxidizeMonk\",\n", "")
+    );
+}
@@ -0,0 +1,40 @@
+use vangers::telemetry::{Event, FrameRateCounter, Telemetry};
+
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct Recorder {
+    reports: Mutex<Vec<(String, f32, u32)>>,
+}
+
+impl Telemetry for Recorder {
+    fn event(&self, event: &Event) {
+        if let Event::FrameRate {
+            technique,
+            average_fps,
+            frames,
+        } = *event
+        {
+            let mut reports = self.reports.lock().unwrap();
+            reports.push((technique.to_string(), average_fps, frames));
+        }
+    }
+}
+
+#[test]
+fn frame_rate_is_reported_per_period() {
+    let recorder = Recorder::default();
+    let mut counter = FrameRateCounter::new("RayTraced", 1.0);
+    for _ in 0..30 {
+        counter.record(0.05, &recorder);
+    }
+    // the rest is only reported on flush
+    counter.flush(&recorder);
+    counter.flush(&recorder);
+
+    let reports = recorder.reports.lock().unwrap();
+    assert_eq!(reports.len(), 2);
+    assert_eq!(reports[0].0, "RayTraced");
+    assert_eq!(reports[0].2 + reports[1].2, 30);
+    assert!((reports[0].1 - 20.0).abs() < 0.1);
+}
@@ -0,0 +1,599 @@
+use vangers::level::{self, Level, WorldPhysics};
+
+const SIZE: (i32, i32) = (64, 16);
+const TOLERANCE: f32 = 2.0;
+
+fn altitude(value: u8) -> f32 {
+    value as f32 / 255.0 * level::HEIGHT_SCALE as f32
+}
+
+fn make_level(texel: impl Fn(i32, i32) -> (u8, u8)) -> Level {
+    let mut level = Level::new_flat(SIZE, 0, 0);
+    for y in 0..SIZE.1 {
+        for x in 0..SIZE.0 {
+            let i = (y * SIZE.0 + x) as usize;
+            let (h, m) = texel(x, y);
+            level.height[i] = h;
+            level.meta[i] = m;
+        }
+    }
+    level
+}
+
+fn terrain_meta(terrain: level::TerrainType) -> u8 {
+    level::TerrainBits::new(8).write(terrain)
+}
+
+fn down() -> cgmath::Vector3<f32> {
+    cgmath::vec3(0.0, 0.0, -1.0)
+}
+
+#[test]
+fn vertical_ray_hits_flat_ground() {
+    let level = make_level(|_, _| (100, terrain_meta(3)));
+    let pt = level::cast_ray(&level, cgmath::vec3(5.5, 5.5, 200.0), down());
+    assert!((pt.pos.z - altitude(100)).abs() < TOLERANCE, "{:?}", pt);
+    assert_eq!(pt.terrain, 3);
+}
+
+#[test]
+fn slanted_ray_matches_level_heights() {
+    let level = make_level(|x, _| (x as u8 * 2, terrain_meta(1)));
+    for i in 0..8 {
+        let base = cgmath::vec3(2.0 + 3.0 * i as f32, 8.5, 120.0);
+        let pt = level::cast_ray(&level, base, cgmath::vec3(0.5, 0.1, -1.0));
+        let top = level.get((pt.pos.x as i32, pt.pos.y as i32)).top();
+        let expected = altitude(top);
+        assert!(
+            pt.pos.z <= expected + 0.01,
+            "{:?} is above {}",
+            pt,
+            expected
+        );
+        assert!(
+            pt.pos.z > expected - TOLERANCE,
+            "{:?} is below {}",
+            pt,
+            expected
+        );
+    }
+}
+
+#[test]
+fn double_level_picks_the_layer() {
+    let (low, high) = (40, 200);
+    let level = make_level(|x, _| {
+        let delta = level::DELTA_MASK;
+        if x & 1 == 0 {
+            (low, level::DOUBLE_LEVEL | terrain_meta(2) | delta)
+        } else {
+            (high, level::DOUBLE_LEVEL | terrain_meta(5) | delta)
+        }
+    });
+    let surface = level::get_surface(&level, cgmath::vec2(10.5, 3.5));
+    assert_eq!(surface.low_alt, altitude(low));
+    assert_eq!(surface.high_alt, altitude(high));
+    assert_ne!(surface.delta, 0.0);
+
+    // from the sky, the upper layer is hit
+    let pt = level::cast_ray(&level, cgmath::vec3(10.5, 3.5, 150.0), down());
+    assert!((pt.pos.z - altitude(high)).abs() < TOLERANCE, "{:?}", pt);
+    assert_eq!(pt.terrain, 5);
+
+    // from within the gap, the lower layer is hit
+    let gap = 0.5 * (altitude(low) + altitude(high) - surface.delta);
+    let pt = level::cast_ray(&level, cgmath::vec3(10.5, 3.5, gap), down());
+    assert!((pt.pos.z - altitude(low)).abs() < TOLERANCE, "{:?}", pt);
+    assert_eq!(pt.terrain, 2);
+}
+
+#[test]
+fn ray_wraps_around_the_edges() {
+    let level = make_level(|x, _| {
+        if x == SIZE.0 - 1 {
+            (150, terrain_meta(6))
+        } else {
+            (50, terrain_meta(1))
+        }
+    });
+    let pt = level::cast_ray(&level, cgmath::vec3(-0.5, 3.5, 200.0), down());
+    assert!((pt.pos.z - altitude(150)).abs() < TOLERANCE, "{:?}", pt);
+    assert_eq!(pt.terrain, 6);
+}
+
+#[test]
+fn ceiling_is_decoded_from_delta_bits() {
+    let (low, high) = (40, 200);
+    let level = make_level(|x, y| {
+        if y != 0 {
+            (low, terrain_meta(1))
+        } else if x & 1 == 0 {
+            (low, level::DOUBLE_LEVEL | level::DELTA_MASK)
+        } else {
+            (high, level::DOUBLE_LEVEL | 1)
+        }
+    });
+    let delta = (level::DELTA_MASK << level::DELTA_SHIFT0) + (1 << level::DELTA_SHIFT1);
+    for &x in &[4, 5] {
+        assert_eq!(level.clearance((x, 0)), Some(delta));
+        assert_eq!(level.ceiling((x, 0)), Some(low + delta));
+    }
+    assert_eq!(level.ceiling((4, 1)), None);
+    assert_eq!(level.clearance((4, 1)), None);
+
+    let ceiling = level.extract_ceiling();
+    assert_eq!(ceiling.len(), level.height.len());
+    assert_eq!(ceiling[5], low + delta);
+    assert_eq!(ceiling[SIZE.0 as usize + 5], 0xFF);
+}
+
+#[test]
+fn flat_regions_skip_slopes_and_water() {
+    let mut level = make_level(|x, _| {
+        if x < 16 {
+            (100, 0) // flat
+        } else if x < 32 {
+            (((x - 16) * 10) as u8, 0) // steep
+        } else {
+            (10, 0) // under water
+        }
+    });
+    level.flood_map = vec![20];
+    let regions = level.find_flat_regions(8, 0.5);
+    assert_eq!(regions.len(), 4);
+    for region in regions {
+        assert!(region.center.x < 16.0, "{:?}", region);
+        assert_eq!(region.slope, 0.0);
+        assert_eq!(region.center.z, altitude(100));
+    }
+}
+
+#[test]
+fn shoreline_borders_the_water() {
+    let mut level = make_level(|x, _| (if x < 32 { 100 } else { 10 }, 0));
+    level.flood_map = vec![20];
+    let shore = level.water_shoreline();
+    // one dry column at each side of the water, which wraps around the edge
+    assert_eq!(shore.len(), 2 * SIZE.1 as usize);
+    for pt in shore {
+        assert!(pt.x == 31.5 || pt.x == 0.5, "{:?}", pt);
+        assert_eq!(pt.z, altitude(20));
+    }
+}
+
+#[test]
+fn raw_level_data_round_trip() {
+    let data = level::LevelData {
+        height: (0..SIZE.0 * SIZE.1).map(|i| i as u8).collect(),
+        meta: (0..SIZE.0 * SIZE.1).map(|i| (i / 3) as u8).collect(),
+        size: SIZE,
+    };
+    let path = std::env::temp_dir().join("vangers-raw-round-trip.vmp");
+    data.save_vmp(&path);
+    let loaded = level::load_vmp(&path, SIZE);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.height, data.height);
+    assert_eq!(loaded.meta, data.meta);
+}
+
+fn compressed_round_trip(data: &level::LevelData, name: &str) -> u64 {
+    let path = std::env::temp_dir().join(name);
+    data.save_vmc(&path);
+    let file_size = std::fs::metadata(&path).unwrap().len();
+    let loaded = level::load_vmc(&path, data.size);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.height, data.height);
+    assert_eq!(loaded.meta, data.meta);
+    file_size
+}
+
+#[test]
+fn compressed_level_data_round_trip() {
+    use rand::{Rng, SeedableRng};
+
+    let total = (SIZE.0 * SIZE.1) as usize;
+    for seed in 0..8 {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        // the alphabet grows with the seed, up to all the byte values
+        let max = (1u16 << (seed + 1)) - 1;
+        let data = level::LevelData {
+            height: (0..total)
+                .map(|_| rng.gen_range(0, max + 1) as u8)
+                .collect(),
+            meta: (0..total)
+                .map(|_| rng.gen_range(0, max + 1) as u8)
+                .collect(),
+            size: SIZE,
+        };
+        compressed_round_trip(&data, "vangers-random-round-trip.vmc");
+    }
+
+    // flat terrain has a single symbol per stream
+    let flat = level::LevelData {
+        height: vec![77; total],
+        meta: vec![terrain_meta(2); total],
+        size: SIZE,
+    };
+    compressed_round_trip(&flat, "vangers-flat-round-trip.vmc");
+
+    // smooth hills are compressed below the raw size, like the original levels
+    let hills = make_level(|x, y| {
+        let h = 120.0 + 60.0 * (x as f32 * 0.2).sin() * (y as f32 * 0.3).cos();
+        (h as u8, terrain_meta((x / 16) as u8))
+    });
+    let file_size = compressed_round_trip(
+        &level::LevelData::from(hills),
+        "vangers-hills-round-trip.vmc",
+    );
+    let table_size = SIZE.1 as u64 * (2 + 4) + splay::Splay::tree_size();
+    assert!(file_size < table_size + total as u64, "{}", file_size);
+}
+
+#[test]
+fn flying_across_the_seams() {
+    use cgmath::vec3;
+
+    let level = make_level(|x, y| ((x * 3 + y * 5) as u8, terrain_meta(x as u8 & 7)));
+    let (w, h) = (SIZE.0 as f32, SIZE.1 as f32);
+    // fly diagonally across the corner of the level, in both directions
+    for i in -40..40 {
+        let t = i as f32 * 0.25;
+        let pos = vec3(w - 0.25 + t, h * 2.0 - t, 50.0);
+        let offset = level.wrap_offset(pos);
+        let wrapped = pos + offset;
+        assert!(wrapped.x >= 0.0 && wrapped.x < w, "{:?}", wrapped);
+        assert!(wrapped.y >= 0.0 && wrapped.y < h, "{:?}", wrapped);
+        assert_eq!(offset.z, 0.0);
+        assert_eq!(offset.x % w, 0.0);
+        assert_eq!(offset.y % h, 0.0);
+
+        assert_eq!(
+            level::get_surface(&level, pos.truncate()),
+            level::get_surface(&level, wrapped.truncate())
+        );
+    }
+}
+
+#[test]
+fn saved_level_loads_back() {
+    let mut level = make_level(|x, y| ((x ^ y) as u8, terrain_meta((x + y) as u8 & 7)));
+    level.flood_map = vec![37];
+    let dir = std::env::temp_dir();
+    let config = level::LevelConfig {
+        path_palette: dir.join("vangers-save-round-trip.pal"),
+        path_data: dir.join("vangers-save-round-trip.vmp"),
+        is_compressed: false,
+        size: (level::Power::new(6).unwrap(), level::Power::new(4).unwrap()),
+        geo: level::Power::new(4).unwrap(),
+        section: level::Power::new(4).unwrap(),
+        min_square: level::Power::new(2).unwrap(),
+        terrains: level.terrains.clone(),
+        physics: WorldPhysics::default(),
+    };
+    level::save(&level, &config);
+    let data = level::load_vmp(&config.path_data, SIZE);
+    let flood_map = level::load_flood(&config);
+    std::fs::remove_file(&config.path_data).unwrap();
+    std::fs::remove_file(config.path_data.with_extension("vpr")).unwrap();
+    assert_eq!(data.height, level.height);
+    assert_eq!(data.meta, level.meta);
+    assert_eq!(flood_map, level.flood_map);
+}
+
+#[test]
+fn flat_region_near_avoids_water_and_slopes() {
+    // a dry plateau on the left, water in the middle, and a ramp on the right
+    let mut level = make_level(|x, _| match x {
+        0..=15 => (100, 0),
+        16..=47 => (10, 0),
+        _ => ((x - 48) as u8 * 10, 0),
+    });
+    level.flood_map = vec![20];
+    let region = level
+        .find_flat_region_near(cgmath::Point2::new(32.0, 8.0), 4, 0.5, 40.0)
+        .unwrap();
+    assert!(region.center.x < 16.0, "{:?}", region);
+    assert_eq!(region.center.z, altitude(100));
+    // the plateau is the only safe spot, and it's too far away
+    assert_eq!(
+        level.find_flat_region_near(cgmath::Point2::new(40.0, 8.0), 4, 0.5, 10.0),
+        None
+    );
+}
+
+#[test]
+fn edits_are_tracked_in_merged_regions() {
+    let mut level = make_level(|_, _| (100, 0));
+    for x in 10..14 {
+        level.set_height(x, 3, 50);
+    }
+    level.set_meta(40, 5, terrain_meta(2));
+    level.set_height(-1, -1, 7);
+    assert_eq!(level.get((12, 3)).top(), 50);
+    assert_eq!(level.get((SIZE.0 - 1, SIZE.1 - 1)).top(), 7);
+
+    let mut rects = level.dirty.take();
+    rects.sort_by_key(|r| (r.x, r.y));
+    let rects = rects
+        .into_iter()
+        .map(|r| (r.x, r.y, r.w, r.h))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rects,
+        vec![(10, 3, 4, 1), (40, 5, 2, 1), (SIZE.0 - 2, SIZE.1 - 1, 2, 1)]
+    );
+    assert!(level.dirty.is_empty());
+}
+
+#[test]
+fn brush_stamps_are_blended() {
+    let mut level = make_level(|_, _| (100, 0));
+    let brush = level::Brush {
+        size: (3, 1),
+        values: vec![0, 255, 128],
+    };
+    level.stamp(&brush, (10, 3), 20.0, level::Blend::Add);
+    assert_eq!(level.get((9, 3)).top(), 100);
+    assert_eq!(level.get((10, 3)).top(), 120);
+    assert_eq!(level.get((11, 3)).top(), 110);
+    level.stamp(&brush, (10, 3), 0.0, level::Blend::Set);
+    assert_eq!(level.get((10, 3)).top(), 0);
+    assert_eq!(level.get((11, 3)).top(), 55);
+    level.stamp(&brush, (10, 3), 50.0, level::Blend::Max);
+    assert_eq!(level.get((10, 3)).top(), 50);
+    assert_eq!(level.get((11, 3)).top(), 55);
+
+    let rects = level.dirty.take();
+    assert_eq!(rects.len(), 1);
+    assert_eq!((rects[0].x, rects[0].w), (10, 2));
+}
+
+#[test]
+fn brush_stamps_the_upper_layer_once_per_pair() {
+    // the delta bits of the floor give a gap of 32 above it
+    let (low, high, gap) = (40, 100, 32);
+    let mut level = make_level(|x, _| {
+        if x & 1 == 0 {
+            (low, level::DOUBLE_LEVEL | terrain_meta(2) | 1)
+        } else {
+            (high, level::DOUBLE_LEVEL | terrain_meta(5))
+        }
+    });
+    assert_eq!(level.ceiling((10, 3)), Some(low + gap));
+    let layers = |level: &Level| {
+        let i = (3 * SIZE.0 + 10) as usize;
+        (level.height[i], level.height[i + 1])
+    };
+    let brush = level::Brush {
+        size: (2, 1),
+        values: vec![255, 128],
+    };
+    level.stamp(&brush, (11, 3), 20.0, level::Blend::Add);
+    assert_eq!(layers(&level), (low, high + 20));
+    // the shelf can't be lowered into the gap
+    level.stamp(&brush, (11, 3), 0.0, level::Blend::Set);
+    assert_eq!(layers(&level), (low, low + gap));
+
+    let rects = level.dirty.take();
+    assert_eq!(rects.len(), 1);
+    assert_eq!((rects[0].x, rects[0].w), (10, 2));
+}
+
+#[test]
+fn collision_surface_skips_the_gap() {
+    let (low, high) = (40, 200);
+    let level = make_level(|x, _| {
+        if x & 1 == 0 {
+            (low, level::DOUBLE_LEVEL | terrain_meta(2))
+        } else {
+            (high, level::DOUBLE_LEVEL | terrain_meta(5))
+        }
+    });
+    let at = |z| level.collision_surface(cgmath::vec3(10.5, 3.5, z));
+    assert_eq!(at(10.0), Some((level::get_height(low), 2)));
+    // just above the middle, the point is in the gap
+    let middle = level::get_middle(low, high);
+    assert_eq!(at(middle + 1.0), None);
+    // close to the upper layer, it's pushed on top
+    assert_eq!(
+        at(level::get_height(high) - 1.0),
+        Some((level::get_height(high), 5))
+    );
+}
+
+#[test]
+fn route_rows_wrap_and_merge() {
+    let waypoints = [
+        cgmath::vec2(0.0, 10.0),
+        cgmath::vec2(5.0, 20.0),
+        cgmath::vec2(5.0, 250.0),
+    ];
+    assert_eq!(level::route_rows(256, &waypoints[..2], 2.0), vec![8..23]);
+    assert_eq!(
+        level::route_rows(256, &waypoints[1..], 10.0),
+        vec![0..5, 10..256]
+    );
+    assert_eq!(level::route_rows(256, &[], 10.0), vec![]);
+}
+
+#[test]
+fn level_diff_reports_the_changes() {
+    let old = make_level(|_, _| (100, terrain_meta(1)));
+    let new = make_level(|x, y| match (x, y) {
+        (3, 2) => (110, terrain_meta(1)),
+        (5, 4) => (96, terrain_meta(2)),
+        _ => (100, terrain_meta(1)),
+    });
+    let diff = level::LevelDiff::new(&old, &new).unwrap();
+    let stats = &diff.stats;
+    assert_eq!((stats.height_changed, stats.terrain_changed), (2, 1));
+    assert_eq!((stats.max_raise, stats.max_drop), (10, 4));
+    assert_eq!(stats.mean_change, 7.0);
+    assert_eq!(
+        stats.bounds,
+        Some(level::Rect {
+            x: 3,
+            y: 2,
+            w: 3,
+            h: 3
+        })
+    );
+    assert_eq!(diff.height_delta[(2 * SIZE.0 + 3) as usize], 10);
+
+    let same = level::LevelDiff::new(&old, &old).unwrap();
+    assert_eq!(same.stats, level::DiffStats::default());
+}
+
+#[test]
+fn level_diff_rejects_other_sizes() {
+    let old = make_level(|_, _| (100, terrain_meta(1)));
+    let mut new = make_level(|_, _| (100, terrain_meta(1)));
+    new.size = (SIZE.0 / 2, SIZE.1 * 2);
+    assert_eq!(
+        level::LevelDiff::new(&old, &new).err(),
+        Some(level::SizeMismatch {
+            old: SIZE,
+            new: (SIZE.0 / 2, SIZE.1 * 2),
+        })
+    );
+}
+
+#[test]
+fn power_is_validated_and_counts_cells() {
+    assert_eq!(level::Power::new(-1), Err(level::InvalidPower(-1)));
+    assert!(level::Power::new(level::Power::MAX + 1).is_err());
+    assert_eq!(level::Power::from_value(48), None);
+
+    let section = level::Power::from_value(16).unwrap();
+    assert_eq!(section.as_power(), 4);
+    assert_eq!(section.index_of(35), 2);
+    assert_eq!(section.offset_of(2), 32);
+    assert_eq!(section.count_in(32), 2);
+    assert_eq!(section.count_in(33), 3);
+    assert_eq!(section.count_in(8), 1);
+}
+
+#[test]
+fn light_probes_average_the_terrain_colors() {
+    use vangers::render::probe::ProbeGrid;
+
+    let mut level = make_level(|x, _| (50, terrain_meta(if x < 32 { 1 } else { 2 })));
+    level.terrains[1].colors = 10..12;
+    level.terrains[2].colors = 20..22;
+    level.palette[11] = [0xFF, 0, 0, 0];
+    level.palette[21] = [0, 0, 0xFF, 0];
+
+    let grid = ProbeGrid::bake(&level, 16, [0.0, 0.0, 0.0, 1.0], 0.5);
+    assert_eq!(grid.size, (4, 1));
+    assert_eq!(grid.colors.len(), 4);
+    // half of the terrain color, blended with the black sky
+    assert_eq!(grid.colors[0], [0x7F, 0, 0, 0xFF]);
+    assert_eq!(grid.colors[1], [0x7F, 0, 0, 0xFF]);
+    assert_eq!(grid.colors[3], [0, 0, 0x7F, 0xFF]);
+}
+
+fn write_gray_png(path: &std::path::Path, size: (u32, u32), data: &[u8]) {
+    let file = std::fs::File::create(path).unwrap();
+    let mut encoder = png::Encoder::new(file, size.0, size.1);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(data)
+        .unwrap();
+}
+
+#[test]
+fn level_is_imported_from_images() {
+    let dir = std::env::temp_dir();
+    let height_path = dir.join("vangers-import-height.png");
+    let terrain_path = dir.join("vangers-import-terrain.png");
+    let palette_path = dir.join("vangers-import.pal");
+    let size = (16, 8);
+    let heights = (0..size.0 * size.1).map(|i| i as u8).collect::<Vec<_>>();
+    let grays = (0..size.0 * size.1)
+        .map(|i| (i * 2) as u8)
+        .collect::<Vec<_>>();
+    write_gray_png(&height_path, size, &heights);
+    write_gray_png(&terrain_path, size, &grays);
+    std::fs::write(&palette_path, vec![0x20; 0x300]).unwrap();
+
+    let level = level::from_image(&height_path, &terrain_path, &palette_path).unwrap();
+    assert_eq!(level.size, (16, 8));
+    assert_eq!(level.terrains.len(), level::IMAGE_TERRAINS as usize);
+    for y in 0..8 {
+        for x in 0..16 {
+            let i = y * 16 + x;
+            match level.get((x, y)) {
+                level::Texel::Single(point) => {
+                    assert_eq!(point.0, i as u8);
+                    assert_eq!(point.1, level::terrain_of_gray((i * 2) as u8));
+                }
+                level::Texel::Dual { .. } => panic!("Unexpected double level at {}x{}", x, y),
+            }
+        }
+    }
+    assert_eq!(level::terrain_of_gray(0), 0);
+    assert_eq!(level::terrain_of_gray(0xFF), level::IMAGE_TERRAINS - 1);
+
+    // the sizes of the images have to match
+    write_gray_png(&terrain_path, (8, 8), &grays[..64]);
+    assert!(level::from_image(&height_path, &terrain_path, &palette_path).is_err());
+
+    for path in &[height_path, terrain_path, palette_path] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn export_rejects_too_many_terrains() {
+    let mut level = make_level(|_, _| (100, terrain_meta(1)));
+    // the count would wrap around to 0 as `u8`
+    level.terrains = (0..0x100).map(|_| level.terrains[0].clone()).collect();
+    let dir = std::env::temp_dir().join("vangers-export-terrains");
+    assert!(level::export_images(&level, &dir).is_err());
+}
+
+#[test]
+fn exported_images_import_back() {
+    let mut level = make_level(|x, y| {
+        let meta = terrain_meta((x ^ y) as u8 & 7);
+        if (8..16).contains(&x) {
+            // a double level pair, with the delta bits split between the texels
+            let delta = (y as u8 >> (x & 1)) & level::DELTA_MASK;
+            ((x * 3 + y) as u8, meta | level::DOUBLE_LEVEL | delta)
+        } else {
+            ((x * 3 + y) as u8, meta)
+        }
+    });
+    level.flood_map = vec![42];
+    let dir = std::env::temp_dir().join("vangers-export-images");
+    level::export_images(&level, &dir).unwrap();
+
+    let palette_path = dir.join("palette.pal");
+    std::fs::write(&palette_path, vec![0x20; 0x300]).unwrap();
+    let imported = level::from_image(
+        &dir.join(level::HEIGHT_IMAGE),
+        &dir.join(level::META_IMAGE),
+        &palette_path,
+    )
+    .unwrap();
+    let flood = std::fs::read(dir.join(level::FLOOD_IMAGE)).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(imported.size, level.size);
+    assert_eq!(imported.height, level.height);
+    assert_eq!(imported.meta, level.meta);
+    match imported.get((9, 5)) {
+        level::Texel::Dual { .. } => {}
+        level::Texel::Single(_) => panic!("Double level is lost"),
+    }
+    assert!(!flood.is_empty());
+    for t in 0..level::IMAGE_TERRAINS {
+        assert_eq!(
+            level::terrain_of_gray(level::gray_of_terrain(t, level::IMAGE_TERRAINS)),
+            t
+        );
+    }
+}